@@ -0,0 +1,230 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    CloseReason, Error, Message, OwnedMessage, PayloadType, SenderHandle, TrySendError, WebSocket,
+    WebSocketStream,
+};
+use bytes::{Bytes, BytesMut};
+use ratchet_ext::SplittableExtension;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::broadcast;
+
+/// The maximum number of messages which may be queued to be sent by a spawned WebSocket.
+const SEND_CAPACITY: usize = 64;
+/// The maximum number of received messages which are retained for a lagging subscriber.
+const BROADCAST_CAPACITY: usize = 64;
+
+/// A handle to a WebSocket whose read loop has been spawned into a task by
+/// [`WebSocket::spawn`].
+///
+/// The handle may be cloned and used from any task. Messages are sent through a
+/// [`SenderHandle`] and each message that is received is broadcast to every subscriber.
+#[derive(Clone, Debug)]
+pub struct WebSocketHandle {
+    sender: SenderHandle,
+    // This is cleared by the read loop once it stops so that the channel is closed.
+    messages: Arc<Mutex<Option<broadcast::Sender<OwnedMessage>>>>,
+}
+
+impl WebSocketHandle {
+    /// Queues a message of `message_type` with a payload of `payload` to be sent. See
+    /// [`SenderHandle::send`].
+    pub async fn send<B>(&self, payload: B, message_type: PayloadType) -> Result<(), Error>
+    where
+        B: Into<Bytes>,
+    {
+        self.sender.send(payload, message_type).await
+    }
+
+    /// Queues a message of `message_type` with a payload of `payload` to be sent if there is space
+    /// in the queue. See [`SenderHandle::try_send`].
+    pub fn try_send<B>(&self, payload: B, message_type: PayloadType) -> Result<(), TrySendError>
+    where
+        B: Into<Bytes>,
+    {
+        self.sender.try_send(payload, message_type)
+    }
+
+    /// Closes the WebSocket with the reason provided. See [`SenderHandle::close`].
+    pub async fn close(&self, reason: CloseReason) -> Result<(), Error> {
+        self.sender.close(reason).await
+    }
+
+    /// Returns a receiver of the messages which are received after this call. The receiver
+    /// observes that the channel has been closed once the read loop has stopped.
+    pub fn subscribe(&self) -> broadcast::Receiver<OwnedMessage> {
+        match &*lock(&self.messages) {
+            Some(messages) => messages.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// Returns the handle which messages are sent through.
+    pub fn sender(&self) -> &SenderHandle {
+        &self.sender
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl<S, E> WebSocket<S, E>
+where
+    S: WebSocketStream + 'static,
+    E: SplittableExtension,
+{
+    /// Splits this WebSocket and spawns its read loop into a task on the current tokio runtime,
+    /// returning a handle to it and a receiver of every message that is received.
+    ///
+    /// `handler` is invoked, and awaited, for each message that is received along with a handle
+    /// which may be used to reply to it. Control frames are handled by the read loop in the same
+    /// manner as [`WebSocket::read`] and are still passed to `handler`. The read loop stops once a
+    /// close frame has been received or a read operation fails.
+    ///
+    /// # Errors
+    /// Errors if the WebSocket is already closed.
+    ///
+    /// # Panics
+    /// Panics if called from outside of a tokio runtime.
+    pub fn spawn<H, Fut>(
+        self,
+        mut handler: H,
+    ) -> Result<(WebSocketHandle, broadcast::Receiver<OwnedMessage>), Error>
+    where
+        H: FnMut(OwnedMessage, SenderHandle) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let (sender, mut receiver) = self.split()?;
+        let sender = sender.into_handle(SEND_CAPACITY);
+        let (messages, subscriber) = broadcast::channel(BROADCAST_CAPACITY);
+
+        let messages = Arc::new(Mutex::new(Some(messages)));
+        let handle = WebSocketHandle {
+            sender: sender.clone(),
+            messages: messages.clone(),
+        };
+
+        tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            loop {
+                let message = match receiver.read(&mut buf).await {
+                    Ok(Message::Text) => OwnedMessage::Text(buf.split().freeze()),
+                    Ok(Message::Binary) => OwnedMessage::Binary(buf.split().freeze()),
+                    Ok(Message::Ping(payload)) => OwnedMessage::Ping(payload),
+                    Ok(Message::Pong(payload)) => OwnedMessage::Pong(payload),
+                    Ok(Message::Close(reason)) => OwnedMessage::Close(reason),
+                    Err(_) => break,
+                };
+                let closed = matches!(message, OwnedMessage::Close(_));
+
+                if let Some(messages) = &*lock(&messages) {
+                    // There may not be any subscribers, in which case the message is only handled.
+                    let _ = messages.send(message.clone());
+                }
+                handler(message, sender.clone()).await;
+
+                if closed {
+                    break;
+                }
+            }
+
+            lock(&messages).take();
+        });
+
+        Ok((handle, subscriber))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        CloseCode, CloseReason, Message, NoExt, OwnedMessage, PayloadType, Role, WebSocket,
+        WebSocketConfig,
+    };
+    use bytes::{Bytes, BytesMut};
+    use tokio::io::duplex;
+    use tokio::sync::broadcast::error::RecvError;
+
+    #[tokio::test]
+    async fn spawned_echo() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        let (handle, mut messages) = server
+            .spawn(|message, sender| async move {
+                if let OwnedMessage::Text(payload) = message {
+                    sender.send(payload, PayloadType::Text).await.unwrap();
+                }
+            })
+            .unwrap();
+
+        client.write_text("hello").await.unwrap();
+
+        let mut buf = BytesMut::new();
+        assert_eq!(client.read(&mut buf).await.unwrap(), Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            OwnedMessage::Text(Bytes::from_static(b"hello"))
+        );
+
+        handle.send("direct", PayloadType::Binary).await.unwrap();
+        buf.clear();
+        assert_eq!(client.read(&mut buf).await.unwrap(), Message::Binary);
+        assert_eq!(buf.as_ref(), b"direct");
+
+        let reason = CloseReason::new(CloseCode::GoingAway, None);
+        handle.close(reason.clone()).await.unwrap();
+        assert_eq!(
+            client.read(&mut buf).await.unwrap(),
+            Message::Close(Some(reason.clone()))
+        );
+
+        // The read loop stops once the peer has echoed the close frame.
+        assert_eq!(messages.recv().await, Err(RecvError::Closed));
+        assert_eq!(handle.subscribe().recv().await, Err(RecvError::Closed));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_peer_close() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        let (_handle, mut messages) = server.spawn(|_, _| async {}).unwrap();
+
+        let reason = CloseReason::new(CloseCode::Normal, Some("done".to_string()));
+        client.close(reason.clone()).await.unwrap();
+
+        assert_eq!(
+            messages.recv().await.unwrap(),
+            OwnedMessage::Close(Some(reason))
+        );
+        assert_eq!(messages.recv().await, Err(RecvError::Closed));
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A blocking WebSocket API.
+//!
+//! CLI tools and test scripts which are otherwise synchronous may use a [`WebSocket`] from this
+//! module rather than managing a runtime themselves. It owns a single-threaded tokio runtime and
+//! each operation blocks the calling thread until the underlying asynchronous operation has
+//! completed. These functions must not be called from within an asynchronous context.
+
+use crate::{CloseReason, Error, Message, PayloadType, WebSocketStream};
+use bytes::BytesMut;
+use ratchet_ext::Extension;
+use std::fmt::{Debug, Formatter};
+use tokio::runtime::Runtime;
+
+/// A WebSocket whose operations block the calling thread.
+pub struct WebSocket<S, E> {
+    runtime: Runtime,
+    websocket: crate::WebSocket<S, E>,
+}
+
+impl<S, E> Debug for WebSocket<S, E>
+where
+    S: Debug,
+    E: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("websocket", &self.websocket)
+            .finish()
+    }
+}
+
+/// Returns a new single-threaded runtime with all of its drivers enabled.
+fn new_runtime() -> Result<Runtime, Error> {
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+    #[cfg(feature = "net")]
+    builder.enable_io();
+    Ok(builder.enable_time().build()?)
+}
+
+/// Opens a client connection over TCP to the `ws` URI of `request` and performs the opening
+/// handshake, blocking the calling thread until it has completed. See [`crate::connect`].
+///
+/// # Panics
+/// Panics if called from within an asynchronous context.
+#[cfg(feature = "net")]
+pub fn connect<I>(
+    config: crate::WebSocketConfig,
+    request: I,
+) -> Result<WebSocket<tokio::net::TcpStream, crate::NoExt>, Error>
+where
+    I: crate::TryIntoRequest,
+{
+    let runtime = new_runtime()?;
+    let upgraded = runtime.block_on(crate::connect(config, request))?;
+    Ok(WebSocket::from_async(runtime, upgraded.websocket))
+}
+
+impl<S, E> WebSocket<S, E>
+where
+    S: WebSocketStream,
+    E: Extension,
+{
+    /// Wraps `websocket` so that its operations are driven by `runtime`. The IO of `websocket` must
+    /// have been created within `runtime` if it requires one of the runtime's drivers.
+    pub fn from_async(runtime: Runtime, websocket: crate::WebSocket<S, E>) -> WebSocket<S, E> {
+        WebSocket { runtime, websocket }
+    }
+
+    /// Wraps `websocket` using a new single-threaded runtime. This is suitable for IO which does not
+    /// require a runtime's drivers, such as in-memory streams, or IO which was created using
+    /// [`std`] types.
+    ///
+    /// # Errors
+    /// Errors if the runtime could not be created.
+    pub fn new(websocket: crate::WebSocket<S, E>) -> Result<WebSocket<S, E>, Error> {
+        Ok(WebSocket::from_async(new_runtime()?, websocket))
+    }
+
+    /// Attempt to read a message from the WebSocket. See [`crate::WebSocket::read`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
+        let WebSocket { runtime, websocket } = self;
+        runtime.block_on(websocket.read(read_buffer))
+    }
+
+    /// Sends a new message to the peer. See [`crate::WebSocket::write`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn write<A>(&mut self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        let WebSocket { runtime, websocket } = self;
+        runtime.block_on(websocket.write(buf, message_type))
+    }
+
+    /// Sends a new text message to the peer. See [`crate::WebSocket::write_text`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn write_text<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text)
+    }
+
+    /// Sends a new binary message to the peer. See [`crate::WebSocket::write_binary`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn write_binary<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data, PayloadType::Binary)
+    }
+
+    /// Flushes the WebSocket's output stream. See [`crate::WebSocket::flush`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let WebSocket { runtime, websocket } = self;
+        runtime.block_on(websocket.flush())
+    }
+
+    /// Closes the WebSocket with the reason provided. See [`crate::WebSocket::close`].
+    ///
+    /// # Panics
+    /// Panics if called from within an asynchronous context.
+    pub fn close(&mut self, reason: CloseReason) -> Result<(), Error> {
+        let WebSocket { runtime, websocket } = self;
+        runtime.block_on(websocket.close(reason))
+    }
+
+    /// Returns whether this WebSocket is closed.
+    pub fn is_closed(&self) -> bool {
+        self.websocket.is_closed()
+    }
+
+    /// Returns a reference to the runtime which drives this WebSocket, so that other asynchronous
+    /// operations may be performed on it.
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    /// Returns a mutable reference to the underlying asynchronous WebSocket.
+    pub fn get_mut(&mut self) -> &mut crate::WebSocket<S, E> {
+        &mut self.websocket
+    }
+
+    /// Returns the runtime and the underlying asynchronous WebSocket.
+    pub fn into_inner(self) -> (Runtime, crate::WebSocket<S, E>) {
+        (self.runtime, self.websocket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blocking::WebSocket;
+    use crate::{CloseCode, CloseReason, Message, NoExt, Role, WebSocketConfig};
+    use bytes::BytesMut;
+    use tokio::io::duplex;
+
+    #[test]
+    fn blocking_echo() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let server = crate::WebSocket::from_upgraded(
+            config,
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let client = crate::WebSocket::from_upgraded(
+            config,
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        let server = std::thread::spawn(move || {
+            let mut server = WebSocket::new(server).unwrap();
+            let mut buf = BytesMut::new();
+            loop {
+                match server.read(&mut buf).unwrap() {
+                    Message::Text => {
+                        server
+                            .write_text(std::str::from_utf8(&buf).unwrap())
+                            .unwrap();
+                        buf.clear();
+                    }
+                    Message::Close(reason) => break reason,
+                    _ => {}
+                }
+            }
+        });
+
+        let mut client = WebSocket::new(client).unwrap();
+        client.write_text("hello").unwrap();
+
+        let mut buf = BytesMut::new();
+        assert_eq!(client.read(&mut buf).unwrap(), Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+
+        let reason = CloseReason::new(CloseCode::Normal, None);
+        client.close(reason.clone()).unwrap();
+        assert_eq!(server.join().unwrap(), Some(reason));
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn blocking_connect() {
+        use tokio::net::TcpListener;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = runtime.spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let upgrader = crate::accept(stream, WebSocketConfig::default())
+                .await
+                .unwrap();
+            let mut websocket = upgrader.upgrade().await.unwrap().websocket;
+            websocket.write_text("hello").await.unwrap();
+        });
+
+        let mut client =
+            crate::blocking::connect(WebSocketConfig::default(), format!("ws://{addr}/")).unwrap();
+        let mut buf = BytesMut::new();
+        assert_eq!(client.read(&mut buf).unwrap(), Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+
+        runtime.block_on(server).unwrap();
+    }
+}
@@ -12,11 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::errors::Error;
+use crate::errors::{Error, HttpError};
 use crate::ext::NoExtProvider;
-use crate::handshake::{SubprotocolRegistry, UpgradedServer};
-use crate::{subscribe_with, TryIntoRequest, UpgradedClient, WebSocketConfig, WebSocketStream};
+use crate::handshake::{
+    accept_with_options, AcceptOptions, Accepted, OriginPolicy, Proxy, SubprotocolRegistry,
+    UpgradedServer,
+};
+use crate::handshake::{
+    add_cookies, basic_auth, store_cookies, subscribe_with_options, CookieStore,
+};
+use crate::{Request, TryIntoRequest, UpgradedClient, WebSocketConfig, WebSocketStream};
+use bytes::BytesMut;
+use http::header::{AUTHORIZATION, COOKIE, HOST, ORIGIN};
+use http::{HeaderMap, HeaderName, HeaderValue, Uri};
 use ratchet_ext::ExtensionProvider;
+#[cfg(feature = "net")]
+use std::net::SocketAddr;
+use std::sync::Arc;
+#[cfg(feature = "net")]
+use std::time::Duration;
+use url::Url;
 
 /// A builder to construct WebSocket clients.
 ///
@@ -28,6 +43,16 @@ pub struct WebSocketClientBuilder<E> {
     config: Option<WebSocketConfig>,
     extension: E,
     subprotocols: SubprotocolRegistry,
+    strict_extensions: bool,
+    proxy: Option<Proxy>,
+    max_redirects: usize,
+    headers: HeaderMap,
+    cookie_store: Option<Arc<dyn CookieStore>>,
+    target: RequestTarget,
+    #[cfg(feature = "net")]
+    resolver: Option<Arc<dyn crate::Resolver>>,
+    #[cfg(feature = "net")]
+    tcp: crate::connect::TcpOptions,
 }
 
 impl Default for WebSocketClientBuilder<NoExtProvider> {
@@ -36,6 +61,16 @@ impl Default for WebSocketClientBuilder<NoExtProvider> {
             config: None,
             extension: NoExtProvider,
             subprotocols: SubprotocolRegistry::default(),
+            strict_extensions: false,
+            proxy: None,
+            max_redirects: 0,
+            headers: HeaderMap::new(),
+            cookie_store: None,
+            target: RequestTarget::default(),
+            #[cfg(feature = "net")]
+            resolver: None,
+            #[cfg(feature = "net")]
+            tcp: Default::default(),
         }
     }
 }
@@ -44,7 +79,7 @@ impl<E> WebSocketClientBuilder<E> {
     /// Attempt to execute a client handshake
     pub async fn subscribe<S, I>(
         self,
-        stream: S,
+        mut stream: S,
         request: I,
     ) -> Result<UpgradedClient<S, E::Extension>, Error>
     where
@@ -56,17 +91,92 @@ impl<E> WebSocketClientBuilder<E> {
             config,
             extension,
             subprotocols,
+            strict_extensions,
+            proxy,
+            headers,
+            cookie_store,
+            target,
+            ..
         } = self;
-        subscribe_with(
+        let request = prepare_request(request, headers, cookie_store.as_deref(), target)?;
+        let uri = request.uri().clone();
+        if let Some(proxy) = &proxy {
+            proxy.tunnel(&mut stream, &uri).await?;
+        }
+        let upgraded = subscribe_with_options(
             config.unwrap_or_default(),
             stream,
             request,
             &extension,
             subprotocols,
+            strict_extensions,
+        )
+        .await?;
+        store_cookies(cookie_store.as_deref(), &uri, &upgraded.set_cookies);
+        Ok(upgraded)
+    }
+
+    /// Attempt to connect to the host of the URI in `request` over TCP and then execute a client
+    /// handshake.
+    #[cfg(feature = "net")]
+    pub async fn connect<I>(
+        self,
+        request: I,
+    ) -> Result<UpgradedClient<tokio::net::TcpStream, E::Extension>, Error>
+    where
+        I: TryIntoRequest,
+        E: ExtensionProvider,
+    {
+        let WebSocketClientBuilder {
+            config,
+            extension,
+            subprotocols,
+            strict_extensions,
+            proxy,
+            max_redirects,
+            headers,
+            cookie_store,
+            target,
+            resolver,
+            tcp,
+        } = self;
+        let request = prepare_request(request, headers, cookie_store.as_deref(), target)?;
+        crate::connect::connect_with_options(
+            config.unwrap_or_default(),
+            request,
+            extension,
+            subprotocols,
+            crate::connect::ConnectOptions {
+                strict_extensions,
+                proxy: proxy.as_ref(),
+                max_redirects,
+                resolver: resolver.as_deref(),
+                tcp,
+                cookie_store: cookie_store.as_deref(),
+            },
         )
         .await
     }
 
+    /// Attempt to connect to the Unix domain socket at `path` and then execute a client handshake.
+    ///
+    /// The URI of `request` is only used for the handshake, with its authority being sent in the
+    /// `Host` header, and so a synthetic URI such as `ws://localhost/path` may be used.
+    #[cfg(all(feature = "net", unix))]
+    pub async fn connect_unix<P, I>(
+        self,
+        path: P,
+        request: I,
+    ) -> Result<UpgradedClient<tokio::net::UnixStream, E::Extension>, Error>
+    where
+        P: AsRef<std::path::Path>,
+        I: TryIntoRequest,
+        E: ExtensionProvider,
+    {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        self.subscribe(stream, request).await
+    }
+
     /// Sets the configuration that will be used for the connection.
     pub fn config(mut self, config: WebSocketConfig) -> Self {
         self.config = Some(config);
@@ -81,15 +191,193 @@ impl<E> WebSocketClientBuilder<E> {
         let WebSocketClientBuilder {
             config,
             subprotocols,
+            strict_extensions,
+            proxy,
+            max_redirects,
+            headers,
+            cookie_store,
+            target,
+            #[cfg(feature = "net")]
+            resolver,
+            #[cfg(feature = "net")]
+            tcp,
             ..
         } = self;
         WebSocketClientBuilder {
             config,
             extension,
             subprotocols,
+            strict_extensions,
+            proxy,
+            max_redirects,
+            headers,
+            cookie_store,
+            target,
+            #[cfg(feature = "net")]
+            resolver,
+            #[cfg(feature = "net")]
+            tcp,
         }
     }
 
+    /// Sets whether the handshake should fail if the server accepts an extension that was not
+    /// offered or which the extension provider could not negotiate. By default, any such
+    /// extensions are ignored.
+    pub fn strict_extensions(mut self, strict_extensions: bool) -> Self {
+        self.strict_extensions = strict_extensions;
+        self
+    }
+
+    /// Adds a header to the upgrade request. Headers which are added to the builder replace any
+    /// headers of the same name in the request.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Sets the path of the upgrade request, replacing the path of the request's URI. Any
+    /// characters which are not permitted in a path are percent-encoded.
+    pub fn path<P>(mut self, path: P) -> Self
+    where
+        P: Into<String>,
+    {
+        self.target.path = Some(path.into());
+        self
+    }
+
+    /// Appends a query parameter to the URI of the upgrade request. The name and value are
+    /// percent-encoded, so a value such as an authentication token may be passed as is.
+    pub fn query<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.target.query.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the `Host` header of the upgrade request, overriding the one which is derived from the
+    /// request's URI.
+    pub fn host<H>(mut self, host: H) -> Result<Self, Error>
+    where
+        H: AsRef<str>,
+    {
+        self.headers
+            .insert(HOST, HeaderValue::from_str(host.as_ref())?);
+        Ok(self)
+    }
+
+    /// Sets the `Authorization` header of the upgrade request to use the bearer authentication
+    /// scheme with `token`.
+    pub fn bearer_auth<T>(mut self, token: T) -> Result<Self, Error>
+    where
+        T: AsRef<str>,
+    {
+        let value = HeaderValue::from_str(&format!("Bearer {}", token.as_ref()))?;
+        self.headers.insert(AUTHORIZATION, value);
+        Ok(self)
+    }
+
+    /// Sets the `Authorization` header of the upgrade request to use the basic authentication
+    /// scheme with the provided credentials.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Result<Self, Error> {
+        self.headers
+            .insert(AUTHORIZATION, basic_auth(username, password)?);
+        Ok(self)
+    }
+
+    /// Sets the `Origin` header of the upgrade request, such as `https://example.com`.
+    pub fn origin<O>(mut self, origin: O) -> Result<Self, Error>
+    where
+        O: AsRef<str>,
+    {
+        self.headers
+            .insert(ORIGIN, HeaderValue::from_str(origin.as_ref())?);
+        Ok(self)
+    }
+
+    /// Adds a cookie to the `Cookie` header of the upgrade request.
+    pub fn cookie(mut self, name: &str, value: &str) -> Result<Self, Error> {
+        let cookie = HeaderValue::from_str(&format!("{name}={value}"))?;
+        add_cookies(&mut self.headers, &cookie)?;
+        Ok(self)
+    }
+
+    /// Sets a cookie store which provides the cookies for the upgrade request and which stores
+    /// the cookies that the server sets in its response.
+    pub fn cookie_store(mut self, cookie_store: Arc<dyn CookieStore>) -> Self {
+        self.cookie_store = Some(cookie_store);
+        self
+    }
+
+    /// Sets an HTTP proxy which the connection is tunnelled through, using the `CONNECT` method,
+    /// before the handshake is executed. When subscribing, the stream must be connected to the
+    /// proxy.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the maximum number of redirects that are followed when connecting. A redirect is only
+    /// followed by `connect` as following it requires opening a new connection. By default, no
+    /// redirects are followed and the handshake fails with `HttpError::Redirected`.
+    #[cfg(feature = "net")]
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sets the resolver which is used to resolve the host of the request's URI, and of the proxy
+    /// if one is set, when connecting. By default, hosts are resolved using `SystemResolver`.
+    #[cfg(feature = "net")]
+    pub fn resolver(mut self, resolver: Arc<dyn crate::Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Sets the time to wait for a connection attempt to complete before an attempt is started to
+    /// the next address of the host when connecting. Attempts alternate between IPv6 and IPv4
+    /// addresses and the first connection to be established is used, as described by RFC 8305.
+    /// Defaults to 250 milliseconds.
+    #[cfg(feature = "net")]
+    pub fn connection_attempt_delay(mut self, delay: Duration) -> Self {
+        self.tcp.connection_attempt_delay = delay;
+        self
+    }
+
+    /// Sets the time to wait for a TCP connection to the host to be established when connecting,
+    /// including the time taken to resolve it. If the connection is not established in time then
+    /// connecting fails with `TimeoutCause::Connect`. By default, no timeout is applied.
+    #[cfg(feature = "net")]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.tcp.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether `TCP_NODELAY` is set on the connection when connecting, disabling Nagle's
+    /// algorithm. Defaults to `false`.
+    #[cfg(feature = "net")]
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp.nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keepalive on the connection when connecting, with probes being sent once the
+    /// connection has been idle for `time`. By default, the system's setting is used.
+    #[cfg(feature = "net")]
+    pub fn tcp_keepalive(mut self, time: Duration) -> Self {
+        self.tcp.keepalive = Some(time);
+        self
+    }
+
+    /// Sets the local address that the connection is bound to when connecting. Only addresses of
+    /// the host in the same family as `addr` can then be connected to.
+    #[cfg(feature = "net")]
+    pub fn local_addr(mut self, addr: SocketAddr) -> Self {
+        self.tcp.local_addr = Some(addr);
+        self
+    }
+
     /// Sets the subprotocols that will be used for the connection.
     pub fn subprotocols<I>(mut self, subprotocols: I) -> Result<Self, Error>
     where
@@ -101,6 +389,61 @@ impl<E> WebSocketClientBuilder<E> {
     }
 }
 
+/// Converts `request` into a `Request` and adds the headers and cookies which were configured on
+/// the builder to it.
+fn prepare_request<I>(
+    request: I,
+    mut headers: HeaderMap,
+    cookie_store: Option<&dyn CookieStore>,
+    target: RequestTarget,
+) -> Result<Request, Error>
+where
+    I: TryIntoRequest,
+{
+    let mut request = request.try_into_request()?;
+    target.apply(request.uri_mut())?;
+    let cookies = headers.remove(COOKIE);
+    request.headers_mut().extend(headers);
+
+    if let Some(cookies) = cookies {
+        add_cookies(request.headers_mut(), &cookies)?;
+    }
+    if let Some(cookies) = cookie_store.and_then(|store| store.cookies(request.uri())) {
+        add_cookies(request.headers_mut(), &cookies)?;
+    }
+
+    Ok(request)
+}
+
+/// A path and query parameters which replace the path and extend the query of an upgrade
+/// request's URI.
+#[derive(Debug, Default)]
+struct RequestTarget {
+    path: Option<String>,
+    query: Vec<(String, String)>,
+}
+
+impl RequestTarget {
+    fn apply(self, uri: &mut Uri) -> Result<(), Error> {
+        let RequestTarget { path, query } = self;
+        if path.is_none() && query.is_empty() {
+            return Ok(());
+        }
+
+        let mut url = Url::parse(&uri.to_string())
+            .map_err(|e| HttpError::MalformattedUri(Some(e.to_string())))?;
+        if let Some(path) = path {
+            url.set_path(&path);
+        }
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        *uri = url.as_str().parse()?;
+        Ok(())
+    }
+}
+
 /// A builder to construct WebSocket servers.
 ///
 /// If a lot of connections will be negotiated it is more efficient to directly use `accept_with`
@@ -111,6 +454,8 @@ pub struct WebSocketServerBuilder<E> {
     config: Option<WebSocketConfig>,
     subprotocols: SubprotocolRegistry,
     extension: E,
+    origin_policy: OriginPolicy,
+    proxy_protocol: bool,
 }
 
 impl Default for WebSocketServerBuilder<NoExtProvider> {
@@ -119,6 +464,8 @@ impl Default for WebSocketServerBuilder<NoExtProvider> {
             config: None,
             extension: NoExtProvider,
             subprotocols: SubprotocolRegistry::default(),
+            origin_policy: OriginPolicy::default(),
+            proxy_protocol: false,
         }
     }
 }
@@ -134,10 +481,59 @@ impl<E> WebSocketServerBuilder<E> {
             config,
             subprotocols,
             extension,
+            origin_policy,
+            proxy_protocol,
+        } = self;
+        let options = AcceptOptions {
+            origin_policy: &origin_policy,
+            allow_http: false,
+            proxy_protocol,
+            buf: BytesMut::new(),
+        };
+        let accepted = accept_with_options(
+            stream,
+            config.unwrap_or_default(),
+            extension,
+            subprotocols,
+            options,
+        )
+        .await?;
+        match accepted {
+            Accepted::Upgrade(upgrader) => upgrader.upgrade().await,
+            Accepted::Http(_) => unreachable!("Plain HTTP requests are not permitted"),
+        }
+    }
+
+    /// Accept `stream` and parse the request that the client sends. If the client requests an
+    /// upgrade then a `WebSocketUpgrader` is returned which may be used to complete the handshake.
+    /// Otherwise, the request is returned so that a plain HTTP response may be sent, such as to a
+    /// load balancer's health check.
+    pub async fn accept_request<S>(self, stream: S) -> Result<Accepted<S, E::Extension>, Error>
+    where
+        S: WebSocketStream,
+        E: ExtensionProvider,
+    {
+        let WebSocketServerBuilder {
+            config,
+            subprotocols,
+            extension,
+            origin_policy,
+            proxy_protocol,
         } = self;
-        let upgrader =
-            crate::accept_with(stream, config.unwrap_or_default(), extension, subprotocols).await?;
-        upgrader.upgrade().await
+        let options = AcceptOptions {
+            origin_policy: &origin_policy,
+            allow_http: true,
+            proxy_protocol,
+            buf: BytesMut::new(),
+        };
+        accept_with_options(
+            stream,
+            config.unwrap_or_default(),
+            extension,
+            subprotocols,
+            options,
+        )
+        .await
     }
 
     /// Sets the configuration that will be used for the connection.
@@ -154,15 +550,37 @@ impl<E> WebSocketServerBuilder<E> {
         let WebSocketServerBuilder {
             config,
             subprotocols,
+            origin_policy,
+            proxy_protocol,
             ..
         } = self;
         WebSocketServerBuilder {
             config,
             extension,
             subprotocols,
+            origin_policy,
+            proxy_protocol,
         }
     }
 
+    /// Sets the policy which decides whether to accept an upgrade request from its `Origin`
+    /// header. A request from an origin that is not permitted is rejected with `403 Forbidden`.
+    /// By default, requests from any origin are accepted.
+    pub fn origin_policy(mut self, origin_policy: OriginPolicy) -> Self {
+        self.origin_policy = origin_policy;
+        self
+    }
+
+    /// Sets whether a PROXY protocol header, of either version 1 or 2, is read from the stream
+    /// before the request. The header is inserted into the extensions of the request as a
+    /// `ProxyHeader` and a connection which does not start with a valid header is rejected.
+    ///
+    /// This should only be enabled when all connections are accepted from a trusted proxy.
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.proxy_protocol = proxy_protocol;
+        self
+    }
+
     /// Sets the subprotocols that will be used for the connection.
     pub fn subprotocols<I>(mut self, subprotocols: I) -> Result<Self, Error>
     where
@@ -0,0 +1,494 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! WebSocket connections over completion-based IO.
+//!
+//! Completion-based runtimes, such as tokio-uring and monoio, require ownership of a buffer for
+//! the duration of an IO operation rather than borrowing it. [`CompletionStream`] abstracts over
+//! such streams and [`CompletionWebSocket`] drives a WebSocket session over one, handing its
+//! read buffer and encoded frames to the stream by value.
+//!
+//! The opening handshake must have been performed before a [`CompletionWebSocket`] is created.
+
+use crate::framed::{
+    close_payload, read_props, CodecFlags, FramedIo, FramedIoParts, FramedRead, FramedWrite, Item,
+    ReadProps,
+};
+use crate::protocol::{ControlCode, HeaderFlags, MessageType, OpCode};
+use crate::ws::{extension_encode, read_error_close_code, CONTROL_MAX_SIZE};
+use crate::{
+    CloseCause, CloseCode, CloseReason, CloseState, Error, ErrorKind, Message, PayloadType, Role,
+    WebSocketConfig,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::future::LocalBoxFuture;
+use ratchet_ext::Extension;
+use std::io;
+
+/// A stream which performs IO using buffers that it takes ownership of.
+///
+/// Each operation returns the buffer that it was provided with once it has completed, so that its
+/// allocation may be reused.
+pub trait CompletionStream {
+    /// Reads bytes from the stream into the spare capacity of `buf`, appending them to its
+    /// contents. Returns the number of bytes that were read, where zero indicates that the stream
+    /// has reached EOF.
+    fn read(&mut self, buf: BytesMut) -> LocalBoxFuture<'_, (io::Result<usize>, BytesMut)>;
+
+    /// Writes bytes from `buf` to the stream. Returns the number of bytes that were written.
+    fn write(&mut self, buf: Bytes) -> LocalBoxFuture<'_, (io::Result<usize>, Bytes)>;
+
+    /// Shuts down the write half of the stream.
+    fn shutdown(&mut self) -> LocalBoxFuture<'_, io::Result<()>>;
+}
+
+/// The minimum spare capacity that is made available to a read operation.
+const MIN_READ_CAPACITY: usize = 4096;
+
+/// A WebSocket connection over a [`CompletionStream`].
+///
+/// This provides the same read and write operations as [`crate::WebSocket`]. Ping frames are
+/// answered automatically during read operations; keepalive pings are not supported.
+#[derive(Debug)]
+pub struct CompletionWebSocket<S, E> {
+    stream: S,
+    reader: FramedRead,
+    writer: FramedWrite,
+    flags: CodecFlags,
+    max_message_size: usize,
+    extension: Option<E>,
+    close_state: CloseState,
+}
+
+impl<S, E> CompletionWebSocket<S, E>
+where
+    S: CompletionStream,
+    E: Extension,
+{
+    /// Initialise a new `CompletionWebSocket` from a stream that has already executed a
+    /// handshake.
+    ///
+    /// # Arguments
+    /// `config` - The configuration to initialise the WebSocket with. Keepalive and autoflush
+    /// options are ignored.
+    /// `stream` - The stream that the handshake was executed on.
+    /// `extension` - A negotiated extension that will be used for the session.
+    /// `read_buffer` - The read buffer which will be used for the session. This **may** contain any
+    /// unread data received after performing the handshake that was not required.
+    /// `role` - The role that this WebSocket will take.
+    pub fn from_upgraded(
+        config: WebSocketConfig,
+        stream: S,
+        extension: Option<E>,
+        read_buffer: BytesMut,
+        role: Role,
+    ) -> CompletionWebSocket<S, E> {
+        let WebSocketConfig {
+            max_message_size,
+            max_frame_size,
+            auto_fragment_above,
+            ..
+        } = config;
+        let mut framed = FramedIo::new(
+            stream,
+            read_buffer,
+            role,
+            max_message_size,
+            extension.bits().into(),
+        );
+        framed.set_reserved_opcodes(extension.reserved_opcodes().into());
+        framed.set_max_frame_size(max_frame_size);
+        framed.set_auto_fragment_above(auto_fragment_above);
+        let FramedIoParts {
+            io: stream,
+            reader,
+            writer,
+            flags,
+            max_message_size,
+        } = framed.into_parts();
+
+        CompletionWebSocket {
+            stream,
+            reader,
+            writer,
+            flags,
+            max_message_size,
+            extension,
+            close_state: CloseState::NotClosed,
+        }
+    }
+
+    /// Returns the role of this WebSocket.
+    pub fn role(&self) -> Role {
+        if self.is_server() {
+            Role::Server
+        } else {
+            Role::Client
+        }
+    }
+
+    fn is_server(&self) -> bool {
+        self.flags.contains(CodecFlags::ROLE)
+    }
+
+    /// Attempt to read a message from the WebSocket. Text and binary payloads are read into
+    /// `read_buffer`.
+    ///
+    /// This behaves in the same manner as [`crate::WebSocket::read`]: pings are answered and
+    /// returned as `Message::Ping`, a close frame from the peer is echoed and returned as
+    /// `Message::Close` and any error results in an attempt to close the connection.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped while an IO operation is
+    /// outstanding then the buffer that the stream owns is lost and the connection state is
+    /// undefined.
+    pub async fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
+        loop {
+            if self.is_closed() {
+                return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+            }
+
+            let props = read_props(&self.flags, self.max_message_size, false);
+            match self.decode(read_buffer, props) {
+                Ok(Some(item)) => match item {
+                    Item::Text => return Ok(Message::Text),
+                    Item::Binary => return Ok(Message::Binary),
+                    Item::Fragment(_) => {}
+                    Item::Ping(payload) => {
+                        let ret = payload.clone().freeze();
+                        self.buffer_control(ControlCode::Pong, payload)?;
+                        self.flush_buffer().await?;
+                        return Ok(Message::Ping(ret));
+                    }
+                    Item::Pong(payload) => return Ok(Message::Pong(payload.freeze())),
+                    Item::Close(reason) => {
+                        let close_state = self.close_state;
+                        let code = reason
+                            .as_ref()
+                            .map(|reason| reason.code)
+                            .unwrap_or(CloseCode::Normal);
+                        self.terminate(close_state, code).await;
+
+                        return match close_state {
+                            CloseState::Closing => {
+                                Err(Error::with_cause(ErrorKind::Close, CloseCause::Stopped))
+                            }
+                            _ => Ok(Message::Close(reason)),
+                        };
+                    }
+                },
+                Ok(None) => {
+                    if let Err(e) = self.fill_buffer().await {
+                        self.close_state = CloseState::Closed;
+                        return Err(e);
+                    }
+                }
+                Err(e) => {
+                    let close_state = self.close_state;
+                    self.terminate(close_state, read_error_close_code(&e)).await;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn decode(
+        &mut self,
+        read_buffer: &mut BytesMut,
+        props: ReadProps,
+    ) -> Result<Option<Item>, Error> {
+        let CompletionWebSocket {
+            reader,
+            flags,
+            extension,
+            ..
+        } = self;
+        reader.decode_item(flags, read_buffer, extension, props)
+    }
+
+    /// Hands the read buffer to the stream to be filled.
+    async fn fill_buffer(&mut self) -> Result<(), Error> {
+        let mut buf = std::mem::take(self.reader.read_buffer_mut());
+        if buf.capacity() - buf.len() < MIN_READ_CAPACITY {
+            buf.reserve(MIN_READ_CAPACITY);
+        }
+
+        let (result, buf) = self.stream.read(buf).await;
+        *self.reader.read_buffer_mut() = buf;
+
+        if result? == 0 {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Closes the connection after a close frame has been received or a read has failed. A close
+    /// frame is sent if one has not already been and then the stream is shut down. Any errors are
+    /// ignored as the peer may have already closed the connection.
+    async fn terminate(&mut self, close_state: CloseState, code: CloseCode) {
+        self.close_state = CloseState::Closed;
+        if close_state == CloseState::NotClosed {
+            let _ = self.buffer_control(ControlCode::Close, u16::from(code).to_be_bytes());
+        }
+        let _ = self.flush_buffer().await;
+        let _ = self.stream.shutdown().await;
+    }
+
+    /// Sends a new text WebSocket message with a payload of `data`.
+    pub async fn write_text<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text).await
+    }
+
+    /// Sends a new binary WebSocket message with a payload of `data`.
+    pub async fn write_binary<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data.as_ref(), PayloadType::Binary).await
+    }
+
+    /// Sends a new ping WebSocket message with a payload of `data`.
+    pub async fn write_ping<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data.as_ref(), PayloadType::Ping).await
+    }
+
+    /// Sends a new pong WebSocket message with a payload of `data`.
+    pub async fn write_pong<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data.as_ref(), PayloadType::Pong).await
+    }
+
+    /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined.
+    pub async fn write<A>(&mut self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        let buf = buf.as_ref();
+        match message_type {
+            PayloadType::Text => self.buffer_message(MessageType::Text, buf)?,
+            PayloadType::Binary => self.buffer_message(MessageType::Binary, buf)?,
+            PayloadType::Ping | PayloadType::Pong => {
+                if buf.len() > CONTROL_MAX_SIZE {
+                    return Err(Error::with_cause(
+                        ErrorKind::Protocol,
+                        crate::ProtocolError::FrameOverflow,
+                    ));
+                }
+                let code = match message_type {
+                    PayloadType::Ping => ControlCode::Ping,
+                    _ => ControlCode::Pong,
+                };
+                self.buffer_control(code, buf)?;
+            }
+        }
+
+        self.flush_buffer().await
+    }
+
+    fn buffer_message(&mut self, message_type: MessageType, buf: &[u8]) -> Result<(), Error> {
+        let is_server = self.is_server();
+        let CompletionWebSocket {
+            writer, extension, ..
+        } = self;
+        writer.buffer_message(is_server, message_type, buf, |payload, header| {
+            extension_encode(extension, payload, header)
+        })
+    }
+
+    fn buffer_control<A>(&mut self, code: ControlCode, payload: A) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        let is_server = self.is_server();
+        self.writer.buffer_frame(
+            is_server,
+            OpCode::ControlCode(code),
+            HeaderFlags::FIN,
+            payload,
+            |_, _| Ok(()),
+        )
+    }
+
+    /// Writes all of the buffered frames to the stream, handing each chunk to it by value.
+    async fn flush_buffer(&mut self) -> Result<(), Error> {
+        while let Some(mut chunk) = self.writer.pop_chunk() {
+            while !chunk.is_empty() {
+                let (result, returned) = self.stream.write(chunk).await;
+                chunk = returned;
+                match result? {
+                    0 => return Err(io::Error::from(io::ErrorKind::WriteZero).into()),
+                    n => chunk.advance(n),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Close this WebSocket with the reason provided. The close handshake is completed once the
+    /// peer's close frame has been received by [`CompletionWebSocket::read`].
+    pub async fn close(&mut self, reason: CloseReason) -> Result<(), Error> {
+        if !self.is_active() {
+            return Ok(());
+        }
+
+        let payload = close_payload(reason)?;
+        self.close_state = CloseState::Closing;
+        self.buffer_control(ControlCode::Close, payload)?;
+        self.flush_buffer().await
+    }
+
+    /// Writes any frames that remain buffered to the stream.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if self.is_closed() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+        self.flush_buffer().await
+    }
+
+    /// Returns whether this WebSocket is closed.
+    pub fn is_closed(&self) -> bool {
+        self.close_state == CloseState::Closed
+    }
+
+    /// Returns whether this WebSocket is closing or closed.
+    pub fn is_active(&self) -> bool {
+        matches!(self.close_state, CloseState::NotClosed)
+    }
+
+    /// Consumes this WebSocket, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompletionStream, CompletionWebSocket};
+    use crate::{
+        CloseCode, CloseReason, Message, NoExt, PayloadType, Role, WebSocket, WebSocketConfig,
+    };
+    use bytes::{Bytes, BytesMut};
+    use futures_util::future::{join, LocalBoxFuture};
+    use futures_util::FutureExt;
+    use std::io;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// A completion stream which takes ownership of each buffer, as tokio-uring does.
+    struct OwnedIo(DuplexStream);
+
+    impl CompletionStream for OwnedIo {
+        fn read(&mut self, mut buf: BytesMut) -> LocalBoxFuture<'_, (io::Result<usize>, BytesMut)> {
+            async move {
+                let result = self.0.read_buf(&mut buf).await;
+                (result, buf)
+            }
+            .boxed_local()
+        }
+
+        fn write(&mut self, buf: Bytes) -> LocalBoxFuture<'_, (io::Result<usize>, Bytes)> {
+            async move {
+                // Write at most a few bytes at a time to exercise partial writes.
+                let len = buf.len().min(7);
+                let result = self.0.write(&buf[..len]).await;
+                (result, buf)
+            }
+            .boxed_local()
+        }
+
+        fn shutdown(&mut self) -> LocalBoxFuture<'_, io::Result<()>> {
+            self.0.shutdown().boxed_local()
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_and_close() {
+        let (server, client) = duplex(512);
+        let mut server = CompletionWebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            OwnedIo(server),
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        let server_task = async move {
+            let mut buf = BytesMut::new();
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Ping(Bytes::from_static(b"ping")));
+
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            assert_eq!(buf.split().as_ref(), b"hello");
+
+            server
+                .write(vec![1; 2048], PayloadType::Binary)
+                .await
+                .expect("Write failure");
+
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(
+                message,
+                Message::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+            );
+            assert!(server.is_closed());
+        };
+        let client_task = async move {
+            client.write_ping("ping").await.expect("Write failure");
+            client.write_text("hello").await.expect("Write failure");
+
+            let mut buf = BytesMut::new();
+            let message = client.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Pong(Bytes::from_static(b"ping")));
+
+            let message = client.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Binary);
+            assert_eq!(buf.as_ref(), &[1; 2048][..]);
+
+            client
+                .close(CloseReason::new(CloseCode::Normal, None))
+                .await
+                .expect("Close failure");
+            let error = client.read(&mut buf).await.expect_err("Expected a close");
+            assert!(error.is_close());
+        };
+
+        join(server_task, client_task).await;
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#[cfg(test)]
+mod tests;
+
+mod resolver;
+
+pub use resolver::{Resolver, Resolving, SystemResolver};
+
+use crate::errors::{Error, ErrorKind, HttpError, TimeoutCause};
+use crate::ext::NoExtProvider;
+use crate::handshake::{
+    add_cookies, store_cookies, subscribe, subscribe_with_options, CookieStore, Proxy,
+    SubprotocolRegistry,
+};
+use crate::{NoExt, Request, TryIntoRequest, UpgradedClient, WebSocketConfig};
+use futures::future::{select, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
+use http::header::{AUTHORIZATION, COOKIE, HOST};
+use http::{HeaderMap, Uri, Version};
+use log::trace;
+use ratchet_ext::ExtensionProvider;
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::net::{TcpSocket, TcpStream};
+use url::Url;
+
+const WS_PORT: u16 = 80;
+/// The recommended connection attempt delay. RFC 8305 § 8.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Resolve the host of the URI in `request`, connect to it over TCP and then execute a WebSocket
+/// client handshake.
+///
+/// Only the `ws` scheme is supported and a request with any other scheme fails with
+/// [`HttpError::UnsupportedScheme`].
+pub async fn connect<I>(
+    config: WebSocketConfig,
+    request: I,
+) -> Result<UpgradedClient<TcpStream, NoExt>, Error>
+where
+    I: TryIntoRequest,
+{
+    connect_with(
+        config,
+        request,
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+}
+
+/// Resolve the host of the URI in `request`, connect to it over TCP and then execute a WebSocket
+/// client handshake using the provided extension and subprotocols.
+///
+/// Only the `ws` scheme is supported and a request with any other scheme fails with
+/// [`HttpError::UnsupportedScheme`].
+pub async fn connect_with<I, E>(
+    config: WebSocketConfig,
+    request: I,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+) -> Result<UpgradedClient<TcpStream, E::Extension>, Error>
+where
+    I: TryIntoRequest,
+    E: ExtensionProvider,
+{
+    connect_with_options(
+        config,
+        request,
+        extension,
+        subprotocols,
+        ConnectOptions::default(),
+    )
+    .await
+}
+
+/// Connect to the Unix domain socket at `path` and then execute a WebSocket client handshake.
+///
+/// The URI of `request` is only used for the handshake, with its authority being sent in the
+/// `Host` header, and so a synthetic URI such as `ws://localhost/path` may be used.
+#[cfg(unix)]
+pub async fn connect_unix<P, I>(
+    config: WebSocketConfig,
+    path: P,
+    request: I,
+) -> Result<UpgradedClient<UnixStream, NoExt>, Error>
+where
+    P: AsRef<Path>,
+    I: TryIntoRequest,
+{
+    let stream = UnixStream::connect(path).await?;
+    subscribe(config, stream, request).await
+}
+
+/// Options for opening a client connection which are only configurable through a
+/// `WebSocketClientBuilder`.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectOptions<'p> {
+    pub strict_extensions: bool,
+    pub proxy: Option<&'p Proxy>,
+    pub max_redirects: usize,
+    pub resolver: Option<&'p dyn Resolver>,
+    pub tcp: TcpOptions,
+    pub cookie_store: Option<&'p dyn CookieStore>,
+}
+
+/// Options for opening TCP connections.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TcpOptions {
+    /// The time to wait for a connection attempt to complete before starting an attempt to the
+    /// next address.
+    pub connection_attempt_delay: Duration,
+    /// The time to wait for a connection to be established to any of the addresses.
+    pub connect_timeout: Option<Duration>,
+    /// Whether to set `TCP_NODELAY` on the connection.
+    pub nodelay: bool,
+    /// If set, TCP keepalive probes are sent after the connection has been idle for this long.
+    pub keepalive: Option<Duration>,
+    /// The local address to bind the connection to.
+    pub local_addr: Option<SocketAddr>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions {
+            connection_attempt_delay: CONNECTION_ATTEMPT_DELAY,
+            connect_timeout: None,
+            nodelay: false,
+            keepalive: None,
+            local_addr: None,
+        }
+    }
+}
+
+pub(crate) async fn connect_with_options<I, E>(
+    config: WebSocketConfig,
+    request: I,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+    options: ConnectOptions<'_>,
+) -> Result<UpgradedClient<TcpStream, E::Extension>, Error>
+where
+    I: TryIntoRequest,
+    E: ExtensionProvider,
+{
+    let ConnectOptions {
+        strict_extensions,
+        proxy,
+        max_redirects,
+        resolver,
+        tcp,
+        cookie_store,
+    } = options;
+    let resolver = resolver.unwrap_or(&SystemResolver);
+
+    let mut request = request.try_into_request()?;
+    let mut redirects = 0;
+
+    loop {
+        let stream = match proxy {
+            Some(proxy) => {
+                // Validate the URI before connecting to the proxy.
+                host_and_port(request.uri())?;
+                let mut stream = connect_to(resolver, &tcp, proxy.host_and_port()).await?;
+                proxy.tunnel(&mut stream, request.uri()).await?;
+                stream
+            }
+            None => connect_to(resolver, &tcp, host_and_port(request.uri())?).await?,
+        };
+
+        let redirect = RedirectRequest::new(&request);
+        let uri = request.uri().clone();
+        let result = subscribe_with_options(
+            config,
+            stream,
+            request,
+            &extension,
+            subprotocols.clone(),
+            strict_extensions,
+        )
+        .await;
+
+        match result {
+            Err(e) if redirects < max_redirects => match e.downcast_ref::<HttpError>() {
+                Some(HttpError::Redirected(location)) => {
+                    trace!("Following redirect to: {location}");
+                    request = redirect.to(location)?;
+                    if request.uri().authority() != uri.authority() {
+                        // The original cookies were removed and so the ones that are stored for
+                        // the new host are sent instead.
+                        if let Some(cookies) =
+                            cookie_store.and_then(|store| store.cookies(request.uri()))
+                        {
+                            add_cookies(request.headers_mut(), &cookies)?;
+                        }
+                    }
+                    redirects += 1;
+                }
+                _ => return Err(e),
+            },
+            Ok(upgraded) => {
+                store_cookies(cookie_store, &uri, &upgraded.set_cookies);
+                return Ok(upgraded);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Resolves `host` using `resolver` and connects to the first of its addresses which accepts the
+/// connection.
+async fn connect_to(
+    resolver: &dyn Resolver,
+    options: &TcpOptions,
+    (host, port): (&str, u16),
+) -> Result<TcpStream, Error> {
+    let connect = async {
+        let addrs = resolver.resolve(host, port).await?;
+        race_connections(addrs, options).await
+    };
+    let stream = match options.connect_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, connect).await {
+            Ok(result) => result?,
+            Err(_) => return Err(TimeoutCause::Connect.into()),
+        },
+        None => connect.await?,
+    };
+
+    stream.set_nodelay(options.nodelay)?;
+    if let Some(time) = options.keepalive {
+        SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+    }
+    Ok(stream)
+}
+
+/// Opens a connection to `addr`, binding it to the local address in `options` if one is set.
+async fn connect_addr(addr: SocketAddr, options: &TcpOptions) -> io::Result<TcpStream> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    if let Some(local_addr) = options.local_addr {
+        socket.bind(local_addr)?;
+    }
+    socket.connect(addr).await
+}
+
+/// Connects to one of `addrs`, racing connection attempts as described by RFC 8305 (Happy
+/// Eyeballs). An attempt is started to the next address if the previous attempt fails or if it
+/// has not completed within the connection attempt delay in `options`, and the first connection
+/// to be established is returned.
+async fn race_connections(addrs: Vec<SocketAddr>, options: &TcpOptions) -> io::Result<TcpStream> {
+    let mut pending = interleave_families(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+
+    if let Some(addr) = pending.next() {
+        attempts.push(connect_addr(addr, options));
+    }
+
+    while !attempts.is_empty() {
+        let delay = Box::pin(tokio::time::sleep(options.connection_attempt_delay));
+        let completed = match select(attempts.next(), delay).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => None,
+        };
+
+        match completed {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => {
+                trace!("Connection attempt failed: {e}");
+                last_error = Some(e);
+            }
+            None => {}
+        }
+
+        if let Some(addr) = pending.next() {
+            attempts.push(connect_addr(addr, options));
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "No addresses were resolved")
+    }))
+}
+
+/// Reorders `addrs` so that they alternate between address families, starting with the family of
+/// the first address. RFC 8305 § 4.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let preferred = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let len = addrs.len();
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == preferred);
+
+    let mut interleaved = Vec::with_capacity(len);
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return interleaved,
+            (a, b) => {
+                interleaved.extend(a);
+                interleaved.extend(b);
+            }
+        }
+    }
+}
+
+/// The parts of a request which are reused when following a redirect.
+struct RedirectRequest {
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+}
+
+impl RedirectRequest {
+    fn new(request: &Request) -> RedirectRequest {
+        RedirectRequest {
+            uri: request.uri().clone(),
+            version: request.version(),
+            headers: request.headers().clone(),
+        }
+    }
+
+    /// Builds a request to `location`, which may be relative to the URI of the original request.
+    fn to(self, location: &str) -> Result<Request, Error> {
+        let RedirectRequest {
+            uri,
+            version,
+            mut headers,
+        } = self;
+
+        let malformatted = || {
+            Error::with_cause(
+                ErrorKind::Http,
+                HttpError::MalformattedUri(Some(location.to_string())),
+            )
+        };
+
+        let base = Url::parse(&uri.to_string()).map_err(|_| malformatted())?;
+        let mut target = base.join(location).map_err(|_| malformatted())?;
+        // A server may redirect to the HTTP equivalent of a WebSocket URI.
+        let scheme = match target.scheme() {
+            "http" => Some("ws"),
+            "https" => Some("wss"),
+            _ => None,
+        };
+        if let Some(scheme) = scheme {
+            target.set_scheme(scheme).map_err(|_| malformatted())?;
+        }
+
+        let target: Uri = target.as_str().parse()?;
+        if target.authority() != uri.authority() {
+            // Credentials are not sent to a different host to the one that they were provided
+            // for.
+            headers.remove(HOST);
+            headers.remove(AUTHORIZATION);
+            headers.remove(COOKIE);
+        }
+
+        let mut request = Request::get(target).version(version).body(())?;
+        *request.headers_mut() = headers;
+        Ok(request)
+    }
+}
+
+fn host_and_port(uri: &Uri) -> Result<(&str, u16), Error> {
+    match uri.scheme_str() {
+        Some(scheme) if scheme.eq_ignore_ascii_case("ws") => {}
+        scheme => {
+            return Err(Error::with_cause(
+                ErrorKind::Http,
+                HttpError::UnsupportedScheme(scheme.map(ToString::to_string)),
+            ))
+        }
+    }
+
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::with_cause(ErrorKind::Http, HttpError::MissingAuthority))?;
+    // An IPv6 address is enclosed in brackets in a URI but not when it is resolved.
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+
+    Ok((host, uri.port_u16().unwrap_or(WS_PORT)))
+}
@@ -0,0 +1,44 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+/// A future which resolves to the socket addresses of a host.
+pub type Resolving = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves the socket addresses of the hosts that clients connect to, such as the host of a
+/// request's URI or of a proxy.
+///
+/// This allows a DNS resolver other than the system's, a caching resolver or a static map of
+/// hosts to be used when connecting.
+pub trait Resolver: Debug + Send + Sync {
+    /// Resolve the socket addresses of `host` on `port`. The addresses are connected to in the
+    /// order that they are returned in.
+    fn resolve(&self, host: &str, port: u16) -> Resolving;
+}
+
+/// Resolves hosts using the system's resolver through `getaddrinfo`. This is the resolver that is
+/// used by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Resolving {
+        let host = host.to_string();
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, port)).await?.collect()) })
+    }
+}
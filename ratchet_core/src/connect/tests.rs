@@ -0,0 +1,461 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::connect::{
+    connect, connect_with, interleave_families, race_connections, RedirectRequest, Resolver,
+    Resolving, TcpOptions,
+};
+use crate::errors::{HttpError, TimeoutCause};
+use crate::handshake::SubprotocolRegistry;
+use crate::{
+    accept_with, CookieStore, Message, NoExtProvider, WebSocketClientBuilder, WebSocketConfig,
+};
+use bytes::BytesMut;
+use http::header::{AUTHORIZATION, COOKIE, HOST, SET_COOKIE, USER_AGENT};
+use http::{HeaderMap, HeaderValue, Request, Uri};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const ERR: &str = "Expected an error";
+
+#[tokio::test]
+async fn connects() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::new(["warp"]).unwrap(),
+        )
+        .await
+        .unwrap();
+        let mut websocket = upgrader.upgrade().await.unwrap().websocket;
+        websocket.write_text("hello").await.unwrap();
+    };
+
+    let client = async move {
+        let upgraded = connect_with(
+            WebSocketConfig::default(),
+            format!("ws://{addr}/test"),
+            NoExtProvider,
+            SubprotocolRegistry::new(["warp"]).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(upgraded.subprotocol, Some("warp".to_string()));
+
+        let mut websocket = upgraded.websocket;
+        let mut buf = BytesMut::new();
+        assert_eq!(websocket.read(&mut buf).await.unwrap(), Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+    };
+
+    tokio::join!(server, client);
+}
+
+#[tokio::test]
+async fn builder_connects() {
+    let listener = TcpListener::bind("[::1]:0").await;
+    let listener = match listener {
+        Ok(listener) => listener,
+        // IPv6 may not be available in the environment that the tests are running in.
+        Err(_) => return,
+    };
+    let port = listener.local_addr().unwrap().port();
+
+    let server = async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        upgrader.upgrade().await.unwrap();
+    };
+
+    let client = async move {
+        WebSocketClientBuilder::default()
+            .connect(format!("ws://[::1]:{port}"))
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server, client);
+}
+
+#[tokio::test]
+async fn unsupported_scheme() {
+    let err = connect(WebSocketConfig::default(), "wss://127.0.0.1:9001")
+        .await
+        .expect_err(ERR);
+    assert!(err.is_http());
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::UnsupportedScheme(Some("wss".to_string())))
+    );
+}
+
+async fn redirect(listener: &TcpListener, location: &str) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut buf = BytesMut::new();
+    while !buf.ends_with(b"\r\n\r\n") {
+        stream.read_buf(&mut buf).await.unwrap();
+    }
+    let response =
+        format!("HTTP/1.1 302 Found\r\nlocation: {location}\r\ncontent-length: 0\r\n\r\n");
+    stream.write_all(response.as_bytes()).await.unwrap();
+}
+
+#[tokio::test]
+async fn follows_redirects() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        redirect(&listener, "/moved").await;
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(upgrader.request().uri(), "/moved");
+        upgrader.upgrade().await.unwrap();
+    };
+
+    let client = async move {
+        WebSocketClientBuilder::default()
+            .max_redirects(1)
+            .connect(format!("ws://{addr}/test"))
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server, client);
+}
+
+/// A cookie store which holds a cookie named after the port of each URI that it is asked for.
+#[derive(Debug, Default)]
+struct PortCookieStore {
+    set: Mutex<Vec<(Uri, HeaderValue)>>,
+}
+
+impl CookieStore for PortCookieStore {
+    fn set_cookies(&self, uri: &Uri, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>) {
+        let mut set = self.set.lock().unwrap();
+        set.extend(cookie_headers.map(|value| (uri.clone(), value.clone())));
+    }
+
+    fn cookies(&self, uri: &Uri) -> Option<HeaderValue> {
+        let port = uri.port_u16()?;
+        HeaderValue::from_str(&format!("port{port}=1")).ok()
+    }
+}
+
+#[tokio::test]
+async fn redirect_to_another_host_uses_its_cookies() {
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let first_addr = first.local_addr().unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second_addr = second.local_addr().unwrap();
+    let location = format!("ws://{second_addr}/moved");
+
+    let server = async move {
+        redirect(&first, &location).await;
+
+        let (stream, _) = second.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        let expected = format!("port{}=1", second_addr.port());
+        assert_eq!(
+            upgrader.request().headers().get(COOKIE),
+            Some(&HeaderValue::from_str(&expected).unwrap())
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, HeaderValue::from_static("session=abc"));
+        upgrader.upgrade_with(headers).await.unwrap();
+    };
+
+    let store = Arc::new(PortCookieStore::default());
+    let client_store = store.clone();
+    let client = async move {
+        WebSocketClientBuilder::default()
+            .max_redirects(1)
+            .cookie_store(client_store)
+            .connect(format!("ws://{first_addr}/test"))
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server, client);
+
+    let set = store.set.lock().unwrap();
+    let uri: Uri = format!("ws://{second_addr}/moved").parse().unwrap();
+    assert_eq!(*set, vec![(uri, HeaderValue::from_static("session=abc"))]);
+}
+
+#[tokio::test]
+async fn redirect_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        redirect(&listener, "/first").await;
+        redirect(&listener, "/second").await;
+    };
+
+    let client = async move {
+        let err = WebSocketClientBuilder::default()
+            .max_redirects(1)
+            .connect(format!("ws://{addr}/test"))
+            .await
+            .expect_err(ERR);
+        assert_eq!(
+            err.downcast_ref::<HttpError>(),
+            Some(&HttpError::Redirected("/second".to_string()))
+        );
+    };
+
+    tokio::join!(server, client);
+}
+
+#[test]
+fn redirect_to_another_host() {
+    let request = Request::get("ws://example.com/test")
+        .header(AUTHORIZATION, "Bearer token")
+        .header(USER_AGENT, "ratchet")
+        .body(())
+        .unwrap();
+
+    let redirected = RedirectRequest::new(&request)
+        .to("http://example.org:8080/moved?a=b")
+        .unwrap();
+    assert_eq!(redirected.uri(), "ws://example.org:8080/moved?a=b");
+    assert!(redirected.headers().get(AUTHORIZATION).is_none());
+    assert_eq!(
+        redirected.headers().get(USER_AGENT),
+        Some(&HeaderValue::from_static("ratchet"))
+    );
+
+    let redirected = RedirectRequest::new(&request).to("/moved").unwrap();
+    assert_eq!(redirected.uri(), "ws://example.com/moved");
+    assert_eq!(
+        redirected.headers().get(AUTHORIZATION),
+        Some(&HeaderValue::from_static("Bearer token"))
+    );
+}
+
+#[tokio::test]
+async fn custom_resolver() {
+    #[derive(Debug)]
+    struct StaticResolver(SocketAddr);
+
+    impl Resolver for StaticResolver {
+        fn resolve(&self, host: &str, port: u16) -> Resolving {
+            assert_eq!(host, "websocket.test");
+            let addr = SocketAddr::new(self.0.ip(), port);
+            Box::pin(async move { Ok(vec![addr]) })
+        }
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            upgrader.request().headers().get(HOST),
+            Some(&HeaderValue::from_str(&format!("websocket.test:{}", addr.port())).unwrap())
+        );
+        upgrader.upgrade().await.unwrap();
+    };
+
+    let client = async move {
+        WebSocketClientBuilder::default()
+            .resolver(Arc::new(StaticResolver(addr)))
+            .connect(format!("ws://websocket.test:{}/test", addr.port()))
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server, client);
+}
+
+#[test]
+fn interleaves_address_families() {
+    let addrs: Vec<SocketAddr> = [
+        "[::1]:1",
+        "[::1]:2",
+        "[::1]:3",
+        "127.0.0.1:4",
+        "127.0.0.1:5",
+    ]
+    .iter()
+    .map(|addr| addr.parse().unwrap())
+    .collect();
+    let ports: Vec<u16> = interleave_families(addrs)
+        .iter()
+        .map(SocketAddr::port)
+        .collect();
+    assert_eq!(ports, [1, 4, 2, 5, 3]);
+}
+
+#[tokio::test]
+async fn races_connection_attempts() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // An address reserved for documentation, which is either unreachable or never responds.
+    let unresponsive = "192.0.2.1:9".parse().unwrap();
+
+    let options = TcpOptions {
+        connection_attempt_delay: Duration::from_millis(10),
+        ..Default::default()
+    };
+
+    let stream = race_connections(vec![unresponsive, addr], &options)
+        .await
+        .unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), addr);
+
+    let err = race_connections(Vec::new(), &options).await.expect_err(ERR);
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn tcp_options() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async move {
+        let (stream, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer.ip(), addr.ip());
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        upgrader.upgrade().await.unwrap();
+    };
+
+    let client = async move {
+        WebSocketClientBuilder::default()
+            .nodelay(true)
+            .tcp_keepalive(Duration::from_secs(30))
+            .local_addr("127.0.0.1:0".parse().unwrap())
+            .connect_timeout(Duration::from_secs(5))
+            .connect(format!("ws://{addr}/test"))
+            .await
+            .unwrap();
+    };
+
+    tokio::join!(server, client);
+}
+
+#[tokio::test(start_paused = true)]
+async fn connect_timeout() {
+    #[derive(Debug)]
+    struct PendingResolver;
+
+    impl Resolver for PendingResolver {
+        fn resolve(&self, _host: &str, _port: u16) -> Resolving {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    let err = WebSocketClientBuilder::default()
+        .resolver(Arc::new(PendingResolver))
+        .connect_timeout(Duration::from_secs(5))
+        .connect("ws://websocket.test/test")
+        .await
+        .expect_err(ERR);
+    assert!(err.is_timeout());
+    assert_eq!(
+        err.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Connect)
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connects_unix() {
+    use crate::connect::connect_unix;
+    use tokio::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("ratchet-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server = async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let upgrader = accept_with(
+            stream,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(upgrader.request().uri(), "/test");
+        assert_eq!(
+            upgrader.request().headers().get(HOST),
+            Some(&HeaderValue::from_static("localhost"))
+        );
+        let mut websocket = upgrader.upgrade().await.unwrap().websocket;
+        websocket.write_text("hello").await.unwrap();
+    };
+
+    let client = async {
+        let mut websocket = connect_unix(WebSocketConfig::default(), &path, "ws://localhost/test")
+            .await
+            .unwrap()
+            .websocket;
+        let mut buf = BytesMut::new();
+        assert_eq!(websocket.read(&mut buf).await.unwrap(), Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+    };
+
+    tokio::join!(server, client);
+    std::fs::remove_file(&path).unwrap();
+}
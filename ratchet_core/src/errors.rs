@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::protocol::{CloseCodeParseErr, OpCodeParseErr};
+use crate::protocol::{CloseCodeParseErr, FrameError, OpCodeParseErr};
+use bytes::Bytes;
 use http::header::{HeaderName, InvalidHeaderValue};
 use http::status::InvalidStatusCode;
 use http::uri::InvalidUri;
+use http::{HeaderMap, StatusCode};
 use std::any::Any;
 use std::error::Error as StdError;
 use std::fmt::{Display, Formatter};
@@ -102,6 +104,16 @@ impl Error {
     pub fn is_close(&self) -> bool {
         matches!(self.inner.kind, ErrorKind::Close)
     }
+
+    /// Whether this error is related to an operation timing out.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.inner.kind, ErrorKind::Timeout)
+    }
+
+    /// Whether this error is related to the peer exceeding a rate limit.
+    pub fn is_rate_limit(&self) -> bool {
+        matches!(self.inner.kind, ErrorKind::RateLimit)
+    }
 }
 
 #[derive(Debug)]
@@ -125,6 +137,10 @@ pub enum ErrorKind {
     Encoding,
     /// A close error.
     Close,
+    /// An operation timed out.
+    Timeout,
+    /// The peer exceeded a rate limit.
+    RateLimit,
 }
 
 impl From<io::Error> for Error {
@@ -157,6 +173,9 @@ pub enum HttpError {
     /// The peer returned with a status code other than 101.
     #[error("Status code: `{0:?}`")]
     Status(u16),
+    /// The server rejected the upgrade request with a response other than 101.
+    #[error("Upgrade rejected with status code: `{}`", .0.status().as_u16())]
+    Rejected(Box<Rejection>),
     /// A request or response was missing its status code.
     #[error("Missing status code")]
     MissingStatus,
@@ -169,6 +188,10 @@ pub enum HttpError {
     /// A request or response contained an invalid header.
     #[error("Invalid header: `{0}`")]
     InvalidHeader(HeaderName),
+    /// An upgrade requested a WebSocket version that is not supported. Contains the versions that
+    /// are supported, which a server advertises in a `426 Upgrade Required` response.
+    #[error("Unsupported WebSocket version. Supported versions: `{0:?}`")]
+    UnsupportedVersion(Vec<String>),
     /// Sec-WebSocket-Key was invalid.
     #[error("Sec-WebSocket-Accept mismatch")]
     KeyMismatch,
@@ -181,6 +204,62 @@ pub enum HttpError {
     /// A request was missing the authority.
     #[error("Missing authority")]
     MissingAuthority,
+    /// A request's URI used a scheme that is not supported.
+    #[error("Unsupported URI scheme: `{0:?}`")]
+    UnsupportedScheme(Option<String>),
+    /// A request contained more headers than are permitted.
+    #[error("Too many headers")]
+    TooManyHeaders,
+    /// A request's headers exceeded the maximum permitted size.
+    #[error("Headers exceeded the maximum permitted size")]
+    HeadersTooLarge,
+    /// The origin of a request was not permitted.
+    #[error("Origin not permitted: `{0:?}`")]
+    ForbiddenOrigin(Option<String>),
+    /// A connection did not start with a valid PROXY protocol header.
+    #[error("Invalid PROXY protocol header: `{0}`")]
+    InvalidProxyHeader(String),
+    /// The `:protocol` pseudo-header of an extended CONNECT request was not `websocket`.
+    #[error("Invalid protocol: `{0:?}`")]
+    InvalidProtocol(Option<String>),
+    /// The server accepted an extension that was not offered or which could not be negotiated.
+    #[error("Unexpected extension: `{0}`")]
+    UnexpectedExtension(String),
+}
+
+/// A response from a server which did not switch protocols, such as `401 Unauthorized` or
+/// `503 Service Unavailable`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Rejection {
+    pub(crate) fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> Rejection {
+        Rejection {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The body of the response. At most 4KiB of the body is read and the body only contains the
+    /// bytes which were received with the response if it did not have a `content-length` header.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
 }
 
 impl From<HttpError> for Error {
@@ -235,6 +314,26 @@ impl From<OpCodeParseErr> for ProtocolError {
     }
 }
 
+impl From<FrameError> for ProtocolError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            FrameError::OpCode(e) => ProtocolError::OpCode(e),
+            FrameError::UnmaskedFrame => ProtocolError::UnmaskedFrame,
+            FrameError::MaskedFrame => ProtocolError::MaskedFrame,
+            FrameError::FragmentedControl => ProtocolError::FragmentedControl,
+            FrameError::FragmentedReserved => ProtocolError::FragmentedReserved,
+            FrameError::FrameOverflow => ProtocolError::FrameOverflow,
+            FrameError::UnknownExtension => ProtocolError::UnknownExtension,
+        }
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(e: FrameError) -> Self {
+        Error::with_cause(ErrorKind::Protocol, ProtocolError::from(e))
+    }
+}
+
 impl From<OpCodeParseErr> for Error {
     fn from(e: OpCodeParseErr) -> Self {
         Error::with_cause(ErrorKind::Protocol, Box::new(ProtocolError::from(e)))
@@ -273,6 +372,77 @@ pub enum CloseCause {
     Error,
 }
 
+/// The cause of a timeout error.
+#[derive(Clone, Copy, Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimeoutCause {
+    /// A keepalive ping was sent and the peer did not respond with a pong before the deadline.
+    #[error("The peer did not respond to a keepalive ping in time")]
+    Pong,
+    /// No text or binary frames were read or written before the idle timeout elapsed.
+    #[error("The connection was idle for too long")]
+    Idle,
+    /// A close frame was sent and the peer did not echo it before the deadline.
+    #[error("The peer did not respond to a close frame in time")]
+    Close,
+    /// The opening handshake did not complete before the deadline.
+    #[error("The handshake did not complete in time")]
+    Handshake,
+    /// A connection to the host could not be established before the deadline.
+    #[error("The connection could not be established in time")]
+    Connect,
+    /// No message was read before the read timeout elapsed.
+    #[error("No message was read in time")]
+    Read,
+    /// The buffered frames were not written before the write timeout elapsed.
+    #[error("The write operation did not complete in time")]
+    Write,
+}
+
+impl From<TimeoutCause> for Error {
+    fn from(e: TimeoutCause) -> Self {
+        Error::with_cause(ErrorKind::Timeout, e)
+    }
+}
+
+/// The limit that was exceeded by a peer when a rate limit error is produced.
+#[derive(Clone, Copy, Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RateLimitCause {
+    /// The peer sent more messages than are permitted per second.
+    #[error("The peer exceeded the message rate limit")]
+    Messages,
+    /// The peer sent more payload bytes than are permitted per second.
+    #[error("The peer exceeded the byte rate limit")]
+    Bytes,
+}
+
+impl From<RateLimitCause> for Error {
+    fn from(e: RateLimitCause) -> Self {
+        Error::with_cause(ErrorKind::RateLimit, e)
+    }
+}
+
+/// The cause of an extension error produced when preparing or sending a
+/// [`PreparedMessage`](crate::PreparedMessage).
+#[derive(Clone, Copy, Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PreparedMessageError {
+    /// The encoder that the message was prepared with does not encode each message independently
+    /// of those before it.
+    #[error("The encoder does not encode messages independently")]
+    StatefulEncoder,
+    /// The message was encoded with parameters other than those of the connection's encoder.
+    #[error("The message was encoded with parameters that the connection does not use")]
+    IncompatibleEncoder,
+}
+
+impl From<PreparedMessageError> for Error {
+    fn from(e: PreparedMessageError) -> Self {
+        Error::with_cause(ErrorKind::Extension, e)
+    }
+}
+
 /// WebSocket protocol errors.
 #[derive(Clone, Debug, Eq, PartialEq, Error)]
 pub enum ProtocolError {
@@ -291,6 +461,9 @@ pub enum ProtocolError {
     /// Received a fragmented control frame
     #[error("Received a fragmented control frame")]
     FragmentedControl,
+    /// Received a fragmented frame with a reserved opcode
+    #[error("Received a fragmented frame with a reserved opcode")]
+    FragmentedReserved,
     /// A received frame exceeded the maximum permitted size
     #[error("A frame exceeded the maximum permitted size")]
     FrameOverflow,
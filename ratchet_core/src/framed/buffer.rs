@@ -0,0 +1,123 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::io::IoSlice;
+
+/// Payloads smaller than this are copied into the buffer alongside their frame headers as the cost
+/// of the copy is lower than that of writing them as a separate slice.
+const INLINE_THRESHOLD: usize = 1024;
+
+/// A write buffer which holds frame headers and small payloads contiguously but retains large
+/// payloads as separate chunks so that they may be written using vectored IO without first being
+/// copied.
+#[derive(Debug, Default)]
+pub struct WriteBuffer {
+    chunks: VecDeque<Bytes>,
+    tail: BytesMut,
+}
+
+impl WriteBuffer {
+    /// Returns the contiguous buffer that frame headers should be encoded into.
+    pub fn tail_mut(&mut self) -> &mut BytesMut {
+        &mut self.tail
+    }
+
+    /// Appends `payload` to the buffer. If the payload is large enough then it is retained as its
+    /// own chunk rather than being copied.
+    pub fn put_payload(&mut self, payload: Bytes) {
+        if payload.len() < INLINE_THRESHOLD {
+            self.tail.extend_from_slice(&payload);
+        } else {
+            if !self.tail.is_empty() {
+                self.chunks.push_back(self.tail.split().freeze());
+            }
+            self.chunks.push_back(payload);
+        }
+    }
+
+    /// Appends the contents of `payload` to the buffer, leaving it empty. Small payloads are copied
+    /// so that `payload` retains its capacity, while large payloads are split off from it and
+    /// retained as their own chunk.
+    pub fn put_payload_from(&mut self, payload: &mut BytesMut) {
+        if payload.len() < INLINE_THRESHOLD {
+            self.tail.extend_from_slice(payload);
+            payload.clear();
+        } else {
+            self.put_payload(payload.split().freeze());
+        }
+    }
+
+    /// Removes the next chunk from the front of the buffer without copying it.
+    #[cfg(feature = "completion")]
+    pub fn pop_chunk(&mut self) -> Option<Bytes> {
+        match self.chunks.pop_front() {
+            Some(chunk) => Some(chunk),
+            None if self.tail.is_empty() => None,
+            None => Some(self.tail.split().freeze()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.tail.is_empty()
+    }
+}
+
+impl Buf for WriteBuffer {
+    fn remaining(&self) -> usize {
+        self.chunks.iter().map(Bytes::len).sum::<usize>() + self.tail.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self.chunks.front() {
+            Some(chunk) => chunk,
+            None => &self.tail,
+        }
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        while cnt > 0 {
+            match self.chunks.front_mut() {
+                Some(chunk) if chunk.len() <= cnt => {
+                    cnt -= chunk.len();
+                    self.chunks.pop_front();
+                }
+                Some(chunk) => {
+                    chunk.advance(cnt);
+                    return;
+                }
+                None => {
+                    self.tail.advance(cnt);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let slices = self
+            .chunks
+            .iter()
+            .map(|chunk| chunk.as_ref())
+            .chain(Some(self.tail.as_ref()).filter(|tail| !tail.is_empty()));
+
+        let mut count = 0;
+        for (slot, slice) in dst.iter_mut().zip(slices) {
+            *slot = IoSlice::new(slice);
+            count += 1;
+        }
+        count
+    }
+}
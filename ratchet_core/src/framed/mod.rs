@@ -12,19 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod buffer;
 #[cfg(test)]
 mod tests;
+mod utf8;
 
-use crate::errors::{Error, ErrorKind, ProtocolError};
+use crate::errors::{Error, ErrorKind, ProtocolError, TimeoutCause};
+use crate::framed::buffer::WriteBuffer;
+use crate::framed::utf8::Utf8Validator;
 use crate::protocol::{
-    apply_mask, CloseCode, CloseReason, ControlCode, DataCode, FrameHeader, HeaderFlags,
-    MessageType, OpCode, Role,
+    apply_mask, CloseCode, CloseReason, ControlCode, DataCode, DecodeResult, FrameDecoder,
+    FrameHeader, HeaderFlags, MessageType, OpCode, Role,
 };
 use crate::protocol::{BorrowedFramePrinter, FramePrinter};
 use crate::ws::CONTROL_MAX_SIZE;
-use crate::WebSocketStream;
+use crate::{PreparedMessage, WebSocketStream};
 use bytes::Buf;
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use either::Either;
 use log::trace;
 use rand::rngs::SmallRng;
@@ -32,12 +36,19 @@ use rand::{Rng, SeedableRng};
 use ratchet_ext::{ExtensionDecoder, FrameHeader as ExtFrameHeader, OpCode as ExtOpCode};
 use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::{poll_read_buf, poll_write_buf};
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Item {
     Binary,
     Text,
+    /// A non-final fragment of a message. Only produced when reading in streaming mode.
+    Fragment(MessageType),
     Ping(BytesMut),
     Pong(BytesMut),
     Close(Option<CloseReason>),
@@ -47,6 +58,8 @@ bitflags::bitflags! {
     #[derive(Debug)]
     pub struct CodecFlags: u8 {
         const R_CONT    = 0b0000_0001;
+        // If high then the message being read has been encoded by an extension
+        const R_EXT     = 0b0000_0010;
         // If high 'text' else 'binary
         const CONT_TYPE = 0b0000_1000;
 
@@ -61,74 +74,35 @@ bitflags::bitflags! {
     }
 }
 
-// todo: this could be reworked to save space as it's 64 bytes
-#[derive(Debug, Default)]
-pub enum FrameDecoder {
-    #[default]
-    DecodingHeader,
-    DecodingPayload(FrameHeader, usize, usize),
-}
-
-pub enum DecodeResult {
-    Incomplete(usize),
-    Finished(FrameHeader, BytesMut),
-}
-
-impl FrameDecoder {
-    pub fn decode(
-        &mut self,
-        buf: &mut BytesMut,
-        is_server: bool,
-        rsv_bits: u8,
-        max_message_size: usize,
-    ) -> Result<DecodeResult, Error> {
-        loop {
-            match self {
-                FrameDecoder::DecodingHeader => {
-                    match FrameHeader::read_from(buf, is_server, rsv_bits, max_message_size)? {
-                        Either::Left((header, header_len, payload_len)) => {
-                            *self = FrameDecoder::DecodingPayload(header, header_len, payload_len);
-                        }
-                        Either::Right(count) => return Ok(DecodeResult::Incomplete(count)),
-                    }
-                }
-                FrameDecoder::DecodingPayload(header, header_len, payload_len) => {
-                    let frame_len = *header_len + *payload_len;
-                    let buf_len = buf.len();
-
-                    if buf_len < frame_len {
-                        let dif = frame_len - buf_len;
-                        return Ok(DecodeResult::Incomplete(dif));
-                    }
-
-                    buf.advance(*header_len);
-
-                    let mut payload = buf.split_to(*payload_len);
-
-                    if let Some(mask) = header.mask {
-                        apply_mask(mask, &mut payload);
-                    }
-
-                    let result = DecodeResult::Finished(*header, payload);
-                    *self = FrameDecoder::DecodingHeader;
-
-                    return Ok(result);
-                }
-            }
-        }
-    }
-}
-
+#[derive(Copy, Clone)]
 pub struct ReadProps {
     pub is_server: bool,
     pub rsv_bits: u8,
     pub max_message_size: usize,
+    /// Whether each fragment of a message should be yielded as it is received rather than the
+    /// message being reassembled.
+    pub streaming: bool,
 }
 
 #[derive(Debug)]
 pub struct FramedRead {
     read_buffer: BytesMut,
     decoder: FrameDecoder,
+    message: MessageState,
+    max_frame_size: usize,
+    // A bitmask of the reserved opcodes that have been claimed by the negotiated extension
+    reserved_opcodes: u16,
+    read_timeout: Option<Duration>,
+}
+
+/// The state of the data message that is currently being read.
+#[derive(Debug, Default)]
+struct MessageState {
+    // The number of bytes of the message that have been read so far in streaming mode.
+    len: usize,
+    // The offset into the caller's buffer at which the message starts when it is reassembled.
+    start: usize,
+    utf8: Utf8Validator,
 }
 
 impl FramedRead {
@@ -136,214 +110,430 @@ impl FramedRead {
         FramedRead {
             read_buffer,
             decoder: FrameDecoder::default(),
+            message: MessageState::default(),
+            max_frame_size: usize::MAX,
+            reserved_opcodes: 0,
+            read_timeout: None,
         }
     }
 
-    async fn read_frame<I>(
+    /// Returns the duration after which a read operation fails with `TimeoutCause::Read`.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    pub fn poll_read_frame<I>(
         &mut self,
+        cx: &mut Context<'_>,
         io: &mut I,
         is_server: bool,
         rsv_bits: u8,
         max_message_size: usize,
-    ) -> Result<(FrameHeader, BytesMut), Error>
+    ) -> Poll<Result<(FrameHeader, BytesMut), Error>>
     where
         I: AsyncRead + Unpin,
     {
         let FramedRead {
             read_buffer,
             decoder,
+            max_frame_size,
+            reserved_opcodes,
+            ..
         } = self;
+        // A frame may not be larger than the message that it belongs to.
+        let max_frame_size = (*max_frame_size).min(max_message_size);
 
         loop {
-            match decoder.decode(read_buffer, is_server, rsv_bits, max_message_size)? {
+            match decoder.decode(
+                read_buffer,
+                is_server,
+                rsv_bits,
+                *reserved_opcodes,
+                max_frame_size,
+            )? {
                 DecodeResult::Incomplete(count) => {
-                    let len = read_buffer.len();
-                    read_buffer.resize(len + count, 0u8);
-                    io.read_exact(&mut read_buffer[len..]).await?;
+                    // Any bytes that are read past the end of the frame are retained in the buffer
+                    // for the next decode call. Nothing is lost if this future is dropped here.
+                    read_buffer.reserve(count);
+                    if ready!(poll_read_buf(Pin::new(&mut *io), cx, read_buffer))? == 0 {
+                        return Poll::Ready(Err(std::io::Error::from(
+                            std::io::ErrorKind::UnexpectedEof,
+                        )
+                        .into()));
+                    }
+                }
+                DecodeResult::Finished(header, payload) => {
+                    return Poll::Ready(Ok((header, payload)))
                 }
-                DecodeResult::Finished(header, payload) => return Ok((header, payload)),
             }
         }
     }
 
-    pub async fn read<I, E>(
+    /// Attempts to read a ping frame from `io` without disturbing any other frames that have been
+    /// received. Returns `Poll::Ready(Ok(None))` if the next frame is not a ping that can be
+    /// answered and so it must be left for the next read operation, or if `io` has reached EOF.
+    ///
+    /// Any errors in the next frame are not reported here and will instead be produced by the next
+    /// read operation.
+    fn poll_read_ping<I>(
         &mut self,
+        cx: &mut Context<'_>,
+        io: &mut I,
+        is_server: bool,
+        rsv_bits: u8,
+        max_message_size: usize,
+    ) -> Poll<Result<Option<BytesMut>, Error>>
+    where
+        I: AsyncRead + Unpin,
+    {
+        let FramedRead {
+            read_buffer,
+            decoder,
+            max_frame_size,
+            reserved_opcodes,
+            ..
+        } = self;
+        // A frame may not be larger than the message that it belongs to.
+        let max_frame_size = (*max_frame_size).min(max_message_size);
+
+        loop {
+            let header = match decoder {
+                FrameDecoder::DecodingHeader => {
+                    match FrameHeader::read_from(
+                        read_buffer,
+                        is_server,
+                        rsv_bits,
+                        *reserved_opcodes,
+                        max_frame_size,
+                    ) {
+                        Ok(Either::Left((header, _, payload_len))) => Some((header, payload_len)),
+                        Ok(Either::Right(count)) => {
+                            read_buffer.reserve(count);
+                            None
+                        }
+                        Err(_) => return Poll::Ready(Ok(None)),
+                    }
+                }
+                FrameDecoder::DecodingPayload(header, _, payload_len) => {
+                    Some((*header, *payload_len))
+                }
+            };
+
+            if let Some((header, payload_len)) = header {
+                if header.opcode != OpCode::ControlCode(ControlCode::Ping)
+                    || payload_len > CONTROL_MAX_SIZE
+                {
+                    return Poll::Ready(Ok(None));
+                }
+
+                match decoder.decode(
+                    read_buffer,
+                    is_server,
+                    rsv_bits,
+                    *reserved_opcodes,
+                    max_frame_size,
+                )? {
+                    DecodeResult::Incomplete(count) => read_buffer.reserve(count),
+                    DecodeResult::Finished(header, payload) => {
+                        trace!("Read frame: {}", FramePrinter(&header));
+                        return Poll::Ready(Ok(Some(payload)));
+                    }
+                }
+            }
+
+            if ready!(poll_read_buf(Pin::new(&mut *io), cx, read_buffer))? == 0 {
+                return Poll::Ready(Ok(None));
+            }
+        }
+    }
+
+    /// Attempts to read the next item from `io`. All partial progress (frame bytes and message
+    /// payloads that have been received so far) is retained in the read buffer and `read_into`
+    /// so this may be safely polled again after returning `Poll::Pending`.
+    pub fn poll_read<I, E>(
+        &mut self,
+        cx: &mut Context<'_>,
         io: &mut I,
         flags: &mut CodecFlags,
         read_into: &mut BytesMut,
         extension: &mut E,
         props: ReadProps,
-    ) -> Result<Item, Error>
+    ) -> Poll<Result<Item, Error>>
     where
         I: AsyncRead + Unpin,
         E: ExtensionDecoder,
     {
+        loop {
+            if let Some(item) = self.decode_item(flags, read_into, extension, props)? {
+                return Poll::Ready(Ok(item));
+            }
+
+            // Any bytes that are read past the end of the frame are retained in the buffer for the
+            // next decode call. Nothing is lost if this future is dropped here.
+            if ready!(poll_read_buf(Pin::new(&mut *io), cx, &mut self.read_buffer))? == 0 {
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+                ));
+            }
+        }
+    }
+
+    /// Attempts to decode the next item from the bytes that have already been received, without
+    /// performing any IO. Returns `Ok(None)` if more bytes are required, in which case capacity for
+    /// them has been reserved in the read buffer.
+    pub fn decode_item<E>(
+        &mut self,
+        flags: &mut CodecFlags,
+        read_into: &mut BytesMut,
+        extension: &mut E,
+        props: ReadProps,
+    ) -> Result<Option<Item>, Error>
+    where
+        E: ExtensionDecoder,
+    {
+        let FramedRead {
+            read_buffer,
+            decoder,
+            message,
+            max_frame_size,
+            reserved_opcodes,
+            ..
+        } = self;
         let ReadProps {
             is_server,
             rsv_bits,
             max_message_size,
+            ..
         } = props;
+        // A frame may not be larger than the message that it belongs to.
+        let max_frame_size = (*max_frame_size).min(max_message_size);
 
         loop {
-            let (header, payload) = self
-                .read_frame(io, is_server, rsv_bits, max_message_size)
-                .await?;
-            trace!("Read frame: {}", FramePrinter(&header));
-
-            match header.opcode {
-                OpCode::DataCode(data_code) => {
-                    if read_into.len() + payload.len() > max_message_size {
-                        return Err(ProtocolError::FrameOverflow.into());
+            match decoder.decode(
+                read_buffer,
+                is_server,
+                rsv_bits,
+                *reserved_opcodes,
+                max_frame_size,
+            )? {
+                DecodeResult::Incomplete(count) => {
+                    read_buffer.reserve(count);
+                    return Ok(None);
+                }
+                DecodeResult::Finished(header, payload) => {
+                    trace!("Read frame: {}", FramePrinter(&header));
+                    if let Some(item) =
+                        on_frame(header, payload, flags, message, read_into, extension, props)?
+                    {
+                        return Ok(Some(item));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the buffer that received bytes should be appended to.
+    #[cfg(feature = "completion")]
+    pub fn read_buffer_mut(&mut self) -> &mut BytesMut {
+        &mut self.read_buffer
+    }
+}
+
+/// Processes a decoded frame. Returns `Ok(None)` if the frame was a non-terminal fragment of a
+/// data message and more frames are required to complete the message.
+fn on_frame<E>(
+    header: FrameHeader,
+    mut payload: BytesMut,
+    flags: &mut CodecFlags,
+    message: &mut MessageState,
+    read_into: &mut BytesMut,
+    extension: &mut E,
+    props: ReadProps,
+) -> Result<Option<Item>, Error>
+where
+    E: ExtensionDecoder,
+{
+    let ReadProps {
+        max_message_size,
+        streaming,
+        ..
+    } = props;
+
+    match header.opcode {
+        OpCode::DataCode(data_code) => {
+            let (message_type, ext_opcode) = match data_code {
+                DataCode::Continuation => {
+                    if !flags.contains(CodecFlags::R_CONT) {
+                        return Err(ProtocolError::ContinuationNotStarted.into());
+                    } else if flags.contains(CodecFlags::CONT_TYPE) {
+                        (MessageType::Text, ExtOpCode::Continuation)
+                    } else {
+                        (MessageType::Binary, ExtOpCode::Continuation)
+                    }
+                }
+                DataCode::Text | DataCode::Binary => {
+                    if flags.contains(CodecFlags::R_CONT) {
+                        return Err(ProtocolError::ContinuationAlreadyStarted.into());
                     }
 
-                    read_into.put(payload);
-
-                    match data_code {
-                        DataCode::Continuation => {
-                            if header.flags.contains(HeaderFlags::FIN) {
-                                let item = if flags.contains(CodecFlags::R_CONT) {
-                                    extension_decode(
-                                        read_into,
-                                        extension,
-                                        &header.flags,
-                                        ExtOpCode::Continuation,
-                                    )?;
-
-                                    if flags.contains(CodecFlags::CONT_TYPE) {
-                                        Item::Text
-                                    } else {
-                                        Item::Binary
-                                    }
+                    message.len = 0;
+                    message.start = read_into.len();
+                    message.utf8.reset();
+                    flags.set(
+                        CodecFlags::R_EXT,
+                        header.flags.intersects(HeaderFlags::RESERVED),
+                    );
+
+                    if data_code == DataCode::Text {
+                        (MessageType::Text, ExtOpCode::Text)
+                    } else {
+                        (MessageType::Binary, ExtOpCode::Binary)
+                    }
+                }
+            };
+
+            // Messages that have been encoded by an extension may only be decoded once all of
+            // their fragments have been received and so they are always reassembled.
+            let stream_fragment = streaming && !flags.contains(CodecFlags::R_EXT);
+
+            if stream_fragment {
+                message.len += payload.len();
+                if message.len > max_message_size {
+                    return Err(ProtocolError::FrameOverflow.into());
+                }
+
+                extension_decode(&mut payload, extension, &header.flags, ext_opcode)?;
+                if message_type == MessageType::Text {
+                    message.utf8.feed(&payload, header.flags.is_fin())?;
+                }
+                read_into.put(payload);
+            } else {
+                // Only the message is decoded and validated, as `read_into` may already contain
+                // data which does not belong to it.
+                let start = message.start.min(read_into.len());
+                if read_into.len() - start + payload.len() > max_message_size {
+                    return Err(ProtocolError::FrameOverflow.into());
+                }
+
+                let is_encoded = flags.contains(CodecFlags::R_EXT);
+                if message_type == MessageType::Text && !is_encoded {
+                    // Unencoded text is validated as each fragment arrives so invalid messages are
+                    // rejected as early as possible.
+                    message.utf8.feed(&payload, header.flags.is_fin())?;
+                }
+
+                read_into.put(payload);
+                let mut message_buf = read_into.split_off(start);
+                extension_decode(&mut message_buf, extension, &header.flags, ext_opcode)?;
+                read_into.unsplit(message_buf);
+
+                if message_type == MessageType::Text && is_encoded && header.flags.is_fin() {
+                    message.utf8.feed(&read_into[start..], true)?;
+                }
+            }
+
+            if header.flags.contains(HeaderFlags::FIN) {
+                flags.remove(CodecFlags::R_CONT | CodecFlags::CONT_TYPE | CodecFlags::R_EXT);
+                match message_type {
+                    MessageType::Text => Ok(Some(Item::Text)),
+                    MessageType::Binary => Ok(Some(Item::Binary)),
+                }
+            } else {
+                flags.insert(CodecFlags::R_CONT);
+                if let MessageType::Text = message_type {
+                    flags.insert(CodecFlags::CONT_TYPE);
+                }
+
+                if stream_fragment {
+                    Ok(Some(Item::Fragment(message_type)))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+        OpCode::Reserved(code) => {
+            if code & 0x8 != 0 && payload.len() > CONTROL_MAX_SIZE {
+                return Err(ProtocolError::FrameOverflow.into());
+            }
+
+            // Frames with reserved opcodes are consumed by the extension that claimed them
+            extension_decode(
+                &mut payload,
+                extension,
+                &header.flags,
+                ExtOpCode::Reserved(code),
+            )?;
+            Ok(None)
+        }
+        OpCode::ControlCode(c) => match c {
+            ControlCode::Close => {
+                let reason = match payload.len() {
+                    0 => None,
+                    1 => {
+                        return Err(ProtocolError::InvalidControlFrame.into());
+                    }
+                    2..=CONTROL_MAX_SIZE => {
+                        let close_reason = std::str::from_utf8(&payload[2..])?.to_string();
+                        match CloseCode::try_from([payload[0], payload[1]])? {
+                            close_code if close_code.is_illegal() => {
+                                return Err(ProtocolError::CloseCode(u16::from(close_code)).into())
+                            }
+                            close_code => {
+                                let description = if close_reason.is_empty() {
+                                    None
                                 } else {
-                                    return Err(ProtocolError::ContinuationNotStarted.into());
+                                    Some(close_reason)
                                 };
-                                flags.remove(CodecFlags::R_CONT | CodecFlags::CONT_TYPE);
-                                return Ok(item);
-                            } else if flags.contains(CodecFlags::R_CONT) {
-                                extension_decode(
-                                    read_into,
-                                    extension,
-                                    &header.flags,
-                                    ExtOpCode::Continuation,
-                                )?;
-                                continue;
-                            } else {
-                                return Err(ProtocolError::ContinuationNotStarted.into());
-                            }
-                        }
-                        DataCode::Text => {
-                            if flags.contains(CodecFlags::R_CONT) {
-                                return Err(ProtocolError::ContinuationAlreadyStarted.into());
-                            } else if header.flags.contains(HeaderFlags::FIN) {
-                                extension_decode(
-                                    read_into,
-                                    extension,
-                                    &header.flags,
-                                    ExtOpCode::Text,
-                                )?;
-                                return Ok(Item::Text);
-                            } else {
-                                flags.insert(CodecFlags::R_CONT | CodecFlags::CONT_TYPE);
-                                extension_decode(
-                                    read_into,
-                                    extension,
-                                    &header.flags,
-                                    ExtOpCode::Text,
-                                )?;
-                                continue;
-                            }
-                        }
-                        DataCode::Binary => {
-                            if flags.contains(CodecFlags::R_CONT) {
-                                return Err(ProtocolError::ContinuationAlreadyStarted.into());
-                            } else if header.flags.contains(HeaderFlags::FIN) {
-                                extension_decode(
-                                    read_into,
-                                    extension,
-                                    &header.flags,
-                                    ExtOpCode::Binary,
-                                )?;
-                                return Ok(Item::Binary);
-                            } else {
-                                debug_assert!(!flags.contains(CodecFlags::CONT_TYPE));
-                                flags.insert(CodecFlags::R_CONT);
-                                extension_decode(
-                                    read_into,
-                                    extension,
-                                    &header.flags,
-                                    ExtOpCode::Binary,
-                                )?;
-                                continue;
+                                Some(CloseReason::new(close_code, description))
                             }
                         }
                     }
+                    _ => {
+                        return Err(ProtocolError::FrameOverflow.into());
+                    }
+                };
+
+                Ok(Some(Item::Close(reason)))
+            }
+            ControlCode::Ping => {
+                if payload.len() > CONTROL_MAX_SIZE {
+                    Err(ProtocolError::FrameOverflow.into())
+                } else {
+                    Ok(Some(Item::Ping(payload)))
                 }
-                OpCode::ControlCode(c) => {
-                    return match c {
-                        ControlCode::Close => {
-                            let reason = match payload.len() {
-                                0 => None,
-                                1 => {
-                                    return Err(ProtocolError::InvalidControlFrame.into());
-                                }
-                                2..=CONTROL_MAX_SIZE => {
-                                    let close_reason =
-                                        std::str::from_utf8(&payload[2..])?.to_string();
-                                    match CloseCode::try_from([payload[0], payload[1]])? {
-                                        close_code if close_code.is_illegal() => {
-                                            return Err(ProtocolError::CloseCode(u16::from(
-                                                close_code,
-                                            ))
-                                            .into())
-                                        }
-                                        close_code => {
-                                            let description = if close_reason.is_empty() {
-                                                None
-                                            } else {
-                                                Some(close_reason)
-                                            };
-                                            Some(CloseReason::new(close_code, description))
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    return Err(ProtocolError::FrameOverflow.into());
-                                }
-                            };
-
-                            Ok(Item::Close(reason))
-                        }
-                        ControlCode::Ping => {
-                            if payload.len() > 125 {
-                                return Err(ProtocolError::FrameOverflow.into());
-                            } else {
-                                Ok(Item::Ping(payload))
-                            }
-                        }
-                        ControlCode::Pong => {
-                            if payload.len() > 125 {
-                                return Err(ProtocolError::FrameOverflow.into());
-                            } else {
-                                Ok(Item::Pong(payload))
-                            }
-                        }
-                    };
+            }
+            ControlCode::Pong => {
+                if payload.len() > CONTROL_MAX_SIZE {
+                    Err(ProtocolError::FrameOverflow.into())
+                } else {
+                    Ok(Some(Item::Pong(payload)))
                 }
             }
-        }
+        },
     }
 }
 
 pub struct FramedWrite {
-    write_buffer: BytesMut,
+    write_buffer: WriteBuffer,
+    scratch: BytesMut,
     rand: SmallRng,
+    auto_fragment_above: Option<usize>,
+    autoflush_threshold: Option<usize>,
+    write_timeout: Option<Duration>,
+    // Whether a fragmented message is being written using `buffer_fragment`
+    fragmenting: bool,
 }
 
 impl Default for FramedWrite {
     fn default() -> Self {
         FramedWrite {
             write_buffer: Default::default(),
+            scratch: BytesMut::new(),
             rand: SmallRng::from_entropy(),
+            auto_fragment_above: None,
+            autoflush_threshold: None,
+            write_timeout: None,
+            fragmenting: false,
         }
     }
 }
@@ -352,6 +542,10 @@ impl Debug for FramedWrite {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FramedWrite")
             .field("write_buffer", &self.write_buffer)
+            .field("auto_fragment_above", &self.auto_fragment_above)
+            .field("autoflush_threshold", &self.autoflush_threshold)
+            .field("write_timeout", &self.write_timeout)
+            .field("fragmenting", &self.fragmenting)
             .finish()
     }
 }
@@ -362,7 +556,7 @@ impl FramedWrite {
         io: &mut I,
         is_server: bool,
         opcode: OpCode,
-        mut header_flags: HeaderFlags,
+        header_flags: HeaderFlags,
         payload_ref: A,
         extension: F,
     ) -> Result<(), Error>
@@ -371,26 +565,77 @@ impl FramedWrite {
         A: AsRef<[u8]>,
         F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
     {
-        let FramedWrite { write_buffer, rand } = self;
-        let payload = payload_ref.as_ref();
+        self.buffer_frame(is_server, opcode, header_flags, payload_ref, extension)?;
+        self.flush(io).await
+    }
+
+    /// Writes all of the buffered frames to `io` and then flushes it, failing with
+    /// `TimeoutCause::Write` if this does not complete before the write timeout. Any frames that
+    /// have not been written when the timeout elapses remain in the write buffer.
+    pub async fn flush<I>(&mut self, io: &mut I) -> Result<(), Error>
+    where
+        I: AsyncWrite + Unpin,
+    {
+        let write_timeout = self.write_timeout;
+        with_timeout(
+            write_timeout,
+            TimeoutCause::Write,
+            poll_fn(|cx| self.poll_flush(cx, io)),
+        )
+        .await
+    }
+
+    /// Flushes `io` if the write buffer has reached the autoflush threshold, or if no threshold has
+    /// been set, with the same timeout as `flush`.
+    pub async fn autoflush<I>(&mut self, io: &mut I) -> Result<(), Error>
+    where
+        I: AsyncWrite + Unpin,
+    {
+        let write_timeout = self.write_timeout;
+        with_timeout(
+            write_timeout,
+            TimeoutCause::Write,
+            poll_fn(|cx| self.poll_autoflush(cx, io)),
+        )
+        .await
+    }
+
+    /// Encodes a frame into the write buffer. No IO is performed and the frame will only be
+    /// written to the underlying IO after a call to `poll_flush` or `poll_write_buffer`.
+    pub fn buffer_frame<A, F>(
+        &mut self,
+        is_server: bool,
+        opcode: OpCode,
+        mut header_flags: HeaderFlags,
+        payload_ref: A,
+        extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        let FramedWrite {
+            write_buffer,
+            scratch,
+            rand,
+            ..
+        } = self;
 
-        let mut payload_bytes = BytesMut::with_capacity(payload.len());
-        payload_bytes.extend_from_slice(payload);
+        // The payload is copied into the scratch buffer so that it can be encoded and masked
+        // without modifying the caller's buffer. Once any large payloads that have been split off
+        // from it have been written, its allocation is reclaimed by the next frame.
+        scratch.clear();
+        scratch.extend_from_slice(payload_ref.as_ref());
 
         if let OpCode::DataCode(data_code) = opcode {
-            extension_encode(
-                &mut payload_bytes,
-                extension,
-                &mut header_flags,
-                data_code.into(),
-            )?;
+            extension_encode(scratch, extension, &mut header_flags, data_code.into())?;
         }
 
         let mask = if is_server {
             None
         } else {
             let mask = rand.gen();
-            apply_mask(mask, payload_bytes.as_mut());
+            apply_mask(mask, scratch.as_mut());
             Some(mask)
         };
 
@@ -400,22 +645,229 @@ impl FramedWrite {
         );
 
         FrameHeader::write_into(
-            write_buffer,
+            write_buffer.tail_mut(),
             opcode,
             header_flags,
             mask,
-            payload_bytes.len(),
+            scratch.len(),
+        );
+        write_buffer.put_payload_from(scratch);
+
+        Ok(())
+    }
+
+    /// Returns an error if a fragmented message is being written, as no other data messages may be
+    /// written until its final fragment has been written.
+    pub fn check_not_fragmenting(&self) -> Result<(), Error> {
+        if self.fragmenting {
+            Err(ProtocolError::ContinuationAlreadyStarted.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Encodes the next fragment of a data message into the write buffer. The first fragment is
+    /// encoded as a `message_type` frame and any subsequent fragments are encoded as continuation
+    /// frames until `is_final` is set. No IO is performed.
+    pub fn buffer_fragment<A, F>(
+        &mut self,
+        is_server: bool,
+        message_type: MessageType,
+        payload_ref: A,
+        is_final: bool,
+        extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        let data_code = if self.fragmenting {
+            DataCode::Continuation
+        } else {
+            message_type.into()
+        };
+        let flags = if is_final {
+            HeaderFlags::FIN
+        } else {
+            HeaderFlags::empty()
+        };
+
+        self.buffer_frame(
+            is_server,
+            OpCode::DataCode(data_code),
+            flags,
+            payload_ref,
+            extension,
+        )?;
+        self.fragmenting = !is_final;
+        Ok(())
+    }
+
+    /// Encodes a data message into the write buffer. If the message is larger than the automatic
+    /// fragmentation threshold then it is encoded as a fragmented message. No IO is performed.
+    pub fn buffer_message<A, F>(
+        &mut self,
+        is_server: bool,
+        message_type: MessageType,
+        payload_ref: A,
+        mut extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        self.check_not_fragmenting()?;
+        let payload = payload_ref.as_ref();
+
+        match self.auto_fragment_above {
+            Some(fragment_size) if payload.len() > fragment_size => {
+                for (opcode, flags, fragment) in fragments(payload, message_type, fragment_size) {
+                    self.buffer_frame(is_server, opcode, flags, fragment, &mut extension)?;
+                }
+                Ok(())
+            }
+            _ => self.buffer_frame(
+                is_server,
+                OpCode::DataCode(message_type.into()),
+                HeaderFlags::FIN,
+                payload,
+                extension,
+            ),
+        }
+    }
+
+    /// Encodes a frame into the write buffer without copying, masking or encoding `payload`. This
+    /// must only be used by a server and for payloads which do not need to be encoded by an
+    /// extension. No IO is performed.
+    pub fn buffer_owned_frame(
+        &mut self,
+        opcode: OpCode,
+        header_flags: HeaderFlags,
+        payload: Bytes,
+    ) {
+        let FramedWrite { write_buffer, .. } = self;
+
+        trace!(
+            "Writing frame: {}",
+            BorrowedFramePrinter::new(&opcode, &header_flags, &None),
         );
 
-        io.write_all(write_buffer).await?;
-        write_buffer.clear();
+        FrameHeader::write_into(
+            write_buffer.tail_mut(),
+            opcode,
+            header_flags,
+            None,
+            payload.len(),
+        );
+        write_buffer.put_payload(payload);
+    }
 
-        io.write_all(payload_bytes.as_ref()).await?;
-        io.flush().await.map_err(Into::into)
+    /// Encodes a data message into the write buffer without copying, masking or encoding it,
+    /// fragmenting it if it is larger than the automatic fragmentation threshold. This has the same
+    /// requirements as `buffer_owned_frame`. No IO is performed.
+    pub fn buffer_owned_message(
+        &mut self,
+        message_type: MessageType,
+        payload: Bytes,
+    ) -> Result<(), Error> {
+        self.check_not_fragmenting()?;
+        match self.auto_fragment_above {
+            Some(fragment_size) if payload.len() > fragment_size => {
+                for (opcode, flags, fragment) in fragments(&payload, message_type, fragment_size) {
+                    self.buffer_owned_frame(opcode, flags, payload.slice_ref(fragment));
+                }
+            }
+            _ => self.buffer_owned_frame(
+                OpCode::DataCode(message_type.into()),
+                HeaderFlags::FIN,
+                payload,
+            ),
+        }
+        Ok(())
+    }
+
+    /// Encodes a prepared message into the write buffer. A server writes the prepared frame without
+    /// copying it, while a client copies and masks its payload. No IO is performed.
+    pub fn buffer_prepared(
+        &mut self,
+        is_server: bool,
+        prepared: &PreparedMessage,
+    ) -> Result<(), Error> {
+        self.check_not_fragmenting()?;
+
+        if is_server {
+            trace!(
+                "Writing frame: {}",
+                BorrowedFramePrinter::new(&prepared.opcode(), &prepared.header_flags(), &None),
+            );
+            self.write_buffer.put_payload(prepared.frame().clone());
+            Ok(())
+        } else {
+            self.buffer_frame(
+                is_server,
+                prepared.opcode(),
+                prepared.header_flags(),
+                prepared.payload(),
+                |_payload, _header| Ok(()),
+            )
+        }
+    }
+
+    /// Removes the next chunk of encoded frames from the write buffer, for completion-based IO
+    /// which requires ownership of the buffer being written.
+    #[cfg(feature = "completion")]
+    pub fn pop_chunk(&mut self) -> Option<Bytes> {
+        self.write_buffer.pop_chunk()
+    }
+
+    /// Attempts to write all of the buffered frames to `io`. This does not flush `io`.
+    pub fn poll_write_buffer<I>(
+        &mut self,
+        cx: &mut Context<'_>,
+        io: &mut I,
+    ) -> Poll<Result<(), Error>>
+    where
+        I: AsyncWrite + Unpin,
+    {
+        let FramedWrite { write_buffer, .. } = self;
+
+        while !write_buffer.is_empty() {
+            // This uses vectored writes, when supported by `io`, so that large payloads are written
+            // without being copied alongside their headers.
+            let n = ready!(poll_write_buf(Pin::new(&mut *io), cx, write_buffer))?;
+            if n == 0 {
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::WriteZero).into()
+                ));
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Attempts to write all of the buffered frames to `io` and then flush it.
+    pub fn poll_flush<I>(&mut self, cx: &mut Context<'_>, io: &mut I) -> Poll<Result<(), Error>>
+    where
+        I: AsyncWrite + Unpin,
+    {
+        ready!(self.poll_write_buffer(cx, io))?;
+        ready!(Pin::new(io).poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Attempts to flush `io` if the write buffer has reached the autoflush threshold, or if no
+    /// threshold has been set.
+    pub fn poll_autoflush<I>(&mut self, cx: &mut Context<'_>, io: &mut I) -> Poll<Result<(), Error>>
+    where
+        I: AsyncWrite + Unpin,
+    {
+        match self.autoflush_threshold {
+            Some(threshold) if self.write_buffer.remaining() < threshold => Poll::Ready(Ok(())),
+            _ => self.poll_flush(cx, io),
+        }
     }
 }
 
-#[cfg(feature = "split")]
 pub struct FramedIoParts<I> {
     pub io: I,
     pub reader: FramedRead,
@@ -434,7 +886,6 @@ pub struct FramedIo<I> {
 }
 
 impl<I> FramedIo<I> {
-    #[cfg(feature = "split")]
     pub fn from_parts(parts: FramedIoParts<I>) -> FramedIo<I> {
         let FramedIoParts {
             io,
@@ -452,7 +903,6 @@ impl<I> FramedIo<I> {
         }
     }
 
-    #[cfg(feature = "split")]
     pub fn into_parts(self) -> FramedIoParts<I> {
         let FramedIo {
             io,
@@ -494,6 +944,45 @@ impl<I> FramedIo<I> {
     pub fn is_server(&self) -> bool {
         self.flags.contains(CodecFlags::ROLE)
     }
+
+    /// Sets the maximum payload size of a single received frame. If unset then frames are only
+    /// limited by the maximum message size.
+    pub fn set_max_frame_size(&mut self, max_frame_size: Option<usize>) {
+        self.reader.max_frame_size = max_frame_size.unwrap_or(usize::MAX);
+    }
+
+    /// Sets the bitmask of the reserved opcodes that have been claimed by the negotiated extension.
+    /// Frames with these opcodes are passed to the extension rather than failing the connection.
+    pub fn set_reserved_opcodes(&mut self, reserved_opcodes: u16) {
+        self.reader.reserved_opcodes = reserved_opcodes;
+    }
+
+    /// Sets the number of buffered bytes at which data messages are automatically flushed. If unset
+    /// then every data message is flushed as soon as it has been written.
+    pub fn set_autoflush_threshold(&mut self, autoflush_threshold: Option<usize>) {
+        self.writer.autoflush_threshold = autoflush_threshold;
+    }
+
+    /// Sets the payload size above which data messages are sent as fragmented messages. A size
+    /// of zero is ignored.
+    pub fn set_auto_fragment_above(&mut self, auto_fragment_above: Option<usize>) {
+        self.writer.auto_fragment_above = auto_fragment_above.filter(|size| *size > 0);
+    }
+
+    /// Sets the duration after which a read operation fails with `TimeoutCause::Read`.
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.reader.read_timeout = read_timeout;
+    }
+
+    /// Sets the duration after which writing and flushing the buffered frames fails with
+    /// `TimeoutCause::Write`.
+    pub fn set_write_timeout(&mut self, write_timeout: Option<Duration>) {
+        self.writer.write_timeout = write_timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.reader.read_timeout
+    }
 }
 
 impl<I> FramedIo<I>
@@ -501,8 +990,132 @@ where
     I: WebSocketStream,
 {
     pub async fn flush(&mut self) -> Result<(), Error> {
-        self.io.flush().await?;
-        Ok(())
+        let FramedIo { io, writer, .. } = self;
+        writer.flush(io).await
+    }
+
+    pub async fn autoflush(&mut self) -> Result<(), Error> {
+        let FramedIo { io, writer, .. } = self;
+        writer.autoflush(io).await
+    }
+
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let FramedIo { io, writer, .. } = self;
+        writer.poll_flush(cx, io)
+    }
+
+    pub fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let FramedIo { io, writer, .. } = self;
+        writer.poll_write_buffer(cx, io)
+    }
+
+    pub fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(Pin::new(&mut self.io).poll_shutdown(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    pub fn buffer<A, F>(
+        &mut self,
+        opcode: OpCode,
+        header_flags: HeaderFlags,
+        payload_ref: A,
+        extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        let FramedIo { writer, flags, .. } = self;
+        writer.buffer_frame(
+            flags.contains(CodecFlags::ROLE),
+            opcode,
+            header_flags,
+            payload_ref,
+            extension,
+        )
+    }
+
+    pub fn buffer_message<A, F>(
+        &mut self,
+        message_type: MessageType,
+        payload_ref: A,
+        extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        let FramedIo { writer, flags, .. } = self;
+        writer.buffer_message(
+            flags.contains(CodecFlags::ROLE),
+            message_type,
+            payload_ref,
+            extension,
+        )
+    }
+
+    pub fn buffer_owned_message(
+        &mut self,
+        message_type: MessageType,
+        payload: Bytes,
+    ) -> Result<(), Error> {
+        self.writer.buffer_owned_message(message_type, payload)
+    }
+
+    pub fn buffer_fragment<A, F>(
+        &mut self,
+        message_type: MessageType,
+        payload_ref: A,
+        is_final: bool,
+        extension: F,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+        F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
+    {
+        let FramedIo { writer, flags, .. } = self;
+        writer.buffer_fragment(
+            flags.contains(CodecFlags::ROLE),
+            message_type,
+            payload_ref,
+            is_final,
+            extension,
+        )
+    }
+
+    pub fn buffer_prepared(&mut self, prepared: &PreparedMessage) -> Result<(), Error> {
+        let FramedIo { writer, flags, .. } = self;
+        writer.buffer_prepared(flags.contains(CodecFlags::ROLE), prepared)
+    }
+
+    pub fn buffer_close(&mut self, reason: CloseReason) -> Result<(), Error> {
+        self.buffer(
+            OpCode::ControlCode(ControlCode::Close),
+            HeaderFlags::FIN,
+            close_payload(reason)?,
+            |_, _| Ok(()),
+        )
+    }
+
+    pub(crate) fn poll_read_next<E>(
+        &mut self,
+        cx: &mut Context<'_>,
+        read_into: &mut BytesMut,
+        extension: &mut E,
+        streaming: bool,
+    ) -> Poll<Result<Item, Error>>
+    where
+        E: ExtensionDecoder,
+    {
+        let FramedIo {
+            io,
+            reader,
+            flags,
+            max_message_size,
+            ..
+        } = self;
+        let props = read_props(flags, *max_message_size, streaming);
+        reader.poll_read(cx, io, flags, read_into, extension, props)
     }
 
     pub async fn write<A, F>(
@@ -531,6 +1144,7 @@ where
             .await
     }
 
+    #[cfg(test)]
     pub(crate) async fn read_next<E>(
         &mut self,
         read_into: &mut BytesMut,
@@ -539,6 +1153,10 @@ where
     where
         E: ExtensionDecoder,
     {
+        poll_fn(|cx| self.poll_read_next(cx, read_into, extension, false)).await
+    }
+
+    pub async fn read_frame(&mut self) -> Result<(FrameHeader, BytesMut), Error> {
         let FramedIo {
             io,
             reader,
@@ -546,7 +1164,50 @@ where
             max_message_size,
             ..
         } = self;
-        read_next(io, reader, flags, *max_message_size, read_into, extension).await
+        let ReadProps {
+            is_server,
+            rsv_bits,
+            ..
+        } = read_props(flags, *max_message_size, false);
+
+        poll_fn(|cx| reader.poll_read_frame(cx, io, is_server, rsv_bits, *max_message_size)).await
+    }
+
+    /// Attempts to flush the write buffer and, while doing so, answers any pings that are received
+    /// from the peer.
+    pub fn poll_flush_answering_pings(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let FramedIo {
+            io,
+            reader,
+            writer,
+            flags,
+            max_message_size,
+        } = self;
+        let ReadProps {
+            is_server,
+            rsv_bits,
+            ..
+        } = read_props(flags, *max_message_size, false);
+
+        while let Poll::Ready(result) =
+            reader.poll_read_ping(cx, io, is_server, rsv_bits, *max_message_size)
+        {
+            match result? {
+                Some(payload) => {
+                    trace!("Received a ping frame while writing. Responding with pong");
+                    writer.buffer_frame(
+                        is_server,
+                        OpCode::ControlCode(ControlCode::Pong),
+                        HeaderFlags::FIN,
+                        payload,
+                        |_, _| Ok(()),
+                    )?;
+                }
+                None => break,
+            }
+        }
+
+        writer.poll_flush(cx, io)
     }
 
     pub async fn write_close(&mut self, reason: CloseReason) -> Result<(), Error> {
@@ -561,25 +1222,24 @@ where
         buf: A,
         message_type: MessageType,
         fragment_size: usize,
-        extension: F,
+        mut extension: F,
     ) -> Result<(), Error>
     where
         A: AsRef<[u8]>,
         F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
     {
-        let FramedIo {
-            io, writer, flags, ..
-        } = self;
-        write_fragmented(
-            io,
-            writer,
-            buf,
-            message_type,
-            fragment_size,
-            flags.contains(CodecFlags::ROLE),
-            extension,
-        )
-        .await
+        self.writer.check_not_fragmenting()?;
+        let write_timeout = self.writer.write_timeout;
+        for (opcode, flags, payload) in fragments(buf.as_ref(), message_type, fragment_size) {
+            self.buffer(opcode, flags, payload, &mut extension)?;
+            with_timeout(
+                write_timeout,
+                TimeoutCause::Write,
+                poll_fn(|cx| self.poll_flush_answering_pings(cx)),
+            )
+            .await?;
+        }
+        Ok(())
     }
 
     pub async fn shutdown(&mut self) -> Result<(), Error> {
@@ -588,28 +1248,45 @@ where
     }
 }
 
-pub async fn read_next<I, E>(
-    io: &mut I,
-    reader: &mut FramedRead,
-    flags: &mut CodecFlags,
-    max_message_size: usize,
-    read_into: &mut BytesMut,
-    extension: &mut E,
-) -> Result<Item, Error>
+/// Awaits `future`, failing with a timeout error of `cause` if it does not complete within
+/// `timeout`. The future must retain any progress that it has made so that the operation may be
+/// retried after it has timed out.
+pub async fn with_timeout<F, T>(
+    timeout: Option<Duration>,
+    cause: TimeoutCause,
+    future: F,
+) -> Result<T, Error>
 where
-    I: AsyncRead + Unpin,
-    E: ExtensionDecoder,
+    F: Future<Output = Result<T, Error>>,
 {
-    let rsv_bits = flags.bits() & 0x70;
-    let is_server = flags.contains(CodecFlags::ROLE);
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or_else(|_| Err(cause.into())),
+        None => future.await,
+    }
+}
 
-    let props = ReadProps {
-        is_server,
-        rsv_bits,
+pub fn read_props(flags: &CodecFlags, max_message_size: usize, streaming: bool) -> ReadProps {
+    ReadProps {
+        is_server: flags.contains(CodecFlags::ROLE),
+        rsv_bits: flags.bits() & 0x70,
         max_message_size,
-    };
+        streaming,
+    }
+}
+
+pub fn close_payload(reason: CloseReason) -> Result<Vec<u8>, Error> {
+    reason.validate()?;
+
+    let CloseReason { code, description } = reason;
+    let mut payload = u16::from(code).to_be_bytes().to_vec();
+
+    if let Some(description) = description {
+        payload.extend_from_slice(description.as_bytes());
+    }
 
-    reader.read(io, flags, read_into, extension, props).await
+    Ok(payload)
 }
 
 pub async fn write_close<I>(
@@ -621,88 +1298,42 @@ pub async fn write_close<I>(
 where
     I: AsyncWrite + Unpin,
 {
-    let CloseReason { code, description } = reason;
-    let mut payload = u16::from(code).to_be_bytes().to_vec();
-
-    if let Some(description) = description {
-        payload.extend_from_slice(description.as_bytes());
-    }
-
     writer
         .write(
             io,
             is_server,
             OpCode::ControlCode(ControlCode::Close),
             HeaderFlags::FIN,
-            payload,
+            close_payload(reason)?,
             |_, _| Ok(()),
         )
         .await
 }
 
-pub async fn write_fragmented<A, I, F>(
-    io: &mut I,
-    framed: &mut FramedWrite,
-    buf_ref: A,
+/// Splits `payload` into the frames of a fragmented message, with each fragment carrying at most
+/// `fragment_size` bytes.
+///
+/// # Panics
+/// Panics if `fragment_size` is zero.
+pub fn fragments(
+    payload: &[u8],
     message_type: MessageType,
     fragment_size: usize,
-    is_server: bool,
-    mut extension: F,
-) -> Result<(), Error>
-where
-    A: AsRef<[u8]>,
-    I: AsyncWrite + Unpin,
-    F: FnMut(&mut BytesMut, &mut ExtFrameHeader) -> Result<(), Error>,
-{
-    let buf = buf_ref.as_ref();
-    let mut chunks = buf.chunks(fragment_size).peekable();
-    match chunks.next() {
-        Some(payload) => {
-            let payload_type = match message_type {
-                MessageType::Text => DataCode::Text,
-                MessageType::Binary => DataCode::Binary,
-            };
-
-            let flags = if chunks.peek().is_none() {
-                HeaderFlags::FIN
-            } else {
-                HeaderFlags::empty()
-            };
-
-            framed
-                .write(
-                    io,
-                    is_server,
-                    OpCode::DataCode(payload_type),
-                    flags,
-                    payload,
-                    &mut extension,
-                )
-                .await?;
-        }
-        None => return Ok(()),
-    }
+) -> impl Iterator<Item = (OpCode, HeaderFlags, &[u8])> {
+    let mut data_code = DataCode::from(message_type);
+    let mut chunks = payload.chunks(fragment_size).peekable();
 
-    while let Some(payload) = chunks.next() {
+    std::iter::from_fn(move || {
+        let chunk = chunks.next()?;
         let flags = if chunks.peek().is_none() {
             HeaderFlags::FIN
         } else {
             HeaderFlags::empty()
         };
+        let opcode = OpCode::DataCode(std::mem::replace(&mut data_code, DataCode::Continuation));
 
-        framed
-            .write(
-                io,
-                is_server,
-                OpCode::DataCode(DataCode::Continuation),
-                flags,
-                payload,
-                &mut extension,
-            )
-            .await?;
-    }
-
-    Ok(())
+        Some((opcode, flags, chunk))
+    })
 }
 
 #[inline]
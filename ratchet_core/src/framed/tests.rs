@@ -14,14 +14,18 @@
 
 use crate::errors::{Error, ProtocolError};
 use crate::ext::NoExt;
-use crate::framed::{CodecFlags, FramedIo, Item};
-use crate::protocol::{CloseCode, CloseCodeParseErr, CloseReason, DataCode, OpCode};
+use crate::framed::{CodecFlags, FramedIo, FramedWrite, Item};
+use crate::protocol::{CloseCode, CloseCodeParseErr, CloseReason, DataCode, MessageType, OpCode};
 use crate::protocol::{HeaderFlags, Role};
 use crate::test_fixture::{expect_err, EmptyIo, MirroredIo};
-use bytes::BytesMut;
+use bytes::{Buf, Bytes, BytesMut};
 use std::error::Error as StdError;
 use std::fmt::Debug;
+use std::io::IoSlice;
 use std::iter::FromIterator;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 #[tokio::test]
 async fn frame_text() {
@@ -259,3 +263,184 @@ async fn close() {
         CloseCodeParseErr(0).to_string()
     );
 }
+
+/// Writer which supports vectored writes but only accepts a limited number of bytes per write.
+#[derive(Default)]
+struct VectoredIo {
+    written: BytesMut,
+    vectored_writes: usize,
+}
+
+impl AsyncRead for VectoredIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for VectoredIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        this.vectored_writes += 1;
+
+        let mut remaining = 1500;
+        for buf in bufs {
+            let len = buf.len().min(remaining);
+            this.written.extend_from_slice(&buf[..len]);
+            remaining -= len;
+        }
+        Poll::Ready(Ok(1500 - remaining))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn vectored_write() {
+    let mut framed = FramedIo::new(
+        VectoredIo::default(),
+        BytesMut::default(),
+        Role::Server,
+        usize::MAX,
+        0,
+    );
+
+    let large = (0..4096_u32).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+    framed
+        .buffer(
+            OpCode::DataCode(DataCode::Text),
+            HeaderFlags::FIN,
+            "small",
+            |_, _| Ok(()),
+        )
+        .unwrap();
+    framed
+        .buffer(
+            OpCode::DataCode(DataCode::Binary),
+            HeaderFlags::FIN,
+            &large,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+    framed.flush().await.unwrap();
+
+    let VectoredIo {
+        written,
+        vectored_writes,
+    } = framed.into_parts().io;
+    assert_eq!(vectored_writes, 3);
+
+    let mut framed = FramedIo::new(EmptyIo, written, Role::Client, usize::MAX, 0);
+    let mut out = BytesMut::new();
+
+    ok_eq(framed.read_next(&mut out, &mut NoExt).await, Item::Text);
+    assert_eq!(out.split().as_ref(), b"small");
+
+    ok_eq(framed.read_next(&mut out, &mut NoExt).await, Item::Binary);
+    assert_eq!(out.as_ref(), large.as_slice());
+}
+
+#[test]
+fn owned_payload_not_copied() {
+    let payload = Bytes::from(vec![7; 4096]);
+    let mut writer = FramedWrite::default();
+    writer
+        .buffer_owned_message(MessageType::Binary, payload.clone())
+        .expect("Buffer failure");
+
+    let mut slices = [IoSlice::new(&[]); 4];
+    assert_eq!(writer.write_buffer.chunks_vectored(&mut slices), 2);
+    assert_eq!(slices[0].as_ref(), &[130, 126, 16, 0]);
+    assert_eq!(slices[1].as_ptr(), payload.as_ptr());
+}
+
+#[tokio::test]
+async fn reuses_scratch_buffer() {
+    let payload = [1, 2, 3, 4];
+    let mut writer = FramedWrite::default();
+
+    for _ in 0..2 {
+        writer
+            .buffer_frame(
+                false,
+                OpCode::DataCode(DataCode::Binary),
+                HeaderFlags::FIN,
+                payload,
+                |_, _| Ok(()),
+            )
+            .unwrap();
+    }
+
+    let scratch = writer.scratch.as_ptr();
+    writer
+        .buffer_frame(
+            false,
+            OpCode::DataCode(DataCode::Binary),
+            HeaderFlags::FIN,
+            payload,
+            |_, _| Ok(()),
+        )
+        .unwrap();
+    assert_eq!(writer.scratch.as_ptr(), scratch);
+
+    let remaining = writer.write_buffer.remaining();
+    let written = BytesMut::from(writer.write_buffer.copy_to_bytes(remaining).as_ref());
+    let mut framed = FramedIo::new(EmptyIo, written, Role::Server, usize::MAX, 0);
+
+    for _ in 0..3 {
+        let mut out = BytesMut::new();
+        ok_eq(framed.read_next(&mut out, &mut NoExt).await, Item::Binary);
+        assert_eq!(out.as_ref(), payload);
+    }
+}
+
+#[test]
+fn utf8_validator() {
+    use crate::framed::utf8::Utf8Validator;
+
+    let text = "κόσμε€𝄞".as_bytes();
+    for split in 0..=text.len() {
+        let mut validator = Utf8Validator::default();
+        validator.feed(&text[..split], false).unwrap();
+        validator.feed(&text[split..], true).unwrap();
+    }
+
+    let mut validator = Utf8Validator::default();
+    validator.feed(&[0xf0, 0x9d], false).unwrap();
+    assert!(validator.feed(&[0x84], true).unwrap_err().is_encoding());
+
+    let mut validator = Utf8Validator::default();
+    validator.feed(&[0xe2], false).unwrap();
+    assert!(validator
+        .feed(&[0x28, 0xa1], false)
+        .unwrap_err()
+        .is_encoding());
+
+    let mut validator = Utf8Validator::default();
+    assert!(validator.feed(&[0xff], false).unwrap_err().is_encoding());
+}
@@ -0,0 +1,85 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::{Error, ErrorKind, ProtocolError};
+
+#[cfg(feature = "simdutf8")]
+use simdutf8::compat::from_utf8;
+#[cfg(not(feature = "simdutf8"))]
+use std::str::from_utf8;
+
+/// Incrementally validates that the payload of a text message is valid UTF-8. A code point may be
+/// split across fragments and so any incomplete code point at the end of a fragment is retained
+/// until the next fragment is received.
+#[derive(Debug, Default)]
+pub struct Utf8Validator {
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl Utf8Validator {
+    /// Discards any incomplete code point from the previous message.
+    pub fn reset(&mut self) {
+        self.partial_len = 0;
+    }
+
+    /// Validates the next fragment of a message. If `fin` is set then the message must not end
+    /// with an incomplete code point.
+    pub fn feed(&mut self, mut bytes: &[u8], fin: bool) -> Result<(), Error> {
+        let Utf8Validator {
+            partial,
+            partial_len,
+        } = self;
+
+        if *partial_len > 0 {
+            let width = code_point_width(partial[0]);
+            let take = (width - *partial_len).min(bytes.len());
+
+            partial[*partial_len..*partial_len + take].copy_from_slice(&bytes[..take]);
+            *partial_len += take;
+            bytes = &bytes[take..];
+
+            match from_utf8(&partial[..*partial_len]) {
+                Ok(_) => *partial_len = 0,
+                Err(e) if e.error_len().is_none() && !fin => return Ok(()),
+                Err(_) => return Err(encoding_error()),
+            }
+        }
+
+        match from_utf8(bytes) {
+            Ok(_) => Ok(()),
+            Err(e) if e.error_len().is_none() && !fin => {
+                let remainder = &bytes[e.valid_up_to()..];
+                partial[..remainder.len()].copy_from_slice(remainder);
+                *partial_len = remainder.len();
+                Ok(())
+            }
+            Err(_) => Err(encoding_error()),
+        }
+    }
+}
+
+/// Returns the length of a code point from its leading byte. This is only called with the first
+/// byte of a valid but incomplete code point.
+fn code_point_width(byte: u8) -> usize {
+    match byte {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    }
+}
+
+fn encoding_error() -> Error {
+    Error::with_cause(ErrorKind::Encoding, ProtocolError::Encoding)
+}
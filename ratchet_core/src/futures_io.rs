@@ -0,0 +1,222 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for streams which implement the `futures::io` traits, such as those provided by the
+//! async-std and smol runtimes.
+//!
+//! The handshake functions in this module accept any stream that implements
+//! `futures::io::{AsyncRead, AsyncWrite}` and wrap it in a [`FuturesIo`] adapter, so no
+//! compatibility wrappers are required by the caller.
+
+use crate::{
+    Error, SubprotocolRegistry, TryIntoRequest, UpgradedClient, WebSocketConfig, WebSocketUpgrader,
+};
+use futures::io::{AsyncRead as FuturesRead, AsyncWrite as FuturesWrite};
+use ratchet_ext::ExtensionProvider;
+use std::io;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// An adapter which implements Tokio's IO traits for a stream that implements the `futures::io`
+/// traits.
+#[derive(Debug)]
+pub struct FuturesIo<S> {
+    inner: S,
+}
+
+impl<S> FuturesIo<S> {
+    /// Wraps `inner` in an adapter.
+    pub fn new(inner: S) -> FuturesIo<S> {
+        FuturesIo { inner }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes this adapter, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> AsyncRead for FuturesIo<S>
+where
+    S: FuturesRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let read = ready!(Pin::new(&mut self.inner).poll_read(cx, buf.initialize_unfilled()))?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for FuturesIo<S>
+where
+    S: FuturesWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        // `futures::io::AsyncWrite` always provides a vectored write, falling back to writing the
+        // first non-empty buffer.
+        true
+    }
+}
+
+/// Execute a WebSocket client handshake on `stream`, opting for no compression on messages and no
+/// subprotocol. See [`crate::subscribe`].
+pub async fn subscribe<S, R>(
+    config: WebSocketConfig,
+    stream: S,
+    request: R,
+) -> Result<UpgradedClient<FuturesIo<S>, crate::NoExt>, Error>
+where
+    S: FuturesRead + FuturesWrite + Send + Unpin,
+    R: TryIntoRequest,
+{
+    crate::subscribe(config, FuturesIo::new(stream), request).await
+}
+
+/// Execute a WebSocket client handshake on `stream`, attempting to negotiate the extension and a
+/// subprotocol. See [`crate::subscribe_with`].
+pub async fn subscribe_with<S, E, R>(
+    config: WebSocketConfig,
+    stream: S,
+    request: R,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+) -> Result<UpgradedClient<FuturesIo<S>, E::Extension>, Error>
+where
+    S: FuturesRead + FuturesWrite + Send + Unpin,
+    E: ExtensionProvider,
+    R: TryIntoRequest,
+{
+    crate::subscribe_with(
+        config,
+        FuturesIo::new(stream),
+        request,
+        extension,
+        subprotocols,
+    )
+    .await
+}
+
+/// Execute a server handshake on the provided stream, opting for no compression on messages and no
+/// subprotocol. See [`crate::accept`].
+pub async fn accept<S>(
+    stream: S,
+    config: WebSocketConfig,
+) -> Result<WebSocketUpgrader<FuturesIo<S>, crate::NoExt>, Error>
+where
+    S: FuturesRead + FuturesWrite + Send + Unpin,
+{
+    crate::accept(FuturesIo::new(stream), config).await
+}
+
+/// Execute a server handshake on the provided stream, attempting to negotiate the extension and
+/// subprotocols provided. See [`crate::accept_with`].
+pub async fn accept_with<S, E>(
+    stream: S,
+    config: WebSocketConfig,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+) -> Result<WebSocketUpgrader<FuturesIo<S>, E::Extension>, Error>
+where
+    S: FuturesRead + FuturesWrite + Send + Unpin,
+    E: ExtensionProvider,
+{
+    crate::accept_with(FuturesIo::new(stream), config, extension, subprotocols).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::futures_io::{accept, subscribe};
+    use crate::{Message, WebSocketConfig};
+    use bytes::BytesMut;
+    use futures::future::join;
+    use tokio::io::duplex;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    #[tokio::test]
+    async fn handshake_and_messages() {
+        let (server, client) = duplex(1024);
+
+        let server = async move {
+            let upgrader = accept(server.compat(), WebSocketConfig::default())
+                .await
+                .expect("Accept failure");
+            let mut websocket = upgrader.upgrade().await.expect("Upgrade failure").websocket;
+
+            let mut buf = BytesMut::new();
+            let message = websocket.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            websocket.write_text("pong").await.expect("Write failure");
+            assert_eq!(buf.as_ref(), b"ping");
+        };
+        let client = async move {
+            let mut websocket = subscribe(
+                WebSocketConfig::default(),
+                client.compat(),
+                "ws://127.0.0.1/",
+            )
+            .await
+            .expect("Subscribe failure")
+            .websocket;
+
+            websocket.write_text("ping").await.expect("Write failure");
+
+            let mut buf = BytesMut::new();
+            let message = websocket.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            assert_eq!(buf.as_ref(), b"pong");
+        };
+
+        join(server, client).await;
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::errors::Error;
+use bytes::BytesMut;
+use http::header::COOKIE;
+use http::{HeaderMap, HeaderValue, Uri};
+use std::fmt::Debug;
+
+/// A store of cookies which are attached to a client's upgrade requests and which is updated from
+/// the `Set-Cookie` headers in the server's responses.
+pub trait CookieStore: Debug + Send + Sync {
+    /// Store the cookies in the `Set-Cookie` headers of a response to a request for `uri`.
+    fn set_cookies(&self, uri: &Uri, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>);
+
+    /// Returns the value of the `Cookie` header to send in a request for `uri`, if any cookies are
+    /// stored for it.
+    fn cookies(&self, uri: &Uri) -> Option<HeaderValue>;
+}
+
+/// Adds `cookies` to the `Cookie` header in `headers`. A request must only contain a single
+/// `Cookie` header and so the cookies are joined to any that already exist. RFC 6265 5.4.
+pub(crate) fn add_cookies(headers: &mut HeaderMap, cookies: &HeaderValue) -> Result<(), Error> {
+    let value = match headers.get(COOKIE) {
+        Some(existing) => {
+            let mut joined = BytesMut::from(existing.as_bytes());
+            joined.extend_from_slice(b"; ");
+            joined.extend_from_slice(cookies.as_bytes());
+            HeaderValue::from_maybe_shared(joined.freeze())?
+        }
+        None => cookies.clone(),
+    };
+    headers.insert(COOKIE, value);
+    Ok(())
+}
+
+/// Stores the cookies in `set_cookies`, which were set in the response to a request for `uri`.
+pub(crate) fn store_cookies(
+    cookie_store: Option<&dyn CookieStore>,
+    uri: &Uri,
+    set_cookies: &[HeaderValue],
+) {
+    if let Some(store) = cookie_store {
+        if !set_cookies.is_empty() {
+            store.set_cookies(uri, &mut set_cookies.iter());
+        }
+    }
+}
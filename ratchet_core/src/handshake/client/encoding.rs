@@ -16,7 +16,7 @@ use base64::Engine;
 use bytes::BytesMut;
 use http::header::{HOST, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL};
 use http::request::Parts;
-use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Version};
+use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Uri, Version};
 
 use ratchet_ext::ExtensionProvider;
 
@@ -128,17 +128,12 @@ where
             // The request should only contain *one* 'host' header, and it must be a single value,
             // not a comma seperated list. If the request doesn't already have one then derive it
             // from the URI if it contains an authority. If it doesn't, then the request is invalid
-            // and any correct server implementation would reject it - including Ratchet.
-            let authority = uri
-                .authority()
-                .ok_or_else(|| Error::with_cause(ErrorKind::Http, HttpError::MissingAuthority))?
-                .as_str()
-                .to_string();
-            validate_or_insert(
-                &mut headers,
-                header::HOST,
-                HeaderValue::from_str(authority.as_ref())?,
-            )?;
+            // and any correct server implementation would reject it - including Ratchet. A 'host'
+            // header which is already present overrides the one derived from the URI.
+            let host = host_header(&uri)?;
+            if !headers.contains_key(HOST) {
+                headers.insert(HOST, host);
+            }
         }
         Err(e) => {
             error!("Request should only contain one 'host' header. {e}");
@@ -196,6 +191,42 @@ where
     })
 }
 
+/// Returns the value of the `Host` header for a request to `uri`. The port is omitted if it is the
+/// default port of the URI's scheme and an IPv6 host is enclosed in brackets.
+///
+/// Fails with [`HttpError::MissingAuthority`] if `uri` does not have a host.
+pub fn host_header(uri: &Uri) -> Result<HeaderValue, Error> {
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::with_cause(ErrorKind::Http, HttpError::MissingAuthority))?;
+    let port = uri
+        .port_u16()
+        .filter(|port| uri.scheme_str().and_then(default_port) != Some(*port));
+
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    let value = match port {
+        Some(port) => format!("{host}:{port}"),
+        None => host,
+    };
+
+    Ok(HeaderValue::from_str(&value)?)
+}
+
+/// Returns the default port of the `ws`, `wss`, `http` and `https` schemes.
+pub fn default_port(scheme: &str) -> Option<u16> {
+    if scheme.eq_ignore_ascii_case("ws") || scheme.eq_ignore_ascii_case("http") {
+        Some(80)
+    } else if scheme.eq_ignore_ascii_case("wss") || scheme.eq_ignore_ascii_case("https") {
+        Some(443)
+    } else {
+        None
+    }
+}
+
 fn validate_or_insert(
     headers: &mut HeaderMap,
     header_name: HeaderName,
@@ -0,0 +1,133 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::handshake::client::encoding::{build_request, encode_request};
+use crate::handshake::client::{HandshakeResult, Nonce, StreamingResponseParser};
+use crate::{Error, SubprotocolRegistry};
+use bytes::BytesMut;
+use http::Request;
+use ratchet_ext::ExtensionProvider;
+use std::task::Poll;
+use tokio_util::codec::Decoder;
+
+/// A client handshake which performs no IO.
+///
+/// The upgrade request is first encoded with [`ClientHandshakeMachine::encode_request`] and
+/// written to the transport, after which the bytes that are read from the transport are passed to
+/// [`ClientHandshakeMachine::feed_bytes`] until it returns the result of the handshake. Any bytes
+/// that were received after the response are available from
+/// [`ClientHandshakeMachine::into_buffer`]. This allows a handshake to be driven from within an
+/// event loop or over a transport which does not implement the tokio IO traits.
+#[derive(Debug)]
+pub struct ClientHandshakeMachine<E> {
+    nonce: Nonce,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+    strict_extensions: bool,
+    lenient: bool,
+    buf: BytesMut,
+}
+
+impl<E> ClientHandshakeMachine<E>
+where
+    E: ExtensionProvider,
+{
+    /// Constructs a new client handshake which will attempt to negotiate `extension` and a
+    /// subprotocol from `subprotocols`.
+    pub fn new(extension: E, subprotocols: SubprotocolRegistry) -> ClientHandshakeMachine<E> {
+        ClientHandshakeMachine {
+            nonce: [0; 24],
+            extension,
+            subprotocols,
+            strict_extensions: false,
+            lenient: false,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Sets whether the handshake should fail if the server accepts an extension that was not
+    /// offered or which the extension provider could not negotiate. By default, any such
+    /// extensions are ignored.
+    pub fn strict_extensions(mut self, strict_extensions: bool) -> ClientHandshakeMachine<E> {
+        self.strict_extensions = strict_extensions;
+        self
+    }
+
+    /// Sets whether technically malformed responses are tolerated, as described by
+    /// [`WebSocketConfig::lenient_handshake`](crate::WebSocketConfig::lenient_handshake).
+    pub fn lenient(mut self, lenient: bool) -> ClientHandshakeMachine<E> {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Validates `request` and encodes it into `dst` so that it can be written to the server.
+    pub fn encode_request(
+        &mut self,
+        request: Request<()>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Error> {
+        let ClientHandshakeMachine {
+            nonce,
+            extension,
+            subprotocols,
+            ..
+        } = self;
+
+        let request = build_request(request, extension, subprotocols)?;
+        encode_request(dst, request, nonce);
+        Ok(())
+    }
+
+    /// Feeds `bytes` that were read from the transport into the handshake.
+    ///
+    /// Returns `Poll::Pending` if more bytes are required to complete the response, or the result
+    /// of the handshake once the response has been parsed. This should not be called before the
+    /// request has been encoded or again once it has returned `Poll::Ready`.
+    pub fn feed_bytes(
+        &mut self,
+        bytes: &[u8],
+    ) -> Poll<Result<HandshakeResult<E::Extension>, Error>> {
+        let ClientHandshakeMachine {
+            nonce,
+            extension,
+            subprotocols,
+            strict_extensions,
+            lenient,
+            buf,
+        } = self;
+        buf.extend_from_slice(bytes);
+
+        let mut parser = StreamingResponseParser {
+            nonce,
+            extension,
+            subprotocols,
+            strict_extensions: *strict_extensions,
+            lenient: *lenient,
+        };
+
+        match parser.decode(buf) {
+            Ok(Some((result, count))) => {
+                let _ = buf.split_to(count);
+                Poll::Ready(Ok(result))
+            }
+            Ok(None) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    /// Consumes the handshake and returns any bytes which were received after the response. These
+    /// should be used as the read buffer of the upgraded WebSocket.
+    pub fn into_buffer(self) -> BytesMut {
+        self.buf
+    }
+}
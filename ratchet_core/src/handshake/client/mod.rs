@@ -15,24 +15,34 @@
 #[cfg(test)]
 mod tests;
 
+mod cookies;
 mod encoding;
+mod machine;
+mod proxy;
+
+pub use cookies::CookieStore;
+pub(crate) use cookies::{add_cookies, store_cookies};
+pub use encoding::{default_port, host_header};
+pub use machine::ClientHandshakeMachine;
+pub use proxy::Proxy;
 
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use bytes::BytesMut;
-use http::{header, Request, StatusCode, Version};
+use bytes::{Bytes, BytesMut};
+use http::{header, HeaderMap, HeaderValue, Request, StatusCode, Version};
 use httparse::{Response, Status};
 use log::{error, trace};
-use sha1::{Digest, Sha1};
 use std::convert::TryFrom;
 
-use crate::errors::{Error, ErrorKind, HttpError};
+use crate::errors::{Error, ErrorKind, HttpError, Rejection, TimeoutCause};
 use crate::handshake::client::encoding::{build_request, encode_request};
 use crate::handshake::io::BufferedIo;
 use crate::handshake::{
-    validate_header, validate_header_value, ParseResult, StreamingParser, SubprotocolRegistry,
-    TryFromWrapper, ACCEPT_KEY, BAD_STATUS_CODE, UPGRADE_STR, WEBSOCKET_STR,
+    trim_whitespace, validate_header, validate_header_token, validate_header_value, ParseResult,
+    StreamingParser, SubprotocolRegistry, TryFromWrapper, BAD_STATUS_CODE, UPGRADE_STR,
+    WEBSOCKET_STR,
 };
+use crate::protocol::accept_key;
 use crate::{
     NoExt, NoExtProvider, Role, TryIntoRequest, WebSocket, WebSocketConfig, WebSocketStream,
 };
@@ -45,6 +55,7 @@ type Nonce = [u8; 24];
 
 const MSG_HANDSHAKE_COMPLETED: &str = "Client handshake completed";
 const MSG_HANDSHAKE_FAILED: &str = "Client handshake failed";
+const MAX_REJECTION_BODY: usize = 4096;
 
 /// A structure representing an upgraded WebSocket session and an optional subprotocol that was
 /// negotiated during the upgrade.
@@ -54,6 +65,13 @@ pub struct UpgradedClient<S, E> {
     pub websocket: WebSocket<S, E>,
     /// An optional subprotocol that was negotiated during the upgrade.
     pub subprotocol: Option<String>,
+    /// The `Sec-WebSocket-Extensions` header that the server responded with, if an extension was
+    /// negotiated.
+    pub extension_header: Option<HeaderValue>,
+    /// The `Set-Cookie` headers that the server responded with.
+    pub set_cookies: Vec<HeaderValue>,
+    /// The `101 Switching Protocols` response that the server sent.
+    pub response: http::Response<()>,
 }
 
 impl<S, E> UpgradedClient<S, E> {
@@ -78,29 +96,56 @@ where
     let HandshakeResult {
         subprotocol,
         extension,
+        extension_header,
+        set_cookies,
+        response,
     } = exec_client_handshake(
         &mut stream,
         request.try_into_request()?,
         NoExtProvider,
         SubprotocolRegistry::default(),
         &mut read_buffer,
+        false,
+        &config,
     )
     .await?;
 
     Ok(UpgradedClient {
         websocket: WebSocket::from_upgraded(config, stream, extension, read_buffer, Role::Client),
         subprotocol,
+        extension_header,
+        set_cookies,
+        response,
     })
 }
 
 /// Execute a WebSocket client handshake on `stream`, attempting to negotiate the extension and a
 /// subprotocol.
 pub async fn subscribe_with<S, E, R>(
+    config: WebSocketConfig,
+    stream: S,
+    request: R,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+) -> Result<UpgradedClient<S, E::Extension>, Error>
+where
+    S: WebSocketStream,
+    E: ExtensionProvider,
+    R: TryIntoRequest,
+{
+    subscribe_with_options(config, stream, request, extension, subprotocols, false).await
+}
+
+/// Execute a WebSocket client handshake on `stream`. If `strict_extensions` is set then the
+/// handshake fails if the server accepts an extension that the provider did not offer or could not
+/// negotiate.
+pub(crate) async fn subscribe_with_options<S, E, R>(
     config: WebSocketConfig,
     mut stream: S,
     request: R,
     extension: E,
     subprotocols: SubprotocolRegistry,
+    strict_extensions: bool,
 ) -> Result<UpgradedClient<S, E::Extension>, Error>
 where
     S: WebSocketStream,
@@ -111,18 +156,26 @@ where
     let HandshakeResult {
         subprotocol,
         extension,
+        extension_header,
+        set_cookies,
+        response,
     } = exec_client_handshake(
         &mut stream,
         request.try_into_request()?,
         extension,
         subprotocols,
         &mut read_buffer,
+        strict_extensions,
+        &config,
     )
     .await?;
 
     Ok(UpgradedClient {
         websocket: WebSocket::from_upgraded(config, stream, extension, read_buffer, Role::Client),
         subprotocol,
+        extension_header,
+        set_cookies,
+        response,
     })
 }
 
@@ -132,14 +185,24 @@ async fn exec_client_handshake<S, E>(
     extension: E,
     subprotocols: SubprotocolRegistry,
     buf: &mut BytesMut,
+    strict_extensions: bool,
+    config: &WebSocketConfig,
 ) -> Result<HandshakeResult<E::Extension>, Error>
 where
     S: WebSocketStream,
     E: ExtensionProvider,
 {
-    let machine = ClientHandshake::new(stream, subprotocols, &extension, buf);
+    let mut machine = ClientHandshake::new(stream, subprotocols, &extension, buf);
+    machine.strict_extensions = strict_extensions;
+    machine.lenient = config.lenient_handshake;
     let uri = request.uri().to_string();
-    let handshake_result = machine.exec(request).await;
+    let handshake_result = match config.handshake_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, machine.exec(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(TimeoutCause::Handshake.into()),
+        },
+        None => machine.exec(request).await,
+    };
     match &handshake_result {
         Ok(HandshakeResult {
             subprotocol,
@@ -167,12 +230,16 @@ struct ClientHandshake<'s, S, E> {
     nonce: Nonce,
     subprotocols: SubprotocolRegistry,
     extension: &'s E,
+    strict_extensions: bool,
+    lenient: bool,
 }
 
 pub struct StreamingResponseParser<'b, E> {
     nonce: &'b Nonce,
     extension: &'b E,
     subprotocols: &'b mut SubprotocolRegistry,
+    strict_extensions: bool,
+    lenient: bool,
 }
 
 impl<'b, E> Decoder for StreamingResponseParser<'b, E>
@@ -187,12 +254,22 @@ where
             nonce,
             extension,
             subprotocols,
+            strict_extensions,
+            lenient,
         } = self;
 
         let mut headers = [httparse::EMPTY_HEADER; 32];
         let response = Response::new(&mut headers);
 
-        match try_parse_response(buf, response, nonce, extension, subprotocols)? {
+        match try_parse_response(
+            buf,
+            response,
+            nonce,
+            extension,
+            subprotocols,
+            *strict_extensions,
+            *lenient,
+        )? {
             ParseResult::Complete(result, count) => Ok(Some((result, count))),
             ParseResult::Partial(response) => {
                 check_partial_response(&response)?;
@@ -218,6 +295,8 @@ where
             nonce: [0; 24],
             subprotocols,
             extension,
+            strict_extensions: false,
+            lenient: false,
         }
     }
 
@@ -227,6 +306,7 @@ where
             nonce,
             extension,
             subprotocols,
+            ..
         } = self;
 
         trace!("Encoding request: {request:?}");
@@ -250,6 +330,8 @@ where
             nonce,
             subprotocols,
             extension,
+            strict_extensions,
+            lenient,
         } = self;
 
         let parser = StreamingParser::new(
@@ -258,6 +340,8 @@ where
                 nonce,
                 extension,
                 subprotocols,
+                strict_extensions: *strict_extensions,
+                lenient: *lenient,
             },
         );
 
@@ -276,10 +360,20 @@ where
     }
 }
 
+/// The result of a successful client handshake.
 #[derive(Debug)]
 pub struct HandshakeResult<E> {
+    /// An optional subprotocol that was negotiated during the upgrade.
     pub subprotocol: Option<String>,
+    /// The extension that was negotiated during the upgrade, if any.
     pub extension: Option<E>,
+    /// The `Sec-WebSocket-Extensions` header that the server responded with, if an extension was
+    /// negotiated.
+    pub extension_header: Option<HeaderValue>,
+    /// The `Set-Cookie` headers that the server responded with.
+    pub set_cookies: Vec<HeaderValue>,
+    /// The `101 Switching Protocols` response that the server sent.
+    pub response: http::Response<()>,
 }
 
 /// Quickly checks a partial response in the order of the expected HTTP response declaration to see
@@ -305,10 +399,9 @@ fn check_partial_response(response: &Response) -> Result<(), Error> {
             Ok(())
         }
         Some(code) => match StatusCode::try_from(code) {
-            Ok(code) => Err(Error::with_cause(
-                ErrorKind::Http,
-                HttpError::Status(code.as_u16()),
-            )),
+            // This keeps the response parsing going until the headers and body have been read
+            // and then the upgrade will fail with the rejection
+            Ok(_) => Ok(()),
             Err(_) => Err(Error::with_cause(ErrorKind::Http, BAD_STATUS_CODE)),
         },
         None => Ok(()),
@@ -321,16 +414,52 @@ fn try_parse_response<'b, E>(
     expected_nonce: &Nonce,
     extension: E,
     subprotocols: &mut SubprotocolRegistry,
+    strict_extensions: bool,
+    lenient: bool,
 ) -> Result<ParseResult<Response<'b, 'b>, HandshakeResult<E::Extension>>, Error>
 where
     E: ExtensionProvider,
 {
     match response.parse(buffer) {
+        Ok(Status::Complete(count)) if is_rejection(&response) => {
+            let body = &buffer[count..];
+            let body_len = match content_length(response.headers)? {
+                Some(len) if body.len() < len.min(MAX_REJECTION_BODY) => {
+                    return Ok(ParseResult::Partial(response));
+                }
+                Some(len) => len.min(MAX_REJECTION_BODY),
+                None => body.len().min(MAX_REJECTION_BODY),
+            };
+            let response = http::Response::try_from(TryFromWrapper(response))?;
+            let (parts, _) = response.into_parts();
+
+            if parts.status == StatusCode::UPGRADE_REQUIRED {
+                let versions = supported_versions(&parts.headers);
+                if !versions.is_empty() {
+                    return Err(Error::with_cause(
+                        ErrorKind::Http,
+                        HttpError::UnsupportedVersion(versions),
+                    ));
+                }
+            }
+
+            let rejection = Rejection::new(
+                parts.status,
+                parts.headers,
+                Bytes::copy_from_slice(&body[..body_len]),
+            );
+            Err(Error::with_cause(
+                ErrorKind::Http,
+                HttpError::Rejected(Box::new(rejection)),
+            ))
+        }
         Ok(Status::Complete(count)) => parse_response(
             TryFromWrapper(response).try_into()?,
             expected_nonce,
             extension,
             subprotocols,
+            strict_extensions,
+            lenient,
         )
         .map(|r| ParseResult::Complete(r, count)),
         Ok(Status::Partial) => Ok(ParseResult::Partial(response)),
@@ -338,11 +467,56 @@ where
     }
 }
 
+/// Returns whether `response` is a valid HTTP/1.1 response with a status code which rejects the
+/// upgrade rather than switching protocols or redirecting.
+fn is_rejection(response: &Response) -> bool {
+    match (response.version, response.code) {
+        (Some(1), Some(code)) => {
+            code != StatusCode::SWITCHING_PROTOCOLS && !(300..400).contains(&code)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the WebSocket versions listed in the `Sec-WebSocket-Version` headers in `headers`.
+fn supported_versions(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::SEC_WEBSOCKET_VERSION)
+        .iter()
+        .flat_map(|value| value.as_bytes().split(|c| c == &b','))
+        .map(trim_whitespace)
+        .filter(|version| !version.is_empty())
+        .map(|version| String::from_utf8_lossy(version).into_owned())
+        .collect()
+}
+
+fn content_length(headers: &[httparse::Header]) -> Result<Option<usize>, Error> {
+    match headers.iter().find(|header| {
+        header
+            .name
+            .eq_ignore_ascii_case(header::CONTENT_LENGTH.as_str())
+    }) {
+        Some(header) => std::str::from_utf8(header.value)
+            .ok()
+            .and_then(|len| len.trim().parse().ok())
+            .map(Some)
+            .ok_or_else(|| {
+                Error::with_cause(
+                    ErrorKind::Http,
+                    HttpError::InvalidHeader(header::CONTENT_LENGTH),
+                )
+            }),
+        None => Ok(None),
+    }
+}
+
 fn parse_response<E>(
     response: http::Response<()>,
     expected_nonce: &Nonce,
     extension: E,
     subprotocols: &SubprotocolRegistry,
+    strict_extensions: bool,
+    lenient: bool,
 ) -> Result<HandshakeResult<E::Extension>, Error>
 where
     E: ExtensionProvider,
@@ -386,18 +560,30 @@ where
         }
     }
 
-    validate_header_value(response.headers(), header::UPGRADE, WEBSOCKET_STR)?;
-    validate_header_value(response.headers(), header::CONNECTION, UPGRADE_STR)?;
+    if lenient {
+        for (name, expected) in [
+            (header::UPGRADE, WEBSOCKET_STR),
+            (header::CONNECTION, UPGRADE_STR),
+        ] {
+            if response.headers().contains_key(&name) {
+                validate_header_token(response.headers(), name, expected)?;
+            }
+        }
+    } else {
+        validate_header_value(response.headers(), header::UPGRADE, WEBSOCKET_STR)?;
+        validate_header_value(response.headers(), header::CONNECTION, UPGRADE_STR)?;
+    }
 
     validate_header(
         response.headers(),
         header::SEC_WEBSOCKET_ACCEPT,
         |_name, actual| {
-            let mut digest = Sha1::new();
-            digest.update(expected_nonce);
-            digest.update(ACCEPT_KEY);
-
-            let expected = STANDARD.encode(digest.finalize());
+            let expected = accept_key(expected_nonce);
+            let actual = if lenient {
+                trim_whitespace(actual.as_bytes())
+            } else {
+                actual.as_bytes()
+            };
             if expected.as_bytes() != actual {
                 Err(Error::with_cause(ErrorKind::Http, HttpError::KeyMismatch))
             } else {
@@ -406,10 +592,114 @@ where
         },
     )?;
 
+    let subprotocol = subprotocols.validate_accepted_subprotocol(response.headers())?;
+    let negotiated = extension
+        .negotiate_client(response.headers())
+        .map_err(|e| Error::with_cause(ErrorKind::Extension, e))?;
+
+    if strict_extensions {
+        check_accepted_extensions(response.headers(), &extension, negotiated.is_some())?;
+    }
+
+    let extension_header = match &negotiated {
+        Some(_) => accepted_extensions(response.headers()),
+        None => None,
+    };
+
+    let set_cookies = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .cloned()
+        .collect();
+
     Ok(HandshakeResult {
-        subprotocol: subprotocols.validate_accepted_subprotocol(response.headers())?,
-        extension: extension
-            .negotiate_client(response.headers())
-            .map_err(|e| Error::with_cause(ErrorKind::Extension, e))?,
+        subprotocol,
+        extension: negotiated,
+        extension_header,
+        set_cookies,
+        response,
     })
 }
+
+/// Returns a header value for the basic authentication scheme with the provided credentials.
+pub(crate) fn basic_auth(username: &str, password: &str) -> Result<HeaderValue, Error> {
+    let credentials = STANDARD.encode(format!("{username}:{password}"));
+    Ok(HeaderValue::from_str(&format!("Basic {credentials}"))?)
+}
+
+/// Returns the `Sec-WebSocket-Extensions` header in `headers`, with the values of any repeated
+/// headers joined into a single list.
+fn accepted_extensions(headers: &HeaderMap) -> Option<HeaderValue> {
+    let mut values = headers.get_all(header::SEC_WEBSOCKET_EXTENSIONS).iter();
+    let first = values.next()?;
+
+    let mut joined = BytesMut::from(first.as_bytes());
+    for value in values {
+        joined.extend_from_slice(b", ");
+        joined.extend_from_slice(value.as_bytes());
+    }
+
+    HeaderValue::from_maybe_shared(joined.freeze()).ok()
+}
+
+/// Returns the names of the extensions in the `Sec-WebSocket-Extensions` headers in `headers`.
+fn extension_names(headers: &HeaderMap) -> Result<Vec<&str>, Error> {
+    let mut names = Vec::new();
+    for value in headers.get_all(header::SEC_WEBSOCKET_EXTENSIONS) {
+        let value = value.to_str().map_err(|_| {
+            Error::with_cause(
+                ErrorKind::Extension,
+                HttpError::InvalidHeader(header::SEC_WEBSOCKET_EXTENSIONS),
+            )
+        })?;
+
+        for extension in value.split(',') {
+            match extension.split(';').next().map(str::trim) {
+                Some(name) if !name.is_empty() => names.push(name),
+                _ => {
+                    return Err(Error::with_cause(
+                        ErrorKind::Extension,
+                        HttpError::InvalidHeader(header::SEC_WEBSOCKET_EXTENSIONS),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// rfc6455 § 4.1: fails the handshake if the server accepted an extension that was not offered or
+/// if the extension provider could not negotiate the extensions that the server accepted.
+fn check_accepted_extensions<E>(
+    headers: &HeaderMap,
+    extension: &E,
+    negotiated: bool,
+) -> Result<(), Error>
+where
+    E: ExtensionProvider,
+{
+    let mut offered = HeaderMap::new();
+    extension.apply_headers(&mut offered);
+    let offered = extension_names(&offered)?;
+
+    let accepted = extension_names(headers)?;
+    if let Some(name) = accepted.iter().find(|name| {
+        !offered
+            .iter()
+            .any(|offered| offered.eq_ignore_ascii_case(name))
+    }) {
+        return Err(Error::with_cause(
+            ErrorKind::Extension,
+            HttpError::UnexpectedExtension(name.to_string()),
+        ));
+    }
+
+    match accepted.first() {
+        Some(name) if !negotiated => Err(Error::with_cause(
+            ErrorKind::Extension,
+            HttpError::UnexpectedExtension(name.to_string()),
+        )),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::errors::{Error, ErrorKind, HttpError};
+use crate::handshake::client::basic_auth;
+use crate::handshake::io::BufferedIo;
+use crate::handshake::{ParseResult, StreamingParser, TryFromWrapper};
+use crate::WebSocketStream;
+use bytes::BytesMut;
+use http::header::{HOST, PROXY_AUTHORIZATION};
+use http::uri::Authority;
+use http::{HeaderMap, HeaderName, HeaderValue, Uri};
+use httparse::{Response, Status};
+use log::trace;
+use std::convert::TryFrom;
+use tokio_util::codec::Decoder;
+
+const HTTP_PORT: u16 = 80;
+const HTTPS_PORT: u16 = 443;
+const MSG_UNEXPECTED_DATA: &str = "Proxy sent data before the tunnel was established";
+
+/// An HTTP proxy which a client tunnels its connection through, using the `CONNECT` method, before
+/// executing the WebSocket handshake.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    authority: Authority,
+    headers: HeaderMap,
+}
+
+impl Proxy {
+    /// Construct a new proxy from its authority, such as `proxy.example.com:3128`. If the
+    /// authority does not contain a port then port 80 is used.
+    pub fn new<A>(authority: A) -> Result<Proxy, Error>
+    where
+        A: AsRef<str>,
+    {
+        let authority = Authority::try_from(authority.as_ref())
+            .map_err(|e| Error::with_cause(ErrorKind::Http, HttpError::from(e)))?;
+        Ok(Proxy {
+            authority,
+            headers: HeaderMap::new(),
+        })
+    }
+
+    /// Sets the `Proxy-Authorization` header to use the basic authentication scheme with the
+    /// provided credentials.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Result<Proxy, Error> {
+        self.headers
+            .insert(PROXY_AUTHORIZATION, basic_auth(username, password)?);
+        Ok(self)
+    }
+
+    /// Adds a header which is sent to the proxy in the `CONNECT` request. The header is not sent
+    /// to the WebSocket server.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Proxy {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Returns the authority of the proxy.
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    /// Returns the headers which are sent to the proxy in the `CONNECT` request.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Returns the host and port of the proxy for connecting to it.
+    #[cfg(feature = "net")]
+    pub(crate) fn host_and_port(&self) -> (&str, u16) {
+        let host = self.authority.host();
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        (host, self.authority.port_u16().unwrap_or(HTTP_PORT))
+    }
+
+    /// Establish a tunnel through the proxy to the host of `uri` over `stream`, which must be
+    /// connected to the proxy.
+    ///
+    /// This only needs to be called directly if another protocol, such as TLS, must be run over
+    /// the tunnel before executing the WebSocket handshake. Otherwise, set the proxy on a
+    /// `WebSocketClientBuilder`.
+    pub async fn tunnel<S>(&self, stream: &mut S, uri: &Uri) -> Result<(), Error>
+    where
+        S: WebSocketStream,
+    {
+        let target = target_authority(uri)?;
+        trace!(
+            "Establishing a tunnel to {target} through {}",
+            self.authority
+        );
+
+        let mut buf = BytesMut::new();
+        encode_connect(&mut buf, &target, &self.headers);
+
+        let mut buffered = BufferedIo::new(stream, &mut buf);
+        buffered.write().await?;
+        buffered.clear();
+
+        StreamingParser::new(&mut buffered, ProxyResponseParser)
+            .parse()
+            .await?;
+
+        if buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::with_cause(ErrorKind::Http, MSG_UNEXPECTED_DATA))
+        }
+    }
+}
+
+/// Returns the authority-form of the request target for tunnelling to the host of `uri`.
+fn target_authority(uri: &Uri) -> Result<String, Error> {
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::with_cause(ErrorKind::Http, HttpError::MissingAuthority))?;
+    let port = match uri.port_u16() {
+        Some(port) => port,
+        None => match uri.scheme_str() {
+            Some(scheme)
+                if scheme.eq_ignore_ascii_case("wss") || scheme.eq_ignore_ascii_case("https") =>
+            {
+                HTTPS_PORT
+            }
+            _ => HTTP_PORT,
+        },
+    };
+    Ok(format!("{host}:{port}"))
+}
+
+fn encode_connect(dst: &mut BytesMut, target: &str, headers: &HeaderMap) {
+    dst.extend_from_slice(format!("CONNECT {target} HTTP/1.1\r\n").as_bytes());
+
+    if !headers.contains_key(HOST) {
+        dst.extend_from_slice(format!("host: {target}\r\n").as_bytes());
+    }
+
+    for (name, value) in headers {
+        dst.extend_from_slice(name.as_str().as_bytes());
+        dst.extend_from_slice(b": ");
+        dst.extend_from_slice(value.as_bytes());
+        dst.extend_from_slice(b"\r\n");
+    }
+
+    dst.extend_from_slice(b"\r\n");
+}
+
+struct ProxyResponseParser;
+
+impl Decoder for ProxyResponseParser {
+    type Item = ((), usize);
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let response = Response::new(&mut headers);
+
+        match try_parse_response(buf, response)? {
+            ParseResult::Complete((), count) => Ok(Some(((), count))),
+            ParseResult::Partial(_) => Ok(None),
+        }
+    }
+}
+
+fn try_parse_response<'b>(
+    buffer: &'b [u8],
+    mut response: Response<'b, 'b>,
+) -> Result<ParseResult<Response<'b, 'b>, ()>, Error> {
+    match response.parse(buffer) {
+        Ok(Status::Complete(count)) => {
+            let response = http::Response::try_from(TryFromWrapper(response))?;
+            // Any 2xx status code indicates that the tunnel has been established. RFC 9110 9.3.6.
+            if response.status().is_success() {
+                Ok(ParseResult::Complete((), count))
+            } else {
+                Err(Error::with_cause(
+                    ErrorKind::Http,
+                    HttpError::Status(response.status().as_u16()),
+                ))
+            }
+        }
+        Ok(Status::Partial) => Ok(ParseResult::Partial(response)),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -12,22 +12,28 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::errors::{Error, HttpError};
+use crate::errors::{Error, HttpError, Rejection, TimeoutCause};
 use crate::ext::NoExt;
 use crate::handshake::client::encoding::build_request;
-use crate::handshake::client::{ClientHandshake, HandshakeResult};
-use crate::handshake::{SubprotocolRegistry, ACCEPT_KEY, UPGRADE_STR, WEBSOCKET_STR};
+use crate::handshake::client::{ClientHandshake, ClientHandshakeMachine, HandshakeResult};
+use crate::handshake::{
+    build_response, ServerHandshakeMachine, SubprotocolRegistry, UPGRADE_STR, WEBSOCKET_STR,
+};
+use crate::protocol::ACCEPT_KEY;
 use crate::test_fixture::mock;
-use crate::{ErrorKind, NoExtProvider, ProtocolError, TryIntoRequest};
+use crate::{
+    accept_with, subscribe, CookieStore, ErrorKind, NoExtProvider, ProtocolError, Proxy,
+    TryIntoRequest, WebSocketClientBuilder, WebSocketConfig, WebSocketResponse,
+};
 use base64::engine::{general_purpose::STANDARD, Engine};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use futures::future::join;
 use futures::FutureExt;
 use http::header::{
     HeaderName, CONNECTION, HOST, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_KEY,
     SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION, UPGRADE,
 };
-use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
 use httparse::{Header, Status};
 use ratchet_ext::{
     Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider, FrameHeader,
@@ -35,8 +41,10 @@ use ratchet_ext::{
 };
 use sha1::{Digest, Sha1};
 use std::convert::Infallible;
-use std::sync::Arc;
-use tokio::io::AsyncReadExt;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Notify;
 
 const TEST_URL: &str = "ws://127.0.0.1:9001/test";
@@ -236,11 +244,12 @@ async fn bad_status_code() {
         .body(())
         .unwrap();
 
-    expect_server_error(
-        response,
-        HttpError::Status(StatusCode::IM_A_TEAPOT.as_u16()),
-    )
-    .await;
+    let mut headers = HeaderMap::new();
+    headers.insert(header::UPGRADE, HeaderValue::from_static(WEBSOCKET_STR));
+    headers.insert(header::CONNECTION, HeaderValue::from_static(UPGRADE_STR));
+    let rejection = Rejection::new(StatusCode::IM_A_TEAPOT, headers, Bytes::new());
+
+    expect_server_error(response, HttpError::Rejected(Box::new(rejection))).await;
 }
 
 #[tokio::test]
@@ -576,6 +585,15 @@ impl SplittableExtension for MockExtension {
 }
 
 async fn extension_test<E, F, R>(ext: E, response_fn: F, result_fn: R)
+where
+    E: ExtensionProvider,
+    F: Fn(&mut Response<()>),
+    R: Fn(Result<HandshakeResult<E::Extension>, Error>),
+{
+    extension_test_with(ext, false, response_fn, result_fn).await
+}
+
+async fn extension_test_with<E, F, R>(ext: E, strict: bool, response_fn: F, result_fn: R)
 where
     E: ExtensionProvider,
     F: Fn(&mut Response<()>),
@@ -590,6 +608,7 @@ where
         let mut buf = BytesMut::new();
         let mut machine =
             ClientHandshake::new(&mut stream, SubprotocolRegistry::default(), &ext, &mut buf);
+        machine.strict_extensions = strict;
         machine
             .encode(Request::get(TEST_URL).body(()).unwrap())
             .unwrap();
@@ -678,7 +697,10 @@ async fn negotiates_extension() {
             );
         },
         |result| match result {
-            Ok(mut handshake_result) => assert!(handshake_result.extension.take().unwrap().0),
+            Ok(mut handshake_result) => {
+                assert!(handshake_result.extension.take().unwrap().0);
+                assert_eq!(handshake_result.extension_header.unwrap(), EXT);
+            }
             Err(e) => {
                 panic!("Expected a valid upgrade: {:?}", e)
             }
@@ -705,6 +727,49 @@ async fn negotiates_no_extension() {
     .await;
 }
 
+#[tokio::test]
+async fn strict_extensions() {
+    const HEADERS: &[(HeaderName, &str)] = &[(header::SEC_WEBSOCKET_EXTENSIONS, "test_extension")];
+
+    async fn test(strict: bool, accepted: &'static str, expected: Option<&str>) {
+        let extension_proxy = MockExtensionProxy(HEADERS, |headers| {
+            let accepted = headers.get(header::SEC_WEBSOCKET_EXTENSIONS).unwrap();
+            Ok((accepted == "test_extension").then_some(MockExtension(true)))
+        });
+
+        extension_test_with(
+            extension_proxy,
+            strict,
+            |r| {
+                r.headers_mut().insert(
+                    header::SEC_WEBSOCKET_EXTENSIONS,
+                    HeaderValue::from_static(accepted),
+                );
+            },
+            |result| match (result, expected) {
+                (Ok(_), None) => {}
+                (Err(e), Some(name)) => {
+                    assert!(e.is_extension());
+                    let error = e.downcast_ref::<HttpError>().expect(ERR);
+                    assert_eq!(error, &HttpError::UnexpectedExtension(name.to_string()));
+                }
+                (result, expected) => {
+                    panic!("Expected: {:?}. Got: {:?}", expected, result)
+                }
+            },
+        )
+        .await;
+    }
+
+    test(true, "test_extension", None).await;
+    // The server accepted an extension that was not offered.
+    test(false, "other_extension", None).await;
+    test(true, "other_extension", Some("other_extension")).await;
+    // The server accepted the extension with parameters that could not be negotiated.
+    test(false, "test_extension; bad", None).await;
+    test(true, "test_extension; bad", Some("test_extension")).await;
+}
+
 #[test]
 fn fails_to_build_request() {
     fn test<E: std::error::Error + PartialEq + 'static>(request: Request<()>, expected_error: E) {
@@ -804,7 +869,559 @@ fn fails_to_build_request() {
         .insert(HOST, HeaderValue::from_static("hostedbyhosts"));
     request
         .headers_mut()
-        .insert(HOST, HeaderValue::from_static("hostymchostface"));
+        .append(HOST, HeaderValue::from_static("hostymchostface"));
 
     test(request, HttpError::InvalidHeader(HOST));
 }
+
+#[tokio::test]
+async fn tunnels_through_proxy() {
+    let (mut peer, stream) = mock();
+    let proxy = Proxy::new("proxy.example.com:3128")
+        .unwrap()
+        .basic_auth("user", "pass")
+        .unwrap();
+
+    let client_task = async move {
+        WebSocketClientBuilder::default()
+            .proxy(proxy)
+            .subscribe(stream, TEST_URL)
+            .await
+            .unwrap();
+    };
+
+    let server_task = async move {
+        let request = peer.read_request().await.unwrap();
+        assert_eq!(request.method(), Method::CONNECT);
+        assert_eq!(request.uri(), "127.0.0.1:9001");
+        assert_eq!(
+            request.headers().get(HOST),
+            Some(&HeaderValue::from_static("127.0.0.1:9001"))
+        );
+        assert_eq!(
+            request.headers().get(header::PROXY_AUTHORIZATION),
+            Some(&HeaderValue::from_static("Basic dXNlcjpwYXNz"))
+        );
+
+        peer.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+
+        let upgrader = accept_with(
+            peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(upgrader.request().uri(), "/test");
+        upgrader.upgrade().await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[tokio::test]
+async fn proxy_rejects_tunnel() {
+    let (mut peer, mut stream) = mock();
+    let proxy = Proxy::new("proxy.example.com").unwrap();
+
+    let client_task = async move {
+        let uri = "ws://example.com/test".parse().unwrap();
+        let err = proxy.tunnel(&mut stream, &uri).await.expect_err(ERR);
+        assert_eq!(
+            err.downcast_ref::<HttpError>(),
+            Some(&HttpError::Status(407))
+        );
+    };
+
+    let server_task = async move {
+        let request = peer.read_request().await.unwrap();
+        assert_eq!(request.uri(), "example.com:80");
+
+        peer.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+            .await
+            .unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn handshake_timeout() {
+    let (_peer, stream) = mock();
+    let config = WebSocketConfig {
+        handshake_timeout: Some(Duration::from_secs(5)),
+        ..Default::default()
+    };
+
+    let err = subscribe(config, stream, TEST_URL).await.expect_err(ERR);
+    assert!(err.is_timeout());
+    assert_eq!(
+        err.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Handshake)
+    );
+}
+
+#[tokio::test]
+async fn builder_headers() {
+    async fn test<F>(builder: F, expected_auth: &'static str)
+    where
+        F: FnOnce(WebSocketClientBuilder<NoExtProvider>) -> WebSocketClientBuilder<NoExtProvider>,
+    {
+        let (mut peer, stream) = mock();
+        let request = Request::get(TEST_URL)
+            .header(header::AUTHORIZATION, "Bearer replaced")
+            .header(header::USER_AGENT, "ratchet")
+            .body(())
+            .unwrap();
+        let builder = builder(WebSocketClientBuilder::default());
+
+        let client_task = async move {
+            builder.subscribe(stream, request).await.unwrap();
+        };
+
+        let server_task = async move {
+            let upgrader = accept_with(
+                &mut peer,
+                WebSocketConfig::default(),
+                NoExtProvider,
+                SubprotocolRegistry::default(),
+            )
+            .await
+            .unwrap();
+            let headers = upgrader.request().headers().clone();
+            upgrader.upgrade().await.unwrap();
+
+            assert_eq!(
+                headers.get(header::AUTHORIZATION),
+                Some(&HeaderValue::from_static(expected_auth))
+            );
+            assert_eq!(
+                headers.get(header::USER_AGENT),
+                Some(&HeaderValue::from_static("ratchet"))
+            );
+            assert_eq!(
+                headers.get("x-custom"),
+                Some(&HeaderValue::from_static("a"))
+            );
+            assert_eq!(
+                headers.get(header::ORIGIN),
+                Some(&HeaderValue::from_static("https://example.com"))
+            );
+        };
+
+        join(client_task, server_task).await;
+    }
+
+    fn custom(
+        builder: WebSocketClientBuilder<NoExtProvider>,
+    ) -> WebSocketClientBuilder<NoExtProvider> {
+        builder
+            .header(
+                HeaderName::from_static("x-custom"),
+                HeaderValue::from_static("a"),
+            )
+            .origin("https://example.com")
+            .unwrap()
+    }
+
+    test(
+        |builder| custom(builder).bearer_auth("token").unwrap(),
+        "Bearer token",
+    )
+    .await;
+    test(
+        |builder| custom(builder).basic_auth("user", "pass").unwrap(),
+        "Basic dXNlcjpwYXNz",
+    )
+    .await;
+}
+
+#[derive(Debug, Default)]
+struct TestCookieStore {
+    set: Mutex<Vec<(Uri, HeaderValue)>>,
+}
+
+impl CookieStore for TestCookieStore {
+    fn set_cookies(&self, uri: &Uri, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>) {
+        let mut set = self.set.lock().unwrap();
+        set.extend(cookie_headers.map(|value| (uri.clone(), value.clone())));
+    }
+
+    fn cookies(&self, uri: &Uri) -> Option<HeaderValue> {
+        assert_eq!(uri, TEST_URL);
+        Some(HeaderValue::from_static("session=abc"))
+    }
+}
+
+#[tokio::test]
+async fn cookies() {
+    let (mut peer, stream) = mock();
+    let store = Arc::new(TestCookieStore::default());
+    let request = Request::get(TEST_URL)
+        .header(header::COOKIE, "theme=dark")
+        .body(())
+        .unwrap();
+    let builder = WebSocketClientBuilder::default()
+        .cookie("lang", "en")
+        .unwrap()
+        .cookie_store(store.clone());
+
+    let client_task = async move {
+        let upgraded = builder.subscribe(stream, request).await.unwrap();
+        assert_eq!(
+            upgraded.set_cookies,
+            vec![
+                HeaderValue::from_static("id=1; HttpOnly"),
+                HeaderValue::from_static("token=2")
+            ]
+        );
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            upgrader.request().headers().get(header::COOKIE),
+            Some(&HeaderValue::from_static(
+                "theme=dark; lang=en; session=abc"
+            ))
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::SET_COOKIE,
+            HeaderValue::from_static("id=1; HttpOnly"),
+        );
+        headers.append(header::SET_COOKIE, HeaderValue::from_static("token=2"));
+        upgrader.upgrade_with(headers).await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+
+    let set = store.set.lock().unwrap();
+    assert_eq!(
+        *set,
+        vec![
+            (
+                TEST_URL.parse().unwrap(),
+                HeaderValue::from_static("id=1; HttpOnly")
+            ),
+            (
+                TEST_URL.parse().unwrap(),
+                HeaderValue::from_static("token=2")
+            )
+        ]
+    );
+}
+
+#[tokio::test]
+async fn exposes_response() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        let upgraded = subscribe(WebSocketConfig::default(), stream, TEST_URL)
+            .await
+            .unwrap();
+        let response = upgraded.response;
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.version(), Version::HTTP_11);
+        assert_eq!(
+            response.headers().get("x-session"),
+            Some(&HeaderValue::from_static("abc"))
+        );
+        assert_eq!(
+            response.headers().get(header::SERVER),
+            Some(&HeaderValue::from_static("ratchet"))
+        );
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-session", HeaderValue::from_static("abc"));
+        headers.insert(header::SERVER, HeaderValue::from_static("ratchet"));
+        upgrader.upgrade_with(headers).await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[tokio::test]
+async fn rejection_details() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        let err = subscribe(WebSocketConfig::default(), stream, TEST_URL)
+            .await
+            .expect_err(ERR);
+        let rejection = match err.downcast_ref::<HttpError>() {
+            Some(HttpError::Rejected(rejection)) => rejection,
+            e => panic!("Unexpected error: {e:?}"),
+        };
+        assert_eq!(rejection.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            rejection.headers().get(header::WWW_AUTHENTICATE),
+            Some(&HeaderValue::from_static("Bearer"))
+        );
+        assert_eq!(rejection.body(), r#"{"error":"unauthorized"}"#.as_bytes());
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        let response = WebSocketResponse::new(401)
+            .unwrap()
+            .header(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))
+            .body(r#"{"error":"unauthorized"}"#);
+        upgrader.reject(response).await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[tokio::test]
+async fn rejection_body_is_bounded() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        let err = subscribe(WebSocketConfig::default(), stream, TEST_URL)
+            .await
+            .expect_err(ERR);
+        match err.downcast_ref::<HttpError>() {
+            Some(HttpError::Rejected(rejection)) => {
+                assert_eq!(rejection.status(), StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(rejection.body().len(), 4096);
+            }
+            e => panic!("Unexpected error: {e:?}"),
+        }
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        let response = WebSocketResponse::new(503).unwrap().body(vec![b'a'; 8192]);
+        upgrader.reject(response).await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[test]
+fn handshake_machines() {
+    let mut client = ClientHandshakeMachine::new(
+        NoExtProvider,
+        SubprotocolRegistry::new(["warp", "warps"]).unwrap(),
+    );
+    let mut server = ServerHandshakeMachine::new(
+        &WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::new(["warps"]).unwrap(),
+    );
+
+    let mut request_bytes = BytesMut::new();
+    client
+        .encode_request(TEST_URL.try_into_request().unwrap(), &mut request_bytes)
+        .unwrap();
+
+    let (head, tail) = request_bytes.split_at(request_bytes.len() / 2);
+    assert!(server.feed_bytes(head).is_pending());
+    let request = match server.feed_bytes(tail) {
+        Poll::Ready(result) => result.unwrap(),
+        Poll::Pending => panic!("Expected the request to be complete"),
+    };
+    assert_eq!(request.subprotocol.as_deref(), Some("warps"));
+    assert!(server.into_buffer().is_empty());
+
+    let response = build_response(request.key, request.subprotocol, None).unwrap();
+    let mut response_bytes = BytesMut::new();
+    crate::server::encode_response(&mut response_bytes, response);
+    response_bytes.extend_from_slice(b"frame");
+
+    for byte in &response_bytes[..response_bytes.len() - 6] {
+        assert!(client.feed_bytes(&[*byte]).is_pending());
+    }
+    let result = match client.feed_bytes(&response_bytes[response_bytes.len() - 6..]) {
+        Poll::Ready(result) => result.unwrap(),
+        Poll::Pending => panic!("Expected the response to be complete"),
+    };
+    assert_eq!(result.subprotocol.as_deref(), Some("warps"));
+    assert_eq!(result.response.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(client.into_buffer().as_ref(), b"frame");
+}
+
+#[test]
+fn lenient_handshake() {
+    let handshake = |lenient| {
+        let mut client = ClientHandshakeMachine::new(NoExtProvider, SubprotocolRegistry::default())
+            .lenient(lenient);
+        let mut request = BytesMut::new();
+        client
+            .encode_request(TEST_URL.try_into_request().unwrap(), &mut request)
+            .unwrap();
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        assert!(parsed.parse(&request).unwrap().is_complete());
+        let key = parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(SEC_WEBSOCKET_KEY.as_str()))
+            .unwrap()
+            .value;
+
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+upgrade: WebSocket, h2c\r\n\
+sec-websocket-accept:  {} \r\n\r\n",
+            crate::protocol::accept_key(key)
+        );
+        match client.feed_bytes(response.as_bytes()) {
+            Poll::Ready(result) => result,
+            Poll::Pending => panic!("Expected the response to be complete"),
+        }
+    };
+
+    assert!(handshake(false).is_err());
+    assert!(handshake(true).is_ok());
+}
+
+#[tokio::test]
+async fn unsupported_version() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        let err = subscribe(WebSocketConfig::default(), stream, TEST_URL)
+            .await
+            .expect_err(ERR);
+        assert_eq!(
+            err.downcast_ref::<HttpError>(),
+            Some(&HttpError::UnsupportedVersion(vec![
+                "13".to_string(),
+                "8".to_string()
+            ]))
+        );
+    };
+
+    let server_task = async move {
+        peer.read_request().await.unwrap();
+        peer.write_all(
+            b"HTTP/1.1 426 Upgrade Required\r\n\
+sec-websocket-version: 13, 8\r\n\
+content-length: 0\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[tokio::test]
+async fn builder_request_target() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        WebSocketClientBuilder::default()
+            .path("/rooms/a b")
+            .query("token", "a+b/c&d=e")
+            .query("lang", "en")
+            .subscribe(stream, "ws://127.0.0.1:9001/test?v=1")
+            .await
+            .unwrap();
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            upgrader.request().uri(),
+            "/rooms/a%20b?v=1&token=a%2Bb%2Fc%26d%3De&lang=en"
+        );
+        upgrader.upgrade().await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
+
+#[test]
+fn host_headers() {
+    use crate::client::host_header;
+
+    let host = |uri: &str| host_header(&uri.parse().unwrap()).unwrap();
+
+    assert_eq!(host("ws://[::1]:9001/test"), "[::1]:9001");
+    assert_eq!(host("ws://[::1]:80/test"), "[::1]");
+    assert_eq!(host("wss://example.com:443"), "example.com");
+    assert_eq!(host("wss://example.com:80"), "example.com:80");
+    assert_eq!(host("ws://user:pass@example.com:9001"), "example.com:9001");
+    assert!(host_header(&"/test".parse().unwrap()).is_err());
+}
+
+#[tokio::test]
+async fn builder_host_override() {
+    let (mut peer, stream) = mock();
+
+    let client_task = async move {
+        WebSocketClientBuilder::default()
+            .host("example.com")
+            .unwrap()
+            .subscribe(stream, "ws://[::1]:80/test")
+            .await
+            .unwrap();
+    };
+
+    let server_task = async move {
+        let upgrader = accept_with(
+            &mut peer,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            upgrader.request().headers().get(HOST),
+            Some(&HeaderValue::from_static("example.com"))
+        );
+        upgrader.upgrade().await.unwrap();
+    };
+
+    join(client_task, server_task).await;
+}
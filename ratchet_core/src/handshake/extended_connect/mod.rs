@@ -0,0 +1,248 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bootstrapping WebSockets with the extended CONNECT method of HTTP/2 and HTTP/3, as specified by
+//! [RFC8441](https://datatracker.ietf.org/doc/html/rfc8441) and
+//! [RFC9220](https://datatracker.ietf.org/doc/html/rfc9220).
+//!
+//! An extended CONNECT request replaces the HTTP/1.1 upgrade: the client sends a `CONNECT`
+//! request with a `:protocol` pseudo-header of `websocket` and the server accepts it with a `200`
+//! response. The `Sec-WebSocket-Key` and `Sec-WebSocket-Accept` headers are not used. Once the
+//! response has been received, the stream of the request carries WebSocket frames and a
+//! `WebSocket` may be initialised over it using `WebSocket::from_upgraded`.
+//!
+//! The framing of the WebSocket is unchanged over either protocol, including the masking of frames
+//! sent by a client.
+//!
+//! These functions do not depend on an HTTP/2 or HTTP/3 implementation. Setting and reading the
+//! `:protocol` pseudo-header is left to the HTTP library as it is not represented by the `http`
+//! crate.
+
+#[cfg(test)]
+mod tests;
+
+use crate::handshake::{validate_header_value, UpgradeResponseParts, WEBSOCKET_VERSION_STR};
+use crate::{Error, ErrorKind, HttpError, Rejection, SubprotocolRegistry};
+use bytes::Bytes;
+use http::header::{SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION};
+use http::uri::{PathAndQuery, Scheme};
+use http::{HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
+use ratchet_ext::ExtensionProvider;
+
+/// The value of the `:protocol` pseudo-header of an extended CONNECT request which bootstraps a
+/// WebSocket.
+pub const PROTOCOL: &str = "websocket";
+
+/// The subprotocol and extension that were negotiated by an extended CONNECT request.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ConnectParts<E> {
+    /// The optional WebSocket subprotocol agreed upon during the handshake.
+    pub subprotocol: Option<String>,
+    /// The optional WebSocket extension negotiated during the handshake.
+    pub extension: Option<E>,
+    /// The `Sec-WebSocket-Extensions` header that the server responded with, if an extension was
+    /// negotiated.
+    pub extension_header: Option<HeaderValue>,
+}
+
+/// Builds an extended CONNECT request from `request`, which is sent by a client to bootstrap a
+/// WebSocket over HTTP/2 or HTTP/3. The request must be sent with a `:protocol` pseudo-header of
+/// [`PROTOCOL`].
+///
+/// The request is built for HTTP/3 if `request` has a version of `HTTP/3` and for HTTP/2
+/// otherwise.
+///
+/// The `ws` and `wss` schemes of the request's URI are replaced with `http` and `https`
+/// respectively and the headers of the extension and subprotocols are applied.
+///
+/// # Errors
+/// Errors if the URI of the request does not have an authority, if the request has a method
+/// other than `GET` or `CONNECT` or if it contains headers which may only be set by an extension
+/// or a subprotocol registry.
+pub fn build_connect_request<E>(
+    request: Request<()>,
+    extension: &E,
+    subprotocols: &SubprotocolRegistry,
+) -> Result<Request<()>, Error>
+where
+    E: ExtensionProvider,
+{
+    let (mut parts, body) = request.into_parts();
+
+    if parts.method != Method::GET && parts.method != Method::CONNECT {
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::HttpMethod(Some(parts.method.to_string())),
+        ));
+    }
+
+    for name in [SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL] {
+        if parts.headers.contains_key(&name) {
+            return Err(Error::with_cause(
+                ErrorKind::Http,
+                HttpError::InvalidHeader(name),
+            ));
+        }
+    }
+
+    parts.uri = connect_uri(parts.uri)?;
+    parts.method = Method::CONNECT;
+    if parts.version != Version::HTTP_3 {
+        parts.version = Version::HTTP_2;
+    }
+    parts.headers.insert(
+        SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static(WEBSOCKET_VERSION_STR),
+    );
+    extension.apply_headers(&mut parts.headers);
+    subprotocols.apply_to(&mut parts.headers);
+
+    Ok(Request::from_parts(parts, body))
+}
+
+fn connect_uri(uri: Uri) -> Result<Uri, Error> {
+    let malformatted = |uri: &Uri| {
+        Error::with_cause(
+            ErrorKind::Http,
+            HttpError::MalformattedUri(Some(uri.to_string())),
+        )
+    };
+    if uri.authority().is_none() {
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::MissingAuthority,
+        ));
+    }
+
+    let scheme = match uri.scheme_str() {
+        Some(scheme) if scheme.eq_ignore_ascii_case("ws") => Scheme::HTTP,
+        Some(scheme) if scheme.eq_ignore_ascii_case("wss") => Scheme::HTTPS,
+        Some(_) => return Ok(uri),
+        None => Scheme::HTTPS,
+    };
+
+    let original = uri.clone();
+    let mut parts = uri.into_parts();
+    parts.scheme = Some(scheme);
+    if parts.path_and_query.is_none() {
+        parts.path_and_query = Some(PathAndQuery::from_static("/"));
+    }
+    Uri::from_parts(parts).map_err(|_| malformatted(&original))
+}
+
+/// Validates the response that a server sent to an extended CONNECT request and negotiates the
+/// subprotocol and extension that it accepted.
+///
+/// # Errors
+/// Errors with [`HttpError::Rejected`] if the server responded with a status code other than
+/// `2xx` or if it accepted a subprotocol or extension that was not offered.
+pub fn validate_connect_response<E>(
+    response: &Response<()>,
+    extension: &E,
+    subprotocols: &SubprotocolRegistry,
+) -> Result<ConnectParts<E::Extension>, Error>
+where
+    E: ExtensionProvider,
+{
+    if !response.status().is_success() {
+        let rejection = Rejection::new(response.status(), response.headers().clone(), Bytes::new());
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::Rejected(Box::new(rejection)),
+        ));
+    }
+
+    let subprotocol = subprotocols.validate_accepted_subprotocol(response.headers())?;
+    let extension = extension
+        .negotiate_client(response.headers())
+        .map_err(|e| Error::with_cause(ErrorKind::Extension, e))?;
+    let extension_header = match &extension {
+        Some(_) => response.headers().get(SEC_WEBSOCKET_EXTENSIONS).cloned(),
+        None => None,
+    };
+
+    Ok(ConnectParts {
+        subprotocol,
+        extension,
+        extension_header,
+    })
+}
+
+/// Validates an extended CONNECT request that a server received and builds the `200` response
+/// which accepts it, negotiating a subprotocol and extension.
+///
+/// `protocol` is the value of the request's `:protocol` pseudo-header.
+///
+/// # Errors
+/// Errors if the request is not an extended CONNECT request for a WebSocket, if it specifies an
+/// unsupported WebSocket version or if the extension or subprotocol negotiation fails.
+pub fn connect_response<E, B>(
+    request: &Request<B>,
+    protocol: Option<&str>,
+    extension: E,
+    subprotocols: &SubprotocolRegistry,
+) -> Result<UpgradeResponseParts<E::Extension>, Error>
+where
+    E: ExtensionProvider,
+{
+    if request.method() != Method::CONNECT {
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::HttpMethod(Some(request.method().to_string())),
+        ));
+    }
+    if request.version() < Version::HTTP_2 {
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::HttpVersion(format!("{:?}", request.version())),
+        ));
+    }
+    match protocol {
+        Some(protocol) if protocol.eq_ignore_ascii_case(PROTOCOL) => {}
+        protocol => {
+            return Err(Error::with_cause(
+                ErrorKind::Http,
+                HttpError::InvalidProtocol(protocol.map(ToString::to_string)),
+            ))
+        }
+    }
+
+    let headers = request.headers();
+    validate_header_value(headers, SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION_STR)?;
+
+    let subprotocol = subprotocols.negotiate_client(headers)?;
+    let (extension, extension_header) = extension
+        .negotiate_server(headers)
+        .map(Option::unzip)
+        .map_err(|e| Error::with_cause(ErrorKind::Extension, e))?;
+
+    let mut response = Response::builder()
+        .version(request.version())
+        .status(StatusCode::OK)
+        .body(())?;
+    let response_headers = response.headers_mut();
+    if let Some(subprotocol) = &subprotocol {
+        response_headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::try_from(subprotocol)?);
+    }
+    if let Some(extension_header) = extension_header {
+        response_headers.insert(SEC_WEBSOCKET_EXTENSIONS, extension_header);
+    }
+
+    Ok(UpgradeResponseParts {
+        response,
+        subprotocol,
+        extension,
+    })
+}
@@ -0,0 +1,169 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::extended_connect::{
+    build_connect_request, connect_response, validate_connect_response, ConnectParts, PROTOCOL,
+};
+use crate::test_fixture::mock;
+use crate::{
+    HttpError, Message, NoExtProvider, Role, SubprotocolRegistry, WebSocket, WebSocketConfig,
+};
+use bytes::BytesMut;
+use futures::future::join;
+use http::header::{SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, SEC_WEBSOCKET_VERSION};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
+
+#[test]
+fn builds_request() {
+    let subprotocols = SubprotocolRegistry::new(["warp"]).unwrap();
+    let request = build_connect_request(
+        Request::get("wss://example.com/chat").body(()).unwrap(),
+        &NoExtProvider,
+        &subprotocols,
+    )
+    .unwrap();
+
+    assert_eq!(request.method(), Method::CONNECT);
+    assert_eq!(request.version(), Version::HTTP_2);
+    assert_eq!(request.uri(), "https://example.com/chat");
+    assert_eq!(
+        request.headers().get(SEC_WEBSOCKET_VERSION),
+        Some(&HeaderValue::from_static("13"))
+    );
+    assert_eq!(
+        request.headers().get(SEC_WEBSOCKET_PROTOCOL),
+        Some(&HeaderValue::from_static("warp"))
+    );
+    assert!(request.headers().get(SEC_WEBSOCKET_KEY).is_none());
+}
+
+#[test]
+fn rejects_invalid_requests() {
+    let subprotocols = SubprotocolRegistry::default();
+    let request = Request::connect("https://example.com/chat")
+        .version(Version::HTTP_2)
+        .header(SEC_WEBSOCKET_VERSION, "13")
+        .body(())
+        .unwrap();
+
+    let err = connect_response(&request, Some("h2c"), NoExtProvider, &subprotocols).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::InvalidProtocol(Some("h2c".to_string())))
+    );
+
+    let mut get = Request::get("https://example.com/chat")
+        .version(Version::HTTP_2)
+        .body(())
+        .unwrap();
+    *get.headers_mut() = request.headers().clone();
+    let err = connect_response(&get, Some(PROTOCOL), NoExtProvider, &subprotocols).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::HttpMethod(Some("GET".to_string())))
+    );
+
+    let response = Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .body(())
+        .unwrap();
+    let err = validate_connect_response(&response, &NoExtProvider, &subprotocols).unwrap_err();
+    match err.downcast_ref::<HttpError>() {
+        Some(HttpError::Rejected(rejection)) => {
+            assert_eq!(rejection.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(rejection.headers(), &HeaderMap::new());
+        }
+        e => panic!("Unexpected error: {e:?}"),
+    }
+}
+
+#[tokio::test]
+async fn bootstraps_websocket() {
+    let client_subprotocols = SubprotocolRegistry::new(["warps", "warp"]).unwrap();
+    let server_subprotocols = SubprotocolRegistry::new(["warp"]).unwrap();
+
+    let request = build_connect_request(
+        Request::get("ws://example.com/chat").body(()).unwrap(),
+        &NoExtProvider,
+        &client_subprotocols,
+    )
+    .unwrap();
+    let parts = connect_response(
+        &request,
+        Some(PROTOCOL),
+        NoExtProvider,
+        &server_subprotocols,
+    )
+    .unwrap();
+    assert_eq!(parts.response.status(), StatusCode::OK);
+    assert_eq!(parts.response.version(), Version::HTTP_2);
+    assert_eq!(parts.subprotocol.as_deref(), Some("warp"));
+
+    let ConnectParts {
+        subprotocol,
+        extension,
+        ..
+    } = validate_connect_response(&parts.response, &NoExtProvider, &client_subprotocols).unwrap();
+    assert_eq!(subprotocol.as_deref(), Some("warp"));
+
+    // The streams stand in for an HTTP/2 stream once the response has been received.
+    let (client_stream, server_stream) = mock();
+    let mut client = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        client_stream,
+        extension,
+        BytesMut::new(),
+        Role::Client,
+    );
+    let mut server = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        server_stream,
+        parts.extension,
+        BytesMut::new(),
+        Role::Server,
+    );
+
+    let client_task = async move {
+        client.write_text("hello").await.unwrap();
+    };
+    let server_task = async move {
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.unwrap();
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"hello");
+    };
+    join(client_task, server_task).await;
+}
+
+#[test]
+fn http3() {
+    let subprotocols = SubprotocolRegistry::default();
+    let request = build_connect_request(
+        Request::get("wss://example.com/chat")
+            .version(Version::HTTP_3)
+            .body(())
+            .unwrap(),
+        &NoExtProvider,
+        &subprotocols,
+    )
+    .unwrap();
+    assert_eq!(request.method(), Method::CONNECT);
+    assert_eq!(request.version(), Version::HTTP_3);
+
+    let parts = connect_response(&request, Some(PROTOCOL), NoExtProvider, &subprotocols).unwrap();
+    assert_eq!(parts.response.status(), StatusCode::OK);
+    assert_eq!(parts.response.version(), Version::HTTP_3);
+
+    validate_connect_response(&parts.response, &NoExtProvider, &subprotocols).unwrap();
+}
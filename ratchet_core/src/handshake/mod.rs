@@ -16,6 +16,7 @@
 mod tests;
 
 mod client;
+pub mod extended_connect;
 mod io;
 mod server;
 mod subprotocols;
@@ -29,24 +30,32 @@ use http::{HeaderMap, HeaderValue, Method, Response};
 use http::{StatusCode, Uri, Version};
 use httparse::Header;
 use log::{error, trace, warn};
+use std::net::SocketAddr;
 use std::str::FromStr;
 use tokio::io::AsyncRead;
 use tokio_util::codec::Decoder;
 use url::Url;
 
-pub use client::{subscribe, subscribe_with, UpgradedClient};
+pub(crate) use client::{add_cookies, basic_auth, store_cookies, subscribe_with_options};
+pub use client::{
+    default_port, host_header, subscribe, subscribe_with, ClientHandshakeMachine, CookieStore,
+    HandshakeResult, Proxy, UpgradedClient,
+};
 pub use server::{
-    accept, accept_with, build_response, build_response_headers, handshake, parse_request_parts,
-    response_from_headers, validate_method_and_version, UpgradeRequest, UpgradeRequestParts,
-    UpgradeResponseParts, UpgradedServer, WebSocketResponse, WebSocketUpgrader,
+    accept, accept_with, accept_with_buffer, build_response, build_response_headers, client_addr,
+    encode_response, handshake, parse_request_parts, read_proxy_header, response_from_headers,
+    validate_method_and_version, Accepted, ClientAddr, ClientAddrSource, HttpRequest, OriginPolicy,
+    ProxyHeader, ServerHandshakeMachine, TrustedProxies, UpgradeDecision, UpgradeRequest,
+    UpgradeRequestParts, UpgradeResponseParts, UpgradedServer, WebSocketResponse,
+    WebSocketUpgrader,
 };
+pub(crate) use server::{accept_with_options, AcceptOptions};
 pub use subprotocols::*;
 
 const WEBSOCKET_STR: &str = "websocket";
 const UPGRADE_STR: &str = "upgrade";
 const WEBSOCKET_VERSION_STR: &str = "13";
 const BAD_STATUS_CODE: &str = "Invalid status code";
-const ACCEPT_KEY: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 const METHOD_GET: &str = "get";
 
 pub struct StreamingParser<'i, 'buf, I, P> {
@@ -65,18 +74,24 @@ where
 
     pub async fn parse(self) -> Result<O, Error> {
         let StreamingParser { io, mut parser } = self;
+        // Any bytes which have already been read are decoded before reading from the stream.
+        let mut buffered = !io.buffer.is_empty();
 
         loop {
-            let n = io.read().await?;
-
-            if n == 0 {
-                warn!("Received early EOF");
-                return Err(Error::with_cause(
-                    ErrorKind::IO,
-                    std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
-                ));
+            if buffered {
+                buffered = false;
             } else {
-                trace!("Read {n} bytes. Attempting to decode");
+                let n = io.read().await?;
+
+                if n == 0 {
+                    warn!("Received early EOF");
+                    return Err(Error::with_cause(
+                        ErrorKind::IO,
+                        std::io::Error::from(std::io::ErrorKind::UnexpectedEof),
+                    ));
+                } else {
+                    trace!("Read {n} bytes. Attempting to decode");
+                }
             }
 
             match parser.decode(io.buffer) {
@@ -106,13 +121,13 @@ pub trait TryIntoRequest {
     fn try_into_request(self) -> Result<Request, Error>;
 }
 
-impl<'a> TryIntoRequest for &'a str {
+impl TryIntoRequest for &str {
     fn try_into_request(self) -> Result<Request, Error> {
         self.parse::<Uri>()?.try_into_request()
     }
 }
 
-impl<'a> TryIntoRequest for &'a String {
+impl TryIntoRequest for &String {
     fn try_into_request(self) -> Result<Request, Error> {
         self.as_str().try_into_request()
     }
@@ -124,7 +139,7 @@ impl TryIntoRequest for String {
     }
 }
 
-impl<'a> TryIntoRequest for &'a Uri {
+impl TryIntoRequest for &Uri {
     fn try_into_request(self) -> Result<Request, Error> {
         self.clone().try_into_request()
     }
@@ -136,7 +151,7 @@ impl TryIntoRequest for Uri {
     }
 }
 
-impl<'a> TryIntoRequest for &'a Url {
+impl TryIntoRequest for &Url {
     fn try_into_request(self) -> Result<Request, Error> {
         self.as_str().try_into_request()
     }
@@ -154,6 +169,42 @@ impl TryIntoRequest for Request {
     }
 }
 
+/// Creates a `ws` request for the path from a host, a port and a path. An IPv6 host may be
+/// provided with or without enclosing brackets.
+impl TryIntoRequest for (&str, u16, &str) {
+    fn try_into_request(self) -> Result<Request, Error> {
+        let (host, port, path) = self;
+        let authority = if host.contains(':') && !host.starts_with('[') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+        ws_request(authority, path)
+    }
+}
+
+/// Creates a `ws` request for the path from a socket address and a path.
+impl TryIntoRequest for (SocketAddr, &str) {
+    fn try_into_request(self) -> Result<Request, Error> {
+        let (addr, path) = self;
+        ws_request(addr.to_string(), path)
+    }
+}
+
+fn ws_request(authority: String, path: &str) -> Result<Request, Error> {
+    let path_and_query = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    };
+    Uri::builder()
+        .scheme("ws")
+        .authority(authority)
+        .path_and_query(path_and_query)
+        .build()?
+        .try_into_request()
+}
+
 fn validate_header_value(
     headers: &HeaderMap,
     name: HeaderName,
@@ -201,6 +252,48 @@ fn validate_header_any(headers: &HeaderMap, name: HeaderName, expected: &str) ->
     })
 }
 
+/// Validates that any of the comma-separated values in the `name` headers in `headers` is
+/// `expected`, ignoring case and any surrounding whitespace.
+fn validate_header_token(
+    headers: &HeaderMap,
+    name: HeaderName,
+    expected: &str,
+) -> Result<(), Error> {
+    let mut values = headers.get_all(&name).iter().peekable();
+    if values.peek().is_none() {
+        return Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::MissingHeader(name),
+        ));
+    }
+
+    if values
+        .flat_map(|value| value.as_bytes().split(|c| c == &b','))
+        .any(|token| trim_whitespace(token).eq_ignore_ascii_case(expected.as_bytes()))
+    {
+        Ok(())
+    } else {
+        Err(Error::with_cause(
+            ErrorKind::Http,
+            HttpError::InvalidHeader(name),
+        ))
+    }
+}
+
+/// Returns `bytes` with any leading and trailing spaces and tabs removed.
+fn trim_whitespace(bytes: &[u8]) -> &[u8] {
+    let is_whitespace = |c: &u8| c == &b' ' || c == &b'\t';
+    let start = bytes
+        .iter()
+        .position(|c| !is_whitespace(c))
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|c| !is_whitespace(c))
+        .map_or(start, |end| end + 1);
+    &bytes[start..end]
+}
+
 struct TryFromWrapper<T>(pub T);
 
 impl<'h> TryFrom<TryFromWrapper<&'h mut [Header<'h>]>> for HeaderMap {
@@ -220,7 +313,7 @@ impl<'h> TryFrom<TryFromWrapper<&'h mut [Header<'h>]>> for HeaderMap {
                 HeaderName::from_str(header.name).map_err(|_| InvalidHeader(header_string()))?;
             let value = HeaderValue::from_bytes(header.value)
                 .map_err(|_| InvalidHeader(header_string()))?;
-            header_map.insert(name, value);
+            header_map.append(name, value);
         }
 
         Ok(header_map)
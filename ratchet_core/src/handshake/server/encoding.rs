@@ -13,51 +13,74 @@
 // limitations under the License.
 
 use crate::handshake::io::BufferedIo;
-use crate::handshake::server::{check_partial_request, UpgradeRequest, UpgradeRequestParts};
+use crate::handshake::server::{
+    check_partial_request, is_websocket_upgrade, parse_request_parts_with, ParsedRequest,
+    UpgradeRequest, UpgradeRequestParts,
+};
 use crate::handshake::{ParseResult, TryFromWrapper};
-use crate::server::parse_request_parts;
-use crate::{Error, SubprotocolRegistry};
-use bytes::{BufMut, BytesMut};
-use http::request::Parts;
-use http::{HeaderMap, Request, StatusCode};
+use crate::{Error, HttpError, SubprotocolRegistry};
+use bytes::{BufMut, Bytes, BytesMut};
+use http::header::CONTENT_LENGTH;
+use http::{HeaderMap, HeaderValue, Request, StatusCode};
 use httparse::Status;
 use ratchet_ext::ExtensionProvider;
 use tokio::io::AsyncWrite;
 use tokio_util::codec::Decoder;
 
-/// The maximum number of headers that will be parsed.
-const MAX_HEADERS: usize = 32;
 const HTTP_VERSION_STR: &[u8] = b"HTTP/1.1 ";
 const STATUS_TERMINATOR_LEN: usize = 2;
 const TERMINATOR_NO_HEADERS: &[u8] = b"\r\n\r\n";
 const TERMINATOR_WITH_HEADER: &[u8] = b"\r\n";
 
+#[derive(Debug)]
 pub struct RequestParser<E> {
     pub subprotocols: SubprotocolRegistry,
     pub extension: E,
+    pub max_headers: usize,
+    pub max_header_bytes: Option<usize>,
+    pub allow_http: bool,
+    pub lenient: bool,
 }
 
 impl<E> Decoder for RequestParser<E>
 where
     E: ExtensionProvider,
 {
-    type Item = (UpgradeRequest<E::Extension>, usize);
+    type Item = (ParsedRequest<E::Extension>, usize);
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         let RequestParser {
             subprotocols,
             extension,
+            max_headers,
+            max_header_bytes,
+            allow_http,
+            lenient,
         } = self;
-        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut headers = vec![httparse::EMPTY_HEADER; *max_headers];
         let request = httparse::Request::new(&mut headers);
 
-        match try_parse_request(buf, request, extension, subprotocols)? {
-            ParseResult::Complete(result, count) => Ok(Some((result, count))),
-            ParseResult::Partial(request) => {
-                check_partial_request(&request)?;
+        let exceeds_limit = |len: usize| max_header_bytes.is_some_and(|max| len > max);
+
+        match try_parse_request(buf, request, extension, subprotocols, *allow_http, *lenient) {
+            Ok(ParseResult::Complete(_, count)) if exceeds_limit(count) => {
+                Err(HttpError::HeadersTooLarge.into())
+            }
+            Ok(ParseResult::Complete(result, count)) => Ok(Some((result, count))),
+            Ok(ParseResult::Partial(_)) if exceeds_limit(buf.len()) => {
+                Err(HttpError::HeadersTooLarge.into())
+            }
+            Ok(ParseResult::Partial(request)) => {
+                if !*allow_http {
+                    check_partial_request(&request)?;
+                }
                 Ok(None)
             }
+            Err(e) if e.downcast_ref() == Some(&httparse::Error::TooManyHeaders) => {
+                Err(HttpError::TooManyHeaders.into())
+            }
+            Err(e) => Err(e),
         }
     }
 }
@@ -67,12 +90,27 @@ pub async fn write_response<S>(
     buf: &mut BytesMut,
     status: StatusCode,
     headers: HeaderMap,
-    body: Option<String>,
+    body: Option<Bytes>,
 ) -> Result<(), Error>
 where
     S: AsyncWrite + Unpin,
 {
     buf.clear();
+    put_response(buf, status, headers, body);
+
+    let mut buffered = BufferedIo::new(stream, buf);
+    buffered.write().await
+}
+
+pub fn put_response(
+    buf: &mut BytesMut,
+    status: StatusCode,
+    mut headers: HeaderMap,
+    body: Option<Bytes>,
+) {
+    if let Some(body) = &body {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
 
     let version_count = HTTP_VERSION_STR.len();
     let status_bytes = status.as_str().as_bytes();
@@ -90,7 +128,11 @@ where
         TERMINATOR_WITH_HEADER.len()
     };
 
-    buf.reserve(version_count + status_bytes.len() + reason_len + headers_len + terminator_len);
+    let body_len = body.as_ref().map(Bytes::len).unwrap_or_default();
+
+    buf.reserve(
+        version_count + status_bytes.len() + reason_len + headers_len + terminator_len + body_len,
+    );
 
     buf.put_slice(HTTP_VERSION_STR);
     buf.put_slice(status.as_str().as_bytes());
@@ -106,18 +148,15 @@ where
         buf.put_slice(b"\r\n");
     }
 
-    if let Some(body) = body {
-        buf.put_slice(body.as_bytes());
-    }
-
     if headers.is_empty() {
         buf.put_slice(TERMINATOR_NO_HEADERS);
     } else {
         buf.put_slice(TERMINATOR_WITH_HEADER);
     }
 
-    let mut buffered = BufferedIo::new(stream, buf);
-    buffered.write().await
+    if let Some(body) = body {
+        buf.put_slice(&body);
+    }
 }
 
 pub fn try_parse_request<'b, E>(
@@ -125,36 +164,41 @@ pub fn try_parse_request<'b, E>(
     mut request: httparse::Request<'b, 'b>,
     extension: E,
     subprotocols: &mut SubprotocolRegistry,
-) -> Result<ParseResult<httparse::Request<'b, 'b>, UpgradeRequest<E::Extension>>, Error>
+    allow_http: bool,
+    lenient: bool,
+) -> Result<ParseResult<httparse::Request<'b, 'b>, ParsedRequest<E::Extension>>, Error>
 where
     E: ExtensionProvider,
 {
     match request.parse(buffer) {
         Ok(Status::Complete(count)) => {
             let request = Request::try_from(TryFromWrapper(request))?;
-            let (parts, body) = request.into_parts();
-            let Parts {
-                method,
-                version,
-                headers,
-                ..
-            } = &parts;
+            if allow_http && !is_websocket_upgrade(request.headers()) {
+                return Ok(ParseResult::Complete(ParsedRequest::Http(request), count));
+            }
 
             let UpgradeRequestParts {
                 key,
                 subprotocol,
                 extension,
                 extension_header,
-            } = parse_request_parts(*version, method, headers, extension, subprotocols)?;
+            } = parse_request_parts_with(
+                request.version(),
+                request.method(),
+                request.headers(),
+                extension,
+                lenient,
+                || subprotocols.negotiate_request(&request),
+            )?;
 
             Ok(ParseResult::Complete(
-                UpgradeRequest {
+                ParsedRequest::Upgrade(UpgradeRequest {
                     key,
                     subprotocol,
                     extension,
-                    request: Request::from_parts(parts, body),
+                    request,
                     extension_header,
-                },
+                }),
                 count,
             ))
         }
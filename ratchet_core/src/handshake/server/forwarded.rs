@@ -0,0 +1,238 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use http::header::FORWARDED;
+use http::{HeaderMap, HeaderName};
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// A set of proxies whose forwarding headers are trusted when resolving the address of a client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Construct a new set of trusted proxies that does not trust any proxies.
+    pub fn new() -> TrustedProxies {
+        TrustedProxies::default()
+    }
+
+    /// Trusts the proxy at `addr`.
+    pub fn trust(self, addr: IpAddr) -> TrustedProxies {
+        let prefix_len = max_prefix_len(addr);
+        self.trust_network(addr, prefix_len)
+    }
+
+    /// Trusts all proxies in the network `addr/prefix_len`, such as `10.0.0.0/8`. A prefix length
+    /// that is longer than the address is treated as the length of the address.
+    pub fn trust_network(mut self, addr: IpAddr, prefix_len: u8) -> TrustedProxies {
+        let prefix_len = prefix_len.min(max_prefix_len(addr));
+        self.networks.push((addr, prefix_len));
+        self
+    }
+
+    /// Returns whether the proxy at `addr` is trusted.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = canonical(addr);
+        self.networks
+            .iter()
+            .any(|(network, prefix_len)| in_network(addr, canonical(*network), *prefix_len))
+    }
+}
+
+fn max_prefix_len(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Maps an IPv4-mapped IPv6 address to its IPv4 address.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+fn in_network(addr: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+    let mask = |bits: u32| {
+        if prefix_len == 0 {
+            0
+        } else {
+            u128::MAX << (bits - u32::from(prefix_len))
+        }
+    };
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = mask(32);
+            (u128::from(u32::from(addr)) & mask) == (u128::from(u32::from(network)) & mask)
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = mask(128);
+            (u128::from(addr) & mask) == (u128::from(network) & mask)
+        }
+        _ => false,
+    }
+}
+
+/// Where the address of a client was resolved from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAddrSource {
+    /// The address of the peer that connected to the server.
+    Peer,
+    /// The `Forwarded` header.
+    Forwarded,
+    /// The `X-Forwarded-For` header.
+    XForwardedFor,
+    /// The `X-Real-IP` header.
+    XRealIp,
+}
+
+/// The address of a client which was resolved from the forwarding headers of its request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientAddr {
+    ip: IpAddr,
+    port: Option<u16>,
+    source: ClientAddrSource,
+}
+
+impl ClientAddr {
+    /// The IP address of the client.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// The port of the client, if it was provided.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Where the address was resolved from.
+    pub fn source(&self) -> ClientAddrSource {
+        self.source
+    }
+}
+
+/// Resolves the address of the client that sent a request with `headers` over a connection from
+/// `peer`.
+///
+/// The forwarding headers are only used if `peer` is a trusted proxy. The `Forwarded` header is
+/// preferred over the `X-Forwarded-For` header, which is preferred over the `X-Real-IP` header.
+/// The addresses that the headers list are walked from the nearest to the furthest proxy and the
+/// first address that is not a trusted proxy is the client's address; addresses before it may
+/// have been provided by the client and are not trusted. If an address is obfuscated or cannot be
+/// parsed, then the address of the proxy that provided it is returned.
+pub fn client_addr(
+    headers: &HeaderMap,
+    peer: IpAddr,
+    trusted_proxies: &TrustedProxies,
+) -> ClientAddr {
+    let mut client = ClientAddr {
+        ip: peer,
+        port: None,
+        source: ClientAddrSource::Peer,
+    };
+    if !trusted_proxies.contains(peer) {
+        return client;
+    }
+
+    let (chain, source) = if headers.contains_key(FORWARDED) {
+        (forwarded_chain(headers), ClientAddrSource::Forwarded)
+    } else if headers.contains_key(X_FORWARDED_FOR) {
+        (
+            list_chain(headers, X_FORWARDED_FOR),
+            ClientAddrSource::XForwardedFor,
+        )
+    } else if headers.contains_key(X_REAL_IP) {
+        (list_chain(headers, X_REAL_IP), ClientAddrSource::XRealIp)
+    } else {
+        return client;
+    };
+
+    for node in chain.into_iter().rev() {
+        match node {
+            Some((ip, port)) => {
+                client = ClientAddr { ip, port, source };
+                if !trusted_proxies.contains(ip) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    client
+}
+
+type Node = Option<(IpAddr, Option<u16>)>;
+
+fn header_values(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = Option<&str>> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .map(|value| value.to_str().ok())
+}
+
+// RFC 7239 § 4
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Node> {
+    let mut chain = Vec::new();
+    for value in header_values(headers, FORWARDED) {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                chain.push(None);
+                continue;
+            }
+        };
+        for element in value.split(',') {
+            let node = element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')));
+            chain.push(node);
+        }
+    }
+    chain
+}
+
+fn list_chain(headers: &HeaderMap, name: HeaderName) -> Vec<Node> {
+    let mut chain = Vec::new();
+    for value in header_values(headers, name) {
+        match value {
+            Some(value) => chain.extend(value.split(',').map(|node| parse_node(node.trim()))),
+            None => chain.push(None),
+        }
+    }
+    chain
+}
+
+/// Parses an IPv4 or IPv6 address that may be bracketed and have a port.
+fn parse_node(node: &str) -> Node {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some((addr.ip(), Some(addr.port())));
+    }
+    let ip = node
+        .strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .unwrap_or(node);
+    ip.parse().ok().map(|ip| (ip, None))
+}
@@ -0,0 +1,99 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::handshake::server::encoding::{put_response, RequestParser};
+use crate::handshake::server::{ParsedRequest, UpgradeRequest};
+use crate::{Error, SubprotocolRegistry, WebSocketConfig};
+use bytes::BytesMut;
+use http::Response;
+use ratchet_ext::ExtensionProvider;
+use std::task::Poll;
+use tokio_util::codec::Decoder;
+
+/// A server handshake which performs no IO.
+///
+/// Bytes that are read from the transport are passed to [`ServerHandshakeMachine::feed_bytes`]
+/// until it returns the parsed upgrade request. The response to write back to the client may then
+/// be encoded with [`encode_response`], and any bytes that were received after the request are
+/// available from [`ServerHandshakeMachine::into_buffer`]. This allows a handshake to be driven
+/// from within an event loop or over a transport which does not implement the tokio IO traits.
+#[derive(Debug)]
+pub struct ServerHandshakeMachine<E> {
+    parser: RequestParser<E>,
+    buf: BytesMut,
+}
+
+impl<E> ServerHandshakeMachine<E>
+where
+    E: ExtensionProvider,
+{
+    /// Constructs a new server handshake which will attempt to negotiate `extension` and a
+    /// subprotocol from `subprotocols`. Header limits are taken from `config`.
+    pub fn new(
+        config: &WebSocketConfig,
+        extension: E,
+        subprotocols: SubprotocolRegistry,
+    ) -> ServerHandshakeMachine<E> {
+        ServerHandshakeMachine {
+            parser: RequestParser {
+                subprotocols,
+                extension,
+                max_headers: config.max_headers,
+                max_header_bytes: config.max_header_bytes,
+                allow_http: false,
+                lenient: config.lenient_handshake,
+            },
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Feeds `bytes` that were read from the transport into the handshake.
+    ///
+    /// Returns `Poll::Pending` if more bytes are required to complete the request, or the upgrade
+    /// request once it has been parsed. This should not be called again once it has returned
+    /// `Poll::Ready`.
+    pub fn feed_bytes(
+        &mut self,
+        bytes: &[u8],
+    ) -> Poll<Result<UpgradeRequest<E::Extension>, Error>> {
+        let ServerHandshakeMachine { parser, buf } = self;
+        buf.extend_from_slice(bytes);
+
+        match parser.decode(buf) {
+            Ok(Some((ParsedRequest::Upgrade(request), count))) => {
+                let _ = buf.split_to(count);
+                Poll::Ready(Ok(request))
+            }
+            Ok(Some((ParsedRequest::Http(_), _))) => {
+                unreachable!("Plain HTTP requests are only returned when they are allowed")
+            }
+            Ok(None) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+
+    /// Consumes the handshake and returns any bytes which were received after the request. These
+    /// should be used as the read buffer of the upgraded WebSocket.
+    pub fn into_buffer(self) -> BytesMut {
+        self.buf
+    }
+}
+
+/// Encodes `response` into `dst` so that it can be written to the client.
+///
+/// This may be used with the response produced by [`build_response`](crate::server::build_response)
+/// to accept an upgrade request, or with any other response to reject one.
+pub fn encode_response(dst: &mut BytesMut, response: Response<()>) {
+    let (parts, _) = response.into_parts();
+    put_response(dst, parts.status, parts.headers, None);
+}
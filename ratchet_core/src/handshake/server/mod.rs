@@ -13,33 +13,45 @@
 // limitations under the License.
 
 mod encoding;
+mod forwarded;
+mod machine;
+mod origin;
+mod proxy_protocol;
 #[cfg(test)]
 mod tests;
 
+pub use forwarded::{client_addr, ClientAddr, ClientAddrSource, TrustedProxies};
+pub use machine::{encode_response, ServerHandshakeMachine};
+pub use origin::OriginPolicy;
+pub use proxy_protocol::{read_proxy_header, ProxyHeader};
+
+use proxy_protocol::read_proxy_header_into;
+
 use crate::handshake::{
-    validate_header_any, validate_header_value, METHOD_GET, WEBSOCKET_VERSION_STR,
+    trim_whitespace, validate_header_any, validate_header_token, validate_header_value, METHOD_GET,
+    WEBSOCKET_VERSION_STR,
 };
 use crate::{
     ext::NoExt,
     handshake::io::BufferedIo,
     handshake::server::encoding::{write_response, RequestParser},
-    handshake::{StreamingParser, ACCEPT_KEY},
+    handshake::StreamingParser,
     handshake::{UPGRADE_STR, WEBSOCKET_STR},
-    protocol::Role,
-    Error, ErrorKind, HttpError, NoExtProvider, Request, SubprotocolRegistry, WebSocket,
-    WebSocketConfig, WebSocketStream,
+    protocol::{accept_key, Role},
+    Error, ErrorKind, HttpError, NoExtProvider, ProtocolError, Request, SubprotocolRegistry,
+    TimeoutCause, WebSocket, WebSocketConfig, WebSocketStream,
 };
-use base64::engine::{general_purpose::STANDARD, Engine};
 use bytes::{Bytes, BytesMut};
-use http::header::{HOST, SEC_WEBSOCKET_KEY};
-use http::request::Parts;
+use http::header::{HeaderName, HOST, ORIGIN, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE};
 use http::status::InvalidStatusCode;
-use http::{HeaderMap, HeaderValue, Method, Response, StatusCode, Uri, Version};
+use http::{Extensions, HeaderMap, HeaderValue, Method, Response, StatusCode, Uri, Version};
 use log::{error, trace};
 use ratchet_ext::{Extension, ExtensionProvider};
-use sha1::{Digest, Sha1};
 use std::convert::TryFrom;
+use std::future::Future;
 use std::iter::FromIterator;
+use std::net::IpAddr;
+use tokio::io::AsyncWriteExt;
 
 const MSG_HANDSHAKE_COMPLETED: &str = "Server handshake completed";
 const MSG_HANDSHAKE_FAILED: &str = "Server handshake failed";
@@ -57,6 +69,9 @@ pub struct UpgradedServer<S, E> {
     pub websocket: WebSocket<S, E>,
     /// An optional subprotocol that was negotiated during the upgrade.
     pub subprotocol: Option<String>,
+    /// The `Sec-WebSocket-Extensions` header that was sent to the peer, if an extension was
+    /// negotiated.
+    pub extension_header: Option<HeaderValue>,
 }
 
 impl<S, E> UpgradedServer<S, E> {
@@ -94,7 +109,7 @@ where
 /// error if the peer's request is malformatted or if an IO error occurs. If the peer is accepted,
 /// then `config`, `extension` and `subprotocols` will be used for building the `WebSocket`.
 pub async fn accept_with<S, E>(
-    mut stream: S,
+    stream: S,
     config: WebSocketConfig,
     extension: E,
     subprotocols: SubprotocolRegistry,
@@ -103,18 +118,122 @@ where
     S: WebSocketStream,
     E: ExtensionProvider,
 {
-    let mut buf = BytesMut::new();
-    let mut io = BufferedIo::new(&mut stream, &mut buf);
-    let parser = StreamingParser::new(
-        &mut io,
-        RequestParser {
-            subprotocols,
-            extension,
+    accept_with_buffer(stream, BytesMut::new(), config, extension, subprotocols).await
+}
+
+/// Execute a server handshake on the provided stream, where `already_read` contains any bytes
+/// which have already been read from the stream. These are consumed by the handshake before any
+/// more bytes are read, which allows the first bytes of a connection to be inspected before
+/// deciding whether to accept it as a WebSocket. An attempt will be made to negotiate the
+/// extension and subprotocols provided.
+///
+/// Returns either a `WebSocketUpgrader` that may be used to either accept or reject the peer or an
+/// error if the peer's request is malformatted or if an IO error occurs. If the peer is accepted,
+/// then `config`, `extension` and `subprotocols` will be used for building the `WebSocket`.
+pub async fn accept_with_buffer<S, E>(
+    stream: S,
+    already_read: BytesMut,
+    config: WebSocketConfig,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+) -> Result<WebSocketUpgrader<S, E::Extension>, Error>
+where
+    S: WebSocketStream,
+    E: ExtensionProvider,
+{
+    let options = AcceptOptions {
+        origin_policy: &OriginPolicy::Any,
+        allow_http: false,
+        proxy_protocol: false,
+        buf: already_read,
+    };
+    match accept_with_options(stream, config, extension, subprotocols, options).await? {
+        Accepted::Upgrade(upgrader) => Ok(upgrader),
+        Accepted::Http(_) => unreachable!("Plain HTTP requests are not permitted"),
+    }
+}
+
+/// Options for accepting a connection which are only configurable through a
+/// `WebSocketServerBuilder`.
+#[derive(Debug)]
+pub(crate) struct AcceptOptions<'p> {
+    /// Requests from an origin that is not permitted are rejected with `403 Forbidden`.
+    pub origin_policy: &'p OriginPolicy,
+    /// Whether to return requests which do not request an upgrade instead of rejecting them.
+    pub allow_http: bool,
+    /// Whether to read a PROXY protocol header before the request.
+    pub proxy_protocol: bool,
+    /// Any bytes which have already been read from the stream.
+    pub buf: BytesMut,
+}
+
+pub(crate) async fn accept_with_options<S, E>(
+    mut stream: S,
+    config: WebSocketConfig,
+    extension: E,
+    subprotocols: SubprotocolRegistry,
+    options: AcceptOptions<'_>,
+) -> Result<Accepted<S, E::Extension>, Error>
+where
+    S: WebSocketStream,
+    E: ExtensionProvider,
+{
+    let AcceptOptions {
+        origin_policy,
+        allow_http,
+        proxy_protocol,
+        mut buf,
+    } = options;
+
+    let parse = async {
+        let proxy_header = if proxy_protocol {
+            Some(read_proxy_header_into(&mut stream, &mut buf).await?)
+        } else {
+            None
+        };
+
+        let mut io = BufferedIo::new(&mut stream, &mut buf);
+        let parser = StreamingParser::new(
+            &mut io,
+            RequestParser {
+                subprotocols,
+                extension,
+                max_headers: config.max_headers,
+                max_header_bytes: config.max_header_bytes,
+                allow_http,
+                lenient: config.lenient_handshake,
+            },
+        );
+        let mut request = parser.parse().await?;
+
+        if let Some(proxy_header) = proxy_header {
+            let request = match &mut request {
+                ParsedRequest::Http(request) => request,
+                ParsedRequest::Upgrade(upgrade) => &mut upgrade.request,
+            };
+            request.extensions_mut().insert(proxy_header);
+        }
+        Ok::<_, Error>(request)
+    };
+
+    let result = match config.handshake_timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, parse).await {
+            Ok(result) => result,
+            Err(_) => Err(TimeoutCause::Handshake.into()),
         },
-    );
+        None => parse.await,
+    };
 
-    match parser.parse().await {
-        Ok(request) => {
+    match result {
+        Ok(ParsedRequest::Http(request)) => {
+            trace!("Received a plain HTTP request for: {}", request.uri());
+            Ok(Accepted::Http(HttpRequest {
+                request,
+                stream,
+                buf,
+            }))
+        }
+        Ok(ParsedRequest::Upgrade(request)) => {
             let UpgradeRequest {
                 key,
                 subprotocol,
@@ -123,6 +242,30 @@ where
                 extension_header,
             } = request;
 
+            if !origin_policy.permits(request.headers()) {
+                let origin = request
+                    .headers()
+                    .get(ORIGIN)
+                    .map(|origin| String::from_utf8_lossy(origin.as_bytes()).into_owned());
+                error!(
+                    "{}. Origin not permitted: {:?}",
+                    MSG_HANDSHAKE_FAILED, origin
+                );
+
+                write_response(
+                    &mut stream,
+                    &mut buf,
+                    StatusCode::FORBIDDEN,
+                    HeaderMap::default(),
+                    None,
+                )
+                .await?;
+                return Err(Error::with_cause(
+                    ErrorKind::Http,
+                    HttpError::ForbiddenOrigin(origin),
+                ));
+            }
+
             trace!(
                 "{}for: {}. Selected subprotocol: {:?} and extension: {:?}",
                 request.uri(),
@@ -131,7 +274,7 @@ where
                 extension
             );
 
-            Ok(WebSocketUpgrader {
+            Ok(Accepted::Upgrade(WebSocketUpgrader {
                 key,
                 buf,
                 stream,
@@ -140,19 +283,34 @@ where
                 subprotocol,
                 extension_header,
                 config,
-            })
+            }))
         }
         Err(e) => {
             error!("{}. Error: {:?}", MSG_HANDSHAKE_FAILED, e);
 
             match e.downcast_ref::<HttpError>() {
                 Some(http_err) => {
+                    let mut headers = HeaderMap::default();
+                    let status = match http_err {
+                        HttpError::TooManyHeaders | HttpError::HeadersTooLarge => {
+                            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+                        }
+                        // rfc6455 § 4.4: advertise the versions that are supported
+                        HttpError::UnsupportedVersion(_) => {
+                            headers.insert(
+                                SEC_WEBSOCKET_VERSION,
+                                HeaderValue::from_static(WEBSOCKET_VERSION_STR),
+                            );
+                            StatusCode::UPGRADE_REQUIRED
+                        }
+                        _ => StatusCode::BAD_REQUEST,
+                    };
                     write_response(
                         &mut stream,
                         &mut buf,
-                        StatusCode::BAD_REQUEST,
-                        HeaderMap::default(),
-                        Some(http_err.to_string()),
+                        status,
+                        headers,
+                        Some(Bytes::from(http_err.to_string())),
                     )
                     .await?;
                     Err(e)
@@ -163,11 +321,93 @@ where
     }
 }
 
-/// A response to send to a client if the connection will not be upgraded.
+/// A parsed request that is either a WebSocket upgrade request or a plain HTTP request.
+pub enum ParsedRequest<E> {
+    Upgrade(UpgradeRequest<E>),
+    Http(Request),
+}
+
+/// Returns whether `headers` contain a request to upgrade to the WebSocket protocol.
+pub(crate) fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers.get_all(UPGRADE).iter().any(|value| {
+        value.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .any(|protocol| protocol.trim().eq_ignore_ascii_case(WEBSOCKET_STR))
+        })
+    })
+}
+
+/// A connection that has been accepted by `WebSocketServerBuilder::accept_request` and which sent
+/// either a WebSocket upgrade request or a plain HTTP request.
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
+pub enum Accepted<S, E> {
+    /// The client requested an upgrade to the WebSocket protocol.
+    Upgrade(WebSocketUpgrader<S, E>),
+    /// The client sent a request that did not request an upgrade, such as a health check.
+    Http(HttpRequest<S>),
+}
+
+/// A plain HTTP request that a client sent instead of a WebSocket upgrade request. A response may
+/// be written to the client over the same stream.
+#[derive(Debug)]
+pub struct HttpRequest<S> {
+    request: Request,
+    stream: S,
+    buf: BytesMut,
+}
+
+impl<S> HttpRequest<S>
+where
+    S: WebSocketStream,
+{
+    /// The request that the client sent.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Respond to the request with the response provided and then shut down the stream.
+    ///
+    /// # Errors
+    /// Errors if there is an IO error.
+    pub async fn respond(self, response: WebSocketResponse) -> Result<(), Error> {
+        let HttpRequest {
+            request,
+            mut stream,
+            mut buf,
+        } = self;
+        let WebSocketResponse {
+            status,
+            headers,
+            body,
+        } = response;
+
+        trace!("Responding with {} to {}", status, request.uri());
+
+        write_response(&mut stream, &mut buf, status, headers, body).await?;
+        stream.shutdown().await.map_err(Into::into)
+    }
+
+    /// Consume self and take the request, the stream and any bytes which were read from the stream
+    /// after the head of the request, such as the start of its body.
+    pub fn into_parts(self) -> (Request, S, BytesMut) {
+        let HttpRequest {
+            request,
+            stream,
+            buf,
+        } = self;
+        (request, stream, buf)
+    }
+}
+
+/// A response to send to a client if the connection will not be upgraded or to a plain HTTP
+/// request.
 #[derive(Debug)]
 pub struct WebSocketResponse {
     status: StatusCode,
     headers: HeaderMap,
+    body: Option<Bytes>,
 }
 
 impl WebSocketResponse {
@@ -179,6 +419,7 @@ impl WebSocketResponse {
         StatusCode::from_u16(code).map(|status| WebSocketResponse {
             status,
             headers: HeaderMap::new(),
+            body: None,
         })
     }
 
@@ -193,8 +434,34 @@ impl WebSocketResponse {
         Ok(WebSocketResponse {
             status: StatusCode::from_u16(code)?,
             headers: HeaderMap::from_iter(headers),
+            body: None,
         })
     }
+
+    /// Inserts a header into the response, replacing any existing values for `name`.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> WebSocketResponse {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the body of the response. A `content-length` header is sent with the body.
+    pub fn body<B>(mut self, body: B) -> WebSocketResponse
+    where
+        B: Into<Bytes>,
+    {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// The status code of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
 }
 
 /// Represents a client connection that has been accepted and the upgrade request validated. This
@@ -222,16 +489,59 @@ where
         self.subprotocol.as_ref()
     }
 
+    /// Negotiates the subprotocol for this connection using `subprotocols`, replacing the
+    /// subprotocol that was negotiated when the request was accepted. This allows a server to
+    /// provide a registry for each connection, such as from the path of the request.
+    ///
+    /// # Errors
+    /// Errors if the client sent a malformed sec-websocket-protocol header.
+    pub fn negotiate_subprotocol(
+        &mut self,
+        subprotocols: &SubprotocolRegistry,
+    ) -> Result<Option<&String>, Error> {
+        self.subprotocol = subprotocols.negotiate_request(&self.request)?;
+        Ok(self.subprotocol.as_ref())
+    }
+
     /// The URI that the client has requested.
     pub fn uri(&self) -> &Uri {
         self.request.uri()
     }
 
+    /// The method of the request that the client sent.
+    pub fn method(&self) -> &Method {
+        self.request.method()
+    }
+
+    /// The query of the URI that the client has requested, if one was provided.
+    pub fn query(&self) -> Option<&str> {
+        self.request.uri().query()
+    }
+
+    /// The headers of the request that the client sent.
+    pub fn headers(&self) -> &HeaderMap {
+        self.request.headers()
+    }
+
     /// The original request that the client sent.
     pub fn request(&self) -> &Request {
         &self.request
     }
 
+    /// Resolves the address of the client that sent the request over a connection from `peer`,
+    /// using the forwarding headers that were set by any trusted proxies. See
+    /// [`server::client_addr`](crate::server::client_addr).
+    pub fn client_addr(&self, peer: IpAddr, trusted_proxies: &TrustedProxies) -> ClientAddr {
+        client_addr(self.request.headers(), peer, trusted_proxies)
+    }
+
+    /// The extensions of the request that the client sent. Any extensions that are inserted, such
+    /// as the result of authenticating the client, are available from
+    /// [`UpgradedServer::request`] once the connection has been upgraded.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        self.request.extensions_mut()
+    }
+
     /// Attempt to upgrade this to a fully negotiated WebSocket connection.
     ///
     /// # Errors
@@ -257,11 +567,7 @@ where
             config,
         } = self;
 
-        let mut digest = Sha1::new();
-        Digest::update(&mut digest, key);
-        Digest::update(&mut digest, ACCEPT_KEY);
-
-        let sec_websocket_accept = STANDARD.encode(digest.finalize());
+        let sec_websocket_accept = accept_key(key);
         headers.insert(
             http::header::SEC_WEBSOCKET_ACCEPT,
             HeaderValue::try_from(sec_websocket_accept)?,
@@ -280,8 +586,11 @@ where
                 HeaderValue::try_from(subprotocol)?,
             );
         }
-        if let Some(extension_header) = extension_header {
-            headers.insert(http::header::SEC_WEBSOCKET_EXTENSIONS, extension_header);
+        if let Some(extension_header) = &extension_header {
+            headers.insert(
+                http::header::SEC_WEBSOCKET_EXTENSIONS,
+                extension_header.clone(),
+            );
         }
 
         write_response(
@@ -301,15 +610,20 @@ where
             request,
             websocket: WebSocket::from_upgraded(config, stream, extension, buf, Role::Server),
             subprotocol,
+            extension_header,
         })
     }
 
-    /// Reject this connection with the response provided.
+    /// Reject this connection with the response provided and then shut down the stream.
     ///
     /// # Errors
     /// Errors if there is an IO error.
     pub async fn reject(self, response: WebSocketResponse) -> Result<(), Error> {
-        let WebSocketResponse { status, headers } = response;
+        let WebSocketResponse {
+            status,
+            headers,
+            body,
+        } = response;
         let WebSocketUpgrader {
             mut stream,
             mut buf,
@@ -319,10 +633,41 @@ where
 
         trace!("{} from {}", REJECT_MSG, request.uri());
 
-        write_response(&mut stream, &mut buf, status, headers, None).await
+        write_response(&mut stream, &mut buf, status, headers, body).await?;
+        stream.shutdown().await.map_err(Into::into)
+    }
+
+    /// Invoke `decide` with the request that the client sent and then either upgrade or reject
+    /// this connection once the future that it returns resolves. This allows asynchronous work,
+    /// such as looking up a client's credentials, to be completed before a response is written.
+    ///
+    /// # Returns
+    /// The upgraded connection if the decision was to accept it or `None` if it was rejected.
+    ///
+    /// # Errors
+    /// Errors if there is an IO error.
+    pub async fn decide<F, Fut>(self, decide: F) -> Result<Option<UpgradedServer<S, E>>, Error>
+    where
+        F: FnOnce(&Request) -> Fut,
+        Fut: Future<Output = UpgradeDecision>,
+    {
+        match decide(&self.request).await {
+            UpgradeDecision::Accept(headers) => self.upgrade_with(headers).await.map(Some),
+            UpgradeDecision::Reject(response) => self.reject(response).await.map(|_| None),
+        }
     }
 }
 
+/// A decision over whether to upgrade a connection, made by the future provided to
+/// `WebSocketUpgrader::decide`.
+#[derive(Debug)]
+pub enum UpgradeDecision {
+    /// Upgrade the connection, inserting the headers into the response.
+    Accept(HeaderMap),
+    /// Reject the connection with the response provided.
+    Reject(WebSocketResponse),
+}
+
 /// Represents a parsed WebSocket connection upgrade HTTP request without the context of the
 /// request that it is responding to.
 #[derive(Debug)]
@@ -411,6 +756,14 @@ pub struct UpgradeRequest<E, B = ()> {
     pub extension_header: Option<HeaderValue>,
 }
 
+impl<E, B> UpgradeRequest<E, B> {
+    /// Resolves the address of the client that sent this request over a connection from `peer`,
+    /// using the forwarding headers that were set by any trusted proxies. See [`client_addr`].
+    pub fn client_addr(&self, peer: IpAddr, trusted_proxies: &TrustedProxies) -> ClientAddr {
+        client_addr(self.request.headers(), peer, trusted_proxies)
+    }
+}
+
 /// Builds an HTTP response to a WebSocket connection upgrade request.
 ///
 /// No validation is performed by this function and it is only guaranteed to be correct if the
@@ -472,10 +825,10 @@ pub fn build_response(
 /// # Arguments
 ///
 /// - `request`: The incoming HTTP request from the client, which contains headers related to the
-///  WebSocket upgrade request.
+///   WebSocket upgrade request.
 /// - `extension`: An extension that may be negotiated for the connection.
 /// - `subprotocols`: A `SubprotocolRegistry`, which will be used to attempt to negotiate a
-/// subprotocol.
+///   subprotocol.
 ///
 /// # Returns
 ///
@@ -486,9 +839,9 @@ pub fn build_response(
 /// - `E`: The type of the extension provider, which must implement the `ExtensionProvider`
 ///   trait. This defines how WebSocket extensions (like compression) are handled.
 /// - `B`: The body type of the HTTP request. While it is discouraged for GET requests to have a body
-///  it is not technically incorrect and the use of this function is lowering the guardrails to
-///  allow for Ratchet to be more easily integrated into other libraries. It is the implementors
-///  responsibility to perform any validation on the body.
+///   it is not technically incorrect and the use of this function is lowering the guardrails to
+///   allow for Ratchet to be more easily integrated into other libraries. It is the implementors
+///   responsibility to perform any validation on the body.
 ///
 /// # Errors
 ///
@@ -505,19 +858,21 @@ where
     E: ExtensionProvider,
 {
     let (parts, _body) = request.into_parts();
-    let Parts {
-        method,
-        version,
-        headers,
-        ..
-    } = parts;
+    let request = Request::from_parts(parts, ());
     let UpgradeRequestParts {
         key,
         subprotocol,
         extension,
         extension_header,
         ..
-    } = parse_request_parts(version, &method, &headers, extension, subprotocols)?;
+    } = parse_request_parts_with(
+        request.version(),
+        request.method(),
+        request.headers(),
+        extension,
+        false,
+        || subprotocols.negotiate_request(&request),
+    )?;
     Ok(UpgradeResponseParts {
         response: build_response(key, subprotocol.clone(), extension_header)?,
         subprotocol,
@@ -530,10 +885,10 @@ where
 /// # Arguments
 ///
 /// - `headers`: A reference to the request's `HeaderMap` containing the HTTP headers. These headers
-///  must include the necessary WebSocket headers such as `Sec-WebSocket-Key` and `Upgrade`.
+///   must include the necessary WebSocket headers such as `Sec-WebSocket-Key` and `Upgrade`.
 /// - `extension`: An extension that may be negotiated for the connection.
 /// - `subprotocols`: A `SubprotocolRegistry`, which will be used to attempt to negotiate a
-/// subprotocol.
+///   subprotocol.
 ///
 /// # Returns
 ///
@@ -599,17 +954,13 @@ where
 ///
 /// # Returns
 /// - `Result<HeaderMap, Error>`: A result that contains either a `HeaderMap` with the constructed
-///  headers or an `Error` if an issue occurs while creating the headers.
+///   headers or an `Error` if an issue occurs while creating the headers.
 pub fn build_response_headers(
     key: Bytes,
     subprotocol: Option<String>,
     extension_header: Option<HeaderValue>,
 ) -> Result<HeaderMap, Error> {
-    let mut digest = Sha1::new();
-    Digest::update(&mut digest, key);
-    Digest::update(&mut digest, ACCEPT_KEY);
-
-    let sec_websocket_accept = STANDARD.encode(digest.finalize());
+    let sec_websocket_accept = accept_key(key);
 
     let mut map = HeaderMap::default();
 
@@ -649,12 +1000,12 @@ pub fn build_response_headers(
 /// - `version`: The HTTP version of the request.
 /// - `method`: The HTTP method of the request.
 /// - `headers`: A reference to the request's `HeaderMap` containing the HTTP headers. These headers
-///  must include the necessary WebSocket headers such as `Sec-WebSocket-Key` and `Upgrade`.
+///   must include the necessary WebSocket headers such as `Sec-WebSocket-Key` and `Upgrade`.
 /// - `extension`: An instance of a type that implements the `ExtensionProvider`
-/// trait. This object is responsible for negotiating any server-supported
-/// extensions requested by the client.
+///   trait. This object is responsible for negotiating any server-supported
+///   extensions requested by the client.
 /// - `subprotocols`: A `SubprotocolRegistry`, which manages the supported subprotocols and attempts
-/// to negotiate one with the client.
+///   to negotiate one with the client.
 ///
 /// # Returns
 /// This function returns a `Result<UpgradeRequestParts<E::Extension>, Error>`, where:
@@ -672,28 +1023,61 @@ pub fn parse_request_parts<E>(
 ) -> Result<UpgradeRequestParts<E::Extension>, Error>
 where
     E: ExtensionProvider,
+{
+    parse_request_parts_with(version, method, headers, extension, false, || {
+        subprotocols.negotiate_client(headers)
+    })
+}
+
+/// Parses the parts of an upgrade request, negotiating its subprotocol using
+/// `negotiate_subprotocol` once the request has been validated. If `lenient` is set then the
+/// request is validated as described by `WebSocketConfig::lenient_handshake`.
+pub(crate) fn parse_request_parts_with<E, F>(
+    version: Version,
+    method: &Method,
+    headers: &HeaderMap,
+    extension: E,
+    lenient: bool,
+    negotiate_subprotocol: F,
+) -> Result<UpgradeRequestParts<E::Extension>, Error>
+where
+    E: ExtensionProvider,
+    F: FnOnce() -> Result<Option<String>, ProtocolError>,
 {
     validate_method_and_version(version, method)?;
-    validate_header_any(headers, http::header::CONNECTION, UPGRADE_STR)?;
-    validate_header_value(headers, http::header::UPGRADE, WEBSOCKET_STR)?;
-    validate_header_value(
-        headers,
-        http::header::SEC_WEBSOCKET_VERSION,
-        WEBSOCKET_VERSION_STR,
-    )?;
 
-    if let Err(e) = validate_host_header(headers) {
-        error!("Server responded with invalid 'host' headers");
-        return Err(e);
+    if lenient {
+        validate_header_token(headers, http::header::CONNECTION, UPGRADE_STR)?;
+        validate_header_token(headers, http::header::UPGRADE, WEBSOCKET_STR)?;
+        if headers.contains_key(SEC_WEBSOCKET_VERSION) {
+            validate_header_token(headers, SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION_STR)
+                .map_err(unsupported_version)?;
+        }
+    } else {
+        validate_header_any(headers, http::header::CONNECTION, UPGRADE_STR)?;
+        validate_header_value(headers, http::header::UPGRADE, WEBSOCKET_STR)?;
+        validate_header_value(headers, SEC_WEBSOCKET_VERSION, WEBSOCKET_VERSION_STR)
+            .map_err(unsupported_version)?;
+
+        if let Err(e) = validate_host_header(headers) {
+            error!("Server responded with invalid 'host' headers");
+            return Err(e);
+        }
     }
 
     let key = headers
         .get(SEC_WEBSOCKET_KEY)
-        .map(|v| Bytes::from(v.as_bytes().to_vec()))
+        .map(|v| {
+            if lenient {
+                Bytes::copy_from_slice(trim_whitespace(v.as_bytes()))
+            } else {
+                Bytes::from(v.as_bytes().to_vec())
+            }
+        })
         .ok_or_else(|| {
             Error::with_cause(ErrorKind::Http, HttpError::MissingHeader(SEC_WEBSOCKET_KEY))
         })?;
-    let subprotocol = subprotocols.negotiate_client(headers)?;
+    let subprotocol = negotiate_subprotocol()?;
     let (extension, extension_header) = extension
         .negotiate_server(headers)
         .map(Option::unzip)
@@ -707,6 +1091,18 @@ where
     })
 }
 
+/// Maps an invalid `Sec-WebSocket-Version` header error to an error containing the supported
+/// versions.
+fn unsupported_version(e: Error) -> Error {
+    match e.downcast_ref::<HttpError>() {
+        Some(HttpError::InvalidHeader(_)) => Error::with_cause(
+            ErrorKind::Http,
+            HttpError::UnsupportedVersion(vec![WEBSOCKET_VERSION_STR.to_string()]),
+        ),
+        _ => e,
+    }
+}
+
 fn check_partial_request(request: &httparse::Request) -> Result<(), Error> {
     match request.version {
         Some(HTTP_VERSION_INT) | None => {}
@@ -0,0 +1,50 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use http::header::ORIGIN;
+use http::{HeaderMap, HeaderValue};
+
+/// A policy which decides whether a server accepts an upgrade request from its `Origin` header.
+///
+/// Browsers always send the `Origin` header in an upgrade request and validating it prevents
+/// other sites from opening a WebSocket connection using a user's credentials. RFC 6455 10.2.
+#[derive(Clone, Debug, Default)]
+pub enum OriginPolicy {
+    /// Requests are accepted from any origin.
+    #[default]
+    Any,
+    /// Requests are only accepted if their origin matches, ignoring ASCII case, one of these
+    /// origins, such as `https://example.com`. Requests without an `Origin` header are accepted
+    /// as they have not been sent by a browser.
+    AllowList(Vec<String>),
+    /// Requests are accepted if this function returns true when it is provided with the request's
+    /// `Origin` header, if one was sent.
+    Filter(fn(Option<&HeaderValue>) -> bool),
+}
+
+impl OriginPolicy {
+    /// Returns whether a request with `headers` is permitted by this policy.
+    pub fn permits(&self, headers: &HeaderMap) -> bool {
+        let origin = headers.get(ORIGIN);
+        match self {
+            OriginPolicy::Any => true,
+            OriginPolicy::AllowList(allowed) => match origin {
+                Some(origin) => allowed
+                    .iter()
+                    .any(|allowed| allowed.as_bytes().eq_ignore_ascii_case(origin.as_bytes())),
+                None => true,
+            },
+            OriginPolicy::Filter(filter) => filter(origin),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::handshake::io::BufferedIo;
+use crate::handshake::StreamingParser;
+use crate::{Error, ErrorKind, HttpError};
+use bytes::BytesMut;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::AsyncRead;
+use tokio_util::codec::Decoder;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// The maximum length of a v1 header, including its CRLF.
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// The addresses of a connection that were received in a
+/// [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt) header, which a
+/// proxy sends before forwarding the connection.
+///
+/// When a server is configured to read the header, it is inserted into the extensions of the
+/// request that the client sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    /// The address of the client that connected to the proxy. This is `None` if the proxy did not
+    /// provide an address, such as for a health check by the proxy itself or a connection over a
+    /// socket that is not TCP.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// The address that the client connected to.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+}
+
+/// Reads a PROXY protocol header, of either version 1 or 2, from `stream`.
+///
+/// # Returns
+/// The header and any bytes which were read from the stream after it.
+///
+/// # Errors
+/// Errors if the stream does not start with a valid header or if an IO error occurs.
+pub async fn read_proxy_header<S>(stream: &mut S) -> Result<(ProxyHeader, BytesMut), Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = BytesMut::new();
+    let header = read_proxy_header_into(stream, &mut buf).await?;
+    Ok((header, buf))
+}
+
+pub(crate) async fn read_proxy_header_into<S>(
+    stream: &mut S,
+    buf: &mut BytesMut,
+) -> Result<ProxyHeader, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut io = BufferedIo::new(stream, buf);
+    StreamingParser::new(&mut io, ProxyHeaderParser)
+        .parse()
+        .await
+}
+
+struct ProxyHeaderParser;
+
+impl Decoder for ProxyHeaderParser {
+    type Item = (ProxyHeader, usize);
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        parse_proxy_header(buf)
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::with_cause(
+        ErrorKind::Http,
+        HttpError::InvalidProxyHeader(reason.to_string()),
+    )
+}
+
+/// Attempts to parse a PROXY protocol header from the start of `buf`.
+///
+/// # Returns
+/// The header and its length, or `None` if more bytes are required.
+pub(crate) fn parse_proxy_header(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, Error> {
+    let prefix_len = buf.len().min(V2_SIGNATURE.len());
+    if buf[..prefix_len] == V2_SIGNATURE[..prefix_len] {
+        return if buf.len() < V2_HEADER_LEN {
+            Ok(None)
+        } else {
+            parse_v2(buf)
+        };
+    }
+
+    let prefix_len = buf.len().min(V1_PREFIX.len());
+    if buf[..prefix_len] == V1_PREFIX[..prefix_len] {
+        return parse_v1(buf);
+    }
+
+    Err(invalid("Missing header"))
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, Error> {
+    let end = match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() < V1_MAX_LEN => return Ok(None),
+        None => return Err(invalid("Header too long")),
+    };
+    if end + 2 > V1_MAX_LEN {
+        return Err(invalid("Header too long"));
+    }
+
+    let line = std::str::from_utf8(&buf[V1_PREFIX.len()..end]).map_err(|_| invalid("Encoding"))?;
+    let mut fields = line.split(' ');
+
+    let header = match fields.next() {
+        Some("UNKNOWN") => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+        Some(protocol @ ("TCP4" | "TCP6")) => {
+            let mut next = || fields.next().ok_or_else(|| invalid("Missing address"));
+            let (source, destination) = (next()?, next()?);
+            let (source_port, destination_port) = (next()?, next()?);
+
+            let parse_ip = |ip: &str| -> Result<IpAddr, Error> {
+                let ip = ip.parse().map_err(|_| invalid("Invalid address"))?;
+                match (protocol, ip) {
+                    ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => Ok(ip),
+                    _ => Err(invalid("Mismatched address family")),
+                }
+            };
+            let parse_port = |port: &str| -> Result<u16, Error> {
+                port.parse().map_err(|_| invalid("Invalid port"))
+            };
+
+            if fields.next().is_some() {
+                return Err(invalid("Unexpected field"));
+            }
+
+            ProxyHeader {
+                source: Some(SocketAddr::new(parse_ip(source)?, parse_port(source_port)?)),
+                destination: Some(SocketAddr::new(
+                    parse_ip(destination)?,
+                    parse_port(destination_port)?,
+                )),
+            }
+        }
+        _ => return Err(invalid("Unsupported protocol")),
+    };
+
+    Ok(Some((header, end + 2)))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, Error> {
+    let version_command = buf[12];
+    let family = buf[13];
+    let len = usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported version"));
+    }
+    if buf.len() < V2_HEADER_LEN + len {
+        return Ok(None);
+    }
+
+    let addresses = &buf[V2_HEADER_LEN..V2_HEADER_LEN + len];
+    let unknown = ProxyHeader {
+        source: None,
+        destination: None,
+    };
+
+    let header = match version_command & 0x0f {
+        // LOCAL: the connection was established by the proxy itself.
+        0x0 => unknown,
+        // PROXY
+        0x1 => match family {
+            // TCP or UDP over IPv4
+            0x11 | 0x12 => {
+                if addresses.len() < 12 {
+                    return Err(invalid("Truncated addresses"));
+                }
+                let ip = |at: usize| {
+                    IpAddr::V4(Ipv4Addr::new(
+                        addresses[at],
+                        addresses[at + 1],
+                        addresses[at + 2],
+                        addresses[at + 3],
+                    ))
+                };
+                let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+                ProxyHeader {
+                    source: Some(SocketAddr::new(ip(0), port(8))),
+                    destination: Some(SocketAddr::new(ip(4), port(10))),
+                }
+            }
+            // TCP or UDP over IPv6
+            0x21 | 0x22 => {
+                if addresses.len() < 36 {
+                    return Err(invalid("Truncated addresses"));
+                }
+                let ip = |at: usize| {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(&addresses[at..at + 16]);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                };
+                let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+                ProxyHeader {
+                    source: Some(SocketAddr::new(ip(0), port(32))),
+                    destination: Some(SocketAddr::new(ip(16), port(34))),
+                }
+            }
+            // Unspecified and Unix sockets
+            _ => unknown,
+        },
+        _ => return Err(invalid("Unsupported command")),
+    };
+
+    Ok(Some((header, V2_HEADER_LEN + len)))
+}
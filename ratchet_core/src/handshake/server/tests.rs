@@ -15,16 +15,21 @@
 use crate::handshake::{UPGRADE_STR, WEBSOCKET_STR, WEBSOCKET_VERSION_STR};
 use crate::test_fixture::{mock, ReadError};
 use crate::{
-    accept_with, Error, ErrorKind, HttpError, NoExtProvider, SubprotocolRegistry, WebSocketConfig,
+    accept_with, accept_with_buffer, Accepted, Error, ErrorKind, HttpError, NoExtProvider,
+    OriginPolicy, ProxyHeader, SubprotocolRegistry, TimeoutCause, UpgradeDecision, WebSocketConfig,
+    WebSocketResponse, WebSocketServerBuilder,
 };
 use bytes::BytesMut;
+use either::Either;
 use http::header::HeaderName;
-use http::{HeaderMap, HeaderValue, Method, Request, Response, Version};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Version};
 use ratchet_ext::{
-    Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider, FrameHeader,
-    ReunitableExtension, RsvBits, SplittableExtension,
+    BoxExtensionError, BoxExtensionProvider, Extension, ExtensionDecoder, ExtensionEncoder,
+    ExtensionProvider, FrameHeader, ReunitableExtension, RsvBits, SplittableExtension,
 };
 use std::convert::Infallible;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 impl From<ReadError<httparse::Error>> for Error {
     fn from(e: ReadError<httparse::Error>) -> Self {
@@ -237,6 +242,28 @@ impl ExtensionProvider for BadExtProvider {
     }
 }
 
+struct HeaderExtProvider;
+impl ExtensionProvider for HeaderExtProvider {
+    type Extension = Ext;
+    type Error = ExtErr;
+
+    fn apply_headers(&self, _headers: &mut HeaderMap) {}
+
+    fn negotiate_client(
+        &self,
+        _headers: &HeaderMap,
+    ) -> Result<Option<Self::Extension>, Self::Error> {
+        panic!("Unexpected client negotiation request")
+    }
+
+    fn negotiate_server(
+        &self,
+        _headers: &HeaderMap,
+    ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
+        Ok(Some((Ext, HeaderValue::from_static("ext; param=1"))))
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct Ext;
 
@@ -326,6 +353,85 @@ async fn bad_extension() {
     }
 }
 
+#[tokio::test]
+async fn bad_boxed_extension() {
+    let (mut client, server) = mock();
+    client.write_request(valid_request()).await.unwrap();
+
+    let provider: BoxExtensionProvider = Box::new(BadExtProvider);
+    let result = accept_with(
+        server,
+        WebSocketConfig::default(),
+        provider,
+        SubprotocolRegistry::default(),
+    )
+    .await;
+
+    match result {
+        Ok(_) => {
+            panic!("Expected the connection to fail")
+        }
+        Err(e) => {
+            let cause = e
+                .downcast_ref::<BoxExtensionError>()
+                .and_then(|e| e.get_ref().downcast_ref::<ExtErr>());
+            if cause.is_none() {
+                panic!("{:?}", e);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn either_extension() {
+    async fn accept(provider: Either<NoExtProvider, BadExtProvider>) -> Result<(), Error> {
+        let (mut client, server) = mock();
+        client.write_request(valid_request()).await.unwrap();
+
+        accept_with(
+            server,
+            WebSocketConfig::default(),
+            provider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    assert!(accept(Either::Left(NoExtProvider)).await.is_ok());
+
+    let err = accept(Either::Right(BadExtProvider))
+        .await
+        .expect_err("Expected the connection to fail");
+    assert!(matches!(
+        err.downcast_ref::<Either<Infallible, ExtErr>>(),
+        Some(Either::Right(ExtErr))
+    ));
+}
+
+#[tokio::test]
+async fn exposes_extension_header() {
+    let (mut client, server) = mock();
+    client.write_request(valid_request()).await.unwrap();
+
+    let upgrader = accept_with(
+        server,
+        WebSocketConfig::default(),
+        HeaderExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .unwrap();
+    let upgraded = upgrader.upgrade().await.unwrap();
+    assert_eq!(upgraded.extension_header.unwrap(), "ext; param=1");
+
+    let response = client.read_response().await.unwrap();
+    assert_eq!(
+        response.headers()[http::header::SEC_WEBSOCKET_EXTENSIONS],
+        "ext; param=1"
+    );
+}
+
 #[tokio::test]
 async fn multiple_connection_headers() {
     let request = Request::builder()
@@ -378,3 +484,640 @@ async fn user_defined_headers() {
     .await
     .unwrap();
 }
+
+async fn exec_request_with_config(
+    request: Request<()>,
+    config: WebSocketConfig,
+) -> (Result<(), Error>, BytesMut) {
+    let (mut client, server) = mock();
+
+    client.write_request(request).await.unwrap();
+
+    let result = accept_with(
+        server,
+        config,
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .map(|_| ());
+
+    let mut response = BytesMut::new();
+    client.read_buf(&mut response).await.unwrap();
+    (result, response)
+}
+
+#[tokio::test]
+async fn too_many_headers() {
+    let config = WebSocketConfig {
+        max_headers: 4,
+        ..Default::default()
+    };
+    let (result, response) = exec_request_with_config(valid_request(), config).await;
+
+    let err = result.expect_err("Expected a test failure");
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::TooManyHeaders)
+    );
+    assert!(response.starts_with(b"HTTP/1.1 431 "));
+}
+
+#[tokio::test]
+async fn headers_too_large() {
+    let config = WebSocketConfig {
+        max_header_bytes: Some(64),
+        ..Default::default()
+    };
+    let (result, response) = exec_request_with_config(valid_request(), config).await;
+
+    let err = result.expect_err("Expected a test failure");
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::HeadersTooLarge)
+    );
+    assert!(response.starts_with(b"HTTP/1.1 431 "));
+}
+
+#[tokio::test(start_paused = true)]
+async fn handshake_timeout() {
+    let (_client, server) = mock();
+    let config = WebSocketConfig {
+        handshake_timeout: Some(Duration::from_secs(5)),
+        ..Default::default()
+    };
+
+    let err = accept_with(
+        server,
+        config,
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .expect_err("Expected a timeout");
+    assert!(err.is_timeout());
+    assert_eq!(
+        err.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Handshake)
+    );
+}
+
+#[tokio::test]
+async fn origin_policy() {
+    async fn test(policy: OriginPolicy, origin: Option<&'static str>, permitted: bool) {
+        let (mut client, server) = mock();
+
+        let mut request = valid_request();
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert(http::header::ORIGIN, HeaderValue::from_static(origin));
+        }
+        client.write_request(request).await.unwrap();
+
+        let result = WebSocketServerBuilder::default()
+            .origin_policy(policy)
+            .accept(server)
+            .await;
+
+        let mut response = BytesMut::new();
+        client.read_buf(&mut response).await.unwrap();
+
+        if permitted {
+            assert!(result.is_ok());
+            assert!(response.starts_with(b"HTTP/1.1 101 "));
+        } else {
+            let err = result.expect_err("Expected a test failure");
+            assert_eq!(
+                err.downcast_ref::<HttpError>(),
+                Some(&HttpError::ForbiddenOrigin(origin.map(ToString::to_string)))
+            );
+            assert!(response.starts_with(b"HTTP/1.1 403 "));
+        }
+    }
+
+    let allow_list = || OriginPolicy::AllowList(vec!["https://example.com".to_string()]);
+    test(allow_list(), Some("https://EXAMPLE.com"), true).await;
+    test(allow_list(), Some("https://attacker.com"), false).await;
+    test(allow_list(), None, true).await;
+
+    fn requires_origin(origin: Option<&HeaderValue>) -> bool {
+        origin.is_some()
+    }
+    test(OriginPolicy::Filter(requires_origin), None, false).await;
+    test(
+        OriginPolicy::Filter(requires_origin),
+        Some("https://example.com"),
+        true,
+    )
+    .await;
+    test(OriginPolicy::Any, Some("https://attacker.com"), true).await;
+}
+
+#[tokio::test]
+async fn subprotocol_selector() {
+    async fn test(offered: Option<&'static str>, uri: &str, expected: Option<&str>) {
+        let (mut client, server) = mock();
+
+        let mut request = valid_request();
+        *request.uri_mut() = uri.parse().unwrap();
+        if let Some(offered) = offered {
+            request.headers_mut().insert(
+                http::header::SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(offered),
+            );
+        }
+        client.write_request(request).await.unwrap();
+
+        let registry = SubprotocolRegistry::with_selector(|offered, request| {
+            if request.uri().path() == "/chat" {
+                offered.last().map(ToString::to_string)
+            } else {
+                Some("unoffered".to_string())
+            }
+        });
+        let upgrader = accept_with(server, WebSocketConfig::default(), NoExtProvider, registry)
+            .await
+            .unwrap();
+
+        assert_eq!(upgrader.subprotocol().map(String::as_str), expected);
+
+        let _upgraded = upgrader.upgrade().await.unwrap();
+        let response = client.read_response().await.unwrap();
+        assert_eq!(
+            response
+                .headers()
+                .get(http::header::SEC_WEBSOCKET_PROTOCOL)
+                .map(|value| value.to_str().unwrap()),
+            expected
+        );
+    }
+
+    test(Some("warp, warps"), "/chat", Some("warps")).await;
+    test(None, "/chat", None).await;
+    test(Some("warp, warps"), "/other", None).await;
+}
+
+#[tokio::test]
+async fn negotiate_subprotocol_per_connection() {
+    let (mut client, server) = mock();
+
+    let mut request = valid_request();
+    *request.uri_mut() = "/chat".parse().unwrap();
+    request.headers_mut().insert(
+        http::header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static("warp, warps"),
+    );
+    client.write_request(request).await.unwrap();
+
+    let mut upgrader = accept_with(
+        server,
+        WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::new(["warp"]).unwrap(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(upgrader.subprotocol().map(String::as_str), Some("warp"));
+
+    let registry = match upgrader.uri().path() {
+        "/chat" => SubprotocolRegistry::new(["warps", "warp"]).unwrap(),
+        _ => SubprotocolRegistry::default(),
+    };
+    let subprotocol = upgrader.negotiate_subprotocol(&registry).unwrap();
+    assert_eq!(subprotocol.map(String::as_str), Some("warps"));
+
+    let _upgraded = upgrader.upgrade().await.unwrap();
+    let response = client.read_response().await.unwrap();
+    assert_eq!(
+        response.headers().get(http::header::SEC_WEBSOCKET_PROTOCOL),
+        Some(&HeaderValue::from_static("warps"))
+    );
+}
+
+#[tokio::test]
+async fn reject_with_body() {
+    let (mut client, server) = mock();
+    client.write_request(valid_request()).await.unwrap();
+
+    let upgrader = accept_with(
+        server,
+        WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .unwrap();
+
+    let response = WebSocketResponse::new(401)
+        .unwrap()
+        .header(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )
+        .body(r#"{"error":"unauthorized"}"#);
+    upgrader.reject(response).await.unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+
+    let mut headers = [httparse::EMPTY_HEADER; 4];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let len = match parsed.parse(&response).unwrap() {
+        httparse::Status::Complete(len) => len,
+        httparse::Status::Partial => panic!("Expected a complete response"),
+    };
+
+    assert_eq!(parsed.code, Some(401));
+    let header = |name: &str| {
+        parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    };
+    assert_eq!(header("content-type"), Some(&b"application/json"[..]));
+    assert_eq!(header("content-length"), Some(&b"24"[..]));
+    assert_eq!(&response[len..], br#"{"error":"unauthorized"}"#);
+}
+
+#[tokio::test]
+async fn plain_http_request() {
+    let (mut client, server) = mock();
+
+    let request = Request::builder()
+        .uri("/healthz")
+        .header(http::header::HOST, "localtoast")
+        .body(())
+        .unwrap();
+    client.write_request(request).await.unwrap();
+
+    let accepted = WebSocketServerBuilder::default()
+        .accept_request(server)
+        .await
+        .unwrap();
+    let request = match accepted {
+        Accepted::Http(request) => request,
+        Accepted::Upgrade(_) => panic!("Expected a plain HTTP request"),
+    };
+    assert_eq!(request.request().uri(), "/healthz");
+
+    request
+        .respond(WebSocketResponse::new(200).unwrap().body("ok"))
+        .await
+        .unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200 "));
+    assert!(response.ends_with(b"\r\n\r\nok"));
+}
+
+#[tokio::test]
+async fn accept_request_upgrade() {
+    let (mut client, server) = mock();
+    client.write_request(valid_request()).await.unwrap();
+
+    let accepted = WebSocketServerBuilder::default()
+        .accept_request(server)
+        .await
+        .unwrap();
+    match accepted {
+        Accepted::Upgrade(upgrader) => {
+            upgrader.upgrade().await.unwrap();
+        }
+        Accepted::Http(_) => panic!("Expected an upgrade request"),
+    }
+
+    let response = client.read_response().await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+}
+
+#[tokio::test]
+async fn deferred_decision() {
+    async fn test(token: Option<&'static str>, upgraded: bool) {
+        let (mut client, server) = mock();
+
+        let mut request = valid_request();
+        if let Some(token) = token {
+            request
+                .headers_mut()
+                .insert(http::header::AUTHORIZATION, HeaderValue::from_static(token));
+        }
+        client.write_request(request).await.unwrap();
+
+        let upgrader = accept_with(
+            server,
+            WebSocketConfig::default(),
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        )
+        .await
+        .unwrap();
+
+        let result = upgrader
+            .decide(|request| {
+                let token = request.headers().get(http::header::AUTHORIZATION).cloned();
+                async move {
+                    tokio::task::yield_now().await;
+                    match token {
+                        Some(token) if token == "Bearer token" => {
+                            UpgradeDecision::Accept(HeaderMap::default())
+                        }
+                        _ => UpgradeDecision::Reject(WebSocketResponse::new(401).unwrap()),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(result.is_some(), upgraded);
+
+        let mut response = BytesMut::new();
+        client.read_buf(&mut response).await.unwrap();
+        if upgraded {
+            assert!(response.starts_with(b"HTTP/1.1 101 "));
+        } else {
+            assert!(response.starts_with(b"HTTP/1.1 401 "));
+        }
+    }
+
+    test(Some("Bearer token"), true).await;
+    test(Some("Bearer other"), false).await;
+    test(None, false).await;
+}
+
+#[tokio::test]
+async fn exposes_request() {
+    #[derive(Clone, Debug, PartialEq)]
+    struct User(&'static str);
+
+    let (mut client, server) = mock();
+
+    let mut request = valid_request();
+    *request.uri_mut() = "/rooms/1?user=a&token=b".parse().unwrap();
+    request.headers_mut().insert(
+        http::header::AUTHORIZATION,
+        HeaderValue::from_static("Bearer token"),
+    );
+    client.write_request(request).await.unwrap();
+
+    let mut upgrader = accept_with(
+        server,
+        WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(upgrader.method(), Method::GET);
+    assert_eq!(upgrader.uri().path(), "/rooms/1");
+    assert_eq!(upgrader.query(), Some("user=a&token=b"));
+    assert_eq!(
+        upgrader.headers().get(http::header::AUTHORIZATION),
+        Some(&HeaderValue::from_static("Bearer token"))
+    );
+    assert_eq!(
+        upgrader.headers().get(http::header::HOST),
+        Some(&HeaderValue::from_static("localtoast"))
+    );
+
+    upgrader.extensions_mut().insert(User("a"));
+
+    let upgraded = upgrader.upgrade().await.unwrap();
+    assert_eq!(upgraded.request.extensions().get(), Some(&User("a")));
+    assert_eq!(upgraded.request.uri().query(), Some("user=a&token=b"));
+}
+
+#[test]
+fn parses_proxy_headers() {
+    use crate::handshake::server::proxy_protocol::parse_proxy_header;
+    use std::net::SocketAddr;
+
+    let addr = |addr: &str| Some(addr.parse::<SocketAddr>().unwrap());
+
+    let v1 = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET /";
+    let (header, len) = parse_proxy_header(v1).unwrap().unwrap();
+    assert_eq!(len, v1.len() - 5);
+    assert_eq!(header.source(), addr("192.168.0.1:56324"));
+    assert_eq!(header.destination(), addr("192.168.0.11:443"));
+
+    let v1 = b"PROXY TCP6 ::1 ::2 1 2\r\n";
+    let (header, _) = parse_proxy_header(v1).unwrap().unwrap();
+    assert_eq!(header.source(), addr("[::1]:1"));
+
+    let (header, _) = parse_proxy_header(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+    assert_eq!(header.source(), None);
+
+    assert!(parse_proxy_header(b"PROXY TCP4 192.168").unwrap().is_none());
+    assert!(parse_proxy_header(b"PROXY TCP4 ::1 ::2 1 2\r\n").is_err());
+    assert!(parse_proxy_header(b"GET / HTTP/1.1\r\n").is_err());
+
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    v2.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    v2.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    v2.extend_from_slice(&1234u16.to_be_bytes());
+    v2.extend_from_slice(&80u16.to_be_bytes());
+    assert!(parse_proxy_header(&v2[..20]).unwrap().is_none());
+
+    let (header, len) = parse_proxy_header(&v2).unwrap().unwrap();
+    assert_eq!(len, v2.len());
+    assert_eq!(header.source(), addr("10.0.0.1:1234"));
+    assert_eq!(header.destination(), addr("10.0.0.2:80"));
+
+    // A LOCAL command carries no addresses.
+    v2[12] = 0x20;
+    let (header, _) = parse_proxy_header(&v2).unwrap().unwrap();
+    assert_eq!(header.source(), None);
+}
+
+#[tokio::test]
+async fn proxy_protocol() {
+    let (mut client, server) = mock();
+
+    let mut bytes = b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 80\r\n".to_vec();
+    let request = valid_request();
+    bytes.extend_from_slice(
+        format!(
+            "GET {} HTTP/1.1\r\n{}\r\n",
+            request.uri(),
+            request
+                .headers()
+                .iter()
+                .map(|(name, value)| format!("{}: {}\r\n", name, value.to_str().unwrap()))
+                .collect::<String>()
+        )
+        .as_bytes(),
+    );
+    // The header and the request are received together.
+    client.write_all(&bytes).await.unwrap();
+
+    let upgraded = WebSocketServerBuilder::default()
+        .proxy_protocol(true)
+        .accept(server)
+        .await
+        .unwrap();
+
+    let header = upgraded.request.extensions().get::<ProxyHeader>().unwrap();
+    assert_eq!(header.source(), Some("203.0.113.7:56324".parse().unwrap()));
+
+    let response = client.read_response().await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+}
+
+#[test]
+fn resolves_client_addr() {
+    use crate::server::client_addr;
+    use crate::{ClientAddrSource, TrustedProxies};
+    use std::net::IpAddr;
+
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    let headers = |headers: &[(&'static str, &'static str)]| {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect::<HeaderMap>()
+    };
+    let trusted = TrustedProxies::new()
+        .trust(ip("192.0.2.1"))
+        .trust_network(ip("10.0.0.0"), 8);
+
+    // The headers from an untrusted peer are ignored.
+    let spoofed = headers(&[("x-forwarded-for", "203.0.113.7")]);
+    let addr = client_addr(&spoofed, ip("198.51.100.1"), &trusted);
+    assert_eq!(addr.ip(), ip("198.51.100.1"));
+    assert_eq!(addr.source(), ClientAddrSource::Peer);
+
+    // Addresses before the first untrusted address may have been provided by the client.
+    let forwarded = headers(&[("x-forwarded-for", "1.1.1.1, 203.0.113.7, 10.1.2.3")]);
+    let addr = client_addr(&forwarded, ip("192.0.2.1"), &trusted);
+    assert_eq!(addr.ip(), ip("203.0.113.7"));
+    assert_eq!(addr.source(), ClientAddrSource::XForwardedFor);
+
+    let forwarded = headers(&[
+        ("forwarded", r#"for="[2001:db8:cafe::17]:4711";proto=https"#),
+        ("forwarded", "for=10.0.0.2"),
+        ("x-forwarded-for", "1.1.1.1"),
+    ]);
+    let addr = client_addr(&forwarded, ip("10.0.0.1"), &trusted);
+    assert_eq!(addr.ip(), ip("2001:db8:cafe::17"));
+    assert_eq!(addr.port(), Some(4711));
+    assert_eq!(addr.source(), ClientAddrSource::Forwarded);
+
+    // An obfuscated address resolves to the proxy that provided it.
+    let obfuscated = headers(&[("forwarded", "for=_hidden, for=10.0.0.2")]);
+    let addr = client_addr(&obfuscated, ip("10.0.0.1"), &trusted);
+    assert_eq!(addr.ip(), ip("10.0.0.2"));
+
+    let real_ip = headers(&[("x-real-ip", "203.0.113.7")]);
+    let addr = client_addr(&real_ip, ip("::ffff:10.0.0.1"), &trusted);
+    assert_eq!(addr.ip(), ip("203.0.113.7"));
+    assert_eq!(addr.source(), ClientAddrSource::XRealIp);
+
+    let addr = client_addr(&HeaderMap::new(), ip("10.0.0.1"), &trusted);
+    assert_eq!(addr.ip(), ip("10.0.0.1"));
+    assert_eq!(addr.source(), ClientAddrSource::Peer);
+}
+
+#[test]
+fn handshake_machine_rejects_invalid_request() {
+    let mut server = crate::server::ServerHandshakeMachine::new(
+        &WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    );
+
+    assert!(server.feed_bytes(b"GET / HTTP/1.1\r\n").is_pending());
+    match server.feed_bytes(b"host: localhost\r\n\r\n") {
+        std::task::Poll::Ready(Err(e)) => assert!(e.is_http()),
+        _ => panic!("Expected the request to be rejected"),
+    }
+}
+
+#[tokio::test]
+async fn accept_with_pre_read_bytes() {
+    let (mut client, mut server) = mock();
+
+    client.write_request(valid_request()).await.unwrap();
+
+    let mut already_read = BytesMut::zeroed(4);
+    server.read_exact(&mut already_read).await.unwrap();
+    assert_eq!(already_read.as_ref(), b"GET ");
+
+    let upgrader = accept_with_buffer(
+        server,
+        already_read,
+        WebSocketConfig::default(),
+        NoExtProvider,
+        SubprotocolRegistry::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(upgrader.request.uri(), "/test");
+
+    let _upgraded = upgrader.upgrade().await.unwrap();
+    let response = client.read_response().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+}
+
+#[test]
+fn lenient_handshake() {
+    const REQUEST: &[u8] = b"GET /test HTTP/1.1\r\n\
+connection: keep-alive\r\n\
+connection:  Upgrade \r\n\
+upgrade: h2c,  WebSocket\r\n\
+sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ== \r\n\r\n";
+
+    let parse = |lenient_handshake| {
+        let config = WebSocketConfig {
+            lenient_handshake,
+            ..Default::default()
+        };
+        let mut server = crate::server::ServerHandshakeMachine::new(
+            &config,
+            NoExtProvider,
+            SubprotocolRegistry::default(),
+        );
+        match server.feed_bytes(REQUEST) {
+            std::task::Poll::Ready(result) => result,
+            std::task::Poll::Pending => panic!("Expected the request to be complete"),
+        }
+    };
+
+    assert!(parse(false).is_err());
+
+    let request = parse(true).unwrap();
+    assert_eq!(request.key.as_ref(), b"dGhlIHNhbXBsZSBub25jZQ==");
+}
+
+#[tokio::test]
+async fn unsupported_version() {
+    let mut request = valid_request();
+    request.headers_mut().insert(
+        http::header::SEC_WEBSOCKET_VERSION,
+        HeaderValue::from_static("8"),
+    );
+    let (result, response) = exec_request_with_config(request, WebSocketConfig::default()).await;
+
+    let err = result.expect_err("Expected a test failure");
+    assert_eq!(
+        err.downcast_ref::<HttpError>(),
+        Some(&HttpError::UnsupportedVersion(vec!["13".to_string()]))
+    );
+    assert!(response.starts_with(b"HTTP/1.1 426 "));
+
+    let mut headers = [httparse::EMPTY_HEADER; 8];
+    let mut parsed = httparse::Response::new(&mut headers);
+    parsed.parse(&response).unwrap();
+    let version = parsed
+        .headers
+        .iter()
+        .find(|header| header.name == http::header::SEC_WEBSOCKET_VERSION.as_str())
+        .map(|header| header.value);
+    assert_eq!(version, Some(b"13".as_ref()));
+}
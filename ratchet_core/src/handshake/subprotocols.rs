@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{Error, ErrorKind, HttpError, ProtocolError};
-use fnv::FnvHashSet;
+use crate::{Error, ErrorKind, HttpError, ProtocolError, Request};
 use http::header::SEC_WEBSOCKET_PROTOCOL;
 use http::{HeaderMap, HeaderValue};
+use std::fmt::{Debug, Formatter};
 use std::sync::Arc;
 
+type Selector = dyn Fn(&[&str], &Request) -> Option<String> + Send + Sync;
+
 /// A subprotocol registry that is used for negotiating a possible subprotocol to use for a
 /// connection.
 #[derive(Default, Debug, Clone)]
@@ -25,26 +27,40 @@ pub struct SubprotocolRegistry {
     inner: Arc<Inner>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 struct Inner {
-    registrants: FnvHashSet<String>,
+    registrants: Vec<String>,
     header: Option<HeaderValue>,
+    selector: Option<Box<Selector>>,
+}
+
+impl Debug for Inner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inner")
+            .field("registrants", &self.registrants)
+            .field("header", &self.header)
+            .field("selector", &self.selector.as_ref().map(|_| "Fn"))
+            .finish()
+    }
 }
 
 impl SubprotocolRegistry {
     /// Construct a new protocol registry that will allow the provided subprotocols. The priority
-    /// of the subprotocols is specified by the order that the iterator yields items.
+    /// of the subprotocols is specified by the order that the iterator yields items; when
+    /// negotiating, a server selects the first registered subprotocol that the client offered and
+    /// a client offers its subprotocols in this order.
     pub fn new<I>(i: I) -> Result<SubprotocolRegistry, Error>
     where
         I: IntoIterator,
         I::Item: Into<String>,
     {
-        let registrants = i.into_iter().map(Into::into).collect::<FnvHashSet<_>>();
-        let header_str = registrants
-            .clone()
-            .into_iter()
-            .collect::<Vec<_>>()
-            .join(", ");
+        let mut registrants = Vec::new();
+        for protocol in i.into_iter().map(Into::into) {
+            if !registrants.contains(&protocol) {
+                registrants.push(protocol);
+            }
+        }
+        let header_str = registrants.join(", ");
         let header = HeaderValue::from_str(&header_str).map_err(|_| {
             Error::with_cause(ErrorKind::Http, HttpError::MalformattedHeader(header_str))
         })?;
@@ -53,14 +69,42 @@ impl SubprotocolRegistry {
             inner: Arc::new(Inner {
                 registrants,
                 header: Some(header),
+                selector: None,
             }),
         })
     }
 
+    /// Construct a new protocol registry for a server which selects a subprotocol for each request
+    /// using `selector`. The selector is provided with the subprotocols that the client offered, in
+    /// its order of preference, and the upgrade request. Any subprotocol which it returns that was
+    /// not offered by the client is ignored.
+    ///
+    /// If the registry is used by [`parse_request_parts`], [`response_from_headers`] or
+    /// [`connect_response`] then the request that the selector is provided with only contains the
+    /// request's headers.
+    ///
+    /// A client does not offer any subprotocols if it is provided with this registry.
+    ///
+    /// [`parse_request_parts`]: crate::server::parse_request_parts
+    /// [`response_from_headers`]: crate::server::response_from_headers
+    /// [`connect_response`]: crate::extended_connect::connect_response
+    pub fn with_selector<F>(selector: F) -> SubprotocolRegistry
+    where
+        F: Fn(&[&str], &Request) -> Option<String> + Send + Sync + 'static,
+    {
+        SubprotocolRegistry {
+            inner: Arc::new(Inner {
+                registrants: Vec::new(),
+                header: None,
+                selector: Some(Box::new(selector)),
+            }),
+        }
+    }
+
     /// Attempts to negotiate a subprotocol offered by a client.
     ///
     /// # Returns
-    /// The subprotocol that was negotiated if one was offered. Or an error if the client send a
+    /// The subprotocol that was negotiated if one was offered. Or an error if the client sent a
     /// malformed header.
     pub fn negotiate_client(
         &self,
@@ -68,17 +112,40 @@ impl SubprotocolRegistry {
     ) -> Result<Option<String>, ProtocolError> {
         let SubprotocolRegistry { inner } = self;
 
-        for header in header_map.get_all(SEC_WEBSOCKET_PROTOCOL) {
-            let header_str = header.to_str().map_err(|_| ProtocolError::Encoding)?;
+        if inner.selector.is_some() {
+            let mut request = Request::new(());
+            *request.headers_mut() = header_map.clone();
+            return self.negotiate_request(&request);
+        }
 
-            for protocol in header_str.split(',') {
-                if let Some(supported_protocol) = inner.registrants.get(protocol.trim()) {
-                    return Ok(Some(supported_protocol.clone()));
-                }
-            }
+        let offered = offered_protocols(header_map)?;
+
+        Ok(inner
+            .registrants
+            .iter()
+            .find(|protocol| offered.contains(&protocol.as_str()))
+            .cloned())
+    }
+
+    /// Attempts to negotiate a subprotocol offered by a client in `request`.
+    ///
+    /// # Returns
+    /// The subprotocol that was negotiated if one was offered. Or an error if the client sent a
+    /// malformed header.
+    pub fn negotiate_request(&self, request: &Request) -> Result<Option<String>, ProtocolError> {
+        let SubprotocolRegistry { inner } = self;
+
+        let selector = match &inner.selector {
+            Some(selector) => selector,
+            None => return self.negotiate_client(request.headers()),
+        };
+
+        let offered = offered_protocols(request.headers())?;
+        if offered.is_empty() {
+            return Ok(None);
         }
 
-        Ok(None)
+        Ok(selector(&offered, request).filter(|selected| offered.contains(&selected.as_str())))
     }
 
     /// Validate a server's response for SEC_WEBSOCKET_PROTOCOL. A server may send at most one
@@ -108,7 +175,11 @@ impl SubprotocolRegistry {
 
         let server_protocol = protocols[0].to_str().map_err(|_| ProtocolError::Encoding)?;
 
-        if inner.registrants.contains(server_protocol) {
+        if inner
+            .registrants
+            .iter()
+            .any(|protocol| protocol == server_protocol)
+        {
             Ok(Some(server_protocol.to_string()))
         } else {
             Err(ProtocolError::InvalidSubprotocolHeader(
@@ -126,3 +197,18 @@ impl SubprotocolRegistry {
         }
     }
 }
+
+/// Parses the subprotocols offered by a client, in its order of preference.
+fn offered_protocols(header_map: &HeaderMap) -> Result<Vec<&str>, ProtocolError> {
+    let mut offered = Vec::new();
+    for header in header_map.get_all(SEC_WEBSOCKET_PROTOCOL) {
+        let header_str = header.to_str().map_err(|_| ProtocolError::Encoding)?;
+        offered.extend(
+            header_str
+                .split(',')
+                .map(str::trim)
+                .filter(|protocol| !protocol.is_empty()),
+        );
+    }
+    Ok(offered)
+}
@@ -27,7 +27,7 @@ fn selects_protocol_ok() {
 
     assert_eq!(
         registry.negotiate_client(&headers),
-        Ok(Some("warp".to_string()))
+        Ok(Some("warps".to_string()))
     );
 }
 
@@ -41,7 +41,7 @@ fn multiple_headers() {
 
     assert_eq!(
         registry.negotiate_client(&headers),
-        Ok(Some("warp".to_string()))
+        Ok(Some("warps".to_string()))
     );
 }
 
@@ -84,3 +84,52 @@ fn no_match() {
 
     assert_eq!(registry.negotiate_client(&headers), Ok(None));
 }
+
+#[test]
+fn registration_order() {
+    let headers =
+        HeaderMap::from_iter([(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("a, b, c"))]);
+
+    let registry = SubprotocolRegistry::new(vec!["c", "b", "c"]).unwrap();
+    assert_eq!(
+        registry.negotiate_client(&headers),
+        Ok(Some("c".to_string()))
+    );
+
+    let mut target = HeaderMap::new();
+    registry.apply_to(&mut target);
+    assert_eq!(
+        target.get(SEC_WEBSOCKET_PROTOCOL),
+        Some(&HeaderValue::from_static("c, b"))
+    );
+}
+
+#[test]
+fn address_requests() {
+    use crate::TryIntoRequest;
+    use std::net::{Ipv6Addr, SocketAddr};
+
+    let uri = |request: crate::Request| request.uri().to_string();
+
+    assert_eq!(
+        uri(("localhost", 9001, "/chat?room=1")
+            .try_into_request()
+            .unwrap()),
+        "ws://localhost:9001/chat?room=1"
+    );
+    assert_eq!(
+        uri(("::1", 9001, "chat").try_into_request().unwrap()),
+        "ws://[::1]:9001/chat"
+    );
+    assert_eq!(
+        uri(("[::1]", 9001, "").try_into_request().unwrap()),
+        "ws://[::1]:9001/"
+    );
+
+    let addr = SocketAddr::from((Ipv6Addr::LOCALHOST, 9001));
+    assert_eq!(
+        uri((addr, "/chat").try_into_request().unwrap()),
+        "ws://[::1]:9001/chat"
+    );
+    assert!(("local host", 9001, "/").try_into_request().is_err());
+}
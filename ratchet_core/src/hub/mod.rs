@@ -0,0 +1,304 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publish/subscribe fan-out of messages to WebSocket connections.
+//!
+//! A [`Hub`] owns the [`Sender`] halves of the connections which have joined it and delivers each
+//! message that is published to a topic to every connection which has subscribed to it. Each
+//! connection has its own bounded queue of outgoing messages, which is drained by a task that is
+//! spawned when it joins, so that a slow peer does not delay delivery to the others. The
+//! [`Backpressure`] policy determines what happens when a connection's queue is full.
+
+#[cfg(test)]
+mod tests;
+
+use crate::manager::ConnectionId;
+use crate::{CloseCode, CloseReason, PayloadType, Sender, WebSocketStream};
+use bytes::Bytes;
+use ratchet_ext::ExtensionEncoder;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// What a [`Hub`] does when a message is published to a subscriber whose queue is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for space in the subscriber's queue. A slow subscriber delays the publisher.
+    Wait,
+    /// Skip delivering the message to the subscriber.
+    DropMessage,
+    /// Remove the subscriber from the hub and close its connection with [`CloseCode::Policy`].
+    /// Any messages which are queued for the subscriber are discarded.
+    Disconnect,
+}
+
+/// The configuration of a [`Hub`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HubConfig {
+    /// The maximum number of messages which may be queued for a subscriber.
+    pub capacity: usize,
+    /// What to do when a subscriber's queue is full.
+    pub backpressure: Backpressure,
+}
+
+impl Default for HubConfig {
+    fn default() -> Self {
+        HubConfig {
+            capacity: 64,
+            backpressure: Backpressure::Wait,
+        }
+    }
+}
+
+struct Subscriber {
+    tx: mpsc::Sender<(PayloadType, Bytes)>,
+    evicted: Arc<AtomicBool>,
+    topics: HashSet<String>,
+}
+
+#[derive(Default)]
+struct HubState {
+    next_id: u64,
+    subscribers: HashMap<ConnectionId, Subscriber>,
+    topics: HashMap<String, HashSet<ConnectionId>>,
+}
+
+impl HubState {
+    fn remove(&mut self, id: ConnectionId) -> Option<Subscriber> {
+        let subscriber = self.subscribers.remove(&id)?;
+        for topic in &subscriber.topics {
+            self.unsubscribe_topic(id, topic);
+        }
+        Some(subscriber)
+    }
+
+    fn unsubscribe_topic(&mut self, id: ConnectionId, topic: &str) {
+        if let Some(subscribers) = self.topics.get_mut(topic) {
+            subscribers.remove(&id);
+            if subscribers.is_empty() {
+                self.topics.remove(topic);
+            }
+        }
+    }
+}
+
+/// A publish/subscribe hub which fans messages out to the connections subscribed to a topic.
+///
+/// The hub may be cloned cheaply and each clone refers to the same subscribers. Once every clone
+/// has been dropped, each subscriber's task sends any messages remaining in its queue and then
+/// drops its [`Sender`].
+#[derive(Clone)]
+pub struct Hub {
+    state: Arc<Mutex<HubState>>,
+    config: HubConfig,
+}
+
+impl Debug for Hub {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Hub")
+            .field("config", &self.config)
+            .field("subscribers", &state.subscribers.len())
+            .field("topics", &state.topics.len())
+            .finish()
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Hub::new(HubConfig::default())
+    }
+}
+
+fn lock(state: &Mutex<HubState>) -> MutexGuard<'_, HubState> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Hub {
+    /// Returns a new hub which has no subscribers.
+    ///
+    /// # Panics
+    /// Panics if `config.capacity` is zero.
+    pub fn new(config: HubConfig) -> Hub {
+        assert!(
+            config.capacity > 0,
+            "Hub capacity must be greater than zero"
+        );
+        Hub {
+            state: Arc::default(),
+            config,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HubState> {
+        lock(&self.state)
+    }
+
+    /// Adds a connection to the hub, returning the identifier that it may be subscribed to topics
+    /// by. A task which writes the messages delivered to the connection is spawned onto the
+    /// current tokio runtime; if a write fails, then the connection is removed from the hub.
+    ///
+    /// # Panics
+    /// Panics if called from outside of a tokio runtime.
+    pub fn join<S, E>(&self, mut sender: Sender<S, E>) -> ConnectionId
+    where
+        S: WebSocketStream + 'static,
+        E: ExtensionEncoder + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel::<(PayloadType, Bytes)>(self.config.capacity);
+        let evicted = Arc::new(AtomicBool::new(false));
+
+        let id = {
+            let state = &mut *self.lock();
+            let id = ConnectionId(state.next_id);
+            state.next_id += 1;
+            state.subscribers.insert(
+                id,
+                Subscriber {
+                    tx,
+                    evicted: evicted.clone(),
+                    topics: HashSet::new(),
+                },
+            );
+            id
+        };
+
+        let state = Arc::downgrade(&self.state);
+        tokio::spawn(async move {
+            while let Some((message_type, payload)) = rx.recv().await {
+                // The messages which were queued for an evicted subscriber are discarded rather
+                // than being written to a peer which has already fallen behind.
+                if evicted.load(Ordering::Acquire) {
+                    break;
+                }
+                if sender.write(payload, message_type).await.is_err() {
+                    remove(&state, id);
+                    return;
+                }
+            }
+
+            if evicted.load(Ordering::Acquire) {
+                let reason = CloseReason::new(
+                    CloseCode::Policy,
+                    Some("Subscriber fell behind".to_string()),
+                );
+                let _ = sender.close(reason).await;
+            }
+        });
+
+        id
+    }
+
+    /// Removes a connection from the hub and all of the topics that it is subscribed to. Messages
+    /// which have already been queued for the connection are still sent. Returns whether the
+    /// connection was present.
+    pub fn leave(&self, id: ConnectionId) -> bool {
+        self.lock().remove(id).is_some()
+    }
+
+    /// Subscribes a connection to `topic`. Returns whether the connection is present in the hub.
+    pub fn subscribe<T>(&self, id: ConnectionId, topic: T) -> bool
+    where
+        T: Into<String>,
+    {
+        let state = &mut *self.lock();
+        match state.subscribers.get_mut(&id) {
+            Some(subscriber) => {
+                let topic = topic.into();
+                state.topics.entry(topic.clone()).or_default().insert(id);
+                subscriber.topics.insert(topic);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Unsubscribes a connection from `topic`. Returns whether the connection was subscribed to it.
+    pub fn unsubscribe(&self, id: ConnectionId, topic: &str) -> bool {
+        let state = &mut *self.lock();
+        let subscribed = state
+            .subscribers
+            .get_mut(&id)
+            .is_some_and(|subscriber| subscriber.topics.remove(topic));
+        if subscribed {
+            state.unsubscribe_topic(id, topic);
+        }
+        subscribed
+    }
+
+    /// Returns the number of connections which are subscribed to `topic`.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.lock().topics.get(topic).map_or(0, HashSet::len)
+    }
+
+    /// Publishes a message of `message_type` with a payload of `payload` to every connection which
+    /// is subscribed to `topic`, returning the number of connections that it was queued for.
+    ///
+    /// The payload is shared by each subscriber rather than being copied. If a subscriber's queue
+    /// is full then the hub's [`Backpressure`] policy is applied to it.
+    pub async fn publish<B>(&self, topic: &str, payload: B, message_type: PayloadType) -> usize
+    where
+        B: Into<Bytes>,
+    {
+        let payload = payload.into();
+        let subscribers = {
+            let state = self.lock();
+            match state.topics.get(topic) {
+                Some(ids) => ids
+                    .iter()
+                    .filter_map(|id| {
+                        let subscriber = state.subscribers.get(id)?;
+                        Some((*id, subscriber.tx.clone()))
+                    })
+                    .collect::<Vec<_>>(),
+                None => return 0,
+            }
+        };
+
+        let mut delivered = 0;
+        for (id, tx) in subscribers {
+            let message = (message_type, payload.clone());
+            let sent = match self.config.backpressure {
+                Backpressure::Wait => tx.send(message).await.is_ok(),
+                Backpressure::DropMessage => tx.try_send(message).is_ok(),
+                Backpressure::Disconnect => match tx.try_send(message) {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) => {
+                        if let Some(subscriber) = self.lock().remove(id) {
+                            subscriber.evicted.store(true, Ordering::Release);
+                        }
+                        false
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                },
+            };
+            if sent {
+                delivered += 1;
+            }
+        }
+
+        delivered
+    }
+}
+
+fn remove(state: &Weak<Mutex<HubState>>, id: ConnectionId) {
+    if let Some(state) = state.upgrade() {
+        lock(&state).remove(id);
+    }
+}
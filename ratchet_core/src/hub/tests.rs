@@ -0,0 +1,154 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::hub::{Backpressure, Hub, HubConfig};
+use crate::manager::ConnectionId;
+use crate::{
+    CloseCode, Message, NoExt, NoExtDecoder, PayloadType, Receiver, Role, WebSocket,
+    WebSocketConfig,
+};
+use bytes::BytesMut;
+use tokio::io::{duplex, DuplexStream};
+
+type Client = (
+    ConnectionId,
+    WebSocket<DuplexStream, NoExt>,
+    Receiver<DuplexStream, NoExtDecoder>,
+);
+
+fn join(hub: &Hub) -> Client {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let (sender, receiver) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let client =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+    (hub.join(sender), client, receiver)
+}
+
+async fn read_text(websocket: &mut WebSocket<DuplexStream, NoExt>) -> String {
+    let mut buf = BytesMut::new();
+    assert_eq!(websocket.read(&mut buf).await.unwrap(), Message::Text);
+    String::from_utf8(buf.to_vec()).unwrap()
+}
+
+fn hub(backpressure: Backpressure) -> Hub {
+    Hub::new(HubConfig {
+        capacity: 1,
+        backpressure,
+    })
+}
+
+#[tokio::test]
+async fn publishes_to_topics() {
+    let hub = Hub::default();
+    let (first_id, mut first, _first) = join(&hub);
+    let (second_id, mut second, _second) = join(&hub);
+
+    assert!(hub.subscribe(first_id, "news"));
+    assert!(hub.subscribe(first_id, "chat"));
+    assert!(hub.subscribe(second_id, "chat"));
+    assert_eq!(hub.subscribers("chat"), 2);
+
+    assert_eq!(hub.publish("news", "headline", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("chat", "hello", PayloadType::Text).await, 2);
+    assert_eq!(hub.publish("sport", "score", PayloadType::Text).await, 0);
+
+    assert_eq!(read_text(&mut first).await, "headline");
+    assert_eq!(read_text(&mut first).await, "hello");
+    assert_eq!(read_text(&mut second).await, "hello");
+
+    assert!(hub.unsubscribe(first_id, "chat"));
+    assert!(!hub.unsubscribe(first_id, "chat"));
+    assert_eq!(hub.subscribers("chat"), 1);
+
+    assert!(hub.leave(second_id));
+    assert_eq!(hub.subscribers("chat"), 0);
+    assert!(!hub.subscribe(second_id, "chat"));
+}
+
+#[tokio::test]
+async fn backpressure_wait() {
+    let hub = hub(Backpressure::Wait);
+    let (id, mut client, _receiver) = join(&hub);
+    hub.subscribe(id, "topic");
+
+    assert_eq!(hub.publish("topic", "first", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("topic", "second", PayloadType::Text).await, 1);
+
+    assert_eq!(read_text(&mut client).await, "first");
+    assert_eq!(read_text(&mut client).await, "second");
+}
+
+#[tokio::test]
+async fn backpressure_drop_message() {
+    let hub = hub(Backpressure::DropMessage);
+    let (id, mut client, _receiver) = join(&hub);
+    hub.subscribe(id, "topic");
+
+    assert_eq!(hub.publish("topic", "first", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("topic", "second", PayloadType::Text).await, 0);
+    assert_eq!(hub.subscribers("topic"), 1);
+
+    assert_eq!(read_text(&mut client).await, "first");
+    assert_eq!(hub.publish("topic", "third", PayloadType::Text).await, 1);
+    assert_eq!(read_text(&mut client).await, "third");
+}
+
+#[tokio::test]
+async fn backpressure_disconnect() {
+    let hub = hub(Backpressure::Disconnect);
+    let (id, mut client, _receiver) = join(&hub);
+    hub.subscribe(id, "topic");
+
+    assert_eq!(hub.publish("topic", "first", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("topic", "second", PayloadType::Text).await, 0);
+    assert_eq!(hub.subscribers("topic"), 0);
+    assert!(!hub.leave(id));
+
+    let mut buf = BytesMut::new();
+    match client.read(&mut buf).await.unwrap() {
+        Message::Close(Some(reason)) => assert_eq!(reason.code, CloseCode::Policy),
+        message => panic!("Expected a close message: {message:?}"),
+    }
+}
+
+#[tokio::test]
+async fn disconnect_discards_queued_messages() {
+    let hub = Hub::new(HubConfig {
+        capacity: 2,
+        backpressure: Backpressure::Disconnect,
+    });
+    let (id, mut client, _receiver) = join(&hub);
+    hub.subscribe(id, "topic");
+
+    assert_eq!(hub.publish("topic", "first", PayloadType::Text).await, 1);
+    assert_eq!(read_text(&mut client).await, "first");
+
+    assert_eq!(hub.publish("topic", "second", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("topic", "third", PayloadType::Text).await, 1);
+    assert_eq!(hub.publish("topic", "fourth", PayloadType::Text).await, 0);
+
+    // The subscriber was evicted before its task wrote the queued messages.
+    let mut buf = BytesMut::new();
+    match client.read(&mut buf).await.unwrap() {
+        Message::Close(Some(reason)) => assert_eq!(reason.code, CloseCode::Policy),
+        message => panic!("Expected a close message: {message:?}"),
+    }
+    assert!(buf.is_empty());
+}
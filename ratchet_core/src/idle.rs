@@ -0,0 +1,112 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, Instant, Sleep};
+
+#[derive(Debug, Copy, Clone)]
+pub struct IdleConfig {
+    pub timeout: Duration,
+    pub count_pings: bool,
+}
+
+/// Tracks when data frames were last read or written so that an idle connection may be closed.
+#[derive(Debug)]
+pub struct IdleTimeout {
+    config: IdleConfig,
+    // Lazily initialised so that a WebSocket may be created outside of a Tokio runtime.
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl IdleTimeout {
+    pub fn new(config: IdleConfig) -> IdleTimeout {
+        IdleTimeout {
+            config,
+            deadline: None,
+        }
+    }
+
+    pub fn config(&self) -> IdleConfig {
+        self.config
+    }
+
+    /// Resets the deadline as a data frame has been read or written.
+    pub fn on_data(&mut self) {
+        if let Some(deadline) = &mut self.deadline {
+            deadline
+                .as_mut()
+                .reset(Instant::now() + self.config.timeout);
+        }
+    }
+
+    /// Resets the deadline as a ping or pong frame has been read or written, if they are
+    /// configured to count as activity.
+    pub fn on_control(&mut self) {
+        if self.config.count_pings {
+            self.on_data();
+        }
+    }
+
+    /// Moves the deadline so that it does not elapse until the timeout after `at`, when a frame was
+    /// last written by a split `Sender`. Returns whether the connection is still active.
+    pub fn extend(&mut self, at: Instant) -> bool {
+        let extended = at + self.config.timeout;
+        if let Some(deadline) = &mut self.deadline {
+            if deadline.deadline() < extended {
+                deadline.as_mut().reset(extended);
+            }
+        }
+        extended > Instant::now()
+    }
+
+    /// Polls the deadline, returning `Poll::Ready` once the connection has been idle for longer
+    /// than the timeout.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let IdleTimeout { config, deadline } = self;
+        deadline
+            .get_or_insert_with(|| Box::pin(sleep(config.timeout)))
+            .as_mut()
+            .poll(cx)
+    }
+}
+
+/// Records when a split `Sender` last wrote a frame that counts as activity, so that the
+/// `Receiver`, which owns the idle timeout, does not close a connection on which messages are only
+/// being sent.
+#[derive(Debug)]
+pub struct WriteActivity {
+    count_pings: bool,
+    last: Option<Instant>,
+}
+
+impl WriteActivity {
+    pub fn new(config: IdleConfig) -> WriteActivity {
+        WriteActivity {
+            count_pings: config.count_pings,
+            last: None,
+        }
+    }
+
+    pub fn on_write(&mut self, is_data: bool) {
+        if is_data || self.count_pings {
+            self.last = Some(Instant::now());
+        }
+    }
+
+    pub fn last(&self) -> Option<Instant> {
+        self.last
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep, Instant, Sleep};
+
+#[derive(Debug, Copy, Clone)]
+pub struct KeepaliveConfig {
+    pub interval: Duration,
+    pub pong_timeout: Option<Duration>,
+}
+
+pub enum KeepaliveEvent {
+    /// The connection has been idle for the interval and a ping should be sent.
+    Ping,
+    /// A ping was sent and the peer did not respond in time.
+    Timeout,
+}
+
+/// Tracks when keepalive pings should be sent to the peer and whether the peer has responded to
+/// them in time.
+#[derive(Debug)]
+pub struct Keepalive {
+    config: KeepaliveConfig,
+    // Lazily initialised so that a WebSocket may be created outside of a Tokio runtime.
+    idle: Option<Pin<Box<Sleep>>>,
+    pong_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl Keepalive {
+    pub fn new(config: KeepaliveConfig) -> Keepalive {
+        Keepalive {
+            config,
+            idle: None,
+            pong_deadline: None,
+        }
+    }
+
+    /// Resets the idle timer as a frame has been received from the peer.
+    pub fn on_frame(&mut self) {
+        if let Some(idle) = &mut self.idle {
+            idle.as_mut().reset(Instant::now() + self.config.interval);
+        }
+    }
+
+    /// Clears any pending pong deadline.
+    pub fn on_pong(&mut self) {
+        self.pong_deadline = None;
+    }
+
+    /// Polls the timers, returning an event if the connection has been idle for the interval or if
+    /// the peer failed to respond to a ping in time.
+    pub fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<KeepaliveEvent> {
+        let Keepalive {
+            config,
+            idle,
+            pong_deadline,
+        } = self;
+
+        if let Some(deadline) = pong_deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                *pong_deadline = None;
+                return Poll::Ready(KeepaliveEvent::Timeout);
+            }
+        }
+
+        let idle = idle.get_or_insert_with(|| Box::pin(sleep(config.interval)));
+        if idle.as_mut().poll(cx).is_ready() {
+            idle.as_mut().reset(Instant::now() + config.interval);
+
+            // If a previous ping is still outstanding then its deadline is left as it is.
+            if let (None, Some(timeout)) = (&pong_deadline, config.pong_timeout) {
+                *pong_deadline = Some(Box::pin(sleep(timeout)));
+            }
+            Poll::Ready(KeepaliveEvent::Ping)
+        } else {
+            Poll::Pending
+        }
+    }
+}
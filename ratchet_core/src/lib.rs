@@ -31,19 +31,45 @@ extern crate core;
 #[cfg(test)]
 mod test_fixture;
 
+mod actor;
 mod builder;
+#[cfg(feature = "net")]
+mod connect;
 mod errors;
 mod ext;
 mod framed;
 mod handshake;
+mod idle;
+mod keepalive;
+mod prepared;
 mod protocol;
+mod rate_limit;
 mod ws;
 
+/// Raw frame-level access to WebSocket connections.
+pub mod raw;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(feature = "completion")]
+pub mod completion;
+
+#[cfg(feature = "hub")]
+pub mod hub;
+
+#[cfg(feature = "manager")]
+pub mod manager;
+
+#[cfg(feature = "reconnect")]
+pub mod reconnect;
+
+#[cfg(feature = "futures-io")]
+pub mod futures_io;
+
 /// Split WebSocket implementation.
-#[cfg(feature = "split")]
 mod split;
-#[cfg(feature = "split")]
-pub use split::{Receiver, ReuniteError, Sender};
+pub use split::{Receiver, ReuniteError, Sender, SenderHandle, SharedWebSocket, TrySendError};
 
 #[allow(missing_docs)]
 #[cfg(feature = "fixture")]
@@ -51,17 +77,26 @@ pub mod fixture {
     pub use super::protocol::write_text_frame_header;
 }
 
+pub use actor::WebSocketHandle;
 pub use builder::{WebSocketClientBuilder, WebSocketServerBuilder};
+#[cfg(all(feature = "net", unix))]
+pub use connect::connect_unix;
+#[cfg(feature = "net")]
+pub use connect::{connect, connect_with, Resolver, Resolving, SystemResolver};
 pub use errors::*;
 pub use ext::{NoExt, NoExtDecoder, NoExtEncoder, NoExtProvider};
 pub use handshake::{
-    accept, accept_with, subscribe, subscribe_with, SubprotocolRegistry, TryIntoRequest,
-    UpgradedClient, UpgradedServer, WebSocketResponse, WebSocketUpgrader,
+    accept, accept_with, accept_with_buffer, subscribe, subscribe_with, Accepted, ClientAddr,
+    ClientAddrSource, CookieStore, HttpRequest, OriginPolicy, Proxy, ProxyHeader,
+    SubprotocolRegistry, TrustedProxies, TryIntoRequest, UpgradeDecision, UpgradedClient,
+    UpgradedServer, WebSocketResponse, WebSocketUpgrader,
 };
+pub use prepared::PreparedMessage;
 pub use protocol::{
-    CloseCode, CloseReason, Message, MessageType, PayloadType, Role, WebSocketConfig,
+    CloseCode, CloseReason, Fragment, Message, MessageType, OwnedMessage, PayloadType, RateLimit,
+    RateLimitAction, Role, WebSocketConfig,
 };
-pub use ws::{CloseState, WebSocket};
+pub use ws::{CloseState, PendingPong, WebSocket};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
@@ -71,6 +106,8 @@ pub(crate) type Request = http::Request<()>;
 pub trait WebSocketStream: AsyncRead + AsyncWrite + Send + Unpin {}
 impl<S> WebSocketStream for S where S: AsyncRead + AsyncWrite + Send + Unpin {}
 
+pub use handshake::extended_connect;
+
 /// Provides utilities for handling WebSocket handshakes on the server side.
 ///
 /// This module includes the necessary components to parse, negotiate, and respond to WebSocket
@@ -79,8 +116,21 @@ impl<S> WebSocketStream for S where S: AsyncRead + AsyncWrite + Send + Unpin {}
 /// It should generally not be required unless integrating Ratchet into other libraries.
 pub mod server {
     pub use crate::handshake::{
-        build_response, build_response_headers, handshake, parse_request_parts,
-        response_from_headers, validate_method_and_version, UpgradeRequest, UpgradeRequestParts,
-        UpgradeResponseParts,
+        build_response, build_response_headers, client_addr, encode_response, handshake,
+        parse_request_parts, read_proxy_header, response_from_headers, validate_method_and_version,
+        ServerHandshakeMachine, UpgradeRequest, UpgradeRequestParts, UpgradeResponseParts,
+    };
+}
+
+/// Provides utilities for handling WebSocket handshakes on the client side.
+///
+/// This module includes a handshake which performs no IO, so that it may be driven over
+/// transports which do not implement the tokio IO traits, and helpers for deriving the `Host`
+/// header of an upgrade request.
+///
+/// It should generally not be required unless integrating Ratchet into other libraries.
+pub mod client {
+    pub use crate::handshake::{
+        default_port, host_header, ClientHandshakeMachine, HandshakeResult,
     };
 }
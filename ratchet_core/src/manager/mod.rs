@@ -0,0 +1,331 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A registry of live WebSocket connections.
+//!
+//! Servers commonly need to send messages to connections other than the one that they are
+//! currently handling, such as to notify a specific user or to close a connection from an admin
+//! endpoint. A [`ConnectionManager`] tracks the [`Sender`] halves of split WebSockets by a
+//! [`ConnectionId`] so that each connection may be written to or closed from any task, while the
+//! corresponding [`Receiver`](crate::Receiver) remains owned by the task that handles its reads.
+//!
+//! For zero-downtime deployments, [`ConnectionManager::shutdown`] and [`shutdown`] close a set of
+//! connections with a reason such as [`CloseCode::GoingAway`](crate::CloseCode::GoingAway) or
+//! [`CloseCode::Restarting`](crate::CloseCode::Restarting) and wait, up to a deadline, for each
+//! peer to complete the closing handshake before shutting down their IO.
+
+#[cfg(test)]
+mod tests;
+
+use crate::{
+    CloseCause, CloseReason, Error, ErrorKind, PayloadType, Sender, WebSocket, WebSocketStream,
+};
+use futures::future::join_all;
+use futures::lock::Mutex;
+use ratchet_ext::{Extension, ExtensionEncoder};
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+
+/// A unique identifier of a connection in a [`ConnectionManager`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionId(pub(crate) u64);
+
+impl Display for ConnectionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// A handle to a connection which is tracked by a [`ConnectionManager`].
+///
+/// The handle may be cloned and used concurrently; write operations are performed one at a time.
+pub struct Connection<S, E> {
+    id: ConnectionId,
+    sender: Arc<Mutex<Sender<S, E>>>,
+}
+
+impl<S, E> Clone for Connection<S, E> {
+    fn clone(&self) -> Self {
+        Connection {
+            id: self.id,
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S, E> Debug for Connection<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection").field("id", &self.id).finish()
+    }
+}
+
+impl<S, E> Connection<S, E>
+where
+    S: WebSocketStream,
+    E: ExtensionEncoder,
+{
+    /// Returns the identifier of this connection.
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Sends a new message to the peer. See [`Sender::write`].
+    pub async fn write<A>(&self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        self.sender.lock().await.write(buf, message_type).await
+    }
+
+    /// Sends a new text message to the peer. See [`Sender::write_text`].
+    pub async fn write_text<I>(&self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text).await
+    }
+
+    /// Sends a new binary message to the peer. See [`Sender::write_binary`].
+    pub async fn write_binary<I>(&self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data, PayloadType::Binary).await
+    }
+
+    /// Closes the connection with the reason provided. See [`Sender::close`].
+    pub async fn close(&self, reason: CloseReason) -> Result<(), Error> {
+        self.sender.lock().await.close(reason).await
+    }
+}
+
+struct Registry<S, E> {
+    next_id: u64,
+    connections: HashMap<ConnectionId, Connection<S, E>>,
+}
+
+/// A registry of live WebSocket connections, keyed by a [`ConnectionId`].
+///
+/// A connection is added to the manager by inserting the [`Sender`] half of a split WebSocket and
+/// is removed either explicitly, when it is closed through the manager or once a write operation
+/// leaves it closed. The manager may be cloned cheaply and each clone refers to the same
+/// connections.
+pub struct ConnectionManager<S, E> {
+    registry: Arc<std::sync::Mutex<Registry<S, E>>>,
+}
+
+impl<S, E> Clone for ConnectionManager<S, E> {
+    fn clone(&self) -> Self {
+        ConnectionManager {
+            registry: self.registry.clone(),
+        }
+    }
+}
+
+impl<S, E> Debug for ConnectionManager<S, E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let registry = self.lock();
+        f.debug_struct("ConnectionManager")
+            .field("connections", &registry.connections.len())
+            .finish()
+    }
+}
+
+impl<S, E> Default for ConnectionManager<S, E> {
+    fn default() -> Self {
+        ConnectionManager {
+            registry: Arc::new(std::sync::Mutex::new(Registry {
+                next_id: 0,
+                connections: HashMap::new(),
+            })),
+        }
+    }
+}
+
+impl<S, E> ConnectionManager<S, E> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Registry<S, E>> {
+        self.registry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S, E> ConnectionManager<S, E>
+where
+    S: WebSocketStream,
+    E: ExtensionEncoder,
+{
+    /// Returns a new manager which contains no connections.
+    pub fn new() -> ConnectionManager<S, E> {
+        ConnectionManager::default()
+    }
+
+    /// Adds the sender half of a WebSocket to the manager, returning the identifier that it may be
+    /// addressed by.
+    pub fn insert(&self, sender: Sender<S, E>) -> ConnectionId {
+        let registry = &mut *self.lock();
+        let id = ConnectionId(registry.next_id);
+        registry.next_id += 1;
+        registry.connections.insert(
+            id,
+            Connection {
+                id,
+                sender: Arc::new(Mutex::new(sender)),
+            },
+        );
+        id
+    }
+
+    /// Removes the connection associated with `id` from the manager without closing it. Returns
+    /// the connection if it was present.
+    pub fn remove(&self, id: ConnectionId) -> Option<Connection<S, E>> {
+        self.lock().connections.remove(&id)
+    }
+
+    /// Returns the connection associated with `id`, if it is present.
+    pub fn get(&self, id: ConnectionId) -> Option<Connection<S, E>> {
+        self.lock().connections.get(&id).cloned()
+    }
+
+    /// Returns whether a connection is associated with `id`.
+    pub fn contains(&self, id: ConnectionId) -> bool {
+        self.lock().connections.contains_key(&id)
+    }
+
+    /// Returns the number of connections in the manager.
+    pub fn len(&self) -> usize {
+        self.lock().connections.len()
+    }
+
+    /// Returns whether the manager contains no connections.
+    pub fn is_empty(&self) -> bool {
+        self.lock().connections.is_empty()
+    }
+
+    /// Returns a snapshot of the connections in the manager, in no particular order. Connections
+    /// which are inserted or removed after this call returns are not reflected in it.
+    pub fn connections(&self) -> Vec<Connection<S, E>> {
+        self.lock().connections.values().cloned().collect()
+    }
+
+    /// Sends a new message to the connection associated with `id`. If the write operation leaves
+    /// the connection closed, then it is removed from the manager.
+    ///
+    /// # Errors
+    /// Returns a close error if no connection is associated with `id`, or the error produced by the
+    /// write operation.
+    pub async fn send<A>(
+        &self,
+        id: ConnectionId,
+        buf: A,
+        message_type: PayloadType,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        let connection = self
+            .get(id)
+            .ok_or_else(|| Error::with_cause(ErrorKind::Close, CloseCause::Error))?;
+
+        let sender = &mut *connection.sender.lock().await;
+        let result = sender.write(buf, message_type).await;
+        if !sender.is_active() {
+            self.remove(id);
+        }
+        result
+    }
+
+    /// Closes the connection associated with `id` with the reason provided and removes it from the
+    /// manager.
+    ///
+    /// # Errors
+    /// Returns a close error if no connection is associated with `id`, or the error produced by
+    /// writing the close frame.
+    pub async fn close(&self, id: ConnectionId, reason: CloseReason) -> Result<(), Error> {
+        match self.remove(id) {
+            Some(connection) => connection.close(reason).await,
+            None => Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)),
+        }
+    }
+
+    /// Removes every connection from the manager, closes each of them with the reason provided and
+    /// waits for the peers to echo the close frame, before shutting down the write half of their
+    /// IO. Returns the number of connections which completed the closing handshake within
+    /// `deadline`; any others are shut down once it has elapsed. A connection which is already
+    /// closing, or has closed, is not sent another close frame but is still counted if its
+    /// handshake completes within `deadline`.
+    ///
+    /// The echoed close frame is observed by each connection's [`Receiver`](crate::Receiver) and so
+    /// the receivers must continue to be read while the manager shuts down.
+    pub async fn shutdown(&self, reason: CloseReason, deadline: Duration) -> usize {
+        let deadline = Instant::now() + deadline;
+        let connections = self
+            .lock()
+            .connections
+            .drain()
+            .map(|(_, connection)| connection)
+            .collect::<Vec<_>>();
+
+        let closing = connections.into_iter().map(|connection| {
+            let reason = reason.clone();
+            async move {
+                let sender = &mut *connection.sender.lock().await;
+                let handshake = async {
+                    // If either half has already sent a close frame then the handshake is already
+                    // in progress and only the peer's echo is waited for.
+                    if sender.is_active() {
+                        sender.close(reason).await?;
+                    }
+                    sender.wait_closed().await;
+                    Ok::<_, Error>(())
+                };
+                let closed = matches!(timeout_at(deadline, handshake).await, Ok(Ok(())));
+                sender.shutdown().await;
+                closed
+            }
+        });
+
+        join_all(closing)
+            .await
+            .into_iter()
+            .filter(|closed| *closed)
+            .count()
+    }
+}
+
+/// Closes each of `websockets` with the reason provided and waits for the peers to echo the close
+/// frame, before dropping them. Returns the number of WebSockets which completed the closing
+/// handshake within `deadline`; any others have their IO shut down once it has elapsed.
+///
+/// See [`WebSocket::close_and_wait`].
+pub async fn shutdown<S, E, I>(websockets: I, reason: CloseReason, deadline: Duration) -> usize
+where
+    S: WebSocketStream,
+    E: Extension,
+    I: IntoIterator<Item = WebSocket<S, E>>,
+{
+    let closing = websockets.into_iter().map(|mut websocket| {
+        let reason = reason.clone();
+        async move { websocket.close_and_wait(reason, deadline).await.is_ok() }
+    });
+
+    join_all(closing)
+        .await
+        .into_iter()
+        .filter(|closed| *closed)
+        .count()
+}
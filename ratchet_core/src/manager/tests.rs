@@ -0,0 +1,234 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::manager::{shutdown, ConnectionId, ConnectionManager};
+use crate::{
+    CloseCode, CloseReason, Message, NoExt, NoExtDecoder, NoExtEncoder, PayloadType, Receiver,
+    Role, WebSocket, WebSocketConfig,
+};
+use bytes::BytesMut;
+use std::time::Duration;
+use tokio::io::{duplex, DuplexStream};
+
+type Manager = ConnectionManager<DuplexStream, NoExtEncoder>;
+
+type Client = (
+    ConnectionId,
+    WebSocket<DuplexStream, NoExt>,
+    Receiver<DuplexStream, NoExtDecoder>,
+);
+
+fn connect(manager: &Manager) -> Client {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let (sender, receiver) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let client =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+    (manager.insert(sender), client, receiver)
+}
+
+async fn read_text(websocket: &mut WebSocket<DuplexStream, NoExt>) -> String {
+    let mut buf = BytesMut::new();
+    assert_eq!(websocket.read(&mut buf).await.unwrap(), Message::Text);
+    String::from_utf8(buf.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn targeted_send() {
+    let manager = Manager::new();
+    let (first_id, mut first, _first) = connect(&manager);
+    let (second_id, mut second, _second) = connect(&manager);
+
+    assert_ne!(first_id, second_id);
+    assert_eq!(manager.len(), 2);
+
+    manager
+        .send(second_id, "second", PayloadType::Text)
+        .await
+        .unwrap();
+    manager
+        .send(first_id, "first", PayloadType::Text)
+        .await
+        .unwrap();
+
+    assert_eq!(read_text(&mut first).await, "first");
+    assert_eq!(read_text(&mut second).await, "second");
+}
+
+#[tokio::test]
+async fn iterates_connections() {
+    let manager = Manager::new();
+    let mut clients = vec![connect(&manager), connect(&manager), connect(&manager)];
+
+    let mut connections = manager.connections();
+    connections.sort_by_key(|connection| connection.id());
+    for connection in connections {
+        connection
+            .write_text(connection.id().to_string())
+            .await
+            .unwrap();
+    }
+
+    for (id, client, _) in &mut clients {
+        assert_eq!(read_text(client).await, id.to_string());
+    }
+}
+
+#[tokio::test]
+async fn forced_close() {
+    let manager = Manager::new();
+    let (id, mut client, _receiver) = connect(&manager);
+
+    let reason = CloseReason::new(CloseCode::Policy, Some("kicked".to_string()));
+    manager.close(id, reason.clone()).await.unwrap();
+    assert!(!manager.contains(id));
+    assert!(manager.is_empty());
+
+    let mut buf = BytesMut::new();
+    assert_eq!(
+        client.read(&mut buf).await.unwrap(),
+        Message::Close(Some(reason))
+    );
+
+    let error = manager
+        .send(id, "hello", PayloadType::Text)
+        .await
+        .unwrap_err();
+    assert!(error.is_close());
+    assert!(manager
+        .close(id, CloseReason::new(CloseCode::Normal, None))
+        .await
+        .unwrap_err()
+        .is_close());
+}
+
+fn spawn_reader(mut receiver: Receiver<DuplexStream, NoExtDecoder>) {
+    tokio::spawn(async move {
+        let mut buf = BytesMut::new();
+        while receiver.read(&mut buf).await.is_ok() {
+            buf.clear();
+        }
+    });
+}
+
+async fn read_close(websocket: &mut WebSocket<DuplexStream, NoExt>) -> Option<CloseReason> {
+    let mut buf = BytesMut::new();
+    loop {
+        match websocket.read(&mut buf).await.unwrap() {
+            Message::Close(reason) => break reason,
+            _ => buf.clear(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let manager = Manager::new();
+    let reason = CloseReason::new(CloseCode::GoingAway, None);
+
+    let mut peers = Vec::new();
+    for _ in 0..2 {
+        let (_, mut client, receiver) = connect(&manager);
+        spawn_reader(receiver);
+        peers.push(tokio::spawn(async move { read_close(&mut client).await }));
+    }
+
+    let closed = manager
+        .shutdown(reason.clone(), Duration::from_secs(5))
+        .await;
+    assert_eq!(closed, 2);
+    assert!(manager.is_empty());
+
+    for peer in peers {
+        assert_eq!(peer.await.unwrap(), Some(reason.clone()));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn graceful_shutdown_deadline() {
+    let manager = Manager::new();
+    let (_, _client, receiver) = connect(&manager);
+    spawn_reader(receiver);
+
+    let start = tokio::time::Instant::now();
+    let reason = CloseReason::new(CloseCode::Restarting, None);
+    let closed = manager.shutdown(reason, Duration::from_secs(1)).await;
+
+    assert_eq!(closed, 0);
+    assert_eq!(start.elapsed(), Duration::from_secs(1));
+    assert!(manager.is_empty());
+}
+
+#[tokio::test]
+async fn graceful_shutdown_of_closing_connection() {
+    let manager = Manager::new();
+    let (_, mut client, mut receiver) = connect(&manager);
+
+    // The receiver has already started the closing handshake.
+    let reason = CloseReason::new(CloseCode::Normal, None);
+    receiver.close(reason.clone()).await.unwrap();
+    spawn_reader(receiver);
+    let peer = tokio::spawn(async move {
+        let reason = read_close(&mut client).await;
+        let mut buf = BytesMut::new();
+        (reason, client.read(&mut buf).await)
+    });
+
+    let closed = manager
+        .shutdown(
+            CloseReason::new(CloseCode::GoingAway, None),
+            Duration::from_secs(5),
+        )
+        .await;
+    assert_eq!(closed, 1);
+
+    // No second close frame was sent.
+    let (received, next) = peer.await.unwrap();
+    assert_eq!(received, Some(reason));
+    assert!(next.unwrap_err().is_close());
+}
+
+#[tokio::test]
+async fn shutdown_websockets() {
+    let config = WebSocketConfig::default();
+    let mut servers = Vec::new();
+    let mut peers = Vec::new();
+
+    for _ in 0..3 {
+        let (server, client) = duplex(512);
+        servers.push(WebSocket::from_upgraded(
+            config,
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        ));
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+        peers.push(tokio::spawn(async move { read_close(&mut client).await }));
+    }
+
+    let reason = CloseReason::new(CloseCode::GoingAway, Some("deploying".to_string()));
+    let closed = shutdown(servers, reason.clone(), Duration::from_secs(5)).await;
+    assert_eq!(closed, 3);
+
+    for peer in peers {
+        assert_eq!(peer.await.unwrap(), Some(reason.clone()));
+    }
+}
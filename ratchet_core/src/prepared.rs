@@ -0,0 +1,156 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::protocol::{DataCode, FrameHeader, HeaderFlags, MessageType, OpCode};
+use crate::ws::extension_encode;
+use crate::{Error, PreparedMessageError};
+use bytes::{Bytes, BytesMut};
+use ratchet_ext::{EncoderParameters, ExtensionEncoder, FrameHeader as ExtFrameHeader};
+
+/// A text or binary message which has been encoded once so that it may be sent to many peers.
+///
+/// When a message is broadcast, encoding it for each connection repeats the same work: copying
+/// the payload, encoding it using the negotiated extension and writing its frame header. A
+/// `PreparedMessage` performs this once and is cheaply cloneable. Servers write the complete frame
+/// without copying it, while clients, which must mask each frame with a new key, copy and mask the
+/// prepared payload.
+///
+/// Prepared messages are always sent as a single frame and are not automatically fragmented.
+#[derive(Clone, Debug)]
+pub struct PreparedMessage {
+    opcode: OpCode,
+    header_flags: HeaderFlags,
+    payload: Bytes,
+    frame: Bytes,
+    // The parameters of the encoder that the message was encoded by, if it was encoded.
+    parameters: Option<EncoderParameters>,
+}
+
+impl PreparedMessage {
+    /// Prepares a message of `message_type` with a payload of `payload` that is not encoded by an
+    /// extension. As with [`WebSocket::write_uncompressed`](crate::WebSocket::write_uncompressed),
+    /// it must only be sent over connections whose extension permits unencoded messages.
+    pub fn new<A>(message_type: MessageType, payload: A) -> PreparedMessage
+    where
+        A: AsRef<[u8]>,
+    {
+        let data_code = DataCode::from(message_type);
+        PreparedMessage::from_parts(
+            OpCode::DataCode(data_code),
+            HeaderFlags::FIN,
+            Bytes::copy_from_slice(payload.as_ref()),
+            None,
+        )
+    }
+
+    /// Prepares a message of `message_type` with a payload of `payload` which is encoded by
+    /// `encoder`, such as compressing it using permessage-deflate.
+    ///
+    /// The message is encoded independently of the encoder of any connection that it is sent over
+    /// and so it may only be sent over connections whose encoder returns the same
+    /// [`stateless_parameters`](ExtensionEncoder::stateless_parameters) as `encoder`. For
+    /// permessage-deflate, this requires that context takeover has been disabled for the sending
+    /// side and that the same window size was negotiated.
+    ///
+    /// # Errors
+    /// Returns an extension error if `encoder` does not encode messages independently or if it
+    /// fails to encode the payload.
+    pub fn encoded<A, E>(
+        message_type: MessageType,
+        payload: A,
+        encoder: &mut E,
+    ) -> Result<PreparedMessage, Error>
+    where
+        A: AsRef<[u8]>,
+        E: ExtensionEncoder,
+    {
+        let parameters = encoder
+            .stateless_parameters()
+            .ok_or(PreparedMessageError::StatefulEncoder)?;
+
+        let data_code = DataCode::from(message_type);
+        let mut buf = BytesMut::from(payload.as_ref());
+        let mut header = ExtFrameHeader {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: data_code.into(),
+        };
+        extension_encode(encoder, &mut buf, &mut header)?;
+
+        let mut header_flags = HeaderFlags::FIN;
+        header_flags.set(HeaderFlags::RSV_1, header.rsv1);
+        header_flags.set(HeaderFlags::RSV_2, header.rsv2);
+        header_flags.set(HeaderFlags::RSV_3, header.rsv3);
+
+        Ok(PreparedMessage::from_parts(
+            OpCode::DataCode(data_code),
+            header_flags,
+            buf.freeze(),
+            Some(parameters),
+        ))
+    }
+
+    fn from_parts(
+        opcode: OpCode,
+        header_flags: HeaderFlags,
+        payload: Bytes,
+        parameters: Option<EncoderParameters>,
+    ) -> PreparedMessage {
+        let mut frame = BytesMut::new();
+        FrameHeader::write_into(&mut frame, opcode, header_flags, None, payload.len());
+        frame.extend_from_slice(&payload);
+
+        PreparedMessage {
+            opcode,
+            header_flags,
+            payload,
+            frame: frame.freeze(),
+            parameters,
+        }
+    }
+
+    /// Returns the encoded payload of this message.
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Returns an error if this message was encoded with parameters other than those of `encoder`,
+    /// in which case the peer of a connection that uses `encoder` may not be able to decode it.
+    pub(crate) fn check_encoder<E>(&self, encoder: &E) -> Result<(), Error>
+    where
+        E: ExtensionEncoder,
+    {
+        match &self.parameters {
+            Some(parameters) if encoder.stateless_parameters().as_ref() != Some(parameters) => {
+                Err(PreparedMessageError::IncompatibleEncoder.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn opcode(&self) -> OpCode {
+        self.opcode
+    }
+
+    pub(crate) fn header_flags(&self) -> HeaderFlags {
+        self.header_flags
+    }
+
+    /// Returns the complete, unmasked, frame of this message, as it is written by a server.
+    pub(crate) fn frame(&self) -> &Bytes {
+        &self.frame
+    }
+}
@@ -12,67 +12,98 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(test)]
-mod tests;
-
-mod frame;
-mod mask;
-
-pub use frame::*;
-pub use mask::apply_mask;
+pub use ratchet_proto::*;
 
+use crate::errors::ProtocolError;
+use crate::ws::CONTROL_MAX_SIZE;
 use bytes::Bytes;
 use std::convert::TryFrom;
-use std::fmt::{Display, Formatter};
-use thiserror::Error;
-
-bitflags::bitflags! {
-    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-    pub struct HeaderFlags: u8 {
-        const FIN       = 0b1000_0000;
+use std::time::Duration;
 
-        const RSV_1     = 0b0100_0000;
-        const RSV_2     = 0b0010_0000;
-        const RSV_3     = 0b0001_0000;
-
-        // The extension bits that *may* be high. Anything outside this range is illegal.
-        const RESERVED  = Self::RSV_1.bits() | Self::RSV_2.bits() | Self::RSV_3.bits();
+/// A received WebSocket frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A text message.
+    ///
+    /// # Note
+    /// The payload has been validated to be UTF-8 and so it may be converted to a string without
+    /// being validated again. A text message with an invalid payload produces an encoding error
+    /// and the connection is closed.
+    Text,
+    /// A binary message.
+    Binary,
+    /// A ping message.
+    Ping(Bytes),
+    /// A pong message.
+    Pong(Bytes),
+    /// A close message.
+    Close(Option<CloseReason>),
+}
 
-        // no new flags should be added
+impl Message {
+    /// Whether this is a text message.
+    pub fn is_text(&self) -> bool {
+        matches!(self, Message::Text)
     }
-}
 
-#[allow(warnings)]
-impl HeaderFlags {
-    pub fn is_fin(&self) -> bool {
-        self.contains(HeaderFlags::FIN)
+    /// Whether this is a binary message.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Message::Binary)
     }
 
-    pub fn is_rsv1(&self) -> bool {
-        self.contains(HeaderFlags::RSV_1)
+    /// Whether this is a ping message.
+    pub fn is_ping(&self) -> bool {
+        matches!(self, Message::Ping(_))
     }
 
-    pub fn is_rsv2(&self) -> bool {
-        self.contains(HeaderFlags::RSV_2)
+    /// Whether this is a pong message.
+    pub fn is_pong(&self) -> bool {
+        matches!(self, Message::Pong(_))
     }
 
-    pub fn is_rsv3(&self) -> bool {
-        self.contains(HeaderFlags::RSV_3)
+    /// Whether this is a close message.
+    pub fn is_close(&self) -> bool {
+        matches!(self, Message::Close(_))
     }
 }
 
-/// A received WebSocket frame.
+/// A received WebSocket message or a fragment of one. This is produced by
+/// [`crate::WebSocket::read_fragment`].
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub enum Message {
-    /// A text message.
+pub enum Fragment {
+    /// A fragment of a text message. `fin` is set if this was the final fragment of the message.
     ///
     /// # Note
-    /// [RFC6455](https://datatracker.ietf.org/doc/html/rfc6455) is not strict as to when UTF-8
-    /// validation takes place. As such, Ratchet opts to not validate the text payload and leaves it
-    /// to the user to validate it once the message has been received.
-    Text,
+    /// As the message may have been fragmented at any point, it is possible for a fragment to end
+    /// partway through a UTF-8 sequence.
+    Text {
+        /// Whether this was the final fragment of the message.
+        fin: bool,
+    },
+    /// A fragment of a binary message. `fin` is set if this was the final fragment of the message.
+    Binary {
+        /// Whether this was the final fragment of the message.
+        fin: bool,
+    },
+    /// A ping message.
+    Ping(Bytes),
+    /// A pong message.
+    Pong(Bytes),
+    /// A close message.
+    Close(Option<CloseReason>),
+}
+
+/// A WebSocket message which owns its payload.
+///
+/// This is the item type that is produced and consumed by the `Stream` and `Sink` implementations
+/// for [`crate::WebSocket`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedMessage {
+    /// A text message. As with [`Message::Text`], a received payload has been validated to be
+    /// UTF-8.
+    Text(Bytes),
     /// A binary message.
-    Binary,
+    Binary(Bytes),
     /// A ping message.
     Ping(Bytes),
     /// A pong message.
@@ -81,30 +112,30 @@ pub enum Message {
     Close(Option<CloseReason>),
 }
 
-impl Message {
+impl OwnedMessage {
     /// Whether this is a text message.
     pub fn is_text(&self) -> bool {
-        matches!(self, Message::Text)
+        matches!(self, OwnedMessage::Text(_))
     }
 
     /// Whether this is a binary message.
     pub fn is_binary(&self) -> bool {
-        matches!(self, Message::Binary)
+        matches!(self, OwnedMessage::Binary(_))
     }
 
     /// Whether this is a ping message.
     pub fn is_ping(&self) -> bool {
-        matches!(self, Message::Ping(_))
+        matches!(self, OwnedMessage::Ping(_))
     }
 
     /// Whether this is a pong message.
     pub fn is_pong(&self) -> bool {
-        matches!(self, Message::Pong(_))
+        matches!(self, OwnedMessage::Pong(_))
     }
 
     /// Whether this is a close message.
     pub fn is_close(&self) -> bool {
-        matches!(self, Message::Close(_))
+        matches!(self, OwnedMessage::Close(_))
     }
 }
 
@@ -117,12 +148,12 @@ pub enum PayloadType {
     Binary,
     /// A ping message.
     Ping,
-    /// A pong message.
+    /// A pong message. This may be sent unsolicited as a unidirectional heartbeat.
     Pong,
 }
 
 /// A message type to send.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MessageType {
     /// A text message.
     Text,
@@ -131,130 +162,281 @@ pub enum MessageType {
 }
 
 /// A configuration for building a WebSocket.
+///
+/// Fields may be added to this struct in future releases and so it may only be constructed from
+/// [`WebSocketConfig::default`] and configured using its fields or its `with_*` functions:
+/// ```
+/// # use ratchet_core::WebSocketConfig;
+/// # use std::time::Duration;
+/// let config = WebSocketConfig::default()
+///     .with_max_message_size(1 << 20)
+///     .with_keepalive_interval(Duration::from_secs(30));
+/// ```
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub struct WebSocketConfig {
     /// The maximum payload size that is permitted to be received.
     pub max_message_size: usize,
+    /// If set, the maximum payload size of a single received frame. This allows large fragmented
+    /// messages to be received while still rejecting oversized frames as soon as their header has
+    /// been read. Frames are always limited by `max_message_size`.
+    pub max_frame_size: Option<usize>,
+    /// If set, a ping frame will automatically be sent to the peer if no frames have been received
+    /// within this interval.
+    ///
+    /// Keepalive pings are sent while the WebSocket is being read from, so the WebSocket must be
+    /// continually polled for messages for them to be sent. Split WebSocket halves do not send
+    /// keepalive pings.
+    pub keepalive_interval: Option<Duration>,
+    /// If set, and a keepalive ping is not responded to by the peer within this duration, then the
+    /// connection will be closed with [`CloseCode::Policy`] and the pending read will return a
+    /// timeout error. This has no effect if `keepalive_interval` is not set.
+    pub pong_timeout: Option<Duration>,
+    /// If set, the connection will be closed with [`CloseCode::GoingAway`] and the pending read
+    /// will return a timeout error if no text or binary frames have been read or written within
+    /// this duration.
+    ///
+    /// As with keepalive pings, the timeout is only enforced while the WebSocket is being read
+    /// from. Split WebSocket halves do not enforce an idle timeout.
+    pub idle_timeout: Option<Duration>,
+    /// Whether ping and pong frames that are read or written, including keepalive pings, reset the
+    /// idle timeout. By default only text and binary frames do, so that a connection which is
+    /// kept alive by pings but is otherwise unused is still closed. This has no effect if
+    /// `idle_timeout` is not set.
+    pub idle_counts_pings: bool,
+    /// If set, the rate at which text and binary messages are read from the peer is limited and
+    /// `action` is taken when the peer exceeds it. See [`RateLimit`].
+    ///
+    /// The limit is enforced while the WebSocket, or the receiver half of a split WebSocket, is
+    /// being read from.
+    pub rate_limit: Option<RateLimit>,
+    /// If set, any text or binary message with a payload larger than this size will be sent as a
+    /// fragmented message, with each fragment carrying at most this many bytes. A size of zero is
+    /// ignored.
+    ///
+    /// This does not apply to messages sent using `write_fragmented`, which are always fragmented
+    /// using the provided fragment size.
+    pub auto_fragment_above: Option<usize>,
+    /// If set, text and binary messages are held in an internal write buffer until it contains
+    /// at least this many bytes, coalescing many small messages into fewer writes to the
+    /// underlying stream. Buffered messages are not sent until the threshold is reached, `flush`
+    /// is called or a control frame is written. If unset, every message is flushed as soon as it
+    /// has been written.
+    pub autoflush_threshold: Option<usize>,
+    /// If set, a read operation fails with `TimeoutCause::Read` if no message has been read
+    /// within this duration. Any part of a message that has been received is retained and the
+    /// connection remains open, so the read may be retried.
+    ///
+    /// This applies to the asynchronous read functions, such as `read`, but not to the poll-based
+    /// functions or the `Stream` implementation.
+    pub read_timeout: Option<Duration>,
+    /// If set, a write or flush operation fails with `TimeoutCause::Write` if the buffered frames
+    /// have not been written to the underlying stream within this duration. The connection remains
+    /// open and any frames which have not yet been written remain buffered, so that they are sent
+    /// by the next write or flush operation.
+    pub write_timeout: Option<Duration>,
+    /// If set, the opening handshake fails with a timeout error if it has not completed within
+    /// this duration. For a client, this is the time taken for the server to respond to the upgrade
+    /// request and, for a server, the time taken for the client to send its upgrade request.
+    pub handshake_timeout: Option<Duration>,
+    /// The maximum number of headers that a server will accept in an upgrade request. A request
+    /// with more headers is rejected with `431 Request Header Fields Too Large`.
+    pub max_headers: usize,
+    /// If set, the maximum size in bytes of an upgrade request that a server will accept,
+    /// including its request line and headers. A larger request is rejected with
+    /// `431 Request Header Fields Too Large`.
+    pub max_header_bytes: Option<usize>,
+    /// If set, the opening handshake tolerates technically malformed requests and responses which
+    /// are commonly sent by embedded devices and older proxies. Token header values may then be
+    /// repeated, listed alongside other values or padded with whitespace, a server no longer
+    /// requires the `Host` and `Sec-WebSocket-Version` headers and a client no longer requires the
+    /// `Upgrade` and `Connection` headers. The `Sec-WebSocket-Key` and `Sec-WebSocket-Accept`
+    /// headers are always required.
+    pub lenient_handshake: bool,
 }
 
 impl Default for WebSocketConfig {
     fn default() -> Self {
         WebSocketConfig {
             max_message_size: 64 << 20,
+            max_frame_size: None,
+            keepalive_interval: None,
+            pong_timeout: None,
+            idle_timeout: None,
+            idle_counts_pings: false,
+            rate_limit: None,
+            auto_fragment_above: None,
+            autoflush_threshold: None,
+            read_timeout: None,
+            write_timeout: None,
+            handshake_timeout: None,
+            max_headers: 32,
+            max_header_bytes: None,
+            lenient_handshake: false,
         }
     }
 }
 
-/// The role of a WebSocket.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Role {
-    /// The WebSocket is a client.
-    Client,
-    /// The WebSocket is a server.
-    Server,
-}
+impl WebSocketConfig {
+    /// Sets the maximum payload size that is permitted to be received.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
 
-impl Role {
-    /// Returns whether this WebSocket is a client.
-    pub fn is_client(&self) -> bool {
-        matches!(self, Role::Client)
+    /// Sets the maximum payload size of a single received frame.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
     }
 
-    /// Returns whether this WebSocket is a server.
-    pub fn is_server(&self) -> bool {
-        matches!(self, Role::Server)
+    /// Sets the interval after which a keepalive ping is sent if no frames have been received.
+    pub fn with_keepalive_interval(mut self, keepalive_interval: Duration) -> Self {
+        self.keepalive_interval = Some(keepalive_interval);
+        self
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum OpCode {
-    DataCode(DataCode),
-    ControlCode(ControlCode),
-}
+    /// Sets the duration within which the peer must respond to a keepalive ping.
+    pub fn with_pong_timeout(mut self, pong_timeout: Duration) -> Self {
+        self.pong_timeout = Some(pong_timeout);
+        self
+    }
 
-impl Display for OpCode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+    /// Sets the duration after which an idle connection is closed.
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
     }
-}
 
-impl OpCode {
-    pub fn is_data(&self) -> bool {
-        matches!(self, OpCode::DataCode(_))
+    /// Sets whether ping and pong frames reset the idle timeout.
+    pub fn with_idle_counts_pings(mut self, idle_counts_pings: bool) -> Self {
+        self.idle_counts_pings = idle_counts_pings;
+        self
     }
 
-    pub fn is_control(&self) -> bool {
-        matches!(self, OpCode::ControlCode(_))
+    /// Sets the rate at which messages may be read from the peer.
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
     }
-}
 
-impl From<OpCode> for u8 {
-    fn from(op: OpCode) -> Self {
-        match op {
-            OpCode::DataCode(code) => code as u8,
-            OpCode::ControlCode(code) => code as u8,
-        }
+    /// Sets the payload size above which messages are sent as fragmented messages.
+    pub fn with_auto_fragment_above(mut self, auto_fragment_above: usize) -> Self {
+        self.auto_fragment_above = Some(auto_fragment_above);
+        self
     }
-}
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum DataCode {
-    Continuation = 0,
-    Text = 1,
-    Binary = 2,
-}
+    /// Sets the number of buffered bytes at which text and binary messages are flushed.
+    pub fn with_autoflush_threshold(mut self, autoflush_threshold: usize) -> Self {
+        self.autoflush_threshold = Some(autoflush_threshold);
+        self
+    }
+
+    /// Sets the duration after which a read operation times out.
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// Sets the duration after which a write or flush operation times out.
+    pub fn with_write_timeout(mut self, write_timeout: Duration) -> Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// Sets the duration after which the opening handshake times out.
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = Some(handshake_timeout);
+        self
+    }
+
+    /// Sets the maximum number of headers that a server accepts in an upgrade request.
+    pub fn with_max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
 
-impl Display for DataCode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+    /// Sets the maximum size of an upgrade request that a server accepts.
+    pub fn with_max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.max_header_bytes = Some(max_header_bytes);
+        self
     }
+
+    /// Sets whether the opening handshake tolerates malformed requests and responses.
+    pub fn with_lenient_handshake(mut self, lenient_handshake: bool) -> Self {
+        self.lenient_handshake = lenient_handshake;
+        self
+    }
+}
+
+/// A limit on the rate at which text and binary messages are read from the peer.
+///
+/// Each limit permits a burst of up to one second's worth of messages or bytes, after which
+/// reads are limited to the configured rate. A message which exceeds a limit is still returned
+/// and the limit is instead enforced by the next read operation, so that a large message does not
+/// need to be rejected partway through. The length of a payload is counted once it has been
+/// decoded by the negotiated extension. Control frames are not counted.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct RateLimit {
+    /// If set, the maximum number of messages that may be read per second. A rate of zero is
+    /// ignored.
+    pub messages_per_second: Option<u32>,
+    /// If set, the maximum number of payload bytes that may be read per second. A rate of zero is
+    /// ignored.
+    pub bytes_per_second: Option<u64>,
+    /// What to do when the peer exceeds either limit.
+    pub action: RateLimitAction,
 }
 
-impl From<DataCode> for ratchet_ext::OpCode {
-    fn from(e: DataCode) -> Self {
-        match e {
-            DataCode::Continuation => ratchet_ext::OpCode::Continuation,
-            DataCode::Text => ratchet_ext::OpCode::Text,
-            DataCode::Binary => ratchet_ext::OpCode::Binary,
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            messages_per_second: None,
+            bytes_per_second: None,
+            action: RateLimitAction::Delay,
         }
     }
 }
 
+/// What a WebSocket does when the peer exceeds its [`RateLimit`].
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum RateLimitAction {
+    /// Stop reading from the peer until the rate has fallen below the limit. This applies
+    /// backpressure to the peer, so ping frames are not answered while reading is delayed.
+    Delay,
+    /// Close the connection with [`CloseCode::Policy`] and return a rate limit error.
+    Close,
+    /// Return a rate limit error without reading from the peer. The connection remains open and
+    /// the read may be retried once the rate has fallen below the limit.
+    Error,
+}
+
+/// The role of a WebSocket.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum ControlCode {
-    Close = 8,
-    Ping = 9,
-    Pong = 10,
+pub enum Role {
+    /// The WebSocket is a client.
+    Client,
+    /// The WebSocket is a server.
+    Server,
 }
 
-impl Display for ControlCode {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+impl Role {
+    /// Returns whether this WebSocket is a client.
+    pub fn is_client(&self) -> bool {
+        matches!(self, Role::Client)
     }
-}
 
-#[derive(Copy, Clone, Debug, Error, PartialEq, Eq)]
-pub enum OpCodeParseErr {
-    #[error("Reserved OpCode: `{0}`")]
-    Reserved(u8),
-    #[error("Invalid OpCode: `{0}`")]
-    Invalid(u8),
+    /// Returns whether this WebSocket is a server.
+    pub fn is_server(&self) -> bool {
+        matches!(self, Role::Server)
+    }
 }
 
-impl TryFrom<u8> for OpCode {
-    type Error = OpCodeParseErr;
-
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(OpCode::DataCode(DataCode::Continuation)),
-            1 => Ok(OpCode::DataCode(DataCode::Text)),
-            2 => Ok(OpCode::DataCode(DataCode::Binary)),
-            r @ 3..=7 => Err(OpCodeParseErr::Reserved(r)),
-            8 => Ok(OpCode::ControlCode(ControlCode::Close)),
-            9 => Ok(OpCode::ControlCode(ControlCode::Ping)),
-            10 => Ok(OpCode::ControlCode(ControlCode::Pong)),
-            r @ 11..=15 => Err(OpCodeParseErr::Reserved(r)),
-            e => Err(OpCodeParseErr::Invalid(e)),
+impl From<MessageType> for DataCode {
+    fn from(message_type: MessageType) -> Self {
+        match message_type {
+            MessageType::Text => DataCode::Text,
+            MessageType::Binary => DataCode::Binary,
         }
     }
 }
@@ -273,100 +455,42 @@ impl CloseReason {
     pub fn new(code: CloseCode, description: Option<String>) -> Self {
         CloseReason { code, description }
     }
-}
 
-/// # Additional implementation sources:
-/// <https://developer.mozilla.org/en-US/docs/Web/API/CloseEvent>
-/// <https://mailarchive.ietf.org/arch/msg/hybi/P_1vbD9uyHl63nbIIbFxKMfSwcM/>
-/// <https://tools.ietf.org/id/draft-ietf-hybi-thewebsocketprotocol-09.html>
-#[allow(missing_docs)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum CloseCode {
-    Normal,
-    GoingAway,
-    Protocol,
-    Unsupported,
-    Status,
-    Abnormal,
-    Invalid,
-    Policy,
-    Overflow,
-    Extension,
-    Unexpected,
-    Restarting,
-    TryAgain,
-    Tls,
-    ReservedExtension(u16),
-    Library(u16),
-    Application(u16),
-}
+    /// Validates that this reason may be sent to a peer. The code must be one that is permitted to
+    /// be sent in a close frame and the encoded reason must fit into a control frame.
+    pub(crate) fn validate(&self) -> Result<(), ProtocolError> {
+        let CloseReason { code, description } = self;
+        let value = u16::from(*code);
 
-impl CloseCode {
-    pub(crate) fn is_illegal(&self) -> bool {
-        matches!(
-            self,
-            CloseCode::Status
-                | CloseCode::Tls
-                | CloseCode::Abnormal
-                | CloseCode::ReservedExtension(_)
-        )
-    }
-}
+        // This catches both illegal codes and codes which are outside of their variant's range.
+        match CloseCode::try_from(value.to_be_bytes()) {
+            Ok(parsed) if parsed == *code && !parsed.is_illegal() => {}
+            _ => return Err(ProtocolError::CloseCode(value)),
+        }
 
-#[derive(Copy, Clone, Error, Debug)]
-#[error("Unknown close code: `{0}`")]
-pub struct CloseCodeParseErr(pub(crate) u16);
-
-impl TryFrom<[u8; 2]> for CloseCode {
-    type Error = CloseCodeParseErr;
-
-    fn try_from(value: [u8; 2]) -> Result<Self, Self::Error> {
-        let value = u16::from_be_bytes(value);
-        match value {
-            n @ 0..=999 => Err(CloseCodeParseErr(n)),
-            1000 => Ok(CloseCode::Normal),
-            1001 => Ok(CloseCode::GoingAway),
-            1002 => Ok(CloseCode::Protocol),
-            1003 => Ok(CloseCode::Unsupported),
-            1005 => Ok(CloseCode::Status),
-            1006 => Ok(CloseCode::Abnormal),
-            1007 => Ok(CloseCode::Invalid),
-            1008 => Ok(CloseCode::Policy),
-            1009 => Ok(CloseCode::Overflow),
-            1010 => Ok(CloseCode::Extension),
-            1011 => Ok(CloseCode::Unexpected),
-            1012 => Ok(CloseCode::Restarting),
-            1013 => Ok(CloseCode::TryAgain),
-            1015 => Ok(CloseCode::Tls),
-            n @ 1016..=1999 => Err(CloseCodeParseErr(n)),
-            n @ 2000..=2999 => Ok(CloseCode::ReservedExtension(n)),
-            n @ 3000..=3999 => Ok(CloseCode::Library(n)),
-            n @ 4000..=4999 => Ok(CloseCode::Application(n)),
-            n => Err(CloseCodeParseErr(n)),
+        let len = description.as_ref().map(String::len).unwrap_or_default();
+        if len + 2 > CONTROL_MAX_SIZE {
+            Err(ProtocolError::FrameOverflow)
+        } else {
+            Ok(())
         }
     }
 }
 
-impl From<CloseCode> for u16 {
-    fn from(code: CloseCode) -> u16 {
-        match code {
-            CloseCode::Normal => 1000,
-            CloseCode::GoingAway => 1001,
-            CloseCode::Protocol => 1002,
-            CloseCode::Unsupported => 1003,
-            CloseCode::Status => 1005,
-            CloseCode::Abnormal => 1006,
-            CloseCode::Invalid => 1007,
-            CloseCode::Policy => 1008,
-            CloseCode::Overflow => 1009,
-            CloseCode::Extension => 1010,
-            CloseCode::Unexpected => 1011,
-            CloseCode::Restarting => 1012,
-            CloseCode::TryAgain => 1013,
-            CloseCode::Tls => 1015,
-            CloseCode::ReservedExtension(n) => n,
-            CloseCode::Library(n) => n,
-            CloseCode::Application(n) => n,
-        }
+impl From<CloseCode> for CloseReason {
+    fn from(code: CloseCode) -> Self {
+        CloseReason::new(code, None)
     }
 }
+
+/// Writes a WebSocket text frame header into `dst` with FIN set high.
+#[cfg(feature = "fixture")]
+pub fn write_text_frame_header(dst: &mut bytes::BytesMut, mask: Option<u32>, payload_len: usize) {
+    FrameHeader::write_into(
+        dst,
+        OpCode::DataCode(DataCode::Text),
+        HeaderFlags::FIN,
+        mask,
+        payload_len,
+    )
+}
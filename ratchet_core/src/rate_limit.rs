@@ -0,0 +1,152 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::errors::RateLimitCause;
+use crate::protocol::{RateLimit, RateLimitAction};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// A token bucket which holds up to one second's worth of tokens and which may be overdrawn.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    // The bucket is full until it is first used.
+    updated: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Bucket {
+        Bucket {
+            rate,
+            tokens: rate,
+            updated: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(updated) = self.updated {
+            let refilled = now.saturating_duration_since(updated).as_secs_f64() * self.rate;
+            self.tokens = (self.tokens + refilled).min(self.rate);
+        }
+        self.updated = Some(now);
+    }
+
+    fn take(&mut self, count: f64, now: Instant) {
+        self.refill(now);
+        self.tokens -= count;
+    }
+
+    /// Returns how long it will be until the bucket holds at least one token, if it doesn't
+    /// already.
+    fn wait(&mut self, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            // Rounded up so that a deficit which is too small to be represented is never waited
+            // for repeatedly.
+            let wait = Duration::from_secs_f64((1.0 - self.tokens) / self.rate);
+            Some(wait.max(Duration::from_millis(1)))
+        }
+    }
+}
+
+/// Enforces a [`RateLimit`] over the messages that are read from the peer.
+#[derive(Debug)]
+pub struct RateLimiter {
+    action: RateLimitAction,
+    messages: Option<Bucket>,
+    bytes: Option<Bucket>,
+    // Lazily initialised so that a WebSocket may be created outside of a Tokio runtime.
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimit) -> RateLimiter {
+        let RateLimit {
+            messages_per_second,
+            bytes_per_second,
+            action,
+        } = config;
+        RateLimiter {
+            action,
+            messages: messages_per_second
+                .filter(|rate| *rate > 0)
+                .map(|rate| Bucket::new(f64::from(rate))),
+            bytes: bytes_per_second
+                .filter(|rate| *rate > 0)
+                .map(|rate| Bucket::new(rate as f64)),
+            delay: None,
+        }
+    }
+
+    pub fn action(&self) -> RateLimitAction {
+        self.action
+    }
+
+    /// Records that a message has been read.
+    pub fn on_message(&mut self) {
+        if let Some(messages) = &mut self.messages {
+            messages.take(1.0, Instant::now());
+        }
+    }
+
+    /// Records that `count` payload bytes have been read.
+    pub fn on_bytes(&mut self, count: usize) {
+        if let (Some(bytes), true) = (&mut self.bytes, count > 0) {
+            bytes.take(count as f64, Instant::now());
+        }
+    }
+
+    /// Polls whether the next frame may be read. If the peer has exceeded a limit then this
+    /// returns `Poll::Pending` until it may be read if the action is to delay, or the limit that
+    /// was exceeded otherwise.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), RateLimitCause>> {
+        let RateLimiter {
+            action,
+            messages,
+            bytes,
+            delay,
+        } = self;
+
+        loop {
+            let now = Instant::now();
+            let exceeded = match messages.as_mut().and_then(|bucket| bucket.wait(now)) {
+                Some(wait) => Some((RateLimitCause::Messages, wait)),
+                None => bytes
+                    .as_mut()
+                    .and_then(|bucket| bucket.wait(now))
+                    .map(|wait| (RateLimitCause::Bytes, wait)),
+            };
+
+            match (exceeded, *action) {
+                (None, _) => return Poll::Ready(Ok(())),
+                (Some((_, wait)), RateLimitAction::Delay) => {
+                    let deadline = now + wait;
+                    let sleep = delay.get_or_insert_with(|| Box::pin(sleep_until(deadline)));
+                    sleep.as_mut().reset(deadline);
+                    if sleep.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                (Some((cause, _)), RateLimitAction::Close | RateLimitAction::Error) => {
+                    return Poll::Ready(Err(cause))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod split;
+#[cfg(test)]
+mod tests;
+
+use crate::framed::FramedIo;
+use crate::{Error, Role, WebSocketConfig, WebSocketStream};
+use bytes::BytesMut;
+use ratchet_ext::RsvBits;
+
+pub use crate::protocol::{ControlCode, DataCode, FrameHeader, HeaderFlags, OpCode};
+pub use split::{RawReceiver, RawReuniteError, RawSender};
+
+/// A WebSocket connection which provides frame-level access to the connection.
+///
+/// Unlike [`crate::WebSocket`], no processing is performed on any of the frames that are read or
+/// written: control frames are not responded to, fragmented messages are not reassembled and no
+/// extension is applied to payloads. Frames that are read are still validated against the
+/// WebSocket protocol's framing rules (masking, negotiated reserved bits, control frame
+/// fragmentation and the maximum message and frame sizes, which are applied per frame) and
+/// payloads are masked and unmasked as required by the role.
+///
+/// This is intended for proxies and testing tools which need to inspect or produce individual
+/// frames.
+#[derive(Debug)]
+pub struct RawWebSocket<S> {
+    framed: FramedIo<S>,
+}
+
+impl<S> RawWebSocket<S> {
+    /// Initialise a new `RawWebSocket` from a stream that has already executed a handshake.
+    ///
+    /// # Arguments
+    /// `config` - The configuration to initialise the WebSocket with. Only the maximum message and
+    /// frame sizes are used. As frames are not reassembled, both limit the size of a single frame.
+    /// `stream` - The stream that the handshake was executed on.
+    /// `read_buffer` - The read buffer which will be used for the session. This **may** contain any
+    /// unread data received after performing the handshake that was not required.
+    /// `role` - The role that this WebSocket will take.
+    /// `rsv_bits` - The reserved bits that the peer is permitted to set high.
+    pub fn from_upgraded(
+        config: WebSocketConfig,
+        stream: S,
+        read_buffer: BytesMut,
+        role: Role,
+        rsv_bits: RsvBits,
+    ) -> RawWebSocket<S> {
+        let mut framed = FramedIo::new(
+            stream,
+            read_buffer,
+            role,
+            config.max_message_size,
+            rsv_bits.into(),
+        );
+        framed.set_max_frame_size(config.max_frame_size);
+
+        RawWebSocket { framed }
+    }
+
+    pub(crate) fn from_framed(framed: FramedIo<S>) -> RawWebSocket<S> {
+        RawWebSocket { framed }
+    }
+
+    /// Returns the role of this WebSocket.
+    pub fn role(&self) -> Role {
+        if self.framed.is_server() {
+            Role::Server
+        } else {
+            Role::Client
+        }
+    }
+
+    /// Splits this WebSocket into its frame-level sender and receiver halves, which may be used
+    /// concurrently, such as by a proxy which forwards the frames of each direction independently.
+    ///
+    /// # Note
+    /// As with [`crate::WebSocket::split`], the IO is not split but is placed into a `BiLock`.
+    /// The halves may be reunited with [`RawSender::reunite`].
+    pub fn split(self) -> (RawSender<S>, RawReceiver<S>) {
+        split::split(self.framed)
+    }
+}
+
+impl<S> RawWebSocket<S>
+where
+    S: WebSocketStream,
+{
+    /// Reads the next frame from the connection, appending its unmasked payload to `payload` and
+    /// returning its header.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe. If the future is dropped before it has completed then
+    /// no frame has been read and any data that has been received is retained for the next call.
+    pub async fn read_frame(&mut self, payload: &mut BytesMut) -> Result<FrameHeader, Error> {
+        let (header, frame_payload) = self.framed.read_frame().await?;
+        payload.extend_from_slice(&frame_payload);
+        Ok(header)
+    }
+
+    /// Writes a frame with the provided opcode, flags and payload to the connection and flushes it.
+    /// If this WebSocket is a client then the payload is masked.
+    ///
+    /// No validation is performed on the frame and so it is possible to write frames which violate
+    /// the WebSocket protocol (such as fragmented or oversized control frames).
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the frame may have only been partially written.
+    pub async fn write_frame<A>(
+        &mut self,
+        opcode: OpCode,
+        flags: HeaderFlags,
+        payload: A,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        self.framed
+            .write(opcode, flags, payload, |_, _| Ok(()))
+            .await
+    }
+
+    /// Flushes the connection, ensuring that any buffered frames reach their destination.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.framed.flush().await
+    }
+
+    /// Shuts down the underlying IO.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        self.framed.shutdown().await
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::framed::{
+    read_props, CodecFlags, FramedIo, FramedIoParts, FramedRead, FramedWrite, ReadProps,
+};
+use crate::raw::{FrameHeader, HeaderFlags, OpCode, RawWebSocket};
+use crate::split::bilock::{bilock, BiLock};
+use crate::{Error, Role, WebSocketStream};
+use bytes::BytesMut;
+use std::future::poll_fn;
+use tokio::io::AsyncWriteExt;
+
+/// Splits a raw WebSocket's parts into send and receive halves which share the IO through a
+/// `BiLock`.
+pub fn split<S>(framed: FramedIo<S>) -> (RawSender<S>, RawReceiver<S>) {
+    let FramedIoParts {
+        io,
+        reader,
+        writer,
+        flags,
+        max_message_size,
+    } = framed.into_parts();
+    let (read_half, write_half) = bilock(io);
+
+    let sender = RawSender {
+        is_server: flags.contains(CodecFlags::ROLE),
+        write_half,
+        writer,
+    };
+    let receiver = RawReceiver {
+        flags,
+        max_message_size,
+        read_half,
+        reader,
+    };
+
+    (sender, receiver)
+}
+
+/// An owned write half of a [`RawWebSocket`].
+///
+/// As with the [`RawWebSocket`], frames are written exactly as they are provided and so it is
+/// the responsibility of the caller to respond to any control frames that are read by the
+/// [`RawReceiver`].
+#[derive(Debug)]
+pub struct RawSender<S> {
+    is_server: bool,
+    write_half: BiLock<S>,
+    writer: FramedWrite,
+}
+
+impl<S> RawSender<S>
+where
+    S: WebSocketStream,
+{
+    /// Returns the role of this sender.
+    pub fn role(&self) -> Role {
+        if self.is_server {
+            Role::Server
+        } else {
+            Role::Client
+        }
+    }
+
+    /// Writes a frame with the provided opcode, flags and payload to the connection and flushes it.
+    /// See [`RawWebSocket::write_frame`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the frame may have only been partially written.
+    pub async fn write_frame<A>(
+        &mut self,
+        opcode: OpCode,
+        flags: HeaderFlags,
+        payload: A,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        let RawSender {
+            is_server,
+            write_half,
+            writer,
+        } = self;
+        writer
+            .write(
+                write_half,
+                *is_server,
+                opcode,
+                flags,
+                payload,
+                |_, _| Ok(()),
+            )
+            .await
+    }
+
+    /// Flushes the connection, ensuring that any buffered frames reach their destination.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        let RawSender {
+            write_half, writer, ..
+        } = self;
+        writer.flush(write_half).await
+    }
+
+    /// Shuts down the underlying IO.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        self.write_half.shutdown().await?;
+        Ok(())
+    }
+
+    /// Attempt to reunite this send half with its receiver.
+    ///
+    /// # Errors
+    /// Errors if `receiver` is not paired with this sender.
+    #[allow(clippy::result_large_err)]
+    pub fn reunite(self, receiver: RawReceiver<S>) -> Result<RawWebSocket<S>, RawReuniteError<S>> {
+        if !self.write_half.same_bilock(&receiver.read_half) {
+            return Err(RawReuniteError {
+                sender: self,
+                receiver,
+            });
+        }
+
+        let RawSender {
+            write_half, writer, ..
+        } = self;
+        let RawReceiver {
+            flags,
+            max_message_size,
+            read_half,
+            reader,
+        } = receiver;
+
+        let framed = FramedIo::from_parts(FramedIoParts {
+            // This is safe as we have checked the pointers
+            io: read_half.reunite(write_half).expect("Failed to reunite IO"),
+            reader,
+            writer,
+            flags,
+            max_message_size,
+        });
+        Ok(RawWebSocket::from_framed(framed))
+    }
+}
+
+/// An owned read half of a [`RawWebSocket`].
+#[derive(Debug)]
+pub struct RawReceiver<S> {
+    flags: CodecFlags,
+    max_message_size: usize,
+    read_half: BiLock<S>,
+    reader: FramedRead,
+}
+
+impl<S> RawReceiver<S>
+where
+    S: WebSocketStream,
+{
+    /// Returns the role of this receiver.
+    pub fn role(&self) -> Role {
+        if self.flags.contains(CodecFlags::ROLE) {
+            Role::Server
+        } else {
+            Role::Client
+        }
+    }
+
+    /// Reads the next frame from the connection, appending its unmasked payload to `payload` and
+    /// returning its header. No frames are responded to and fragmented messages are not
+    /// reassembled, so the fragmentation boundaries of the peer's messages are preserved exactly.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe. If the future is dropped before it has completed then
+    /// no frame has been read and any data that has been received is retained for the next call.
+    pub async fn read_frame(&mut self, payload: &mut BytesMut) -> Result<FrameHeader, Error> {
+        let RawReceiver {
+            flags,
+            max_message_size,
+            read_half,
+            reader,
+        } = self;
+        let ReadProps {
+            is_server,
+            rsv_bits,
+            ..
+        } = read_props(flags, *max_message_size, false);
+
+        let (header, frame_payload) = poll_fn(|cx| {
+            reader.poll_read_frame(cx, read_half, is_server, rsv_bits, *max_message_size)
+        })
+        .await?;
+        payload.extend_from_slice(&frame_payload);
+        Ok(header)
+    }
+}
+
+/// An error produced by [`RawSender::reunite`] if the halves do not match.
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub struct RawReuniteError<S> {
+    pub sender: RawSender<S>,
+    pub receiver: RawReceiver<S>,
+}
@@ -0,0 +1,268 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::raw::{ControlCode, DataCode, HeaderFlags, OpCode, RawWebSocket};
+use crate::{Message, NoExt, Role, WebSocket, WebSocketConfig};
+use bytes::{Bytes, BytesMut};
+use ratchet_ext::RsvBits;
+use tokio::io::{duplex, DuplexStream};
+
+fn fixture() -> (RawWebSocket<DuplexStream>, RawWebSocket<DuplexStream>) {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+    let rsv_bits = || RsvBits {
+        rsv1: false,
+        rsv2: false,
+        rsv3: false,
+    };
+
+    let server =
+        RawWebSocket::from_upgraded(config, server, BytesMut::new(), Role::Server, rsv_bits());
+    let client =
+        RawWebSocket::from_upgraded(config, client, BytesMut::new(), Role::Client, rsv_bits());
+
+    (client, server)
+}
+
+#[tokio::test]
+async fn frames() {
+    let (mut client, mut server) = fixture();
+
+    client
+        .write_frame(
+            OpCode::DataCode(DataCode::Text),
+            HeaderFlags::empty(),
+            "hello",
+        )
+        .await
+        .expect("Write failed");
+    client
+        .write_frame(
+            OpCode::ControlCode(ControlCode::Ping),
+            HeaderFlags::FIN,
+            "ping",
+        )
+        .await
+        .expect("Write failed");
+    client
+        .write_frame(
+            OpCode::DataCode(DataCode::Continuation),
+            HeaderFlags::FIN,
+            " world",
+        )
+        .await
+        .expect("Write failed");
+
+    let mut payload = BytesMut::new();
+
+    let header = server.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Text));
+    assert!(!header.flags.is_fin());
+    assert!(header.mask.is_some());
+
+    let header = server.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::ControlCode(ControlCode::Ping));
+    assert!(header.flags.is_fin());
+
+    let header = server.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Continuation));
+    assert!(header.flags.is_fin());
+
+    assert_eq!(payload.as_ref(), b"helloping world");
+
+    server
+        .write_frame(
+            OpCode::DataCode(DataCode::Binary),
+            HeaderFlags::FIN,
+            [1, 2, 3],
+        )
+        .await
+        .expect("Write failed");
+
+    payload.clear();
+    let header = client.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Binary));
+    assert!(header.mask.is_none());
+    assert_eq!(payload.as_ref(), &[1, 2, 3]);
+}
+
+#[tokio::test]
+async fn into_raw() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let mut server =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+    let mut client =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client)
+            .into_raw();
+
+    assert_eq!(client.role(), Role::Client);
+
+    // An unsolicited pong isn't answered by the raw WebSocket and so it can be read as-is.
+    server.write_pong("pong").await.expect("Write failed");
+    server.write_text("text").await.expect("Write failed");
+
+    let mut payload = BytesMut::new();
+    let header = client.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::ControlCode(ControlCode::Pong));
+
+    let header = client.read_frame(&mut payload).await.expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Text));
+    assert_eq!(payload.as_ref(), b"pongtext");
+
+    client
+        .write_frame(
+            OpCode::DataCode(DataCode::Binary),
+            HeaderFlags::FIN,
+            "binary",
+        )
+        .await
+        .expect("Write failed");
+
+    let mut buf = BytesMut::new();
+    let message = server.read(&mut buf).await.expect("Read failed");
+    assert_eq!(message, Message::Binary);
+    assert_eq!(buf.freeze(), Bytes::from_static(b"binary"));
+}
+
+#[tokio::test]
+async fn split_frames() {
+    let (client, server) = fixture();
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut server_tx, mut server_rx) = server.split();
+
+    assert_eq!(client_tx.role(), Role::Client);
+    assert_eq!(server_rx.role(), Role::Server);
+
+    // Relay each frame back to the client exactly as it was received.
+    let relay = tokio::spawn(async move {
+        let mut payload = BytesMut::new();
+        for _ in 0..3 {
+            let header = server_rx
+                .read_frame(&mut payload)
+                .await
+                .expect("Read failed");
+            server_tx
+                .write_frame(header.opcode, header.flags, payload.split())
+                .await
+                .expect("Write failed");
+        }
+        server_tx.reunite(server_rx).expect("Reunite failed")
+    });
+
+    let frames = [
+        (
+            OpCode::DataCode(DataCode::Text),
+            HeaderFlags::empty(),
+            "hel",
+        ),
+        (
+            OpCode::ControlCode(ControlCode::Ping),
+            HeaderFlags::FIN,
+            "ping",
+        ),
+        (
+            OpCode::DataCode(DataCode::Continuation),
+            HeaderFlags::FIN,
+            "lo",
+        ),
+    ];
+    for (opcode, flags, payload) in frames {
+        client_tx
+            .write_frame(opcode, flags, payload)
+            .await
+            .expect("Write failed");
+    }
+
+    let mut payload = BytesMut::new();
+    for (opcode, flags, expected) in frames {
+        let header = client_rx
+            .read_frame(&mut payload)
+            .await
+            .expect("Read failed");
+        assert_eq!(header.opcode, opcode);
+        assert_eq!(header.flags, flags);
+        assert_eq!(payload.split().as_ref(), expected.as_bytes());
+    }
+
+    let server = relay.await.unwrap();
+    assert_eq!(server.role(), Role::Server);
+}
+
+#[tokio::test]
+async fn reunite_mismatched_halves() {
+    let (client, server) = fixture();
+    let (client_tx, _client_rx) = client.split();
+    let (_server_tx, server_rx) = server.split();
+
+    assert!(client_tx.reunite(server_rx).is_err());
+}
+
+#[tokio::test]
+async fn split_raw() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let mut server =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+    let (mut client_tx, mut client_rx) =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client)
+            .split_raw();
+
+    server
+        .write_fragmented("fragments", crate::MessageType::Text, 4)
+        .await
+        .expect("Write failed");
+
+    let mut payload = BytesMut::new();
+    let header = client_rx
+        .read_frame(&mut payload)
+        .await
+        .expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Text));
+    assert!(!header.flags.is_fin());
+    assert_eq!(payload.split().as_ref(), b"frag");
+
+    let header = client_rx
+        .read_frame(&mut payload)
+        .await
+        .expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Continuation));
+    assert!(!header.flags.is_fin());
+
+    let header = client_rx
+        .read_frame(&mut payload)
+        .await
+        .expect("Read failed");
+    assert_eq!(header.opcode, OpCode::DataCode(DataCode::Continuation));
+    assert!(header.flags.is_fin());
+    assert_eq!(payload.as_ref(), b"ments");
+
+    client_tx
+        .write_frame(
+            OpCode::DataCode(DataCode::Binary),
+            HeaderFlags::FIN,
+            "binary",
+        )
+        .await
+        .expect("Write failed");
+
+    let mut buf = BytesMut::new();
+    assert_eq!(
+        server.read(&mut buf).await.expect("Read failed"),
+        Message::Binary
+    );
+    assert_eq!(buf.as_ref(), b"binary");
+}
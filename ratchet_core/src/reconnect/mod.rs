@@ -0,0 +1,398 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client WebSocket connections which transparently reconnect after IO errors.
+//!
+//! A [`ReconnectingWebSocket`] wraps a function which opens a new WebSocket connection, such as
+//! one which calls `connect` or `subscribe`, so that the opening handshake and extension
+//! negotiation are performed again for each new connection. When an IO error is encountered the
+//! connection is redialed with an exponential backoff between each failed attempt, as configured
+//! by a [`ReconnectConfig`]. Messages which could not be sent during an outage may be retained in
+//! a bounded queue and sent once a new connection has been established.
+
+#[cfg(test)]
+mod tests;
+
+use crate::{CloseReason, Error, Message, PayloadType, WebSocket, WebSocketStream};
+use bytes::{Bytes, BytesMut};
+use rand::Rng;
+use ratchet_ext::Extension;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+/// The configuration of how a [`ReconnectingWebSocket`] redials its connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// The delay after the first failed connection attempt. Each subsequent failure doubles the
+    /// delay, up to `max_delay`.
+    pub initial_delay: Duration,
+    /// The maximum delay between two connection attempts.
+    pub max_delay: Duration,
+    /// The maximum number of consecutive connection attempts before the error produced by the
+    /// last one is returned. If `None`, then connection attempts are made until one succeeds.
+    pub max_attempts: Option<usize>,
+    /// Whether to randomise each delay to between half of and the full computed delay, so that
+    /// many clients which were disconnected at the same time do not redial in lockstep.
+    pub jitter: bool,
+    /// The maximum number of messages which are retained while the connection is lost. If zero,
+    /// then messages are not retained and write operations return the error that was encountered.
+    pub queue_capacity: usize,
+    /// What to do with a message which could not be sent when the queue is full.
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+            jitter: true,
+            queue_capacity: 0,
+            overflow_policy: OverflowPolicy::Error,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Returns the delay to wait for after `attempt` consecutive connection attempts have failed.
+    fn delay(&self, attempt: usize) -> Duration {
+        let ReconnectConfig {
+            initial_delay,
+            max_delay,
+            jitter,
+            ..
+        } = self;
+
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = 2u32
+            .checked_pow(exponent)
+            .and_then(|factor| initial_delay.checked_mul(factor))
+            .map_or(*max_delay, |delay| delay.min(*max_delay));
+
+        if *jitter {
+            rand::thread_rng().gen_range(delay / 2..=delay)
+        } else {
+            delay
+        }
+    }
+}
+
+/// The policy applied to a message which could not be sent when the outgoing queue of a
+/// [`ReconnectingWebSocket`] is full.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest message in the queue and retain the new message.
+    DropOldest,
+    /// Discard the new message.
+    DropNewest,
+    /// Return the error which prevented the new message from being sent.
+    Error,
+}
+
+/// A change in the state of a [`ReconnectingWebSocket`]'s connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection attempt is being made. `attempt` is one for the first attempt after the
+    /// connection was lost and is incremented for each consecutive attempt.
+    Connecting {
+        /// The number of this attempt.
+        attempt: usize,
+    },
+    /// A connection has been established and the opening handshake completed.
+    Connected,
+    /// The connection was lost or the maximum number of connection attempts was reached.
+    Disconnected,
+}
+
+/// A client WebSocket which redials its connection after IO errors.
+///
+/// The connection is opened by calling the function that this WebSocket was created with, which
+/// performs the opening handshake, and this is done lazily by the first operation or by
+/// [`ReconnectingWebSocket::connection`]. Once the connection has been lost, either due to an IO
+/// error or the peer closing it, the next operation redials it.
+///
+/// Read operations are retried transparently over the new connection. A write operation which
+/// could not be sent, either due to an IO error or because a new connection could not be
+/// established, is retained in the outgoing queue if [`ReconnectConfig::queue_capacity`] is
+/// non-zero. Queued messages are sent in order, before any other message, once a new opening
+/// handshake has completed. If the queue is disabled then a failed write operation returns its
+/// error so that the caller may decide whether to send the message again.
+pub struct ReconnectingWebSocket<F, S, E> {
+    connect: F,
+    config: ReconnectConfig,
+    websocket: Option<WebSocket<S, E>>,
+    queue: VecDeque<(PayloadType, Bytes)>,
+    on_state_change: Option<Box<dyn FnMut(ConnectionState) + Send>>,
+}
+
+impl<F, S, E> Debug for ReconnectingWebSocket<F, S, E>
+where
+    S: Debug,
+    E: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectingWebSocket")
+            .field("config", &self.config)
+            .field("websocket", &self.websocket)
+            .field("queue", &self.queue)
+            .finish()
+    }
+}
+
+impl<F, Fut, S, E> ReconnectingWebSocket<F, S, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<WebSocket<S, E>, Error>>,
+    S: WebSocketStream,
+    E: Extension,
+{
+    /// Returns a new WebSocket which opens its connections using `connect`. No connection attempt
+    /// is made until the WebSocket is first used.
+    pub fn new(connect: F, config: ReconnectConfig) -> ReconnectingWebSocket<F, S, E> {
+        ReconnectingWebSocket {
+            connect,
+            config,
+            websocket: None,
+            queue: VecDeque::new(),
+            on_state_change: None,
+        }
+    }
+
+    /// Sets a function which is called each time that the state of the connection changes.
+    pub fn on_state_change<G>(mut self, on_state_change: G) -> ReconnectingWebSocket<F, S, E>
+    where
+        G: FnMut(ConnectionState) + Send + 'static,
+    {
+        self.on_state_change = Some(Box::new(on_state_change));
+        self
+    }
+
+    /// Returns whether there is currently an open connection.
+    pub fn is_connected(&self) -> bool {
+        self.websocket.as_ref().is_some_and(WebSocket::is_active)
+    }
+
+    /// Returns the number of messages in the outgoing queue.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns the current connection, redialing it if it has been lost. Any queued messages are
+    /// sent over a new connection before it is returned.
+    ///
+    /// # Errors
+    /// Returns the error produced by the last connection attempt if
+    /// [`ReconnectConfig::max_attempts`] consecutive attempts have failed.
+    pub async fn connection(&mut self) -> Result<&mut WebSocket<S, E>, Error> {
+        loop {
+            if self.websocket.as_ref().is_some_and(WebSocket::is_closed) {
+                self.disconnect();
+            }
+
+            if self.websocket.is_none() {
+                let websocket = self.reconnect().await?;
+                self.websocket = Some(websocket);
+            }
+
+            let websocket = match &mut self.websocket {
+                Some(websocket) => websocket,
+                None => unreachable!(),
+            };
+
+            while let Some((message_type, payload)) = self.queue.front() {
+                match websocket.write(payload, *message_type).await {
+                    Ok(()) => {
+                        self.queue.pop_front();
+                    }
+                    Err(e) if e.is_io() => break,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if self.queue.is_empty() {
+                break;
+            }
+            self.disconnect();
+        }
+
+        match &mut self.websocket {
+            Some(websocket) => Ok(websocket),
+            None => unreachable!(),
+        }
+    }
+
+    /// Attempt to read a message from the WebSocket, redialing the connection and retrying the read
+    /// if an IO error is encountered. See [`WebSocket::read`] for details.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then both `read_buffer` and the connection state are undefined.
+    pub async fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
+        loop {
+            let websocket = self.connection().await?;
+            match websocket.read(read_buffer).await {
+                Err(e) if e.is_io() => {
+                    read_buffer.clear();
+                    self.disconnect();
+                }
+                result => break result,
+            }
+        }
+    }
+
+    /// Sends a new message to the peer, redialing the connection first if it has been lost. If an
+    /// IO error is encountered then the connection is marked as lost and the message is queued, or
+    /// the error is returned if the queue is disabled or full. See [`WebSocket::write`] for
+    /// details.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined.
+    pub async fn write<A>(&mut self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        let error = match self.connection().await {
+            Ok(websocket) => match websocket.write(buf.as_ref(), message_type).await {
+                Err(e) if e.is_io() => {
+                    self.disconnect();
+                    e
+                }
+                result => return result,
+            },
+            Err(e) => e,
+        };
+        self.enqueue(buf.as_ref(), message_type, error)
+    }
+
+    /// Sends a new text message to the peer. See [`ReconnectingWebSocket::write`].
+    pub async fn write_text<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text).await
+    }
+
+    /// Sends a new binary message to the peer. See [`ReconnectingWebSocket::write`].
+    pub async fn write_binary<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data, PayloadType::Binary).await
+    }
+
+    /// Flushes the current connection, if there is one.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.websocket {
+            Some(websocket) => {
+                let result = websocket.flush().await;
+                self.check_io(result)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the current connection, if there is one, with the reason provided. No further
+    /// connection attempts are made.
+    pub async fn close(mut self, reason: CloseReason) -> Result<(), Error> {
+        match self.websocket.take() {
+            Some(mut websocket) => {
+                let result = websocket.close(reason).await;
+                self.notify(ConnectionState::Disconnected);
+                result
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the current connection, if there is one, consuming this WebSocket.
+    pub fn into_inner(self) -> Option<WebSocket<S, E>> {
+        self.websocket
+    }
+
+    fn enqueue(
+        &mut self,
+        buf: &[u8],
+        message_type: PayloadType,
+        error: Error,
+    ) -> Result<(), Error> {
+        let ReconnectConfig {
+            queue_capacity,
+            overflow_policy,
+            ..
+        } = self.config;
+
+        if queue_capacity == 0 {
+            return Err(error);
+        }
+
+        if self.queue.len() >= queue_capacity {
+            match overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    self.queue.pop_front();
+                }
+                OverflowPolicy::DropNewest => return Ok(()),
+                OverflowPolicy::Error => return Err(error),
+            }
+        }
+
+        self.queue
+            .push_back((message_type, Bytes::copy_from_slice(buf)));
+        Ok(())
+    }
+
+    fn check_io(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+        if result.as_ref().is_err_and(Error::is_io) {
+            self.disconnect();
+        }
+        result
+    }
+
+    fn disconnect(&mut self) {
+        if self.websocket.take().is_some() {
+            self.notify(ConnectionState::Disconnected);
+        }
+    }
+
+    fn notify(&mut self, state: ConnectionState) {
+        if let Some(on_state_change) = &mut self.on_state_change {
+            on_state_change(state);
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<WebSocket<S, E>, Error> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.notify(ConnectionState::Connecting { attempt });
+
+            match (self.connect)().await {
+                Ok(websocket) => {
+                    self.notify(ConnectionState::Connected);
+                    break Ok(websocket);
+                }
+                Err(e) if self.config.max_attempts.is_some_and(|max| attempt >= max) => {
+                    self.notify(ConnectionState::Disconnected);
+                    break Err(e);
+                }
+                Err(_) => tokio::time::sleep(self.config.delay(attempt)).await,
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::reconnect::{ConnectionState, OverflowPolicy, ReconnectConfig, ReconnectingWebSocket};
+use crate::test_fixture::{mock, MockPeer};
+use crate::{accept, subscribe, Error, Message, NoExt, WebSocket, WebSocketConfig};
+use bytes::{Bytes, BytesMut};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+async fn connect_mock<F, Fut>(server: F) -> Result<WebSocket<MockPeer, NoExt>, Error>
+where
+    F: FnOnce(WebSocket<MockPeer, NoExt>) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send,
+{
+    let (client, peer) = mock();
+    tokio::spawn(async move {
+        let upgrader = accept(peer, WebSocketConfig::default()).await.unwrap();
+        server(upgrader.upgrade().await.unwrap().websocket).await;
+    });
+
+    subscribe(WebSocketConfig::default(), client, "ws://127.0.0.1/")
+        .await
+        .map(|upgraded| upgraded.websocket)
+}
+
+#[tokio::test]
+async fn reconnects_after_io_error() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let states = Arc::new(Mutex::new(Vec::new()));
+
+    let connect = {
+        let attempts = attempts.clone();
+        move || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            connect_mock(move |mut websocket| async move {
+                if attempt > 0 {
+                    websocket.write_text("hello").await.unwrap();
+                }
+            })
+        }
+    };
+
+    let mut websocket = ReconnectingWebSocket::new(connect, ReconnectConfig::default())
+        .on_state_change({
+            let states = states.clone();
+            move |state| states.lock().unwrap().push(state)
+        });
+
+    let mut buf = BytesMut::new();
+    assert_eq!(websocket.read(&mut buf).await.unwrap(), Message::Text);
+    assert_eq!(std::str::from_utf8(&buf).unwrap(), "hello");
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert!(websocket.is_connected());
+
+    assert_eq!(
+        *states.lock().unwrap(),
+        [
+            ConnectionState::Connecting { attempt: 1 },
+            ConnectionState::Connected,
+            ConnectionState::Disconnected,
+            ConnectionState::Connecting { attempt: 1 },
+            ConnectionState::Connected,
+        ]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn stops_after_max_attempts() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let states = Arc::new(Mutex::new(Vec::new()));
+
+    let connect = {
+        let attempts = attempts.clone();
+        move || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async {
+                Err::<WebSocket<MockPeer, NoExt>, _>(
+                    io::Error::from(io::ErrorKind::ConnectionRefused).into(),
+                )
+            }
+        }
+    };
+    let config = ReconnectConfig {
+        max_attempts: Some(3),
+        jitter: false,
+        ..ReconnectConfig::default()
+    };
+
+    let mut websocket = ReconnectingWebSocket::new(connect, config).on_state_change({
+        let states = states.clone();
+        move |state| states.lock().unwrap().push(state)
+    });
+
+    let start = tokio::time::Instant::now();
+    let error = websocket.write_text("hello").await.unwrap_err();
+    assert!(error.is_io());
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    assert_eq!(start.elapsed(), Duration::from_millis(300));
+
+    assert_eq!(
+        *states.lock().unwrap(),
+        [
+            ConnectionState::Connecting { attempt: 1 },
+            ConnectionState::Connecting { attempt: 2 },
+            ConnectionState::Connecting { attempt: 3 },
+            ConnectionState::Disconnected,
+        ]
+    );
+}
+
+#[test]
+fn backoff() {
+    let config = ReconnectConfig {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        max_attempts: None,
+        jitter: false,
+        ..ReconnectConfig::default()
+    };
+
+    assert_eq!(config.delay(1), Duration::from_millis(100));
+    assert_eq!(config.delay(2), Duration::from_millis(200));
+    assert_eq!(config.delay(4), Duration::from_millis(800));
+    assert_eq!(config.delay(5), Duration::from_secs(1));
+    assert_eq!(config.delay(usize::MAX), Duration::from_secs(1));
+
+    let config = ReconnectConfig {
+        jitter: true,
+        ..config
+    };
+    for _ in 0..32 {
+        let delay = config.delay(3);
+        assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+    }
+}
+
+fn refused() -> Error {
+    io::Error::from(io::ErrorKind::ConnectionRefused).into()
+}
+
+#[tokio::test]
+async fn queues_messages_during_outage() {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let tx = Arc::new(Mutex::new(Some(tx)));
+
+    let connect = {
+        let attempts = attempts.clone();
+        move || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            let tx = tx.clone();
+            async move {
+                if attempt < 2 {
+                    return Err(refused());
+                }
+                connect_mock(move |mut websocket| async move {
+                    let mut received = Vec::new();
+                    let mut buf = BytesMut::new();
+                    for _ in 0..3 {
+                        websocket.read(&mut buf).await.unwrap();
+                        received.push(String::from_utf8(buf.split().to_vec()).unwrap());
+                    }
+                    let _ = tx.lock().unwrap().take().unwrap().send(received);
+                })
+                .await
+            }
+        }
+    };
+    let config = ReconnectConfig {
+        max_attempts: Some(1),
+        queue_capacity: 4,
+        ..ReconnectConfig::default()
+    };
+
+    let mut websocket = ReconnectingWebSocket::new(connect, config);
+    websocket.write_text("a").await.unwrap();
+    websocket.write_text("b").await.unwrap();
+    assert_eq!(websocket.queued(), 2);
+    assert!(!websocket.is_connected());
+
+    websocket.write_text("c").await.unwrap();
+    assert_eq!(websocket.queued(), 0);
+    assert_eq!(rx.await.unwrap(), ["a", "b", "c"]);
+}
+
+async fn overflow(policy: OverflowPolicy) -> (Result<(), Error>, Vec<Bytes>) {
+    let connect = || async { Err::<WebSocket<MockPeer, NoExt>, _>(refused()) };
+    let config = ReconnectConfig {
+        max_attempts: Some(1),
+        queue_capacity: 1,
+        overflow_policy: policy,
+        ..ReconnectConfig::default()
+    };
+
+    let mut websocket = ReconnectingWebSocket::new(connect, config);
+    websocket.write_text("a").await.unwrap();
+    let result = websocket.write_text("b").await;
+    let queue = websocket.queue.into_iter().map(|(_, payload)| payload);
+    (result, queue.collect())
+}
+
+#[tokio::test]
+async fn overflow_policies() {
+    let (result, queue) = overflow(OverflowPolicy::DropOldest).await;
+    assert!(result.is_ok());
+    assert_eq!(queue, ["b"]);
+
+    let (result, queue) = overflow(OverflowPolicy::DropNewest).await;
+    assert!(result.is_ok());
+    assert_eq!(queue, ["a"]);
+
+    let (result, queue) = overflow(OverflowPolicy::Error).await;
+    assert!(result.unwrap_err().is_io());
+    assert_eq!(queue, ["a"]);
+}
+
+#[tokio::test]
+async fn queue_disabled() {
+    let connect = || async { Err::<WebSocket<MockPeer, NoExt>, _>(refused()) };
+    let config = ReconnectConfig {
+        max_attempts: Some(1),
+        ..ReconnectConfig::default()
+    };
+
+    let mut websocket = ReconnectingWebSocket::new(connect, config);
+    assert!(websocket.write_text("a").await.unwrap_err().is_io());
+    assert_eq!(websocket.queued(), 0);
+}
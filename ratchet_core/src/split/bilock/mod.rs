@@ -21,6 +21,7 @@ use futures::{ready, Future};
 use std::cell::UnsafeCell;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
+use std::io::IoSlice;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -194,6 +195,21 @@ where
         Pin::new(guard.deref_mut()).poll_write(cx, buf)
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let mut guard = ready!(self.get_mut().poll_lock(cx));
+        Pin::new(guard.deref_mut()).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        // The inner value can't be inspected without acquiring the lock. If it doesn't support
+        // vectored writes then its default implementation writes the first non-empty buffer.
+        true
+    }
+
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
         let mut guard = ready!(self.get_mut().poll_lock(cx));
         Pin::new(guard.deref_mut()).poll_flush(cx)
@@ -204,6 +220,6 @@ where
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), std::io::Error>> {
         let mut guard = ready!(self.get_mut().poll_lock(cx));
-        Pin::new(guard.deref_mut()).poll_flush(cx)
+        Pin::new(guard.deref_mut()).poll_shutdown(cx)
     }
 }
@@ -0,0 +1,192 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{CloseCause, CloseReason, Error, ErrorKind, PayloadType, Sender, WebSocketStream};
+use bytes::Bytes;
+use log::error;
+use ratchet_ext::ExtensionEncoder;
+use thiserror::Error;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+
+enum Command {
+    Write(PayloadType, Bytes),
+    Close(CloseReason, oneshot::Sender<Result<(), Error>>),
+}
+
+/// An error produced by [`SenderHandle::try_send`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum TrySendError {
+    /// The queue of messages waiting to be written is full.
+    #[error("The send queue is full")]
+    Full,
+    /// The writer task has stopped, either because the connection has been closed or because a
+    /// write operation failed.
+    #[error("The WebSocket has been closed")]
+    Closed,
+}
+
+/// A cloneable handle to the send half of a WebSocket, allowing multiple tasks to send messages
+/// to the peer without coordinating access to a [`Sender`].
+///
+/// Messages are placed into a bounded queue which is drained by a writer task that owns the
+/// [`Sender`], and so they are written in the order that they were queued. Once a write operation
+/// fails, or the connection has been closed, the writer task stops and any further messages are
+/// rejected; the error that stopped it is logged. The writer task also stops once every handle has
+/// been dropped, flushing any messages that are still buffered and dropping the [`Sender`].
+///
+/// A handle is created by [`Sender::into_handle`].
+#[derive(Clone, Debug)]
+pub struct SenderHandle {
+    tx: mpsc::Sender<Command>,
+}
+
+impl<S, E> Sender<S, E>
+where
+    S: WebSocketStream + 'static,
+    E: ExtensionEncoder + Send + 'static,
+{
+    /// Moves this sender into a writer task which is spawned onto the current tokio runtime and
+    /// returns a cloneable handle to it. At most `capacity` messages may be queued for the writer
+    /// task.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is zero or if called from outside of a tokio runtime.
+    pub fn into_handle(self, capacity: usize) -> SenderHandle {
+        let (tx, rx) = mpsc::channel(capacity);
+        tokio::spawn(run_writer(self, rx));
+        SenderHandle { tx }
+    }
+}
+
+async fn run_writer<S, E>(mut sender: Sender<S, E>, mut rx: mpsc::Receiver<Command>)
+where
+    S: WebSocketStream,
+    E: ExtensionEncoder,
+{
+    let mut unflushed = false;
+
+    loop {
+        let command = match rx.try_recv() {
+            Ok(command) => Some(command),
+            Err(TryRecvError::Empty) => {
+                // Any frames which are held back by the autoflush threshold are flushed once there
+                // are no more messages waiting to be written.
+                if unflushed {
+                    if !flush(&mut sender).await {
+                        break;
+                    }
+                    unflushed = false;
+                }
+                rx.recv().await
+            }
+            Err(TryRecvError::Disconnected) => None,
+        };
+
+        match command {
+            Some(Command::Write(message_type, payload)) => {
+                if let Err(e) = sender.write_owned(payload, message_type).await {
+                    error!("Sender handle failed to write a message: {:?}", e);
+                    break;
+                }
+                unflushed = true;
+            }
+            Some(Command::Close(reason, callback)) => {
+                let _ = callback.send(sender.close(reason).await);
+                break;
+            }
+            None => {
+                // Every handle has been dropped but the last messages may still be buffered.
+                if unflushed {
+                    flush(&mut sender).await;
+                }
+                break;
+            }
+        }
+    }
+}
+
+/// Flushes `sender`, returning whether it succeeded.
+async fn flush<S, E>(sender: &mut Sender<S, E>) -> bool
+where
+    S: WebSocketStream,
+    E: ExtensionEncoder,
+{
+    match sender.flush().await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Sender handle failed to flush: {:?}", e);
+            false
+        }
+    }
+}
+
+fn closed() -> Error {
+    Error::with_cause(ErrorKind::Close, CloseCause::Error)
+}
+
+impl SenderHandle {
+    /// Queues a message of `message_type` with a payload of `payload`, waiting for space in the
+    /// queue if it is full. The message has not necessarily been written once this returns.
+    ///
+    /// # Errors
+    /// Returns a close error if the writer task has stopped.
+    pub async fn send<B>(&self, payload: B, message_type: PayloadType) -> Result<(), Error>
+    where
+        B: Into<Bytes>,
+    {
+        self.tx
+            .send(Command::Write(message_type, payload.into()))
+            .await
+            .map_err(|_| closed())
+    }
+
+    /// Queues a message of `message_type` with a payload of `payload` if there is space in the
+    /// queue.
+    ///
+    /// # Errors
+    /// Returns [`TrySendError::Full`] if the queue is full or [`TrySendError::Closed`] if the
+    /// writer task has stopped.
+    pub fn try_send<B>(&self, payload: B, message_type: PayloadType) -> Result<(), TrySendError>
+    where
+        B: Into<Bytes>,
+    {
+        self.tx
+            .try_send(Command::Write(message_type, payload.into()))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => TrySendError::Full,
+                mpsc::error::TrySendError::Closed(_) => TrySendError::Closed,
+            })
+    }
+
+    /// Closes the WebSocket with the reason provided once the messages which have already been
+    /// queued have been written, and then stops the writer task. See [`Sender::close`].
+    ///
+    /// # Errors
+    /// Returns a close error if the writer task has already stopped, or the error produced by
+    /// writing the close frame.
+    pub async fn close(&self, reason: CloseReason) -> Result<(), Error> {
+        let (callback_tx, callback_rx) = oneshot::channel();
+        self.tx
+            .send(Command::Close(reason, callback_tx))
+            .await
+            .map_err(|_| closed())?;
+        callback_rx.await.map_err(|_| closed())?
+    }
+
+    /// Returns whether the writer task has stopped.
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
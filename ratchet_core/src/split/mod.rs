@@ -13,33 +13,46 @@
 // limitations under the License.
 
 use std::fmt::Debug;
+use std::future::poll_fn;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 
-use bytes::BytesMut;
-use futures_util::future::BoxFuture;
-use futures_util::{FutureExt, TryFutureExt};
+use bytes::{Bytes, BytesMut};
+use futures_util::{Sink, Stream};
 use log::{error, trace};
-use tokio::io::AsyncWriteExt;
+use tokio::io::AsyncWrite;
+use tokio::sync::Notify;
 
-use bilock::{bilock, BiLock};
+use bilock::{bilock, BiLock, BiLockGuard};
 use ratchet_ext::{ExtensionDecoder, ExtensionEncoder, ReunitableExtension, SplittableExtension};
 
 use crate::framed::{
-    read_next, write_close, write_fragmented, CodecFlags, FramedIoParts, FramedRead, FramedWrite,
-    Item,
+    close_payload, fragments, read_props, with_timeout, write_close, CodecFlags, FramedIoParts,
+    FramedRead, FramedWrite, Item,
 };
-use crate::protocol::{CloseReason, ControlCode, DataCode, HeaderFlags, MessageType, OpCode};
-use crate::ws::{extension_encode, CloseState, WebSocketClose, CONTROL_MAX_SIZE};
+use crate::idle::{IdleTimeout, WriteActivity};
+use crate::keepalive::{Keepalive, KeepaliveEvent};
+use crate::protocol::{
+    CloseReason, ControlCode, HeaderFlags, MessageType, OpCode, RateLimitAction,
+};
+use crate::rate_limit::RateLimiter;
+use crate::ws::{extension_encode, read_error_close_code, CloseState, CONTROL_MAX_SIZE};
 use crate::{
-    framed, CloseCause, CloseCode, Error, ErrorKind, Message, PayloadType, ProtocolError, Role,
-    WebSocket, WebSocketStream,
+    framed, CloseCause, CloseCode, Error, ErrorKind, Message, OwnedMessage, PayloadType,
+    PreparedMessage, ProtocolError, Role, TimeoutCause, WebSocket, WebSocketStream,
 };
 
-mod bilock;
+pub(crate) mod bilock;
+mod handle;
+mod shared;
 #[cfg(test)]
 mod tests;
 
+pub use handle::{SenderHandle, TrySendError};
+pub use shared::SharedWebSocket;
+
 type ReuniteFailure<S, E> = ReuniteError<
     S,
     <E as SplittableExtension>::SplitEncoder,
@@ -54,6 +67,17 @@ const STATE_CLOSED: u8 = 2;
 /// over the IO and one on the write half to send any responses to any control frames that are
 /// received.
 ///
+/// # Closing
+/// Both halves share the state of the closing handshake, which is available from either half
+/// through `close_state`. Either half may initiate a closure, after which no further messages may
+/// be sent. The `Receiver` continues to read any messages that the peer sends until it receives
+/// the peer's close frame, completing the handshake.
+///
+/// # Timers
+/// Keepalive pings, the pong timeout and the idle timeout are driven by reading from the
+/// `Receiver`, which must be polled for them to be enforced. Frames written by the `Sender` count
+/// as activity for the idle timeout.
+///
 /// # Note
 /// It is possible to reunite the halves back into a WebSocket if the extension implements
 /// `ReunitableExtension`.
@@ -61,6 +85,9 @@ pub fn split<S, E>(
     framed: framed::FramedIo<S>,
     control_buffer: BytesMut,
     extension: Option<E>,
+    keepalive: Option<Keepalive>,
+    idle: Option<IdleTimeout>,
+    rate_limiter: Option<RateLimiter>,
 ) -> (Sender<S, E::SplitEncoder>, Receiver<S, E::SplitDecoder>)
 where
     S: WebSocketStream,
@@ -74,13 +101,14 @@ where
         max_message_size,
     } = framed.into_parts();
 
-    let close_state = Arc::new(AtomicU8::new(STATE_OPEN));
+    let close_state = Arc::new(SharedCloseState::default());
     let (read_half, write_half) = bilock(io);
     let (sender_writer, reader_writer) = bilock(WriteHalf {
         control_buffer,
         split_writer: write_half,
         writer,
         is_server: flags.contains(CodecFlags::ROLE),
+        activity: idle.as_ref().map(|idle| WriteActivity::new(idle.config())),
     });
 
     let (ext_encoder, ext_decoder) = extension.split();
@@ -96,10 +124,16 @@ where
         close_state: close_state.clone(),
         split_writer: sender_writer,
         ext_encoder,
+        pending_send: None,
     };
     let receiver = Receiver {
         role,
         close_state,
+        keepalive,
+        idle,
+        rate_limiter,
+        pending_read: PendingRead::Idle,
+        message_buffer: BytesMut::new(),
         framed: FramedIo {
             flags,
             max_message_size,
@@ -118,8 +152,12 @@ where
     S: WebSocketStream,
 {
     async fn flush(&mut self) -> Result<(), Error> {
-        self.split_writer.flush().await?;
-        Ok(())
+        let WriteHalf {
+            split_writer,
+            writer,
+            ..
+        } = self;
+        writer.flush(split_writer).await
     }
 
     async fn write<A, E>(
@@ -134,6 +172,11 @@ where
         A: AsRef<[u8]>,
         E: ExtensionEncoder,
     {
+        self.on_write(matches!(
+            message_type,
+            PayloadType::Text | PayloadType::Binary
+        ));
+
         let WriteHalf {
             split_writer,
             writer,
@@ -143,28 +186,18 @@ where
         let buf = buf_ref.as_ref();
 
         match message_type {
-            PayloadType::Text => writer
-                .write(
-                    split_writer,
-                    is_server,
-                    OpCode::DataCode(DataCode::Text),
-                    header_flags,
-                    buf,
-                    |payload, header| extension_encode(extension, payload, header),
-                )
-                .await
-                .map_err(Into::into),
-            PayloadType::Binary => writer
-                .write(
-                    split_writer,
-                    is_server,
-                    OpCode::DataCode(DataCode::Binary),
-                    header_flags,
-                    buf,
-                    |payload, header| extension_encode(extension, payload, header),
-                )
-                .await
-                .map_err(Into::into),
+            PayloadType::Text => {
+                writer.buffer_message(is_server, MessageType::Text, buf, |payload, header| {
+                    extension_encode(extension, payload, header)
+                })?;
+                writer.autoflush(split_writer).await
+            }
+            PayloadType::Binary => {
+                writer.buffer_message(is_server, MessageType::Binary, buf, |payload, header| {
+                    extension_encode(extension, payload, header)
+                })?;
+                writer.autoflush(split_writer).await
+            }
             PayloadType::Ping => {
                 if buf.len() > CONTROL_MAX_SIZE {
                     Err(Error::with_cause(
@@ -185,7 +218,6 @@ where
                             |payload, header| extension_encode(extension, payload, header),
                         )
                         .await
-                        .map_err(Into::into)
                 }
             }
             PayloadType::Pong => {
@@ -205,19 +237,74 @@ where
                             |payload, header| extension_encode(extension, payload, header),
                         )
                         .await
-                        .map_err(Into::into)
                 }
             }
         }
     }
 }
 
+impl<S> WriteHalf<S> {
+    /// Records that the `Sender` has written a frame, for the idle timeout.
+    fn on_write(&mut self, is_data: bool) {
+        if let Some(activity) = &mut self.activity {
+            activity.on_write(is_data);
+        }
+    }
+
+    /// Encodes `message` into the write buffer. No IO is performed.
+    fn buffer_owned<E>(
+        &mut self,
+        message: OwnedMessage,
+        ext_encoder: &mut Option<E>,
+    ) -> Result<(), Error>
+    where
+        E: ExtensionEncoder,
+    {
+        self.on_write(matches!(
+            message,
+            OwnedMessage::Text(_) | OwnedMessage::Binary(_)
+        ));
+
+        let WriteHalf {
+            writer,
+            control_buffer,
+            is_server,
+            ..
+        } = self;
+        let is_server = *is_server;
+
+        match message {
+            OwnedMessage::Text(payload) => {
+                buffer_data(writer, is_server, ext_encoder, MessageType::Text, payload)
+            }
+            OwnedMessage::Binary(payload) => {
+                buffer_data(writer, is_server, ext_encoder, MessageType::Binary, payload)
+            }
+            OwnedMessage::Ping(payload) => {
+                buffer_control(writer, is_server, ControlCode::Ping, &payload).map(|_| {
+                    control_buffer.clear();
+                    control_buffer.extend_from_slice(&payload);
+                })
+            }
+            OwnedMessage::Pong(payload) => {
+                buffer_control(writer, is_server, ControlCode::Pong, &payload)
+            }
+            OwnedMessage::Close(reason) => close_payload(
+                reason.unwrap_or_else(|| CloseCode::Normal.into()),
+            )
+            .and_then(|payload| buffer_control(writer, is_server, ControlCode::Close, payload)),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct WriteHalf<S> {
     split_writer: BiLock<S>,
     writer: FramedWrite,
     control_buffer: BytesMut,
     is_server: bool,
+    // Present if an idle timeout has been configured.
+    activity: Option<WriteActivity>,
 }
 
 #[derive(Debug)]
@@ -234,9 +321,11 @@ struct FramedIo<S, E> {
 #[derive(Debug)]
 pub struct Sender<S, E> {
     role: Role,
-    close_state: Arc<AtomicU8>,
+    close_state: Arc<SharedCloseState>,
     split_writer: BiLock<WriteHalf<S>>,
     ext_encoder: Option<E>,
+    // A message that has been provided to the `Sink` implementation but not yet encoded.
+    pending_send: Option<OwnedMessage>,
 }
 
 impl<S, E> Sender<S, E>
@@ -246,8 +335,14 @@ where
 {
     /// Attempt to reunite this send half with its receiver.
     ///
+    /// The halves may be reunited regardless of the state of the connection. If either half has
+    /// produced an error, or the connection has otherwise been closed, then the returned WebSocket
+    /// is closed and the underlying stream may be recovered using [`WebSocket::into_inner`] so it
+    /// can be cleaned up or reused.
+    ///
     /// # Errors
     /// Errors if `receiver` is not paired with this sender.
+    #[allow(clippy::result_large_err)]
     pub fn reunite<Ext>(
         self,
         receiver: Receiver<S, Ext::SplitDecoder>,
@@ -264,14 +359,32 @@ where
         self.role
     }
 
+    /// Returns a reference to the encoder of the extension that was negotiated for this
+    /// WebSocket, if any.
+    pub fn extension(&self) -> Option<&E> {
+        self.ext_encoder.as_ref()
+    }
+
+    /// Returns a mutable reference to the encoder of the extension that was negotiated for this
+    /// WebSocket, if any. This may be used to change how the extension encodes subsequent
+    /// messages.
+    pub fn extension_mut(&mut self) -> Option<&mut E> {
+        self.ext_encoder.as_mut()
+    }
+
+    /// Returns the state of the closing handshake, which is shared with the other half.
+    pub fn close_state(&self) -> CloseState {
+        load_close_state(&self.close_state)
+    }
+
     /// Returns whether this WebSocket is closed.
     pub fn is_closed(&self) -> bool {
-        self.close_state.load(Ordering::SeqCst) == STATE_CLOSED
+        self.close_state.load() == STATE_CLOSED
     }
 
     /// Returns whether this WebSocket is closing or closed.
     pub fn is_active(&self) -> bool {
-        matches!(self.close_state.load(Ordering::SeqCst), STATE_OPEN)
+        matches!(self.close_state.load(), STATE_OPEN)
     }
 
     /// Sends a new text WebSocket message with a payload of `data`.
@@ -321,6 +434,11 @@ where
 
     /// Sends a new pong WebSocket message with a payload of `data`.
     ///
+    /// Pongs that are sent in response to a ping are sent automatically when reading. This may be
+    /// used to send an unsolicited pong which, as per
+    /// [RFC6455 section 5.5.3](https://datatracker.ietf.org/doc/html/rfc6455#section-5.5.3), serves
+    /// as a unidirectional heartbeat and does not expect a response from the peer.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -336,6 +454,9 @@ where
 
     /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
     ///
+    /// `buf` is copied into a reusable internal buffer before it is encoded and masked, so it is
+    /// never modified and the same payload may be written to multiple connections.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -361,9 +482,11 @@ where
             .await
     }
 
-    /// Constructs a new WebSocket message of `message_type` and with a payload of `buf` and
-    /// chunked by `fragment_size`. If the length of the buffer is less than the chunk size then
-    /// only a single message is sent.
+    /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
+    ///
+    /// If this is a server and no extension has been negotiated then text and binary payloads are
+    /// written without being copied. Otherwise, `buf` is copied so that it can be masked or
+    /// encoded, as with [`Sender::write`].
     ///
     /// # Cancel safety
     ///
@@ -371,40 +494,223 @@ where
     /// then the connection state is undefined. It may not be possible to recover the connection due
     /// the write operation having written only part of the buffer and the state of the write
     /// operation has been lost.
-    pub async fn write_fragmented<A>(
+    pub async fn write_owned(
+        &mut self,
+        buf: Bytes,
+        message_type: PayloadType,
+    ) -> Result<(), Error> {
+        let zero_copy = self.role.is_server() && self.ext_encoder.is_none();
+        let message_type = match message_type {
+            PayloadType::Text if zero_copy => MessageType::Text,
+            PayloadType::Binary if zero_copy => MessageType::Binary,
+            // Control frames are small enough that they are always copied into the write buffer.
+            _ => return self.write(buf, message_type).await,
+        };
+
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        let write_half = &mut *self.split_writer.lock().await;
+        write_half.on_write(true);
+        let WriteHalf {
+            split_writer,
+            writer,
+            ..
+        } = write_half;
+        writer.buffer_owned_message(message_type, buf)?;
+        writer.autoflush(split_writer).await
+    }
+
+    /// Sends a message which has been prepared with [`PreparedMessage`]. See
+    /// [`WebSocket::send_prepared`].
+    ///
+    /// # Errors
+    /// Returns an extension error if the message was encoded with parameters other than those of
+    /// the connection's encoder.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the frame and the state of the write
+    /// operation has been lost.
+    pub async fn send_prepared(&mut self, prepared: &PreparedMessage) -> Result<(), Error> {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        prepared.check_encoder(&self.ext_encoder)?;
+        let is_server = self.role.is_server();
+        let write_half = &mut *self.split_writer.lock().await;
+        write_half.on_write(true);
+        let WriteHalf {
+            split_writer,
+            writer,
+            ..
+        } = write_half;
+        writer.buffer_prepared(is_server, prepared)?;
+        writer.autoflush(split_writer).await
+    }
+
+    /// Sends a new text or binary WebSocket message with a payload of `buf` without encoding it
+    /// using the negotiated extension. Compression remains enabled for any subsequent messages.
+    ///
+    /// The message is sent with all of its reserved bits unset and so it must only be used with
+    /// extensions that permit unencoded messages, such as permessage-deflate.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_uncompressed<A>(
         &mut self,
         buf: A,
         message_type: MessageType,
-        fragment_size: usize,
     ) -> Result<(), Error>
     where
         A: AsRef<[u8]>,
     {
-        if self.is_closed() {
+        if !self.is_active() {
             return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
         }
+
+        let is_server = self.role.is_server();
+        let write_half = &mut *self.split_writer.lock().await;
+        write_half.on_write(true);
         let WriteHalf {
             split_writer,
             writer,
             ..
-        } = &mut *self.split_writer.lock().await;
-        let ext_encoder = &mut self.ext_encoder;
-        write_fragmented(
+        } = write_half;
+        writer.buffer_message(is_server, message_type, buf, |_payload, _header| Ok(()))?;
+        writer.autoflush(split_writer).await
+    }
+
+    /// Sends the next fragment of a message of `message_type` with a payload of `buf`, allowing a
+    /// large message to be produced and sent incrementally without holding all of it in memory.
+    /// The message is completed by writing a fragment with `is_final` set.
+    ///
+    /// `message_type` is only used for the first fragment of a message. If an extension has been
+    /// negotiated then each fragment is encoded as it is written, such as being compressed by
+    /// permessage-deflate. Control frames may be sent while the message is being written but any
+    /// other data messages produce an error until the message has been completed.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_fragment<A>(
+        &mut self,
+        buf: A,
+        message_type: MessageType,
+        is_final: bool,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        let is_server = self.role.is_server();
+        let write_half = &mut *self.split_writer.lock().await;
+        write_half.on_write(true);
+        let WriteHalf {
             split_writer,
             writer,
-            buf,
-            message_type,
-            fragment_size,
-            self.role.is_server(),
-            |payload, header| extension_encode(ext_encoder, payload, header),
-        )
-        .await
+            ..
+        } = write_half;
+        let ext_encoder = &mut self.ext_encoder;
+        writer.buffer_fragment(is_server, message_type, buf, is_final, |payload, header| {
+            extension_encode(ext_encoder, payload, header)
+        })?;
+        writer.autoflush(split_writer).await
+    }
+
+    /// Constructs a new WebSocket message of `message_type` and with a payload of `buf` and
+    /// chunked by `fragment_size`. If the length of the buffer is less than the chunk size then
+    /// only a single message is sent.
+    ///
+    /// # Control frames
+    /// The underlying writer is released between fragments so that a concurrent read operation on
+    /// the [`Receiver`] is able to answer any ping frames that are received while the message is
+    /// being sent.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_fragmented<A>(
+        &mut self,
+        buf: A,
+        message_type: MessageType,
+        fragment_size: usize,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        self.split_writer
+            .lock()
+            .await
+            .writer
+            .check_not_fragmenting()?;
+
+        let is_server = self.role.is_server();
+        for (opcode, flags, payload) in fragments(buf.as_ref(), message_type, fragment_size) {
+            let mut guard = self.split_writer.lock().await;
+            // Either half may have started the closing handshake while the writer was unlocked.
+            if !self.is_active() {
+                return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+            }
+
+            guard.on_write(true);
+            let WriteHalf {
+                split_writer,
+                writer,
+                ..
+            } = &mut *guard;
+            let ext_encoder = &mut self.ext_encoder;
+            writer
+                .write(
+                    split_writer,
+                    is_server,
+                    opcode,
+                    flags,
+                    payload,
+                    |payload, header| extension_encode(ext_encoder, payload, header),
+                )
+                .await?;
+
+            // The writer is unlocked between fragments so that the receiver is able to respond to
+            // any control frames that it receives while this message is being sent.
+            drop(guard);
+            tokio::task::yield_now().await;
+        }
+        Ok(())
     }
 
     /// Close this WebSocket with the reason provided.
     ///
     /// If the WebSocket is already closed then `Ok(())` is returned.
     ///
+    /// # Closing handshake
+    /// This only sends the close frame and moves both halves into the closing state; no further
+    /// messages may then be sent. The handshake is completed by the [`Receiver`], which continues
+    /// to return any messages that the peer sent before it echoed the close frame. Once the echo
+    /// has been received, the `Receiver` returns an error with a cause of
+    /// [`CloseCause::Stopped`] and both halves are closed.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -416,7 +722,10 @@ where
             return Ok(());
         }
 
-        self.close_state.store(STATE_CLOSING, Ordering::SeqCst);
+        reason.validate()?;
+        if !self.close_state.start_closing() {
+            return Ok(());
+        }
 
         let WriteHalf {
             split_writer,
@@ -441,14 +750,93 @@ where
         let writer = &mut *self.split_writer.lock().await;
         writer.flush().await
     }
+
+    /// Encodes any message that has been provided to the `Sink` implementation into the write
+    /// buffer, returning a guard over the write half once the message has been buffered.
+    fn poll_pending_send(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<BiLockGuard<'_, WriteHalf<S>>, Error>> {
+        let Sender {
+            split_writer,
+            ext_encoder,
+            pending_send,
+            ..
+        } = self;
+
+        let mut guard = ready!(split_writer.poll_lock(cx));
+        let message = match pending_send.take() {
+            Some(message) => message,
+            None => return Poll::Ready(Ok(guard)),
+        };
+
+        let result = guard.buffer_owned(message, ext_encoder);
+        Poll::Ready(result.map(|_| guard))
+    }
+
+    /// Waits until the [`Receiver`] has completed the closing handshake or the connection has
+    /// otherwise closed.
+    #[cfg(feature = "manager")]
+    pub(crate) async fn wait_closed(&self) {
+        self.close_state.wait_closed().await
+    }
+
+    /// Marks this WebSocket as closed and shuts down the write half of the underlying IO without
+    /// sending a close frame.
+    #[cfg(feature = "manager")]
+    pub(crate) async fn shutdown(&mut self) {
+        use tokio::io::AsyncWriteExt;
+
+        self.close_state.close();
+        // The peer may have already closed the stream and so any IO errors are ignored.
+        let _ = self.split_writer.lock().await.split_writer.shutdown().await;
+    }
 }
 
 /// An owned read half of a WebSocket connection.
 #[derive(Debug)]
 pub struct Receiver<S, E> {
     role: Role,
-    close_state: Arc<AtomicU8>,
+    close_state: Arc<SharedCloseState>,
+    keepalive: Option<Keepalive>,
+    idle: Option<IdleTimeout>,
+    rate_limiter: Option<RateLimiter>,
     framed: FramedIo<S, E>,
+    pending_read: PendingRead,
+    // Used by the `Stream` implementation to read messages into.
+    message_buffer: BytesMut,
+}
+
+/// An operation that must be completed, after a frame has been received, before the result of a
+/// read operation is returned.
+#[derive(Debug)]
+enum PendingRead {
+    /// There is no pending read operation.
+    Idle,
+    /// A ping was received and a pong frame must be sent in response.
+    Ping {
+        payload: Bytes,
+        /// Whether the pong frame has been buffered but not yet flushed.
+        buffered: bool,
+    },
+    /// A pong was received and any outstanding ping must be cleared.
+    Pong(Bytes),
+    /// A keepalive ping must be sent to the peer.
+    Keepalive {
+        /// Whether the ping frame has been buffered but not yet flushed.
+        buffered: bool,
+    },
+    /// The connection is being closed after receiving a close frame or due to an error.
+    Close {
+        /// The code of the close frame that still needs to be buffered, if any.
+        code: Option<CloseCode>,
+        /// Whether the buffered close frame still needs to be flushed.
+        flush: bool,
+        /// Whether the underlying IO still needs to be shutdown.
+        shutdown: bool,
+        /// The result to return once the sequence has completed.
+        result: Option<Result<Message, Error>>,
+    },
 }
 
 impl<S, E> Receiver<S, E>
@@ -461,6 +849,18 @@ where
         self.role
     }
 
+    /// Returns a reference to the decoder of the extension that was negotiated for this
+    /// WebSocket, if any.
+    pub fn extension(&self) -> Option<&E> {
+        self.framed.ext_decoder.as_ref()
+    }
+
+    /// Returns a mutable reference to the decoder of the extension that was negotiated for this
+    /// WebSocket, if any.
+    pub fn extension_mut(&mut self) -> Option<&mut E> {
+        self.framed.ext_decoder.as_mut()
+    }
+
     /// Attempt to read some data from the WebSocket. Returning either the type of the message
     /// received or the error that was produced.
     ///
@@ -481,114 +881,336 @@ where
     /// contain the data received up to that point. The callee must ensure that the contents of
     /// `read_buffer` are **not** then modified before calling `read` again.
     ///
+    /// # Closing
+    /// If the peer initiates the closure then its close frame is echoed and `Message::Close` is
+    /// returned. If the closure was initiated by either half then messages continue to be returned
+    /// until the peer echoes the close frame, at which point an error with a cause of
+    /// [`CloseCause::Stopped`] is returned. In both cases, the halves are then closed and any
+    /// further reads produce an error.
+    ///
     /// # Cancel safety
     ///
-    /// This function is not cancellation safe. If the future is dropped before it has completed
-    /// then both `buf` and the connection state are undefined. It may not be possible to recover
-    /// the connection due the read operation partially completing and the state has been lost.
+    /// This function is cancellation safe, so it may be used as a branch of `tokio::select!`. If
+    /// the future is dropped before it has completed then any part of a message that has been
+    /// received, and any pong or close frame that is being sent in response to a frame, is
+    /// retained and completed by the next read operation. As `read_buffer` may contain part of a
+    /// message, the same buffer must be provided to the next call and its contents must not be
+    /// modified.
     pub async fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
-        if self.is_closed() {
-            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        let read_timeout = self.framed.reader.read_timeout();
+        with_timeout(
+            read_timeout,
+            TimeoutCause::Read,
+            poll_fn(|cx| self.poll_read(cx, read_buffer)),
+        )
+        .await
+    }
+
+    /// Attempt to read some data from the WebSocket, registering the current task for wakeup if
+    /// no message is available yet.
+    ///
+    /// This is the poll-based equivalent of [`Receiver::read`]. Any pong or close frame that must
+    /// be sent in response to a frame that has been received is written by subsequent calls and
+    /// the message is only returned once it has been sent.
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        read_buffer: &mut BytesMut,
+    ) -> Poll<Result<Message, Error>> {
+        loop {
+            if let Some(result) = ready!(self.poll_pending_read(cx)) {
+                return Poll::Ready(result);
+            }
+
+            if self.is_closed() {
+                return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+            }
+
+            let Receiver {
+                role,
+                close_state,
+                framed,
+                pending_read,
+                keepalive,
+                idle,
+                rate_limiter,
+                ..
+            } = self;
+            let FramedIo {
+                flags,
+                max_message_size,
+                read_half,
+                reader,
+                ext_decoder,
+                split_writer,
+            } = framed;
+            let is_server = role.is_server();
+            let props = read_props(flags, *max_message_size, false);
+
+            let state = close_state.load();
+            if let (Some(timeout), STATE_OPEN) = (idle.as_mut(), state) {
+                if timeout.poll_expired(cx).is_ready() {
+                    // The sender may have written frames since the deadline was last reset.
+                    let write_half = ready!(split_writer.poll_lock(cx));
+                    let last_write = write_half.activity.as_ref().and_then(WriteActivity::last);
+                    drop(write_half);
+                    if last_write.is_some_and(|at| timeout.extend(at)) {
+                        continue;
+                    }
+
+                    trace!("Connection idle. Closing");
+                    let previous = close_state.close();
+                    *pending_read = start_close(
+                        previous,
+                        is_server,
+                        CloseCode::GoingAway,
+                        Err(TimeoutCause::Idle.into()),
+                    );
+                    continue;
+                }
+            }
+
+            if let (Some(keepalive), STATE_OPEN) = (keepalive.as_mut(), state) {
+                match keepalive.poll_event(cx) {
+                    Poll::Ready(KeepaliveEvent::Ping) => {
+                        trace!("Connection idle. Sending a keepalive ping");
+                        if let Some(timeout) = idle.as_mut() {
+                            timeout.on_control();
+                        }
+                        *pending_read = PendingRead::Keepalive { buffered: false };
+                        continue;
+                    }
+                    Poll::Ready(KeepaliveEvent::Timeout) => {
+                        error!("Peer failed to respond to a keepalive ping in time");
+                        let previous = close_state.close();
+                        *pending_read = start_close(
+                            previous,
+                            is_server,
+                            CloseCode::Policy,
+                            Err(TimeoutCause::Pong.into()),
+                        );
+                        continue;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if let (Some(limiter), STATE_OPEN) = (rate_limiter.as_mut(), state) {
+                if let Err(cause) = ready!(limiter.poll_ready(cx)) {
+                    if limiter.action() == RateLimitAction::Error {
+                        return Poll::Ready(Err(cause.into()));
+                    }
+
+                    error!("Peer exceeded the rate limit. Closing");
+                    let previous = close_state.close();
+                    *pending_read =
+                        start_close(previous, is_server, CloseCode::Policy, Err(cause.into()));
+                    continue;
+                }
+            }
+
+            let len = read_buffer.len();
+            let poll = reader.poll_read(cx, read_half, flags, read_buffer, ext_decoder, props);
+            if let Some(limiter) = rate_limiter {
+                // Fragments of a message may be appended to the buffer by calls which return
+                // `Poll::Pending`, so every call accounts for what it appended.
+                limiter.on_bytes(read_buffer.len().saturating_sub(len));
+                if let Poll::Ready(Ok(Item::Binary | Item::Text)) = &poll {
+                    limiter.on_message();
+                }
+            }
+
+            let result = ready!(poll);
+            if let Some(keepalive) = keepalive {
+                keepalive.on_frame();
+            }
+            if let (Some(timeout), Ok(item)) = (idle.as_mut(), &result) {
+                match item {
+                    Item::Binary | Item::Text | Item::Fragment(_) => timeout.on_data(),
+                    Item::Ping(_) | Item::Pong(_) => timeout.on_control(),
+                    Item::Close(_) => {}
+                }
+            }
+
+            match result {
+                Ok(item) => match item {
+                    Item::Binary => return Poll::Ready(Ok(Message::Binary)),
+                    Item::Text => return Poll::Ready(Ok(Message::Text)),
+                    Item::Fragment(_) => unreachable!("Fragments are only read in streaming mode"),
+                    Item::Ping(payload) => {
+                        trace!("Received a ping frame. Responding with pong");
+                        *pending_read = PendingRead::Ping {
+                            payload: payload.freeze(),
+                            buffered: false,
+                        };
+                    }
+                    Item::Pong(payload) => {
+                        if let Some(keepalive) = keepalive {
+                            keepalive.on_pong();
+                        }
+                        *pending_read = PendingRead::Pong(payload.freeze());
+                    }
+                    Item::Close(reason) => {
+                        let code = reason
+                            .as_ref()
+                            .map(|reason| reason.code)
+                            .unwrap_or(CloseCode::Normal);
+                        let previous = close_state.close();
+
+                        let result = match previous {
+                            STATE_OPEN => Ok(Message::Close(reason)),
+                            _ => Err(Error::with_cause(ErrorKind::Close, CloseCause::Stopped)),
+                        };
+                        *pending_read = start_close(previous, is_server, code, result);
+                    }
+                },
+                Err(e) => {
+                    error!("WebSocket read failure: {:?}", e);
+
+                    // We want to close the connection but return the error produced during the
+                    // session, not any during the close sequence.
+                    let previous = close_state.close();
+                    *pending_read =
+                        start_close(previous, is_server, read_error_close_code(&e), Err(e));
+                }
+            }
         }
+    }
 
+    /// Drives any IO that is required to complete a previous read operation. Returns the result of
+    /// the read operation if one was pending.
+    fn poll_pending_read(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Message, Error>>> {
         let Receiver {
-            role,
-            close_state,
             framed,
+            pending_read,
             ..
         } = self;
-        let FramedIo {
-            flags,
-            max_message_size,
-            read_half,
-            reader,
+
+        if let PendingRead::Idle = pending_read {
+            return Poll::Ready(None);
+        }
+
+        let mut guard = ready!(framed.split_writer.poll_lock(cx));
+        let WriteHalf {
             split_writer,
-            ext_decoder,
-        } = framed;
-        let is_server = role.is_server();
+            writer,
+            control_buffer,
+            is_server,
+            ..
+        } = &mut *guard;
 
-        match read_next(
-            read_half,
-            reader,
-            flags,
-            *max_message_size,
-            read_buffer,
-            ext_decoder,
-        )
-        .await
-        {
-            Ok(item) => match item {
-                Item::Binary => Ok(Message::Binary),
-                Item::Text => Ok(Message::Text),
-                Item::Ping(payload) => {
-                    trace!("Received a ping frame. Responding with pong");
-
-                    let WriteHalf {
-                        split_writer,
-                        writer,
-                        ..
-                    } = &mut *split_writer.lock().await;
-
-                    let ret = payload.clone().freeze();
-                    writer
-                        .write(
-                            split_writer,
-                            is_server,
-                            OpCode::ControlCode(ControlCode::Pong),
+        match pending_read {
+            PendingRead::Idle => Poll::Ready(None),
+            PendingRead::Ping { payload, buffered } => {
+                if !*buffered {
+                    let result = writer.buffer_frame(
+                        *is_server,
+                        OpCode::ControlCode(ControlCode::Pong),
+                        HeaderFlags::FIN,
+                        &payload,
+                        |_, _| Ok(()),
+                    );
+                    if let Err(e) = result {
+                        *pending_read = PendingRead::Idle;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    *buffered = true;
+                }
+
+                let result = ready!(writer.poll_flush(cx, split_writer));
+                let payload = std::mem::take(payload);
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(Some(result.map(|_| Message::Ping(payload))))
+            }
+            PendingRead::Pong(payload) => {
+                if control_buffer.is_empty() {
+                    trace!("Received an unsolicited pong frame");
+                } else {
+                    control_buffer.clear();
+                    trace!("Received pong frame");
+                }
+                let payload = std::mem::take(payload);
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(Some(Ok(Message::Pong(payload))))
+            }
+            PendingRead::Keepalive { buffered } => {
+                if !*buffered {
+                    let result = writer.buffer_frame(
+                        *is_server,
+                        OpCode::ControlCode(ControlCode::Ping),
+                        HeaderFlags::FIN,
+                        [],
+                        |_, _| Ok(()),
+                    );
+                    if let Err(e) = result {
+                        *pending_read = PendingRead::Idle;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    *buffered = true;
+                }
+
+                let result = ready!(writer.poll_flush(cx, split_writer));
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(result.err().map(Err))
+            }
+            PendingRead::Close {
+                code,
+                flush,
+                shutdown,
+                result,
+            } => {
+                if let Some(code) = code.take() {
+                    *flush = writer
+                        .buffer_frame(
+                            *is_server,
+                            OpCode::ControlCode(ControlCode::Close),
                             HeaderFlags::FIN,
-                            payload,
+                            u16::from(code).to_be_bytes(),
                             |_, _| Ok(()),
                         )
-                        .await?;
-                    Ok(Message::Ping(ret))
+                        .is_ok();
                 }
-                Item::Pong(payload) => {
-                    let WriteHalf { control_buffer, .. } = &mut *split_writer.lock().await;
-
-                    if control_buffer.is_empty() {
-                        trace!("Received an unsolicited pong frame");
-                    } else {
-                        control_buffer.clear();
-                        trace!("Received pong frame");
-                    }
-                    Ok(Message::Pong(payload.freeze()))
+                if *flush {
+                    // We aren't interested in any IO errors produced here as the peer *may* have
+                    // already closed the TCP stream.
+                    let _ = ready!(writer.poll_flush(cx, split_writer));
+                    *flush = false;
                 }
-                Item::Close(reason) => {
-                    let code = reason
-                        .as_ref()
-                        .map(|reason| reason.code)
-                        .unwrap_or(CloseCode::Normal);
-
-                    close(
-                        role.is_server(),
-                        close_state,
-                        &mut *split_writer.lock().await,
-                        code,
-                    )
-                    .await?;
-                    Ok(Message::Close(reason))
+                if *shutdown {
+                    let _ = ready!(Pin::new(&mut *split_writer).poll_shutdown(cx));
+                    *shutdown = false;
                 }
-            },
-            Err(e) => {
-                error!("WebSocket read failure: {:?}", e);
-
-                // We want to close the connection but return the error produced during the session,
-                // not any during the close sequence.
-                let _ = close(
-                    role.is_server(),
-                    close_state,
-                    &mut *split_writer.lock().await,
-                    CloseCode::Protocol,
-                )
-                .await;
-                Err(e)
+                let result = result.take();
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(result)
             }
         }
     }
 
+    fn poll_read_owned(&mut self, cx: &mut Context<'_>) -> Poll<Result<OwnedMessage, Error>> {
+        let mut message_buffer = std::mem::take(&mut self.message_buffer);
+        let result = self.poll_read(cx, &mut message_buffer);
+        self.message_buffer = message_buffer;
+
+        let message = match ready!(result) {
+            Ok(Message::Text) => OwnedMessage::Text(self.message_buffer.split().freeze()),
+            Ok(Message::Binary) => OwnedMessage::Binary(self.message_buffer.split().freeze()),
+            Ok(Message::Ping(payload)) => OwnedMessage::Ping(payload),
+            Ok(Message::Pong(payload)) => OwnedMessage::Pong(payload),
+            Ok(Message::Close(reason)) => OwnedMessage::Close(reason),
+            Err(e) => {
+                self.message_buffer.clear();
+                return Poll::Ready(Err(e));
+            }
+        };
+
+        Poll::Ready(Ok(message))
+    }
+
     /// Close this WebSocket with the reason provided.
     ///
-    /// If the WebSocket is already closed then `Ok(())` is returned.
+    /// If the WebSocket is already closed then `Ok(())` is returned. As with [`Sender::close`],
+    /// the closing handshake is completed by continuing to read from this `Receiver`.
     ///
     /// # Cancel safety
     ///
@@ -601,7 +1223,10 @@ where
             return Ok(());
         }
 
-        self.close_state.store(STATE_CLOSING, Ordering::SeqCst);
+        reason.validate()?;
+        if !self.close_state.start_closing() {
+            return Ok(());
+        }
 
         let WriteHalf {
             split_writer,
@@ -611,67 +1236,257 @@ where
         write_close(split_writer, writer, reason, self.role.is_server()).await
     }
 
+    /// Returns the state of the closing handshake, which is shared with the other half.
+    pub fn close_state(&self) -> CloseState {
+        load_close_state(&self.close_state)
+    }
+
     /// Returns whether this WebSocket is closed.
     pub fn is_closed(&self) -> bool {
-        self.close_state.load(Ordering::SeqCst) == STATE_CLOSED
+        self.close_state.load() == STATE_CLOSED
     }
 
     /// Returns whether this WebSocket is closing or closed.
     pub fn is_active(&self) -> bool {
-        matches!(self.close_state.load(Ordering::SeqCst), STATE_OPEN)
+        matches!(self.close_state.load(), STATE_OPEN)
+    }
+}
+
+/// Returns the pending operation that closes the connection after a close frame has been
+/// received or an error has been produced. This mirrors the semantics of `ws::start_close`.
+fn start_close(
+    previous: u8,
+    is_server: bool,
+    code: CloseCode,
+    result: Result<Message, Error>,
+) -> PendingRead {
+    match previous {
+        // we don't want to immediately await the echoed close frame as the peer may elect to
+        // drain any pending messages **before** echoing the close frame
+        STATE_OPEN => PendingRead::Close {
+            code: Some(code),
+            flush: false,
+            // 7.1.1: the TCP stream should be closed first by the server
+            shutdown: is_server,
+            result: Some(result),
+        },
+        STATE_CLOSING | STATE_CLOSED => PendingRead::Close {
+            code: None,
+            flush: false,
+            shutdown: is_server,
+            result: Some(result),
+        },
+        s => panic!("Unexpected close state: {}", s),
+    }
+}
+
+fn buffer_data<E>(
+    writer: &mut FramedWrite,
+    is_server: bool,
+    ext_encoder: &mut Option<E>,
+    message_type: MessageType,
+    payload: Bytes,
+) -> Result<(), Error>
+where
+    E: ExtensionEncoder,
+{
+    if is_server && ext_encoder.is_none() {
+        writer.buffer_owned_message(message_type, payload)
+    } else {
+        writer.buffer_message(is_server, message_type, payload, |payload, header| {
+            extension_encode(ext_encoder, payload, header)
+        })
+    }
+}
+
+fn buffer_control<A>(
+    writer: &mut FramedWrite,
+    is_server: bool,
+    code: ControlCode,
+    payload: A,
+) -> Result<(), Error>
+where
+    A: AsRef<[u8]>,
+{
+    if payload.as_ref().len() > CONTROL_MAX_SIZE {
+        Err(Error::with_cause(
+            ErrorKind::Protocol,
+            ProtocolError::FrameOverflow,
+        ))
+    } else {
+        writer.buffer_frame(
+            is_server,
+            OpCode::ControlCode(code),
+            HeaderFlags::FIN,
+            payload,
+            |_, _| Ok(()),
+        )
+    }
+}
+
+impl<S, E> Stream for Receiver<S, E>
+where
+    S: WebSocketStream,
+    E: ExtensionDecoder + Unpin,
+{
+    type Item = Result<OwnedMessage, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.is_closed() && matches!(this.pending_read, PendingRead::Idle) {
+            return Poll::Ready(None);
+        }
+
+        match ready!(this.poll_read_owned(cx)) {
+            Ok(message) => Poll::Ready(Some(Ok(message))),
+            // The closing handshake that we initiated has completed.
+            Err(e) if e.is_close() && e.downcast_ref() == Some(&CloseCause::Stopped) => {
+                Poll::Ready(None)
+            }
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
     }
 }
 
-impl<S> WebSocketClose for WriteHalf<S>
+/// Messages are encoded into the write buffer by the next call to `poll_ready`, `poll_flush` or
+/// `poll_close` and so any other write operation that is performed in the meantime may be sent
+/// before them.
+impl<S, E> Sink<OwnedMessage> for Sender<S, E>
 where
     S: WebSocketStream,
+    E: ExtensionEncoder + Unpin,
 {
-    fn write_close_frame(&mut self, code: CloseCode) -> BoxFuture<Result<(), Error>> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if !this.is_active() {
+            return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+        }
+
+        let mut guard = ready!(this.poll_pending_send(cx))?;
         let WriteHalf {
             split_writer,
             writer,
-            is_server,
             ..
-        } = self;
+        } = &mut *guard;
+        writer.poll_write_buffer(cx, split_writer)
+    }
 
-        Box::pin(async move {
-            writer
-                .write(
-                    split_writer,
-                    *is_server,
-                    OpCode::ControlCode(ControlCode::Close),
-                    HeaderFlags::FIN,
-                    &mut u16::from(code).to_be_bytes(),
-                    |_, _| Ok(()),
-                )
-                .await
-        })
+    fn start_send(self: Pin<&mut Self>, item: OwnedMessage) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if !this.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        if let OwnedMessage::Close(Some(reason)) = &item {
+            reason.validate()?;
+        }
+        if let OwnedMessage::Close(_) = &item {
+            if !this.close_state.start_closing() {
+                return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+            }
+        }
+        this.pending_send = Some(item);
+        Ok(())
     }
 
-    fn shutdown(&mut self) -> BoxFuture<Result<(), Error>> {
-        self.split_writer.shutdown().map_err(Into::into).boxed()
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.is_closed() {
+            return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+        }
+
+        let mut guard = ready!(this.poll_pending_send(cx))?;
+        let WriteHalf {
+            split_writer,
+            writer,
+            ..
+        } = &mut *guard;
+        writer.poll_flush(cx, split_writer)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.is_active() {
+            // Any message that has already been provided must be sent before the close frame.
+            drop(ready!(this.poll_pending_send(cx))?);
+            if this.close_state.start_closing() {
+                this.pending_send = Some(OwnedMessage::Close(None));
+            }
+        }
+        if this.is_closed() {
+            return Poll::Ready(Ok(()));
+        }
+
+        let mut guard = ready!(this.poll_pending_send(cx))?;
+        let WriteHalf {
+            split_writer,
+            writer,
+            ..
+        } = &mut *guard;
+        writer.poll_flush(cx, split_writer)
     }
 }
 
-async fn close<S>(
-    is_server: bool,
-    state_ref: &AtomicU8,
-    framed: &mut WriteHalf<S>,
-    code: CloseCode,
-) -> Result<(), Error>
-where
-    S: WebSocketStream,
-{
-    let close_state = match state_ref.load(Ordering::SeqCst) {
+/// The state of the closing handshake, which is shared by both halves.
+#[derive(Debug, Default)]
+pub(crate) struct SharedCloseState {
+    state: AtomicU8,
+    // Notified once the state has moved to closed.
+    closed: Notify,
+}
+
+impl SharedCloseState {
+    pub(crate) fn load(&self) -> u8 {
+        self.state.load(Ordering::SeqCst)
+    }
+
+    /// Moves an open connection into the closing state, returning whether it was open. Only the
+    /// caller that succeeds may send the close frame, so that racing halves never send two close
+    /// frames or overwrite a state of closed.
+    fn start_closing(&self) -> bool {
+        self.state
+            .compare_exchange(
+                STATE_OPEN,
+                STATE_CLOSING,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+    }
+
+    /// Moves the connection into the closed state and wakes any task that is waiting for it to
+    /// close, returning the previous state.
+    fn close(&self) -> u8 {
+        let previous = self.state.swap(STATE_CLOSED, Ordering::SeqCst);
+        self.closed.notify_waiters();
+        previous
+    }
+
+    /// Waits until the connection has closed.
+    #[cfg(feature = "manager")]
+    async fn wait_closed(&self) {
+        loop {
+            // The future is created before the state is checked so that a concurrent transition
+            // to closed is not missed.
+            let notified = self.closed.notified();
+            if self.load() == STATE_CLOSED {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+fn load_close_state(state: &SharedCloseState) -> CloseState {
+    match state.load() {
         STATE_OPEN => CloseState::NotClosed,
         STATE_CLOSING => CloseState::Closing,
-        STATE_CLOSED => CloseState::Closing,
-        s => panic!("Unexpected close state: {}", s),
-    };
-    let close_result = crate::ws::close(framed, is_server, close_state, code).await;
-
-    state_ref.store(STATE_CLOSED, Ordering::SeqCst);
-    close_result
+        STATE_CLOSED => CloseState::Closed,
+        s => panic!("Unknown close state: {}", s),
+    }
 }
 
 /// An error produced by `reunite` if the halves do not match.
@@ -683,7 +1498,9 @@ pub struct ReuniteError<S, E, D> {
 }
 
 /// Attempts to reunites the send and receive halves that form a WebSocket or returns an error if
-/// they do not represent the same connection.
+/// they do not represent the same connection. The halves may be reunited in any state, such as
+/// after a read error, in which case the WebSocket is closed.
+#[allow(clippy::result_large_err)]
 fn reunite<S, E>(
     sender: Sender<S, E::SplitEncoder>,
     receiver: Receiver<S, E::SplitDecoder>,
@@ -698,12 +1515,16 @@ where
     {
         let Sender {
             split_writer: sender_writer,
-            ext_encoder,
+            mut ext_encoder,
+            pending_send,
             ..
         } = sender;
         let Receiver {
             close_state,
             framed,
+            keepalive,
+            mut idle,
+            rate_limiter,
             ..
         } = receiver;
         let FramedIo {
@@ -715,15 +1536,29 @@ where
             split_writer: reader_writer,
         } = framed;
 
+        let mut write_half = sender_writer
+            .reunite(reader_writer)
+            // This is safe as we have checked the pointers
+            .expect("Failed to reunite writer");
+        if let Some(message) = pending_send {
+            // A message that has been accepted by the sink is still sent by the WebSocket. If it
+            // fails to encode then the sink would have failed the next operation but, as there
+            // isn't one, the message is discarded.
+            let _ = write_half.buffer_owned(message, &mut ext_encoder);
+        }
         let WriteHalf {
             split_writer,
             writer,
             control_buffer,
+            activity,
             ..
-        } = sender_writer
-            .reunite(reader_writer)
-            // This is safe as we have checked the pointers
-            .expect("Failed to reunite writer");
+        } = write_half;
+        if let (Some(idle), Some(at)) = (
+            idle.as_mut(),
+            activity.as_ref().and_then(WriteActivity::last),
+        ) {
+            idle.extend(at);
+        }
 
         let framed = framed::FramedIo::from_parts(FramedIoParts {
             // This is safe as we have checked the pointers
@@ -736,18 +1571,16 @@ where
             max_message_size,
         });
 
-        let close_state = match close_state.load(Ordering::SeqCst) {
-            STATE_OPEN => CloseState::NotClosed,
-            STATE_CLOSING => CloseState::Closing,
-            STATE_CLOSED => CloseState::Closed,
-            s => panic!("Unknown close state: {}", s),
-        };
+        let close_state = load_close_state(&close_state);
 
         Ok(WebSocket::from_parts(
             framed,
             control_buffer,
             Option::<E>::reunite(ext_encoder, ext_decoder),
             close_state,
+            keepalive,
+            idle,
+            rate_limiter,
         ))
     } else {
         Err(ReuniteError { sender, receiver })
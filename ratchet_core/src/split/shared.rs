@@ -0,0 +1,129 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::split::{SharedCloseState, STATE_CLOSED};
+use crate::{
+    CloseReason, Error, Message, PayloadType, Receiver, Sender, WebSocket, WebSocketStream,
+};
+use bytes::BytesMut;
+use futures::lock::Mutex;
+use ratchet_ext::SplittableExtension;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+type SharedHalves<S, E> = (
+    Mutex<Sender<S, <E as SplittableExtension>::SplitEncoder>>,
+    Mutex<Receiver<S, <E as SplittableExtension>::SplitDecoder>>,
+);
+
+/// A WebSocket which may be cloned and used from multiple tasks without being split.
+///
+/// The WebSocket is split internally and each half is guarded by its own lock, so read operations
+/// are performed one at a time and so are write operations, but a pending read does not prevent
+/// messages from being written. This is simpler, although less efficient, than sharing a
+/// [`Sender`] or using a [`SenderHandle`](crate::SenderHandle).
+pub struct SharedWebSocket<S, E>
+where
+    E: SplittableExtension,
+{
+    halves: Arc<SharedHalves<S, E>>,
+    close_state: Arc<SharedCloseState>,
+}
+
+impl<S, E> Clone for SharedWebSocket<S, E>
+where
+    E: SplittableExtension,
+{
+    fn clone(&self) -> Self {
+        SharedWebSocket {
+            halves: self.halves.clone(),
+            close_state: self.close_state.clone(),
+        }
+    }
+}
+
+impl<S, E> Debug for SharedWebSocket<S, E>
+where
+    E: SplittableExtension,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedWebSocket")
+            .field("close_state", &self.close_state.load())
+            .finish()
+    }
+}
+
+impl<S, E> SharedWebSocket<S, E>
+where
+    S: WebSocketStream,
+    E: SplittableExtension,
+{
+    /// Returns a new shared WebSocket.
+    ///
+    /// # Errors
+    /// Errors if `websocket` is already closed.
+    pub fn new(websocket: WebSocket<S, E>) -> Result<SharedWebSocket<S, E>, Error> {
+        let (sender, receiver) = websocket.split()?;
+        Ok(SharedWebSocket {
+            close_state: sender.close_state.clone(),
+            halves: Arc::new((Mutex::new(sender), Mutex::new(receiver))),
+        })
+    }
+
+    /// Attempt to read a message from the WebSocket, waiting for any other read operation to
+    /// complete first. See [`Receiver::read`].
+    pub async fn read(&self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
+        self.halves.1.lock().await.read(read_buffer).await
+    }
+
+    /// Sends a new message to the peer, waiting for any other write operation to complete first.
+    /// See [`Sender::write`].
+    pub async fn write<A>(&self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        self.halves.0.lock().await.write(buf, message_type).await
+    }
+
+    /// Sends a new text message to the peer. See [`SharedWebSocket::write`].
+    pub async fn write_text<I>(&self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text).await
+    }
+
+    /// Sends a new binary message to the peer. See [`SharedWebSocket::write`].
+    pub async fn write_binary<I>(&self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data, PayloadType::Binary).await
+    }
+
+    /// Flushes the WebSocket's output stream. See [`Sender::flush`].
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.halves.0.lock().await.flush().await
+    }
+
+    /// Closes the WebSocket with the reason provided. See [`Sender::close`].
+    pub async fn close(&self, reason: CloseReason) -> Result<(), Error> {
+        self.halves.0.lock().await.close(reason).await
+    }
+
+    /// Returns whether the WebSocket is closed.
+    pub fn is_closed(&self) -> bool {
+        self.close_state.load() == STATE_CLOSED
+    }
+}
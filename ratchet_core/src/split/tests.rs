@@ -12,18 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::framed::{read_next, write_close, FramedWrite, Item};
+use crate::framed::{read_props, write_close, FramedWrite, Item};
 use crate::protocol::{ControlCode, DataCode, HeaderFlags, OpCode};
-use crate::split::{FramedIo, Receiver, Sender, WriteHalf};
+use crate::raw::RawWebSocket;
+use crate::split::{
+    FramedIo, Receiver, ReuniteError, Sender, SenderHandle, SharedWebSocket, TrySendError,
+    WriteHalf,
+};
 use crate::ws::extension_encode;
 use crate::{
-    CloseCause, CloseCode, CloseReason, Error, Message, NoExt, NoExtDecoder, NoExtEncoder, Role,
-    WebSocket, WebSocketConfig, WebSocketStream,
+    CloseCause, CloseCode, CloseReason, CloseState, Error, Message, MessageType, NoExt,
+    NoExtDecoder, NoExtEncoder, OwnedMessage, PayloadType, RateLimit, RateLimitAction,
+    RateLimitCause, Role, TimeoutCause, WebSocket, WebSocketConfig, WebSocketStream,
 };
 use bytes::{Bytes, BytesMut};
-use ratchet_ext::{ExtensionDecoder, ExtensionEncoder};
-use tokio::io::{duplex, DuplexStream};
+use futures::{stream, SinkExt, StreamExt, TryStreamExt};
+use ratchet_ext::{ExtensionDecoder, ExtensionEncoder, RsvBits};
+use std::future::poll_fn;
+use std::time::Duration;
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
 use tokio::net::TcpStream;
+use tokio::time::{sleep, Instant};
 
 #[test]
 fn bounds() {
@@ -31,6 +40,8 @@ fn bounds() {
 
     is::<Sender<TcpStream, NoExt>>();
     is::<Receiver<TcpStream, NoExt>>();
+    is::<SenderHandle>();
+    is::<SharedWebSocket<TcpStream, NoExt>>();
 }
 
 impl<S, E> Sender<S, E>
@@ -38,7 +49,12 @@ where
     S: WebSocketStream,
     E: ExtensionEncoder,
 {
-    pub async fn write_frame<A>(&mut self, buf: A, opcode: OpCode, fin: bool) -> Result<(), Error>
+    pub(crate) async fn write_frame<A>(
+        &mut self,
+        buf: A,
+        opcode: OpCode,
+        fin: bool,
+    ) -> Result<(), Error>
     where
         A: AsRef<[u8]>,
     {
@@ -64,7 +80,6 @@ where
             |payload, header| extension_encode(ext_encoder, payload, header),
         )
         .await
-        .map_err(Into::into)
     }
 }
 
@@ -73,7 +88,8 @@ where
     S: WebSocketStream,
     E: ExtensionDecoder,
 {
-    pub async fn read_frame(&mut self, read_buffer: &mut BytesMut) -> Result<Item, Error> {
+    #[allow(dead_code)]
+    pub(crate) async fn read_frame(&mut self, read_buffer: &mut BytesMut) -> Result<Item, Error> {
         let Receiver { framed, .. } = self;
         let FramedIo {
             flags,
@@ -84,15 +100,8 @@ where
             ..
         } = framed;
 
-        read_next(
-            read_half,
-            reader,
-            flags,
-            *max_message_size,
-            read_buffer,
-            ext_decoder,
-        )
-        .await
+        let props = read_props(flags, *max_message_size, false);
+        poll_fn(|cx| reader.poll_read(cx, read_half, flags, read_buffer, ext_decoder, props)).await
     }
 }
 
@@ -210,6 +219,55 @@ async fn interleaved_control_frames() {
     );
 }
 
+#[tokio::test]
+async fn write_fragmented_releases_writer() {
+    let ((mut client_tx, mut client_rx), (mut server_tx, mut server_rx)) = fixture();
+
+    client_tx.write_ping("ping").await.expect("Write failure");
+
+    let mut server_buf = BytesMut::new();
+    let (write_result, read_result) = tokio::join!(
+        server_tx.write_fragmented("fragmented", MessageType::Text, 4),
+        server_rx.read(&mut server_buf)
+    );
+    write_result.expect("Write failure");
+    assert_eq!(
+        read_result.expect("Read failure"),
+        Message::Ping(Bytes::from_static(b"ping"))
+    );
+
+    // The pong is written between the fragments of the message.
+    let mut buf = BytesMut::new();
+    let message = client_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Pong(Bytes::from_static(b"ping")));
+
+    let message = client_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    assert_eq!(buf.as_ref(), b"fragmented");
+}
+
+#[tokio::test]
+async fn write_fragmented_after_close() {
+    let ((mut client_tx, mut client_rx), (_server_tx, mut server_rx)) = fixture();
+    let reason = CloseReason::new(CloseCode::Normal, None);
+
+    client_rx
+        .close(reason.clone())
+        .await
+        .expect("Close failure");
+    let error = client_tx
+        .write_fragmented("fragmented", MessageType::Text, 4)
+        .await
+        .expect_err("Expected a write failure");
+    assert!(error.is_close());
+
+    // Only the close frame was sent.
+    let mut buf = BytesMut::new();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Close(Some(reason)));
+    assert!(buf.is_empty());
+}
+
 #[tokio::test]
 async fn large_control_frames() {
     {
@@ -435,3 +493,705 @@ async fn reuse_after_closure() {
     assert!(server_tx.is_closed());
     assert!(server_rx.is_closed());
 }
+
+#[tokio::test]
+async fn sender_handle() {
+    let ((client_tx, _client_rx), (_server_tx, mut server_rx)) = fixture();
+    let handle = client_tx.into_handle(8);
+
+    let producers = (0..4).map(|i| {
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            handle
+                .send(format!("message {i}"), PayloadType::Text)
+                .await
+                .unwrap();
+        })
+    });
+    for producer in producers.collect::<Vec<_>>() {
+        producer.await.unwrap();
+    }
+
+    let mut received = Vec::new();
+    let mut buf = BytesMut::new();
+    for _ in 0..4 {
+        let message = server_rx.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        received.push(String::from_utf8(buf.split().to_vec()).unwrap());
+    }
+    received.sort();
+    assert_eq!(
+        received,
+        ["message 0", "message 1", "message 2", "message 3"]
+    );
+
+    handle
+        .close(CloseReason::new(CloseCode::Normal, None))
+        .await
+        .unwrap();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(
+        message,
+        Message::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+    );
+
+    tokio::task::yield_now().await;
+    assert!(handle.is_closed());
+    assert!(handle
+        .send("late", PayloadType::Text)
+        .await
+        .unwrap_err()
+        .is_close());
+    assert_eq!(
+        handle.try_send("late", PayloadType::Text),
+        Err(TrySendError::Closed)
+    );
+}
+
+#[tokio::test]
+async fn sender_handle_try_send() {
+    let ((client_tx, _client_rx), (_server_tx, mut server_rx)) = fixture();
+    let handle = client_tx.into_handle(1);
+
+    // The writer task does not run until this task yields, so the queue fills immediately.
+    handle.try_send("first", PayloadType::Text).unwrap();
+    assert_eq!(
+        handle.try_send("second", PayloadType::Text),
+        Err(TrySendError::Full)
+    );
+
+    let mut buf = BytesMut::new();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    assert_eq!(buf.as_ref(), b"first");
+}
+
+#[tokio::test]
+async fn sender_handle_flushes_when_dropped() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig {
+        autoflush_threshold: Some(4096),
+        ..Default::default()
+    };
+
+    let (_server_tx, mut server_rx) = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        server,
+        Some(NoExt),
+        BytesMut::new(),
+        Role::Server,
+    )
+    .split()
+    .unwrap();
+    let (client_tx, _client_rx) =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client)
+            .split()
+            .unwrap();
+
+    // The handle is dropped before the writer task runs, so both messages are still queued when
+    // it is disconnected.
+    let handle = client_tx.into_handle(2);
+    handle.try_send("first", PayloadType::Text).unwrap();
+    handle.try_send("second", PayloadType::Text).unwrap();
+    drop(handle);
+
+    let mut buf = BytesMut::new();
+    for expected in ["first", "second"] {
+        let message = tokio::time::timeout(Duration::from_secs(5), server_rx.read(&mut buf))
+            .await
+            .expect("The buffered messages were not flushed")
+            .expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), expected.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn shared_websocket() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let server =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+    let mut client =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+    let shared = SharedWebSocket::new(server).unwrap();
+
+    // A pending read must not prevent other tasks from writing.
+    let reader = tokio::spawn({
+        let shared = shared.clone();
+        async move {
+            let mut buf = BytesMut::new();
+            let message = shared.read(&mut buf).await.expect("Read failure");
+            (message, buf)
+        }
+    });
+
+    let writers = (0..3).map(|i| {
+        let shared = shared.clone();
+        tokio::spawn(async move { shared.write_text(format!("message {i}")).await.unwrap() })
+    });
+    for writer in writers.collect::<Vec<_>>() {
+        writer.await.unwrap();
+    }
+
+    let mut received = Vec::new();
+    let mut buf = BytesMut::new();
+    for _ in 0..3 {
+        assert_eq!(client.read(&mut buf).await.unwrap(), Message::Text);
+        received.push(String::from_utf8(buf.split().to_vec()).unwrap());
+    }
+    received.sort();
+    assert_eq!(received, ["message 0", "message 1", "message 2"]);
+
+    client.write_binary("reply").await.unwrap();
+    let (message, buf) = reader.await.unwrap();
+    assert_eq!(message, Message::Binary);
+    assert_eq!(buf.as_ref(), b"reply");
+
+    assert!(!shared.is_closed());
+    shared
+        .close(CloseReason::new(CloseCode::Normal, None))
+        .await
+        .unwrap();
+    let mut buf = BytesMut::new();
+    assert!(matches!(
+        client.read(&mut buf).await.unwrap(),
+        Message::Close(_)
+    ));
+}
+
+#[tokio::test]
+async fn stream_sink() {
+    let ((mut client_tx, mut client_rx), (_server_tx, mut server_rx)) = fixture();
+
+    client_tx
+        .send(OwnedMessage::Text(Bytes::from("hello")))
+        .await
+        .expect("Send failure");
+    client_tx
+        .send(OwnedMessage::Ping(Bytes::from("ping!")))
+        .await
+        .expect("Send failure");
+
+    let message = server_rx
+        .next()
+        .await
+        .expect("Stream ended")
+        .expect("Read failure");
+    assert_eq!(message, OwnedMessage::Text(Bytes::from("hello")));
+
+    let message = server_rx
+        .next()
+        .await
+        .expect("Stream ended")
+        .expect("Read failure");
+    assert_eq!(message, OwnedMessage::Ping(Bytes::from("ping!")));
+
+    let message = client_rx
+        .next()
+        .await
+        .expect("Stream ended")
+        .expect("Read failure");
+    assert_eq!(message, OwnedMessage::Pong(Bytes::from("ping!")));
+}
+
+#[tokio::test]
+async fn stream_forward() {
+    let ((mut client_tx, mut client_rx), (server_tx, server_rx)) = fixture();
+
+    // Echo the first two messages back to the client and then close the connection.
+    let echo = tokio::spawn(server_rx.take(2).forward(server_tx));
+
+    let messages = vec![
+        OwnedMessage::Text(Bytes::from("hello")),
+        OwnedMessage::Binary(Bytes::from(vec![1, 2, 3])),
+    ];
+    client_tx
+        .send_all(&mut stream::iter(messages.clone()).map(Ok))
+        .await
+        .expect("Send failure");
+
+    let received = (&mut client_rx)
+        .take(2)
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("Read failure");
+    assert_eq!(received, messages);
+
+    echo.await.unwrap().expect("Echo failure");
+
+    let message = client_rx
+        .next()
+        .await
+        .expect("Stream ended")
+        .expect("Read failure");
+    assert_eq!(
+        message,
+        OwnedMessage::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+    );
+    assert!(client_rx.next().await.is_none());
+    assert!(client_tx.is_closed());
+}
+
+#[tokio::test]
+async fn stream_ends_after_close() {
+    let ((mut client_tx, mut client_rx), (_server_tx, mut server_rx)) = fixture();
+
+    SinkExt::close(&mut client_tx).await.expect("Close failure");
+    assert!(!client_tx.is_active());
+    client_tx
+        .send(OwnedMessage::Text(Bytes::from("hello")))
+        .await
+        .expect_err("Expected a send failure");
+
+    let message = server_rx
+        .next()
+        .await
+        .expect("Stream ended")
+        .expect("Read failure");
+    assert_eq!(
+        message,
+        OwnedMessage::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+    );
+    assert!(server_rx.next().await.is_none());
+
+    assert!(client_rx.next().await.is_none());
+    assert!(client_rx.is_closed());
+}
+
+#[tokio::test]
+async fn sender_close_receiver_drains() {
+    let ((mut client_tx, mut client_rx), (mut server_tx, mut server_rx)) = fixture();
+    let reason = CloseReason::new(CloseCode::Normal, None);
+
+    client_tx
+        .close(reason.clone())
+        .await
+        .expect("Close failure");
+    assert_eq!(client_tx.close_state(), CloseState::Closing);
+    assert_eq!(client_rx.close_state(), CloseState::Closing);
+
+    // The server sends some messages before it receives the close frame.
+    for i in 0..3 {
+        server_tx
+            .write_text(i.to_string())
+            .await
+            .expect("Write failure");
+    }
+
+    let mut buf = BytesMut::new();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Close(Some(reason)));
+    assert_eq!(server_tx.close_state(), CloseState::Closed);
+    server_tx
+        .write_text("late")
+        .await
+        .expect_err("Expected a write failure");
+
+    for i in 0..3 {
+        let message = client_rx.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), i.to_string().as_bytes());
+    }
+
+    let err = client_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected a nominal closure");
+    assert_eq!(
+        err.downcast_ref::<CloseCause>().unwrap(),
+        &CloseCause::Stopped
+    );
+    assert_eq!(client_tx.close_state(), CloseState::Closed);
+    assert_eq!(client_rx.close_state(), CloseState::Closed);
+}
+
+#[tokio::test]
+async fn server_shuts_down_after_close() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let (_server_tx, mut server_rx) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let mut client = RawWebSocket::from_upgraded(
+        config,
+        client,
+        BytesMut::new(),
+        Role::Client,
+        RsvBits {
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+        },
+    );
+
+    client
+        .write_frame(
+            OpCode::ControlCode(ControlCode::Close),
+            HeaderFlags::FIN,
+            u16::from(CloseCode::Normal).to_be_bytes(),
+        )
+        .await
+        .expect("Write failure");
+
+    let mut buf = BytesMut::new();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(
+        message,
+        Message::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+    );
+
+    let header = client.read_frame(&mut buf).await.expect("Read failure");
+    assert_eq!(header.opcode, OpCode::ControlCode(ControlCode::Close));
+
+    // 7.1.1: the server closes the TCP stream after echoing the close frame.
+    let err = client
+        .read_frame(&mut buf)
+        .await
+        .expect_err("Expected the stream to be closed");
+    assert!(err.is_io());
+}
+
+#[tokio::test]
+async fn close_from_both_halves() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig::default();
+
+    let (mut server_tx, mut server_rx) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let mut client = RawWebSocket::from_upgraded(
+        config,
+        client,
+        BytesMut::new(),
+        Role::Client,
+        RsvBits {
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+        },
+    );
+
+    let going_away = CloseReason::new(CloseCode::GoingAway, None);
+    let (sent, received) = tokio::join!(
+        server_tx.close(going_away.clone()),
+        server_rx.close(CloseReason::new(CloseCode::Normal, None))
+    );
+    sent.expect("Close failure");
+    received.expect("Close failure");
+    assert_eq!(server_tx.close_state(), CloseState::Closing);
+    drop((server_tx, server_rx));
+
+    // Only the first half to close sends a close frame.
+    let mut buf = BytesMut::new();
+    let header = client.read_frame(&mut buf).await.expect("Read failure");
+    assert_eq!(header.opcode, OpCode::ControlCode(ControlCode::Close));
+    assert_eq!(
+        buf.as_ref(),
+        u16::from(CloseCode::GoingAway).to_be_bytes().as_slice()
+    );
+    let err = client
+        .read_frame(&mut buf)
+        .await
+        .expect_err("Expected the stream to be closed");
+    assert!(err.is_io());
+}
+
+#[tokio::test]
+async fn close_after_peer_close() {
+    let ((mut client_tx, mut client_rx), (mut server_tx, _server_rx)) = fixture();
+    let reason = CloseReason::new(CloseCode::Normal, None);
+
+    server_tx
+        .close(reason.clone())
+        .await
+        .expect("Close failure");
+    let message = client_rx
+        .read(&mut BytesMut::new())
+        .await
+        .expect("Read failure");
+    assert_eq!(message, Message::Close(Some(reason.clone())));
+    assert_eq!(client_tx.close_state(), CloseState::Closed);
+
+    // The closed state is not overwritten by a close that lost the race.
+    client_tx.close(reason).await.expect("Close failure");
+    assert_eq!(client_tx.close_state(), CloseState::Closed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn keepalive_pong_timeout() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig {
+        keepalive_interval: Some(Duration::from_secs(5)),
+        pong_timeout: Some(Duration::from_secs(2)),
+        ..Default::default()
+    };
+
+    let mut server = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        server,
+        Some(NoExt),
+        BytesMut::new(),
+        Role::Server,
+    );
+    let (_client_tx, mut client_rx) =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client)
+            .split()
+            .unwrap();
+
+    let start = Instant::now();
+    let mut buf = BytesMut::new();
+    let error = client_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected a timeout");
+    assert_eq!(
+        error.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Pong)
+    );
+    assert!(start.elapsed() >= Duration::from_secs(7));
+    assert!(client_rx.is_closed());
+
+    let message = server.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Ping(Bytes::new()));
+    let message = server.read(&mut buf).await.expect("Read failure");
+    assert_eq!(
+        message,
+        Message::Close(Some(CloseReason::new(CloseCode::Policy, None)))
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_timeout() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig {
+        idle_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+
+    let (mut server_tx, mut server_rx) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let mut client = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        client,
+        Some(NoExt),
+        BytesMut::new(),
+        Role::Client,
+    );
+
+    // Messages that are only written by the sender count as activity.
+    let writer = tokio::spawn(async move {
+        sleep(Duration::from_secs(6)).await;
+        server_tx.write_text("activity").await
+    });
+
+    let start = Instant::now();
+    let mut buf = BytesMut::new();
+    let error = server_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected a timeout");
+    assert_eq!(
+        error.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Idle)
+    );
+    assert!(start.elapsed() >= Duration::from_secs(16));
+    assert!(server_rx.is_closed());
+    writer
+        .await
+        .expect("Writer task panicked")
+        .expect("Write failure");
+
+    let message = client.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    let message = client.read(&mut buf).await.expect("Read failure");
+    assert_eq!(
+        message,
+        Message::Close(Some(CloseReason::new(CloseCode::GoingAway, None)))
+    );
+}
+
+#[tokio::test]
+async fn reunite_after_read_error() {
+    let ((mut client_tx, _client_rx), (server_tx, mut server_rx)) = fixture();
+
+    // An invalid UTF-8 payload fails the connection.
+    client_tx
+        .write_frame([0xff], OpCode::DataCode(DataCode::Text), true)
+        .await
+        .expect("Write failure");
+
+    let mut buf = BytesMut::new();
+    server_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected a read failure");
+    assert!(server_tx.is_closed());
+
+    let server = server_tx
+        .reunite::<NoExt>(server_rx)
+        .expect("Reunite failure");
+    assert!(server.is_closed());
+
+    // The underlying stream can still be recovered.
+    let mut stream = server.into_inner();
+    stream.shutdown().await.expect("Shutdown failure");
+}
+
+#[tokio::test]
+async fn reunite_mismatched_halves() {
+    let ((client_tx, client_rx), (server_tx, server_rx)) = fixture();
+
+    let ReuniteError { sender, receiver } = client_tx
+        .reunite::<NoExt>(server_rx)
+        .expect_err("Expected the halves to not match");
+
+    assert!(sender.reunite::<NoExt>(client_rx).is_ok());
+    assert!(server_tx.reunite::<NoExt>(receiver).is_ok());
+}
+
+#[tokio::test]
+async fn reunite_sends_accepted_message() {
+    let ((mut client_tx, client_rx), (_server_tx, mut server_rx)) = fixture();
+
+    client_tx
+        .feed(OwnedMessage::Text(Bytes::from("hello")))
+        .await
+        .expect("Send failure");
+
+    let mut client = client_tx
+        .reunite::<NoExt>(client_rx)
+        .expect("Reunite failure");
+    client.flush().await.expect("Flush failure");
+
+    let mut buf = BytesMut::new();
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    assert_eq!(buf.as_ref(), b"hello");
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_timeout() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig {
+        read_timeout: Some(std::time::Duration::from_secs(1)),
+        ..Default::default()
+    };
+
+    let (mut server_tx, _server_rx) = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        server,
+        Some(NoExt),
+        BytesMut::new(),
+        Role::Server,
+    )
+    .split()
+    .unwrap();
+    let (_client_tx, mut client_rx) =
+        WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client)
+            .split()
+            .unwrap();
+
+    let mut buf = BytesMut::new();
+    let error = client_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected a timeout");
+    assert_eq!(
+        error.downcast_ref::<TimeoutCause>(),
+        Some(&TimeoutCause::Read)
+    );
+    assert!(client_rx.is_active());
+
+    server_tx.write_text("late").await.expect("Write failure");
+    let message = client_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    assert_eq!(buf.as_ref(), b"late");
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_cancel_safe() {
+    let ((mut client_tx, _client_rx), (_server_tx, mut server_rx)) = fixture();
+
+    client_tx
+        .write_frame("123", OpCode::DataCode(DataCode::Text), false)
+        .await
+        .expect("Write failure");
+
+    let mut buf = BytesMut::new();
+    tokio::select! {
+        _ = server_rx.read(&mut buf) => panic!("Unexpected message"),
+        _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {}
+    }
+
+    client_tx
+        .write_frame("456", OpCode::DataCode(DataCode::Continuation), true)
+        .await
+        .expect("Write failure");
+
+    let message = server_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(message, Message::Text);
+    assert_eq!(buf.as_ref(), b"123456");
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limit() {
+    let (server, client) = duplex(512);
+    let config = WebSocketConfig {
+        rate_limit: Some(RateLimit {
+            messages_per_second: Some(2),
+            action: RateLimitAction::Close,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let (_server_tx, mut server_rx) =
+        WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server)
+            .split()
+            .unwrap();
+    let (mut client_tx, mut client_rx) = WebSocket::from_upgraded(
+        WebSocketConfig::default(),
+        client,
+        Some(NoExt),
+        BytesMut::new(),
+        Role::Client,
+    )
+    .split()
+    .unwrap();
+
+    for _ in 0..3 {
+        client_tx
+            .write_text("message")
+            .await
+            .expect("Write failure");
+    }
+
+    let mut buf = BytesMut::new();
+    for _ in 0..2 {
+        let message = server_rx.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+    }
+
+    let error = server_rx
+        .read(&mut buf)
+        .await
+        .expect_err("Expected an error");
+    assert_eq!(
+        error.downcast_ref::<RateLimitCause>(),
+        Some(&RateLimitCause::Messages)
+    );
+    assert_eq!(server_rx.close_state(), CloseState::Closed);
+
+    let message = client_rx.read(&mut buf).await.expect("Read failure");
+    assert_eq!(
+        message,
+        Message::Close(Some(CloseReason::new(CloseCode::Policy, None)))
+    );
+}
@@ -12,27 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::errors::{CloseCause, Error, ErrorKind, ProtocolError};
-use crate::framed::{FramedIo, Item};
+use crate::errors::{CloseCause, Error, ErrorKind, ProtocolError, TimeoutCause};
+use crate::framed::{with_timeout, FramedIo, Item};
+use crate::idle::{IdleConfig, IdleTimeout};
+use crate::keepalive::{Keepalive, KeepaliveConfig, KeepaliveEvent};
 use crate::protocol::{
-    CloseReason, ControlCode, DataCode, HeaderFlags, Message, MessageType, OpCode, PayloadType,
-    Role,
+    CloseReason, ControlCode, Fragment, HeaderFlags, Message, MessageType, OpCode, OwnedMessage,
+    PayloadType, RateLimitAction, Role,
 };
-use crate::{CloseCode, WebSocketConfig, WebSocketStream};
-use bytes::BytesMut;
-use futures_util::future::BoxFuture;
-use futures_util::TryFutureExt;
+use crate::rate_limit::RateLimiter;
+use crate::{CloseCode, PreparedMessage, WebSocketConfig, WebSocketStream};
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures_util::{Sink, Stream};
 use log::{error, trace};
 use ratchet_ext::{Extension, ExtensionEncoder, FrameHeader as ExtFrameHeader};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::time::Instant;
 
-#[cfg(feature = "split")]
+use crate::raw::{RawReceiver, RawSender, RawWebSocket};
 use crate::split::{split, Receiver, Sender};
-#[cfg(feature = "split")]
 use ratchet_ext::SplittableExtension;
 
 pub const CONTROL_MAX_SIZE: usize = 125;
 
-#[cfg(feature = "split")]
+/// The maximum number of pings sent by [`WebSocket::ping`] that may await a pong at once.
+const MAX_PENDING_PINGS: usize = 64;
+
 type SplitSocket<S, E> = (
     Sender<S, <E as SplittableExtension>::SplitEncoder>,
     Receiver<S, <E as SplittableExtension>::SplitDecoder>,
@@ -74,12 +83,100 @@ type SplitSocket<S, E> = (
 /// }
 /// # }
 /// ```
+///
+/// # Streams and sinks
+/// `WebSocket` also implements `Stream` and `Sink` for [`OwnedMessage`] so that it may be used
+/// with the `StreamExt` and `SinkExt` combinators. The stream yields `None` once the closing
+/// handshake has completed.
+///
+/// ```no_run
+/// # use ratchet_core::{subscribe, UpgradedClient, Error, OwnedMessage, WebSocketConfig};
+/// # use tokio::net::TcpStream;
+/// # use futures::{SinkExt, StreamExt};
+///
+/// # #[tokio::main]
+/// # async fn main()-> Result<(), Error> {
+/// let stream = TcpStream::connect("127.0.0.1:9001").await?;
+/// let upgraded = subscribe(WebSocketConfig::default(), stream, "ws://127.0.0.1/hello").await?;
+/// let UpgradedClient { mut  websocket, .. } = upgraded;
+///
+/// while let Some(message) = websocket.next().await {
+///     match message? {
+///         message @ (OwnedMessage::Text(_) | OwnedMessage::Binary(_)) => {
+///             websocket.send(message).await?;
+///         }
+///         _ => {}
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct WebSocket<S, E> {
     framed: FramedIo<S>,
     control_buffer: BytesMut,
     extension: Option<E>,
     close_state: CloseState,
+    pending_read: PendingRead,
+    message_buffer: BytesMut,
+    keepalive: Option<Keepalive>,
+    idle: Option<IdleTimeout>,
+    rate_limiter: Option<RateLimiter>,
+    pending_pings: Vec<PendingPing>,
+}
+
+/// A ping that has been sent using [`WebSocket::ping`] and which has not yet been responded to.
+#[derive(Debug)]
+struct PendingPing {
+    payload: Bytes,
+    sent_at: Instant,
+    tx: oneshot::Sender<Duration>,
+}
+
+/// A future which resolves to the measured round-trip time once the peer has responded to a ping
+/// sent by [`WebSocket::ping`].
+///
+/// The pong frame is only processed while the WebSocket is being read from and so this future will
+/// not resolve unless messages are being read from the WebSocket concurrently. If the WebSocket is
+/// closed, dropped or split before the pong is received then this future resolves to an error. At
+/// most 64 pings may await a pong at once; once this is exceeded, the oldest ping is abandoned and
+/// its future resolves to an error.
+#[derive(Debug)]
+pub struct PendingPong {
+    rx: oneshot::Receiver<Duration>,
+}
+
+impl Future for PendingPong {
+    type Output = Result<Duration, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match ready!(Pin::new(&mut self.rx).poll(cx)) {
+            Ok(rtt) => Poll::Ready(Ok(rtt)),
+            Err(_) => Poll::Ready(Err(Error::new(ErrorKind::Close))),
+        }
+    }
+}
+
+/// The state of a read operation which has received a frame but has not yet completed the IO
+/// required to respond to it. This is stored in the `WebSocket` so that reads may be resumed if
+/// they return `Poll::Pending`.
+#[derive(Debug)]
+enum PendingRead {
+    /// There is no pending read operation.
+    Idle,
+    /// A ping was received and the responding pong frame has been buffered but not yet flushed.
+    Pong(Bytes),
+    /// A keepalive ping has been buffered but not yet flushed.
+    Flush,
+    /// The connection is being closed after receiving a close frame or due to an error.
+    Close {
+        /// Whether the buffered close frame still needs to be flushed.
+        flush: bool,
+        /// Whether the underlying IO still needs to be shutdown.
+        shutdown: bool,
+        /// The result to return once the sequence has completed.
+        result: Option<Result<Fragment, Error>>,
+    },
 }
 
 /// Denotes the current state of a WebSocket session.
@@ -97,18 +194,26 @@ impl<S, E> WebSocket<S, E>
 where
     E: Extension,
 {
-    #[cfg(feature = "split")]
     pub(crate) fn from_parts(
         framed: FramedIo<S>,
         control_buffer: BytesMut,
         extension: Option<E>,
         close_state: CloseState,
+        keepalive: Option<Keepalive>,
+        idle: Option<IdleTimeout>,
+        rate_limiter: Option<RateLimiter>,
     ) -> WebSocket<S, E> {
         WebSocket {
             framed,
             control_buffer,
             extension,
             close_state,
+            pending_read: PendingRead::Idle,
+            message_buffer: BytesMut::new(),
+            keepalive,
+            idle,
+            rate_limiter,
+            pending_pings: Vec::new(),
         }
     }
 
@@ -128,18 +233,55 @@ where
         read_buffer: BytesMut,
         role: Role,
     ) -> WebSocket<S, E> {
-        let WebSocketConfig { max_message_size } = config;
+        let WebSocketConfig {
+            max_message_size,
+            max_frame_size,
+            keepalive_interval,
+            pong_timeout,
+            idle_timeout,
+            idle_counts_pings,
+            rate_limit,
+            auto_fragment_above,
+            autoflush_threshold,
+            read_timeout,
+            write_timeout,
+            ..
+        } = config;
+        let mut framed = FramedIo::new(
+            stream,
+            read_buffer,
+            role,
+            max_message_size,
+            extension.bits().into(),
+        );
+        framed.set_reserved_opcodes(extension.reserved_opcodes().into());
+        framed.set_max_frame_size(max_frame_size);
+        framed.set_auto_fragment_above(auto_fragment_above);
+        framed.set_autoflush_threshold(autoflush_threshold);
+        framed.set_read_timeout(read_timeout);
+        framed.set_write_timeout(write_timeout);
+
         WebSocket {
-            framed: FramedIo::new(
-                stream,
-                read_buffer,
-                role,
-                max_message_size,
-                extension.bits().into(),
-            ),
+            framed,
             extension,
             control_buffer: BytesMut::with_capacity(CONTROL_MAX_SIZE),
             close_state: CloseState::NotClosed,
+            pending_read: PendingRead::Idle,
+            message_buffer: BytesMut::new(),
+            keepalive: keepalive_interval.map(|interval| {
+                Keepalive::new(KeepaliveConfig {
+                    interval,
+                    pong_timeout,
+                })
+            }),
+            idle: idle_timeout.map(|timeout| {
+                IdleTimeout::new(IdleConfig {
+                    timeout,
+                    count_pings: idle_counts_pings,
+                })
+            }),
+            rate_limiter: rate_limit.map(RateLimiter::new),
+            pending_pings: Vec::new(),
         }
     }
 }
@@ -158,6 +300,17 @@ where
         }
     }
 
+    /// Returns a reference to the extension that was negotiated for this WebSocket, if any.
+    pub fn extension(&self) -> Option<&E> {
+        self.extension.as_ref()
+    }
+
+    /// Returns a mutable reference to the extension that was negotiated for this WebSocket, if
+    /// any. This may be used to change how the extension encodes subsequent messages.
+    pub fn extension_mut(&mut self) -> Option<&mut E> {
+        self.extension.as_mut()
+    }
+
     /// Attempt to read some data from the WebSocket. Returning either the type of the message
     /// received or the error that was produced.
     ///
@@ -180,72 +333,348 @@ where
     ///
     /// # Cancel safety
     ///
-    /// This function is not cancellation safe. If the future is dropped before it has completed
-    /// then both `buf` and the connection state are undefined. It may not be possible to recover
-    /// the connection due the read operation partially completing and the state has been lost.
+    /// This function is cancellation safe, so it may be used as a branch of `tokio::select!`. If
+    /// the future is dropped before it has completed then any part of a message that has been
+    /// received, and any pong or close frame that is being sent in response to a frame, is
+    /// retained and completed by the next read operation. As `read_buffer` may contain part of a
+    /// message, the same buffer must be provided to the next call and its contents must not be
+    /// modified.
     pub async fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
-        if self.is_closed() {
-            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
-        }
+        let read_timeout = self.framed.read_timeout();
+        with_timeout(
+            read_timeout,
+            TimeoutCause::Read,
+            poll_fn(|cx| self.poll_read_message(cx, read_buffer)),
+        )
+        .await
+    }
 
-        let WebSocket {
-            framed,
-            close_state,
-            control_buffer,
-            extension,
-            ..
-        } = self;
+    /// Attempt to read a message from the WebSocket, returning a message which owns its payload.
+    ///
+    /// This behaves in the same manner as [`WebSocket::read`] but text and binary payloads are
+    /// read into a buffer which is managed by the WebSocket and handed to the caller as `Bytes`.
+    /// This allows the payload to be moved (into a channel for example) without needing to copy
+    /// it out of a shared read buffer.
+    ///
+    /// # Errors
+    /// See [`WebSocket::read`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe. If the future is dropped before it has completed then
+    /// any part of a message that has been received is retained in the buffer that is managed by
+    /// the WebSocket, and any pending response to a control frame is sent, by the next read
+    /// operation.
+    pub async fn read_owned(&mut self) -> Result<OwnedMessage, Error> {
+        let read_timeout = self.framed.read_timeout();
+        with_timeout(
+            read_timeout,
+            TimeoutCause::Read,
+            poll_fn(|cx| self.poll_read_owned(cx)),
+        )
+        .await
+    }
 
-        match framed.read_next(read_buffer, extension).await {
-            Ok(item) => match item {
-                Item::Binary => Ok(Message::Binary),
-                Item::Text => Ok(Message::Text),
-                Item::Ping(payload) => {
-                    trace!("Received a ping frame. Responding with pong");
-                    let ret = payload.clone().freeze();
-                    framed
-                        .write(
-                            OpCode::ControlCode(ControlCode::Pong),
+    fn poll_read_owned(&mut self, cx: &mut Context<'_>) -> Poll<Result<OwnedMessage, Error>> {
+        let mut message_buffer = std::mem::take(&mut self.message_buffer);
+        let result = self.poll_read_message(cx, &mut message_buffer);
+        self.message_buffer = message_buffer;
+
+        let message = match ready!(result) {
+            Ok(Message::Text) => OwnedMessage::Text(self.message_buffer.split().freeze()),
+            Ok(Message::Binary) => OwnedMessage::Binary(self.message_buffer.split().freeze()),
+            Ok(Message::Ping(payload)) => OwnedMessage::Ping(payload),
+            Ok(Message::Pong(payload)) => OwnedMessage::Pong(payload),
+            Ok(Message::Close(reason)) => OwnedMessage::Close(reason),
+            Err(e) => {
+                self.message_buffer.clear();
+                return Poll::Ready(Err(e));
+            }
+        };
+
+        Poll::Ready(Ok(message))
+    }
+
+    /// Attempt to read a message, or a fragment of one, from the WebSocket.
+    ///
+    /// This behaves in the same manner as [`WebSocket::read`] except that the fragments of
+    /// uncompressed text and binary messages are yielded as they are received rather than the
+    /// message being reassembled first. As each fragment is received, its payload is appended to
+    /// `read_buffer` and so the caller may process the message incrementally by draining the buffer
+    /// between calls. Control frames that are interleaved between fragments are returned as they
+    /// are received.
+    ///
+    /// The maximum message size is still enforced over the length of the entire message.
+    ///
+    /// # Note
+    /// Messages which have been encoded by an extension (such as a compressed message) may only be
+    /// decoded once every fragment has been received. These messages are reassembled and returned
+    /// as a single final fragment.
+    ///
+    /// Calls to this function should not be interleaved with calls to [`WebSocket::read`] while a
+    /// fragmented message is being received.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe in the same manner as [`WebSocket::read`] and the same
+    /// `read_buffer` must be provided to the next call.
+    pub async fn read_fragment(&mut self, read_buffer: &mut BytesMut) -> Result<Fragment, Error> {
+        let read_timeout = self.framed.read_timeout();
+        with_timeout(
+            read_timeout,
+            TimeoutCause::Read,
+            poll_fn(|cx| self.poll_read_fragment(cx, read_buffer, true)),
+        )
+        .await
+    }
+
+    /// Attempt to read a message from the WebSocket, registering the current task for wakeup if
+    /// no message is available yet.
+    ///
+    /// This is the poll-based equivalent of [`WebSocket::read`] and allows the WebSocket to be
+    /// driven from a manual `Future` implementation. Partially read frames and any control frame
+    /// responses are retained by the WebSocket between calls, so the same `read_buffer` must be
+    /// provided until the call returns `Poll::Ready`.
+    ///
+    /// # Errors
+    /// See [`WebSocket::read`].
+    pub fn poll_read_message(
+        &mut self,
+        cx: &mut Context<'_>,
+        read_buffer: &mut BytesMut,
+    ) -> Poll<Result<Message, Error>> {
+        let message = match ready!(self.poll_read_fragment(cx, read_buffer, false))? {
+            Fragment::Text { .. } => Message::Text,
+            Fragment::Binary { .. } => Message::Binary,
+            Fragment::Ping(payload) => Message::Ping(payload),
+            Fragment::Pong(payload) => Message::Pong(payload),
+            Fragment::Close(reason) => Message::Close(reason),
+        };
+        Poll::Ready(Ok(message))
+    }
+
+    fn poll_read_fragment(
+        &mut self,
+        cx: &mut Context<'_>,
+        read_buffer: &mut BytesMut,
+        streaming: bool,
+    ) -> Poll<Result<Fragment, Error>> {
+        loop {
+            if let Some(result) = ready!(self.poll_pending_read(cx)) {
+                return Poll::Ready(result);
+            }
+
+            if self.is_closed() {
+                return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+            }
+
+            let WebSocket {
+                framed,
+                close_state,
+                control_buffer,
+                extension,
+                pending_read,
+                keepalive,
+                idle,
+                rate_limiter,
+                pending_pings,
+                ..
+            } = self;
+
+            if let (Some(timeout), CloseState::NotClosed) = (idle.as_mut(), *close_state) {
+                if timeout.poll_expired(cx).is_ready() {
+                    trace!("Connection idle. Closing");
+                    *close_state = CloseState::Closed;
+                    *pending_read = start_close(
+                        framed,
+                        CloseState::NotClosed,
+                        CloseCode::GoingAway,
+                        Err(TimeoutCause::Idle.into()),
+                    );
+                    continue;
+                }
+            }
+
+            if let (Some(keepalive), CloseState::NotClosed) = (keepalive.as_mut(), *close_state) {
+                match keepalive.poll_event(cx) {
+                    Poll::Ready(KeepaliveEvent::Ping) => {
+                        trace!("Connection idle. Sending a keepalive ping");
+                        framed.buffer(
+                            OpCode::ControlCode(ControlCode::Ping),
                             HeaderFlags::FIN,
-                            payload,
+                            [],
                             |_, _| Ok(()),
-                        )
-                        .await?;
-                    Ok(Message::Ping(ret))
+                        )?;
+                        if let Some(timeout) = idle {
+                            timeout.on_control();
+                        }
+                        *pending_read = PendingRead::Flush;
+                        continue;
+                    }
+                    Poll::Ready(KeepaliveEvent::Timeout) => {
+                        error!("Peer failed to respond to a keepalive ping in time");
+                        *close_state = CloseState::Closed;
+                        *pending_read = start_close(
+                            framed,
+                            CloseState::NotClosed,
+                            CloseCode::Policy,
+                            Err(TimeoutCause::Pong.into()),
+                        );
+                        continue;
+                    }
+                    Poll::Pending => {}
                 }
-                Item::Pong(payload) => {
-                    if control_buffer.is_empty() {
-                        trace!("Received an unsolicited pong frame");
-                    } else {
-                        control_buffer.clear();
-                        trace!("Received pong frame");
+            }
+
+            if let (Some(limiter), CloseState::NotClosed) = (rate_limiter.as_mut(), *close_state) {
+                if let Err(cause) = ready!(limiter.poll_ready(cx)) {
+                    if limiter.action() == RateLimitAction::Error {
+                        return Poll::Ready(Err(cause.into()));
                     }
-                    Ok(Message::Pong(payload.freeze()))
+
+                    error!("Peer exceeded the rate limit. Closing");
+                    *close_state = CloseState::Closed;
+                    *pending_read = start_close(
+                        framed,
+                        CloseState::NotClosed,
+                        CloseCode::Policy,
+                        Err(cause.into()),
+                    );
+                    continue;
+                }
+            }
+
+            let len = read_buffer.len();
+            let poll = framed.poll_read_next(cx, read_buffer, extension, streaming);
+            if let Some(limiter) = rate_limiter {
+                // Fragments of a message may be appended to the buffer by calls which return
+                // `Poll::Pending`, so every call accounts for what it appended.
+                limiter.on_bytes(read_buffer.len().saturating_sub(len));
+                if let Poll::Ready(Ok(Item::Binary | Item::Text)) = &poll {
+                    limiter.on_message();
                 }
-                Item::Close(reason) => {
-                    let is_server = framed.is_server();
-                    let code = reason
-                        .as_ref()
-                        .map(|reason| reason.code)
-                        .unwrap_or(CloseCode::Normal);
+            }
+            let result = ready!(poll);
+            if let Some(keepalive) = keepalive {
+                keepalive.on_frame();
+            }
+            if let (Some(timeout), Ok(item)) = (idle.as_mut(), &result) {
+                match item {
+                    Item::Binary | Item::Text | Item::Fragment(_) => timeout.on_data(),
+                    Item::Ping(_) | Item::Pong(_) => timeout.on_control(),
+                    Item::Close(_) => {}
+                }
+            }
+
+            match result {
+                Ok(item) => match item {
+                    Item::Binary => return Poll::Ready(Ok(Fragment::Binary { fin: true })),
+                    Item::Text => return Poll::Ready(Ok(Fragment::Text { fin: true })),
+                    Item::Fragment(MessageType::Binary) => {
+                        return Poll::Ready(Ok(Fragment::Binary { fin: false }))
+                    }
+                    Item::Fragment(MessageType::Text) => {
+                        return Poll::Ready(Ok(Fragment::Text { fin: false }))
+                    }
+                    Item::Ping(payload) => {
+                        trace!("Received a ping frame. Responding with pong");
+                        let ret = payload.clone().freeze();
+                        framed.buffer(
+                            OpCode::ControlCode(ControlCode::Pong),
+                            HeaderFlags::FIN,
+                            payload,
+                            |_, _| Ok(()),
+                        )?;
+                        *pending_read = PendingRead::Pong(ret);
+                    }
+                    Item::Pong(payload) => {
+                        if let Some(keepalive) = keepalive {
+                            keepalive.on_pong();
+                        }
+                        complete_pending_pings(pending_pings, &payload);
+                        if control_buffer.is_empty() {
+                            trace!("Received an unsolicited pong frame");
+                        } else {
+                            control_buffer.clear();
+                            trace!("Received pong frame");
+                        }
+                        return Poll::Ready(Ok(Fragment::Pong(payload.freeze())));
+                    }
+                    Item::Close(reason) => {
+                        let code = reason
+                            .as_ref()
+                            .map(|reason| reason.code)
+                            .unwrap_or(CloseCode::Normal);
+                        let current_close_state = *close_state;
+                        *close_state = CloseState::Closed;
+
+                        let result = match current_close_state {
+                            CloseState::Closing => {
+                                Err(Error::with_cause(ErrorKind::Close, CloseCause::Stopped))
+                            }
+                            _ => Ok(Fragment::Close(reason)),
+                        };
+                        *pending_read = start_close(framed, current_close_state, code, result);
+                    }
+                },
+                Err(e) => {
+                    error!("WebSocket read failure: {:?}", e);
 
+                    // We want to close the connection but return the error produced during the
+                    // session, not any during the close sequence.
                     let current_close_state = *close_state;
                     *close_state = CloseState::Closed;
-
-                    close(framed, is_server, current_close_state, code).await?;
-                    Ok(Message::Close(reason))
+                    *pending_read = start_close(
+                        framed,
+                        current_close_state,
+                        read_error_close_code(&e),
+                        Err(e),
+                    );
                 }
-            },
-            Err(e) => {
-                error!("WebSocket read failure: {:?}", e);
+            }
+        }
+    }
 
-                let is_server = framed.is_server();
+    /// Drives any IO that is required to complete a previous read operation. Returns the result of
+    /// the read operation if one was pending.
+    fn poll_pending_read(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Fragment, Error>>> {
+        let WebSocket {
+            framed,
+            pending_read,
+            ..
+        } = self;
 
-                // We want to close the connection but return the error produced during the session,
-                // not any during the close sequence.
-                let _ = close(framed, is_server, *close_state, CloseCode::Protocol).await;
-                *close_state = CloseState::Closed;
-                Err(e)
+        match pending_read {
+            PendingRead::Idle => Poll::Ready(None),
+            PendingRead::Pong(payload) => {
+                let result = ready!(framed.poll_flush(cx));
+                let payload = std::mem::take(payload);
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(Some(result.map(|_| Fragment::Ping(payload))))
+            }
+            PendingRead::Flush => {
+                let result = ready!(framed.poll_flush(cx));
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(result.err().map(Err))
+            }
+            PendingRead::Close {
+                flush,
+                shutdown,
+                result,
+            } => {
+                if *flush {
+                    // We aren't interested in any IO errors produced here as the peer *may* have
+                    // already closed the TCP stream.
+                    let _ = ready!(framed.poll_flush(cx));
+                    *flush = false;
+                }
+                if *shutdown {
+                    let _ = ready!(framed.poll_shutdown(cx));
+                    *shutdown = false;
+                }
+                let result = result.take();
+                *pending_read = PendingRead::Idle;
+                Poll::Ready(result)
             }
         }
     }
@@ -295,8 +724,70 @@ where
         self.write(data.as_ref(), PayloadType::Ping).await
     }
 
+    /// Sends a new ping WebSocket message with a payload of `data` and returns a future which
+    /// resolves to the round-trip time once the peer has responded with a matching pong.
+    ///
+    /// As the pong is received by reading from the WebSocket, the returned future will only resolve
+    /// while the WebSocket is being read from. The peer may elect to only respond to the most
+    /// recent ping that it has received and so when a pong is received, any pings that were sent
+    /// before the one that it matches are also considered to have been responded to.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use ratchet_core::{Error, NoExt, WebSocket};
+    /// # use tokio::net::TcpStream;
+    /// # use bytes::BytesMut;
+    /// # async fn f(mut websocket: WebSocket<TcpStream, NoExt>) -> Result<(), Error> {
+    /// let pong = websocket.ping("heartbeat").await?;
+    ///
+    /// let mut buf = BytesMut::new();
+    /// // Read until the pong has been received.
+    /// let _message = websocket.read(&mut buf).await?;
+    ///
+    /// let rtt = pong.await?;
+    /// println!("Round trip time: {:?}", rtt);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the control frame and the state of the write
+    /// operation has been lost.
+    pub async fn ping<I>(&mut self, data: I) -> Result<PendingPong, Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        let payload = Bytes::copy_from_slice(data.as_ref());
+        let sent_at = Instant::now();
+        self.write(&payload, PayloadType::Ping).await?;
+
+        // A peer which never responds must not grow the pending pings without bound, so abandoned
+        // pings are discarded and then the oldest ping if the limit has been reached.
+        let pending_pings = &mut self.pending_pings;
+        pending_pings.retain(|ping| !ping.tx.is_canceled());
+        if pending_pings.len() == MAX_PENDING_PINGS {
+            pending_pings.remove(0);
+        }
+
+        let (tx, rx) = oneshot::channel();
+        pending_pings.push(PendingPing {
+            payload,
+            sent_at,
+            tx,
+        });
+        Ok(PendingPong { rx })
+    }
+
     /// Sends a new pong WebSocket message with a payload of `data`.
     ///
+    /// Pongs that are sent in response to a ping are sent automatically when reading. This may be
+    /// used to send an unsolicited pong which, as per
+    /// [RFC6455 section 5.5.3](https://datatracker.ietf.org/doc/html/rfc6455#section-5.5.3), serves
+    /// as a unidirectional heartbeat and does not expect a response from the peer.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -312,6 +803,12 @@ where
 
     /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
     ///
+    /// `buf` is copied into a reusable internal buffer before it is encoded and masked, so it is
+    /// never modified and the same payload may be written to multiple connections.
+    ///
+    /// If an autoflush threshold has been configured then text and binary messages may remain in
+    /// the write buffer until the threshold is reached or [`WebSocket::flush`] is called.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -325,11 +822,158 @@ where
             return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
         }
 
+        self.buffer_payload(buf, message_type)?;
+        self.flush_payload(message_type).await
+    }
+
+    /// Sends a new text or binary WebSocket message with a payload of `buf` without encoding it
+    /// using the negotiated extension. Compression remains enabled for any subsequent messages.
+    ///
+    /// This is useful for payloads which are already compressed or too small to benefit from
+    /// compression. The message is sent with all of its reserved bits unset and so it must only be
+    /// used with extensions that permit unencoded messages, such as permessage-deflate.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_uncompressed<A>(
+        &mut self,
+        buf: A,
+        message_type: MessageType,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        self.framed
+            .buffer_message(message_type, buf, |_payload, _header| Ok(()))?;
+        self.on_write(true);
+        self.framed.autoflush().await
+    }
+
+    /// Sends a message which has been prepared with [`PreparedMessage`], so that a message which is
+    /// broadcast to many peers is only encoded once. See [`PreparedMessage`] for the requirements
+    /// on the connection's negotiated extension.
+    ///
+    /// # Errors
+    /// Returns an extension error if the message was encoded with parameters other than those of
+    /// the connection's encoder.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the frame and the state of the write
+    /// operation has been lost.
+    pub async fn send_prepared(&mut self, prepared: &PreparedMessage) -> Result<(), Error> {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        prepared.check_encoder(&self.extension)?;
+        self.framed.buffer_prepared(prepared)?;
+        self.on_write(true);
+        self.framed.autoflush().await
+    }
+
+    /// Flushes the write buffer after a payload of `message_type` has been written to it. Data
+    /// messages are only flushed once the autoflush threshold has been reached.
+    async fn flush_payload(&mut self, message_type: PayloadType) -> Result<(), Error> {
+        match message_type {
+            PayloadType::Text | PayloadType::Binary => self.framed.autoflush().await,
+            PayloadType::Ping | PayloadType::Pong => self.framed.flush().await,
+        }
+    }
+
+    /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
+    ///
+    /// If this WebSocket is a server and no extension has been negotiated then text and binary
+    /// payloads are written without being copied. Otherwise, `buf` is copied so that it can be
+    /// masked or encoded, as with [`WebSocket::write`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_owned(
+        &mut self,
+        buf: Bytes,
+        message_type: PayloadType,
+    ) -> Result<(), Error> {
+        self.buffer_owned_payload(buf, message_type)?;
+        self.flush_payload(message_type).await
+    }
+
+    /// Encodes a new WebSocket message into the write buffer, avoiding copying `buf` where
+    /// possible. No IO is performed.
+    fn buffer_owned_payload(&mut self, buf: Bytes, message_type: PayloadType) -> Result<(), Error> {
+        let zero_copy = self.framed.is_server() && self.extension.is_none();
+        let message_type = match message_type {
+            PayloadType::Text if zero_copy => MessageType::Text,
+            PayloadType::Binary if zero_copy => MessageType::Binary,
+            // Control frames are small enough that they are always copied into the write buffer.
+            _ => return self.buffer_payload(buf, message_type),
+        };
+
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        self.framed.buffer_owned_message(message_type, buf)?;
+        self.on_write(true);
+        Ok(())
+    }
+
+    /// Resets the idle timeout after a frame has been placed into the write buffer.
+    fn on_write(&mut self, is_data: bool) {
+        if let Some(idle) = &mut self.idle {
+            if is_data {
+                idle.on_data();
+            } else {
+                idle.on_control();
+            }
+        }
+    }
+
+    /// Encodes a new WebSocket message into the write buffer. No IO is performed.
+    fn buffer_payload<A>(&mut self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
         let buf = buf.as_ref();
+        self.on_write(matches!(
+            message_type,
+            PayloadType::Text | PayloadType::Binary
+        ));
 
+        let encoder = &mut self.extension;
         let op_code = match message_type {
-            PayloadType::Text => OpCode::DataCode(DataCode::Text),
-            PayloadType::Binary => OpCode::DataCode(DataCode::Binary),
+            PayloadType::Text => {
+                return self
+                    .framed
+                    .buffer_message(MessageType::Text, buf, |payload, header| {
+                        extension_encode(encoder, payload, header)
+                    });
+            }
+            PayloadType::Binary => {
+                return self
+                    .framed
+                    .buffer_message(MessageType::Binary, buf, |payload, header| {
+                        extension_encode(encoder, payload, header)
+                    });
+            }
             PayloadType::Ping => {
                 if buf.len() > CONTROL_MAX_SIZE {
                     return Err(Error::with_cause(
@@ -354,18 +998,32 @@ where
             }
         };
 
-        let encoder = &mut self.extension;
         self.framed
-            .write(op_code, HeaderFlags::FIN, buf, |payload, header| {
+            .buffer(op_code, HeaderFlags::FIN, buf, |payload, header| {
                 extension_encode(encoder, payload, header)
             })
-            .await
     }
 
     /// Close this WebSocket with the reason provided.
     ///
     /// If the WebSocket is already closed then `Ok(())` is returned.
     ///
+    /// Any [`CloseCode`] that is permitted to be sent in a close frame may be used, including
+    /// library and application codes. A `CloseCode` may be converted directly into a
+    /// `CloseReason` if no description is required:
+    /// ```no_run
+    /// # use ratchet_core::{CloseCode, Error, NoExt, WebSocket};
+    /// # use tokio::net::TcpStream;
+    /// # async fn f(mut websocket: WebSocket<TcpStream, NoExt>) -> Result<(), Error> {
+    /// websocket.close(CloseCode::Policy.into()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// Returns a protocol error if the close code may not be sent to a peer (such as
+    /// [`CloseCode::Abnormal`]) or if the description is too large to fit into a control frame.
+    ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
@@ -377,33 +1035,131 @@ where
             return Ok(());
         }
 
+        reason.validate()?;
         self.close_state = CloseState::Closing;
         self.framed.write_close(reason).await
     }
 
-    /// Constructs a new WebSocket message of `message_type` and with a payload of `buf` and
-    /// chunked by `fragment_size`. If the length of the buffer is less than the chunk size then
-    /// only a single message is sent.
+    /// Close this WebSocket with the reason provided and wait for the peer to echo the close frame.
+    ///
+    /// Any messages that the peer sends before echoing the close frame are discarded. If the peer
+    /// does not echo the close frame within `timeout` then the underlying IO is shutdown and a
+    /// timeout error is returned. If the WebSocket is already closed then `Ok(())` is returned.
+    ///
+    /// This follows the closing sequence in
+    /// [RFC6455 section 7.1.1](https://datatracker.ietf.org/doc/html/rfc6455#section-7.1.1): a
+    /// server will shutdown the underlying IO once the close frame has been echoed and a client
+    /// waits for the server to do so.
     ///
     /// # Cancel safety
     ///
     /// This function is not cancellation safe. If the future is dropped before it has completed
-    /// then the connection state is undefined. It may not be possible to recover the connection due
-    /// the write operation having written only part of the buffer and the state of the write
-    /// operation has been lost.
-    pub async fn write_fragmented<A>(
+    /// then the connection state is undefined.
+    pub async fn close_and_wait(
         &mut self,
-        buf: A,
-        message_type: MessageType,
-        fragment_size: usize,
-    ) -> Result<(), Error>
-    where
-        A: AsRef<[u8]>,
+        reason: CloseReason,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        if self.is_closed() {
+            return Ok(());
+        }
+
+        self.close(reason).await?;
+
+        match tokio::time::timeout(timeout, self.await_close()).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.close_state = CloseState::Closed;
+                // We aren't interested in any IO errors produced here as the peer *may* have
+                // already closed the TCP stream.
+                let _ = self.framed.shutdown().await;
+                Err(TimeoutCause::Close.into())
+            }
+        }
+    }
+
+    /// Reads from the peer, discarding any messages, until the close frame that we sent has been
+    /// echoed.
+    async fn await_close(&mut self) -> Result<(), Error> {
+        let mut buf = BytesMut::new();
+
+        loop {
+            match self.read(&mut buf).await {
+                Ok(_) => buf.clear(),
+                Err(e) if e.is_close() && e.downcast_ref() == Some(&CloseCause::Stopped) => {
+                    break Ok(());
+                }
+                Err(e) => break Err(e),
+            }
+        }
+    }
+
+    /// Sends the next fragment of a message of `message_type` with a payload of `buf`, allowing a
+    /// large message to be produced and sent incrementally without holding all of it in memory.
+    /// The message is completed by writing a fragment with `is_final` set.
+    ///
+    /// `message_type` is only used for the first fragment of a message. If an extension has been
+    /// negotiated then each fragment is encoded as it is written, such as being compressed by
+    /// permessage-deflate. Control frames may be sent while the message is being written but any
+    /// other data messages produce an error until the message has been completed.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_fragment<A>(
+        &mut self,
+        buf: A,
+        message_type: MessageType,
+        is_final: bool,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
     {
         if !self.is_active() {
             return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
         }
 
+        self.on_write(true);
+        let encoder = &mut self.extension;
+        self.framed
+            .buffer_fragment(message_type, buf, is_final, |payload, header| {
+                extension_encode(encoder, payload, header)
+            })?;
+        self.framed.autoflush().await
+    }
+
+    /// Constructs a new WebSocket message of `message_type` and with a payload of `buf` and
+    /// chunked by `fragment_size`. If the length of the buffer is less than the chunk size then
+    /// only a single message is sent.
+    ///
+    /// # Control frames
+    /// Any ping frames that are received from the peer while the message is being sent are
+    /// answered with a pong frame between fragments. These pings are not returned by subsequent
+    /// read operations.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is not cancellation safe. If the future is dropped before it has completed
+    /// then the connection state is undefined. It may not be possible to recover the connection due
+    /// the write operation having written only part of the buffer and the state of the write
+    /// operation has been lost.
+    pub async fn write_fragmented<A>(
+        &mut self,
+        buf: A,
+        message_type: MessageType,
+        fragment_size: usize,
+    ) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        self.on_write(true);
         let encoder = &mut self.extension;
         self.framed
             .write_fragmented(buf, message_type, fragment_size, |payload, header| {
@@ -412,6 +1168,72 @@ where
             .await
     }
 
+    /// Attempt to prepare the WebSocket to write a new message, draining any previously buffered
+    /// messages until the write buffer is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the WebSocket is closing or closed or if writing the buffered messages
+    /// fails.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if !self.is_active() {
+            return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+        }
+        self.framed.poll_write_buffer(cx)
+    }
+
+    /// Attempt to write a new WebSocket message of `message_type` and with a payload of `buf`.
+    ///
+    /// This is the poll-based equivalent of [`WebSocket::write`]. The message is only encoded
+    /// once [`WebSocket::poll_ready`] would have returned `Poll::Ready`, at which point it is
+    /// placed into the write buffer and this function returns `Poll::Ready(Ok(()))`. The message
+    /// is then sent by a subsequent call to [`WebSocket::poll_flush`] or any other write
+    /// operation.
+    ///
+    /// If this function returns `Poll::Pending` then no part of `buf` has been written and the
+    /// same message should be provided in the next call.
+    pub fn poll_write<A>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: A,
+        message_type: PayloadType,
+    ) -> Poll<Result<(), Error>>
+    where
+        A: AsRef<[u8]>,
+    {
+        ready!(self.poll_ready(cx))?;
+        Poll::Ready(self.buffer_payload(buf, message_type))
+    }
+
+    /// Attempt to flush the WebSocket's output stream, registering the current task for wakeup
+    /// if the stream is not yet writable.
+    ///
+    /// This is the poll-based equivalent of [`WebSocket::flush`].
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+        }
+        self.framed.poll_flush(cx)
+    }
+
+    /// Attempt to initiate a normal closure of the WebSocket.
+    ///
+    /// On the first call a close frame is placed into the write buffer and subsequent calls flush
+    /// it to the peer. This function returns `Poll::Ready(Ok(()))` once the close frame has been
+    /// sent, or if the WebSocket has already been closed. The peer's close frame is then received
+    /// by reading from the WebSocket.
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.is_active() {
+            self.close_state = CloseState::Closing;
+            self.framed
+                .buffer_close(CloseReason::new(CloseCode::Normal, None))?;
+        }
+        if self.is_closed() {
+            return Poll::Ready(Ok(()));
+        }
+        self.framed.poll_flush(cx)
+    }
+
     /// Flushes the WebSocket's output stream, ensuring that all intermediately buffered contents
     /// reach their destination.
     ///
@@ -423,7 +1245,6 @@ where
         if self.is_closed() {
             return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
         }
-
         self.framed.flush().await
     }
 
@@ -446,11 +1267,13 @@ where
     /// In addition to this, the internal framed writer is placed into a `BiLock` so
     /// the receiver half can transparently handle control frames that may be received.
     ///
+    /// Keepalive pings and the pong and idle timeouts are driven by reading from the receiver
+    /// half, so it must continue to be read from for them to be enforced.
+    ///
     /// See: [Tokio#3200](https://github.com/tokio-rs/tokio/issues/3200) and [Tokio#40](https://github.com/tokio-rs/tls/issues/40)
     ///
     /// # Errors
     /// This function will only error if the `WebSocket` is already closed.
-    #[cfg(feature = "split")]
     pub fn split(self) -> Result<SplitSocket<S, E>, Error>
     where
         E: SplittableExtension,
@@ -462,80 +1285,174 @@ where
                 framed,
                 control_buffer,
                 extension,
+                keepalive,
+                idle,
+                rate_limiter,
                 ..
             } = self;
-            Ok(split(framed, control_buffer, extension))
+            Ok(split(
+                framed,
+                control_buffer,
+                extension,
+                keepalive,
+                idle,
+                rate_limiter,
+            ))
         }
     }
-}
 
-/// Trait for closing a WebSocket connection. This is to abstract over both owned and split
-/// WebSockets.
-pub trait WebSocketClose {
-    /// Write a WebSocket close frame. The frame *must* have the FIN flag set high and be
-    /// uncompressed.
-    fn write_close_frame(&mut self, code: CloseCode) -> BoxFuture<Result<(), Error>>;
+    /// Converts this `WebSocket` into a [`RawWebSocket`] which provides frame-level access to the
+    /// connection.
+    ///
+    /// # Note
+    /// The negotiated extension is dropped and any frames which are subsequently read or written
+    /// are not encoded or decoded by it. Any partially read message is discarded and keepalive
+    /// pings are no longer sent.
+    pub fn into_raw(self) -> RawWebSocket<S> {
+        RawWebSocket::from_framed(self.framed)
+    }
+
+    /// Splits this `WebSocket` into frame-level sender and receiver halves which expose the
+    /// opcode, flags and payload of each frame, preserving the fragmentation boundaries of the
+    /// messages that are read and written. This is equivalent to calling
+    /// [`RawWebSocket::split`] on the result of [`WebSocket::into_raw`] and the same caveats apply.
+    pub fn split_raw(self) -> (RawSender<S>, RawReceiver<S>) {
+        self.into_raw().split()
+    }
 
-    /// Shutdown the connection's underlying IO.
-    fn shutdown(&mut self) -> BoxFuture<Result<(), Error>>;
+    /// Consumes this `WebSocket`, returning the underlying stream.
+    ///
+    /// # Note
+    /// Any data that has been received but not yet read, or that has been buffered but not yet
+    /// written, is discarded and no closing handshake is performed.
+    pub fn into_inner(self) -> S {
+        self.framed.into_parts().io
+    }
 }
 
-impl<S> WebSocketClose for FramedIo<S>
-where
-    S: WebSocketStream,
-{
-    fn write_close_frame(&mut self, code: CloseCode) -> BoxFuture<Result<(), Error>> {
-        Box::pin(async move {
-            self.write(
-                OpCode::ControlCode(ControlCode::Close),
-                HeaderFlags::FIN,
-                &mut u16::from(code).to_be_bytes(),
-                |_, _| Ok(()),
-            )
-            .await
-        })
+/// Completes the pending ping that matches `payload` and any pings which were sent before it.
+fn complete_pending_pings(pending_pings: &mut Vec<PendingPing>, payload: &[u8]) {
+    if let Some(idx) = pending_pings
+        .iter()
+        .position(|ping| ping.payload.as_ref() == payload)
+    {
+        let now = Instant::now();
+        for PendingPing { sent_at, tx, .. } in pending_pings.drain(..=idx) {
+            let _ = tx.send(now.duration_since(sent_at));
+        }
     }
+}
 
-    fn shutdown(&mut self) -> BoxFuture<Result<(), Error>> {
-        Box::pin(FramedIo::shutdown(self).map_err(Into::into))
+/// Returns the close code that should be sent to the peer after a read operation fails.
+pub(crate) fn read_error_close_code(error: &Error) -> CloseCode {
+    if error.is_encoding() {
+        // rfc6455 § 7.4.1: the endpoint received data within a message that was not consistent
+        // with the type of the message.
+        CloseCode::Invalid
+    } else {
+        CloseCode::Protocol
     }
 }
 
-pub async fn close(
-    closer: &mut impl WebSocketClose,
-    is_server: bool,
+fn start_close<S>(
+    framed: &mut FramedIo<S>,
     close_state: CloseState,
     code: CloseCode,
-) -> Result<(), Error> {
+    result: Result<Fragment, Error>,
+) -> PendingRead
+where
+    S: WebSocketStream,
+{
+    // This mirrors the semantics of `close`.
+    let is_server = framed.is_server();
     match close_state {
         CloseState::NotClosed => {
             // we don't want to immediately await the echoed close frame as the peer may elect to
             // drain any pending messages **before** echoing the close frame
-
-            let _ = closer.write_close_frame(code).await;
-
-            if is_server {
+            let flush = framed.buffer_close(CloseReason::new(code, None)).is_ok();
+            PendingRead::Close {
+                flush,
                 // 7.1.1: the TCP stream should be closed first by the server
-                //
-                // We aren't interested in any IO errors produced here as the peer *may* have
-                // already closed the TCP stream.
-                let _ = closer.shutdown().await;
+                shutdown: is_server,
+                result: Some(result),
             }
+        }
+        CloseState::Closing => PendingRead::Close {
+            flush: false,
+            shutdown: is_server,
+            result: Some(result),
+        },
+        CloseState::Closed => PendingRead::Close {
+            flush: false,
+            shutdown: false,
+            result: Some(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error))),
+        },
+    }
+}
 
-            Ok(())
+impl<S, E> Stream for WebSocket<S, E>
+where
+    S: WebSocketStream,
+    E: Extension + Unpin,
+{
+    type Item = Result<OwnedMessage, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.is_closed() && matches!(this.pending_read, PendingRead::Idle) {
+            return Poll::Ready(None);
         }
-        CloseState::Closing => {
-            if is_server {
-                // 7.1.1: the TCP stream should be closed first by the server
-                //
-                // We aren't interested in any IO errors produced here as the peer *may* have
-                // already closed the TCP stream.
-                let _ = closer.shutdown().await;
+
+        match ready!(this.poll_read_owned(cx)) {
+            Ok(message) => Poll::Ready(Some(Ok(message))),
+            // The closing handshake that we initiated has completed.
+            Err(e) if e.is_close() && e.downcast_ref() == Some(&CloseCause::Stopped) => {
+                Poll::Ready(None)
             }
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+impl<S, E> Sink<OwnedMessage> for WebSocket<S, E>
+where
+    S: WebSocketStream,
+    E: Extension + Unpin,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        WebSocket::poll_ready(self.get_mut(), cx)
+    }
 
-            Err(Error::with_cause(ErrorKind::Close, CloseCause::Stopped))
+    fn start_send(self: Pin<&mut Self>, item: OwnedMessage) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        match item {
+            OwnedMessage::Text(payload) => this.buffer_owned_payload(payload, PayloadType::Text),
+            OwnedMessage::Binary(payload) => {
+                this.buffer_owned_payload(payload, PayloadType::Binary)
+            }
+            OwnedMessage::Ping(payload) => this.buffer_payload(payload, PayloadType::Ping),
+            OwnedMessage::Pong(payload) => this.buffer_payload(payload, PayloadType::Pong),
+            OwnedMessage::Close(reason) => {
+                if !this.is_active() {
+                    return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+                }
+                this.framed
+                    .buffer_close(reason.unwrap_or_else(|| CloseCode::Normal.into()))?;
+                this.close_state = CloseState::Closing;
+                Ok(())
+            }
         }
-        CloseState::Closed => Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)),
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        WebSocket::poll_flush(self.get_mut(), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        WebSocket::poll_close(self.get_mut(), cx)
     }
 }
 
@@ -556,21 +1473,35 @@ where
 mod tests {
     use crate::framed::Item;
     use crate::protocol::{ControlCode, DataCode, HeaderFlags, OpCode};
-    use crate::ws::extension_encode;
+    use crate::ws::{extension_encode, MAX_PENDING_PINGS};
     use crate::{
-        CloseCause, CloseCode, CloseReason, Error, Message, NoExt, Role, WebSocket,
-        WebSocketConfig, WebSocketStream,
+        CloseCause, CloseCode, CloseReason, Error, Fragment, Message, MessageType, NoExt,
+        OwnedMessage, PayloadType, PreparedMessage, RateLimit, RateLimitAction, RateLimitCause,
+        Role, TimeoutCause, WebSocket, WebSocketConfig, WebSocketStream,
     };
     use bytes::{Bytes, BytesMut};
-    use ratchet_ext::Extension;
+    use futures::{FutureExt, SinkExt, StreamExt};
+    use http::header::SEC_WEBSOCKET_EXTENSIONS;
+    use http::HeaderMap;
+    use ratchet_deflate::{Deflate, DeflateExtProvider};
+    use ratchet_ext::{
+        EncoderParameters, Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider,
+        FrameHeader as ExtFrameHeader, OpCode as ExtOpCode, ReservedOpCodes, ReunitableExtension,
+        RsvBits, SplittableExtension,
+    };
+    use std::convert::Infallible;
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
     use tokio::io::{duplex, DuplexStream};
+    use tokio::time::Instant;
 
     impl<S, E> WebSocket<S, E>
     where
         S: WebSocketStream,
         E: Extension,
     {
-        pub async fn write_frame<A>(
+        pub(crate) async fn write_frame<A>(
             &mut self,
             buf: A,
             opcode: OpCode,
@@ -596,7 +1527,11 @@ mod tests {
                 .await
         }
 
-        pub async fn read_frame(&mut self, read_buffer: &mut BytesMut) -> Result<Item, Error> {
+        #[allow(dead_code)]
+        pub(crate) async fn read_frame(
+            &mut self,
+            read_buffer: &mut BytesMut,
+        ) -> Result<Item, Error> {
             let WebSocket {
                 framed, extension, ..
             } = self;
@@ -901,4 +1836,1446 @@ mod tests {
         assert!(client.is_closed());
         assert!(server.is_closed());
     }
+
+    #[tokio::test]
+    async fn stream_sink() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .send(OwnedMessage::Text(Bytes::from("hello")))
+            .await
+            .expect("Send failure");
+        client
+            .send(OwnedMessage::Binary(Bytes::from(vec![1, 2, 3])))
+            .await
+            .expect("Send failure");
+
+        let message = server
+            .next()
+            .await
+            .expect("Stream ended")
+            .expect("Read failure");
+        assert_eq!(message, OwnedMessage::Text(Bytes::from("hello")));
+
+        let message = server
+            .next()
+            .await
+            .expect("Stream ended")
+            .expect("Read failure");
+        assert_eq!(message, OwnedMessage::Binary(Bytes::from(vec![1, 2, 3])));
+    }
+
+    #[tokio::test]
+    async fn stream_responds_to_ping() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .send(OwnedMessage::Ping(Bytes::from("ping!")))
+            .await
+            .expect("Send failure");
+
+        let message = server
+            .next()
+            .await
+            .expect("Stream ended")
+            .expect("Read failure");
+        assert_eq!(message, OwnedMessage::Ping(Bytes::from("ping!")));
+
+        let message = client
+            .next()
+            .await
+            .expect("Stream ended")
+            .expect("Read failure");
+        assert_eq!(message, OwnedMessage::Pong(Bytes::from("ping!")));
+    }
+
+    #[tokio::test]
+    async fn stream_ends_after_close() {
+        let (mut client, mut server) = fixture();
+
+        SinkExt::close(&mut client).await.expect("Close failure");
+        assert!(!client.is_active());
+        client
+            .send(OwnedMessage::Text(Bytes::from("hello")))
+            .await
+            .expect_err("Expected a send failure");
+
+        let message = server
+            .next()
+            .await
+            .expect("Stream ended")
+            .expect("Read failure");
+        assert_eq!(
+            message,
+            OwnedMessage::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+        );
+        assert!(server.next().await.is_none());
+
+        assert!(client.next().await.is_none());
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn read_owned() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .write_frame("123", OpCode::DataCode(DataCode::Text), false)
+            .await
+            .expect("Write failure");
+        client
+            .write_frame("data", OpCode::ControlCode(ControlCode::Ping), true)
+            .await
+            .expect("Write failure");
+        client
+            .write_frame("456", OpCode::DataCode(DataCode::Continuation), true)
+            .await
+            .expect("Write failure");
+        client.write_binary([1, 2, 3]).await.expect("Write failure");
+
+        let message = server.read_owned().await.expect("Read failure");
+        assert_eq!(message, OwnedMessage::Ping(Bytes::from("data")));
+
+        let first = server.read_owned().await.expect("Read failure");
+        assert_eq!(first, OwnedMessage::Text(Bytes::from("123456")));
+
+        let second = server.read_owned().await.expect("Read failure");
+        assert_eq!(second, OwnedMessage::Binary(Bytes::from(vec![1, 2, 3])));
+
+        // the first payload must not have been modified by the subsequent read
+        assert_eq!(first, OwnedMessage::Text(Bytes::from("123456")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_keepalive_pings() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            keepalive_interval: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        let server_task = tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            server.read(&mut buf).await
+        });
+
+        let start = Instant::now();
+        let mut buf = BytesMut::new();
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Ping(Bytes::new()));
+        assert!(start.elapsed() >= Duration::from_secs(5));
+
+        let message = server_task
+            .await
+            .expect("Server task panicked")
+            .expect("Read failure");
+        assert_eq!(message, Message::Pong(Bytes::new()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pong_timeout() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            keepalive_interval: Some(Duration::from_secs(5)),
+            pong_timeout: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+
+        let mut server = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        let start = Instant::now();
+        let mut buf = BytesMut::new();
+        let error = client.read(&mut buf).await.expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Pong)
+        );
+        assert!(start.elapsed() >= Duration::from_secs(7));
+        assert!(client.is_closed());
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Ping(Bytes::new()));
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseReason::new(CloseCode::Policy, None)))
+        );
+    }
+
+    /// Reads from `websocket` until a close frame is received, returning the messages read.
+    async fn read_until_close<S>(mut websocket: WebSocket<S, NoExt>) -> Vec<Message>
+    where
+        S: WebSocketStream,
+    {
+        let mut buf = BytesMut::new();
+        let mut messages = Vec::new();
+        loop {
+            let message = websocket.read(&mut buf).await.expect("Read failure");
+            let is_close = matches!(message, Message::Close(_));
+            messages.push(message);
+            if is_close {
+                break messages;
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            keepalive_interval: Some(Duration::from_secs(3)),
+            idle_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+        let client_task = tokio::spawn(read_until_close(client));
+
+        let start = Instant::now();
+        let mut buf = BytesMut::new();
+        while start.elapsed() < Duration::from_secs(6) {
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Pong(Bytes::new()));
+        }
+        server.write_text("activity").await.expect("Write failure");
+
+        // Keepalive pings and the pongs that answer them do not count as activity.
+        let error = loop {
+            match server.read(&mut buf).await {
+                Ok(message) => assert_eq!(message, Message::Pong(Bytes::new())),
+                Err(e) => break e,
+            }
+        };
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Idle)
+        );
+        assert!(start.elapsed() >= Duration::from_secs(16));
+        assert!(server.is_closed());
+
+        let messages = client_task.await.expect("Client task panicked");
+        assert!(messages.contains(&Message::Text));
+        assert_eq!(
+            messages.last(),
+            Some(&Message::Close(Some(CloseReason::new(
+                CloseCode::GoingAway,
+                None
+            ))))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout_counts_pings() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            keepalive_interval: Some(Duration::from_secs(3)),
+            idle_timeout: Some(Duration::from_secs(10)),
+            idle_counts_pings: true,
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+        let _client_task = tokio::spawn(read_until_close(client));
+
+        let start = Instant::now();
+        let mut buf = BytesMut::new();
+        while start.elapsed() < Duration::from_secs(30) {
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Pong(Bytes::new()));
+        }
+        assert!(server.is_active());
+    }
+
+    fn rate_limited(
+        rate_limit: RateLimit,
+    ) -> (
+        WebSocket<DuplexStream, NoExt>,
+        WebSocket<DuplexStream, NoExt>,
+    ) {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            rate_limit: Some(rate_limit),
+            ..Default::default()
+        };
+
+        let server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        (client, server)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_delay() {
+        let (mut client, mut server) = rate_limited(RateLimit {
+            messages_per_second: Some(2),
+            ..Default::default()
+        });
+        for _ in 0..4 {
+            client.write_text("message").await.expect("Write failure");
+        }
+
+        let start = Instant::now();
+        let mut buf = BytesMut::new();
+        for _ in 0..2 {
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+        }
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        for _ in 0..2 {
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+        }
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(server.is_active());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_close() {
+        let (mut client, mut server) = rate_limited(RateLimit {
+            bytes_per_second: Some(10),
+            action: RateLimitAction::Close,
+            ..Default::default()
+        });
+        client.write_binary([0; 20]).await.expect("Write failure");
+        client.write_binary([0; 1]).await.expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Binary);
+        assert_eq!(buf.len(), 20);
+
+        let error = server.read(&mut buf).await.expect_err("Expected an error");
+        assert!(error.is_rate_limit());
+        assert_eq!(
+            error.downcast_ref::<RateLimitCause>(),
+            Some(&RateLimitCause::Bytes)
+        );
+        assert!(server.is_closed());
+
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseReason::new(CloseCode::Policy, None)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_error() {
+        let (mut client, mut server) = rate_limited(RateLimit {
+            messages_per_second: Some(1),
+            action: RateLimitAction::Error,
+            ..Default::default()
+        });
+        client.write_text("first").await.expect("Write failure");
+        client.write_text("second").await.expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"first");
+
+        let error = server.read(&mut buf).await.expect_err("Expected an error");
+        assert_eq!(
+            error.downcast_ref::<RateLimitCause>(),
+            Some(&RateLimitCause::Messages)
+        );
+        assert!(server.is_active());
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"second");
+    }
+
+    #[tokio::test]
+    async fn close_and_wait() {
+        let (mut client, mut server) = fixture();
+        let reason = CloseReason::new(CloseCode::GoingAway, None);
+
+        let server_task = tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            server.read(&mut buf).await.expect("Read failure")
+        });
+
+        client.write_text("unread").await.expect("Write failure");
+        client
+            .close_and_wait(reason.clone(), Duration::from_secs(5))
+            .await
+            .expect("Close failure");
+        assert!(client.is_closed());
+
+        let message = server_task.await.expect("Server task panicked");
+        assert_eq!(message, Message::Close(Some(reason)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_and_wait_timeout() {
+        let (mut client, mut server) = fixture();
+
+        let error = server
+            .close_and_wait(
+                CloseReason::new(CloseCode::Normal, None),
+                Duration::from_secs(5),
+            )
+            .await
+            .expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Close)
+        );
+        assert!(server.is_closed());
+
+        let mut buf = BytesMut::new();
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+        );
+    }
+
+    #[tokio::test]
+    async fn close_with_code() {
+        let (mut client, mut server) = fixture();
+
+        let reason = CloseReason::new(CloseCode::Application(4001), Some("Bye".to_string()));
+        client.close(reason.clone()).await.expect("Close failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Close(Some(reason)));
+    }
+
+    #[tokio::test]
+    async fn close_with_invalid_reason() {
+        let (mut client, _server) = fixture();
+
+        for reason in [
+            CloseReason::from(CloseCode::Abnormal),
+            CloseReason::from(CloseCode::Application(1000)),
+            CloseReason::new(CloseCode::Normal, Some("a".repeat(124))),
+        ] {
+            let error = client
+                .close(reason)
+                .await
+                .expect_err("Expected a close failure");
+            assert!(error.is_protocol());
+            assert!(client.is_active());
+        }
+    }
+
+    #[tokio::test]
+    async fn ping_resolves_with_pong() {
+        let (mut client, mut server) = fixture();
+
+        let first = client.ping("first").await.expect("Ping failure");
+        let second = client.ping("second").await.expect("Ping failure");
+        let third = client.ping("third").await.expect("Ping failure");
+
+        let mut buf = BytesMut::new();
+        server.write_pong("second").await.expect("Write failure");
+
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Pong(Bytes::from("second")));
+
+        first.await.expect("Expected the first ping to resolve");
+        second.await.expect("Expected the second ping to resolve");
+
+        drop(client);
+        third.await.expect_err("Expected the third ping to fail");
+    }
+
+    #[tokio::test]
+    async fn pending_pings_are_bounded() {
+        let (mut client, mut server) = fixture();
+
+        let oldest = client.ping([]).await.expect("Ping failure");
+        let mut pongs = Vec::new();
+        for _ in 0..MAX_PENDING_PINGS {
+            pongs.push(client.ping([]).await.expect("Ping failure"));
+        }
+        assert_eq!(client.pending_pings.len(), MAX_PENDING_PINGS);
+        oldest
+            .await
+            .expect_err("Expected the oldest ping to be abandoned");
+
+        // Pings whose futures have been dropped are discarded.
+        pongs.truncate(1);
+        let newest = client.ping([]).await.expect("Ping failure");
+        assert_eq!(client.pending_pings.len(), 2);
+
+        let mut buf = BytesMut::new();
+        server.write_pong([]).await.expect("Write failure");
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Pong(Bytes::new()));
+        pongs
+            .pop()
+            .unwrap()
+            .await
+            .expect("Expected the ping to resolve");
+        assert_eq!(client.pending_pings.len(), 1);
+        drop(newest);
+    }
+
+    #[tokio::test]
+    async fn read_fragments() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .write_frame("123", OpCode::DataCode(DataCode::Text), false)
+            .await
+            .expect("Write failure");
+        client
+            .write_frame("data", OpCode::ControlCode(ControlCode::Ping), true)
+            .await
+            .expect("Write failure");
+        client
+            .write_frame("456", OpCode::DataCode(DataCode::Continuation), false)
+            .await
+            .expect("Write failure");
+        client
+            .write_frame("789", OpCode::DataCode(DataCode::Continuation), true)
+            .await
+            .expect("Write failure");
+        client.write_binary([1, 2, 3]).await.expect("Write failure");
+
+        let mut buf = BytesMut::new();
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Text { fin: false });
+        assert_eq!(buf.split().as_ref(), b"123");
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Ping(Bytes::from("data")));
+        assert!(buf.is_empty());
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Text { fin: false });
+        assert_eq!(buf.split().as_ref(), b"456");
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Text { fin: true });
+        assert_eq!(buf.split().as_ref(), b"789");
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Binary { fin: true });
+        assert_eq!(buf.split().as_ref(), &[1, 2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_cancel_safe() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .write_frame("123", OpCode::DataCode(DataCode::Text), false)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        tokio::select! {
+            _ = server.read(&mut buf) => panic!("Unexpected message"),
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+        }
+
+        client
+            .write_frame("456", OpCode::DataCode(DataCode::Continuation), true)
+            .await
+            .expect("Write failure");
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"123456");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_cancel_safe_pong() {
+        let (server, client) = duplex(64);
+        let config = WebSocketConfig::default();
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        // Fill the stream so that the pong can only be partially written.
+        server.write_binary([0; 60]).await.expect("Write failure");
+        client.write_ping([1; 40]).await.expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let result = tokio::time::timeout(Duration::from_secs(1), server.read(&mut buf)).await;
+        assert!(result.is_err());
+
+        let (server_result, client_messages) = tokio::join!(server.read(&mut buf), async {
+            let mut buf = BytesMut::new();
+            let binary = client.read(&mut buf).await.expect("Read failure");
+            let pong = client.read(&mut buf).await.expect("Read failure");
+            (binary, pong)
+        });
+
+        assert_eq!(
+            server_result.expect("Read failure"),
+            Message::Ping(Bytes::from_static(&[1; 40]))
+        );
+        assert_eq!(
+            client_messages,
+            (Message::Binary, Message::Pong(Bytes::from_static(&[1; 40])))
+        );
+    }
+
+    #[tokio::test]
+    async fn fragmented_utf8() {
+        let (mut client, mut server) = fixture();
+        let text = "€uro";
+
+        // Split the first code point across all three fragments.
+        client
+            .write_frame(
+                &text.as_bytes()[..1],
+                OpCode::DataCode(DataCode::Text),
+                false,
+            )
+            .await
+            .expect("Write failure");
+        client
+            .write_frame(
+                &text.as_bytes()[1..2],
+                OpCode::DataCode(DataCode::Continuation),
+                false,
+            )
+            .await
+            .expect("Write failure");
+        client
+            .write_frame(
+                &text.as_bytes()[2..],
+                OpCode::DataCode(DataCode::Continuation),
+                true,
+            )
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), text.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8() {
+        let (mut client, mut server) = fixture();
+
+        client
+            .write_frame([0xce, 0xba, 0xe1], OpCode::DataCode(DataCode::Text), true)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let error = server.read(&mut buf).await.expect_err("Expected an error");
+        assert!(error.is_encoding());
+
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseReason::new(CloseCode::Invalid, None)))
+        );
+    }
+
+    /// Negotiates permessage-deflate using `provider` for both peers, returning the server's and
+    /// the client's extensions.
+    fn negotiate_deflate(provider: &DeflateExtProvider) -> (Deflate, Option<Deflate>) {
+        let mut request = HeaderMap::new();
+        provider.apply_headers(&mut request);
+        let (server_ext, header) = provider
+            .negotiate_server(&request)
+            .expect("Negotiation failure")
+            .expect("Deflate was not negotiated");
+        let mut response = HeaderMap::new();
+        response.insert(SEC_WEBSOCKET_EXTENSIONS, header);
+        let client_ext = provider
+            .negotiate_client(&response)
+            .expect("Negotiation failure");
+        (server_ext, client_ext)
+    }
+
+    #[tokio::test]
+    async fn compressed_text_after_existing_data() {
+        let (server_ext, client_ext) = negotiate_deflate(&DeflateExtProvider::default());
+
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+        let mut server = WebSocket::from_upgraded(
+            config,
+            server,
+            Some(server_ext),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, client_ext, BytesMut::new(), Role::Client);
+
+        client.write_text("hello").await.expect("Write failure");
+        client
+            .write_fragmented("fragmented", MessageType::Text, 4)
+            .await
+            .expect("Write failure");
+
+        // The buffer already holds data which is neither compressed nor valid UTF-8.
+        let mut buf = BytesMut::from(&[0xff, 0xfe][..]);
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"\xff\xfehello");
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"\xff\xfehellofragmented");
+    }
+
+    #[tokio::test]
+    async fn write_owned() {
+        let (server, client) = duplex(4096);
+        let config = WebSocketConfig::default();
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, None::<NoExt>, BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, None::<NoExt>, BytesMut::new(), Role::Client);
+        let payload = Bytes::from(vec![1; 2048]);
+
+        server
+            .write_owned(payload.clone(), PayloadType::Binary)
+            .await
+            .expect("Write failure");
+        client
+            .write_owned(payload.clone(), PayloadType::Binary)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Binary);
+        assert_eq!(buf.split().freeze(), payload);
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Binary);
+        assert_eq!(buf.freeze(), payload);
+    }
+
+    /// An extension which converts the payloads of the messages that it encodes to uppercase.
+    #[derive(Debug)]
+    struct UppercaseExt;
+
+    impl Extension for UppercaseExt {
+        fn bits(&self) -> RsvBits {
+            RsvBits {
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+            }
+        }
+    }
+
+    impl ExtensionEncoder for UppercaseExt {
+        type Error = Infallible;
+
+        fn encode(
+            &mut self,
+            payload: &mut BytesMut,
+            _header: &mut ExtFrameHeader,
+        ) -> Result<(), Self::Error> {
+            payload.make_ascii_uppercase();
+            Ok(())
+        }
+
+        fn stateless_parameters(&self) -> Option<EncoderParameters> {
+            Some(EncoderParameters::new("uppercase", Vec::new()))
+        }
+    }
+
+    impl ExtensionDecoder for UppercaseExt {
+        type Error = Infallible;
+
+        fn decode(
+            &mut self,
+            _payload: &mut BytesMut,
+            _header: &mut ExtFrameHeader,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl SplittableExtension for UppercaseExt {
+        type SplitEncoder = UppercaseExt;
+        type SplitDecoder = UppercaseExt;
+
+        fn split(self) -> (Self::SplitEncoder, Self::SplitDecoder) {
+            (UppercaseExt, UppercaseExt)
+        }
+    }
+
+    impl ReunitableExtension for UppercaseExt {
+        fn reunite(_encoder: Self::SplitEncoder, _decoder: Self::SplitDecoder) -> Self {
+            UppercaseExt
+        }
+    }
+
+    #[tokio::test]
+    async fn write_uncompressed() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, None::<NoExt>, BytesMut::new(), Role::Server);
+        let mut client = WebSocket::from_upgraded(
+            config,
+            client,
+            Some(UppercaseExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        client.write_text("encoded").await.expect("Write failure");
+        client
+            .write_uncompressed("unencoded", MessageType::Text)
+            .await
+            .expect("Write failure");
+        client.write_text("encoded").await.expect("Write failure");
+
+        for expected in ["ENCODED", "unencoded", "ENCODED"] {
+            let mut buf = BytesMut::new();
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            assert_eq!(buf.as_ref(), expected.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn send_prepared() {
+        let prepared = PreparedMessage::new(MessageType::Text, "prepared");
+
+        let mut peers = Vec::new();
+        for _ in 0..2 {
+            let (mut client, mut server) = fixture();
+            server
+                .send_prepared(&prepared)
+                .await
+                .expect("Write failure");
+            client
+                .send_prepared(&prepared)
+                .await
+                .expect("Write failure");
+            peers.push((client, server));
+        }
+
+        for (client, server) in &mut peers {
+            for websocket in [client, server] {
+                let mut buf = BytesMut::new();
+                let message = websocket.read(&mut buf).await.expect("Read failure");
+                assert_eq!(message, Message::Text);
+                assert_eq!(buf.as_ref(), b"prepared");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_prepared_encoded() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let (mut sender, _receiver) = WebSocket::from_upgraded(
+            config,
+            client,
+            Some(UppercaseExt),
+            BytesMut::new(),
+            Role::Client,
+        )
+        .split()
+        .unwrap();
+
+        let prepared =
+            PreparedMessage::encoded(MessageType::Binary, "encoded", &mut UppercaseExt).unwrap();
+        assert_eq!(prepared.payload().as_ref(), b"ENCODED");
+        sender
+            .send_prepared(&prepared)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Binary);
+        assert_eq!(buf.as_ref(), b"ENCODED");
+    }
+
+    #[tokio::test]
+    async fn send_prepared_incompatible_encoder() {
+        let prepared =
+            PreparedMessage::encoded(MessageType::Binary, "encoded", &mut UppercaseExt).unwrap();
+
+        let (mut client, _server) = fixture();
+        let error = client
+            .send_prepared(&prepared)
+            .await
+            .expect_err("Expected an error");
+        assert!(error.is_extension());
+
+        // Deflate with context takeover compresses each message using those before it.
+        let provider = DeflateExtProvider::default().request_server_no_context_takeover(false);
+        let (server_ext, _client_ext) = negotiate_deflate(&provider);
+        let (server, _client) = duplex(512);
+        let (mut sender, _receiver) = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            server,
+            Some(server_ext),
+            BytesMut::new(),
+            Role::Server,
+        )
+        .split()
+        .unwrap();
+        let error = sender
+            .send_prepared(&prepared)
+            .await
+            .expect_err("Expected an error");
+        assert!(error.is_extension());
+
+        // Nor may a message be prepared using such an encoder.
+        let (mut server_ext, _client_ext) = negotiate_deflate(&provider);
+        let error = PreparedMessage::encoded(MessageType::Binary, "encoded", &mut server_ext)
+            .expect_err("Expected an error");
+        assert!(error.is_extension());
+    }
+
+    #[tokio::test]
+    async fn send_prepared_deflate() {
+        let provider = DeflateExtProvider::default();
+        let (mut prepare_ext, _client_ext) = negotiate_deflate(&provider);
+        let prepared =
+            PreparedMessage::encoded(MessageType::Text, "prepared".repeat(16), &mut prepare_ext)
+                .unwrap();
+
+        let (server_ext, client_ext) = negotiate_deflate(&provider);
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+        let mut server = WebSocket::from_upgraded(
+            config,
+            server,
+            Some(server_ext),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, client_ext, BytesMut::new(), Role::Client);
+
+        for _ in 0..2 {
+            server
+                .send_prepared(&prepared)
+                .await
+                .expect("Write failure");
+
+            let mut buf = BytesMut::new();
+            let message = client.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            assert_eq!(buf.as_ref(), "prepared".repeat(16).as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn write_fragment() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, None::<NoExt>, BytesMut::new(), Role::Server);
+        let mut client = WebSocket::from_upgraded(
+            config,
+            client,
+            Some(UppercaseExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        client
+            .write_fragment("frag", MessageType::Text, false)
+            .await
+            .expect("Write failure");
+        // Data messages may not be interleaved with the fragments of another message.
+        let error = client.write_text("text").await.unwrap_err();
+        assert!(error.is_protocol());
+        client.write_ping("ping").await.expect("Write failure");
+        client
+            .write_fragment("men", MessageType::Binary, false)
+            .await
+            .expect("Write failure");
+        client
+            .write_fragment("ted", MessageType::Binary, true)
+            .await
+            .expect("Write failure");
+        client.write_text("text").await.expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Ping(Bytes::from_static(b"ping")));
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"FRAGMENTED");
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"TEXT");
+    }
+
+    /// An extension which claims reserved opcode 3 and records the frames that it receives with it.
+    #[derive(Debug, Default)]
+    struct ReservedExt {
+        received: Arc<Mutex<Vec<BytesMut>>>,
+    }
+
+    impl Extension for ReservedExt {
+        fn bits(&self) -> RsvBits {
+            RsvBits {
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+            }
+        }
+
+        fn reserved_opcodes(&self) -> ReservedOpCodes {
+            ReservedOpCodes::empty().with(3)
+        }
+    }
+
+    impl ExtensionEncoder for ReservedExt {
+        type Error = Infallible;
+
+        fn encode(
+            &mut self,
+            _payload: &mut BytesMut,
+            _header: &mut ExtFrameHeader,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl ExtensionDecoder for ReservedExt {
+        type Error = Infallible;
+
+        fn decode(
+            &mut self,
+            payload: &mut BytesMut,
+            header: &mut ExtFrameHeader,
+        ) -> Result<(), Self::Error> {
+            if header.opcode == ExtOpCode::Reserved(3) {
+                self.received.lock().unwrap().push(payload.split());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reserved_opcodes() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig::default();
+        let extension = ReservedExt::default();
+        let received = extension.received.clone();
+
+        let mut server = WebSocket::from_upgraded(
+            config,
+            server,
+            Some(extension),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, None::<NoExt>, BytesMut::new(), Role::Client);
+
+        client
+            .write_frame("reserved", OpCode::Reserved(3), true)
+            .await
+            .expect("Write failure");
+        client.write_text("text").await.expect("Write failure");
+
+        // The reserved frame is consumed by the extension.
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"text");
+        assert_eq!(*received.lock().unwrap(), [BytesMut::from("reserved")]);
+
+        // Reserved opcodes which have not been claimed still fail the connection.
+        client
+            .write_frame("reserved", OpCode::Reserved(4), true)
+            .await
+            .expect("Write failure");
+        let error = server.read(&mut buf).await.expect_err("Expected an error");
+        assert!(error.is_protocol());
+    }
+
+    #[tokio::test]
+    async fn write_fragmented_answers_pings() {
+        let (mut client, mut server) = fixture();
+
+        server.write_ping("ping").await.expect("Write failure");
+        client
+            .write_fragmented("fragmented", MessageType::Text, 4)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Pong(Bytes::from_static(b"ping")));
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"fragmented");
+
+        // The ping has already been answered and so isn't returned by the client.
+        server.write_text("text").await.expect("Write failure");
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"text");
+    }
+
+    #[tokio::test]
+    async fn max_frame_size() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            max_frame_size: Some(4),
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            client,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Client,
+        );
+
+        client
+            .write_fragmented([1; 12], MessageType::Binary, 4)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Binary);
+        assert_eq!(buf.as_ref(), &[1; 12]);
+
+        client.write_binary([1; 5]).await.expect("Write failure");
+
+        let error = server
+            .read(&mut buf)
+            .await
+            .expect_err("Expected an overflow");
+        assert!(error.is_protocol());
+    }
+
+    #[tokio::test]
+    async fn read_fragments_overflow() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            max_message_size: 8,
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        client
+            .write_fragmented([0; 12], MessageType::Binary, 4)
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        for _ in 0..2 {
+            let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+            assert_eq!(fragment, Fragment::Binary { fin: false });
+            buf.clear();
+        }
+
+        let error = server
+            .read_fragment(&mut buf)
+            .await
+            .expect_err("Expected an overflow");
+        assert!(error.is_protocol());
+    }
+
+    #[tokio::test]
+    async fn poll_api() {
+        let (mut server, mut client) = fixture();
+
+        poll_fn(|cx| client.poll_write(cx, "hello", PayloadType::Text))
+            .await
+            .expect("Write failure");
+        poll_fn(|cx| client.poll_flush(cx))
+            .await
+            .expect("Flush failure");
+
+        let mut buf = BytesMut::new();
+        let message = poll_fn(|cx| server.poll_read_message(cx, &mut buf))
+            .await
+            .expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"hello");
+
+        poll_fn(|cx| client.poll_ready(cx))
+            .await
+            .expect("Ready failure");
+        poll_fn(|cx| client.poll_close(cx))
+            .await
+            .expect("Close failure");
+        assert!(!client.is_active());
+        assert!(poll_fn(|cx| client.poll_ready(cx)).await.is_err());
+
+        let message = poll_fn(|cx| server.poll_read_message(cx, &mut buf))
+            .await
+            .expect("Read failure");
+        assert_eq!(
+            message,
+            Message::Close(Some(CloseReason::new(CloseCode::Normal, None)))
+        );
+    }
+
+    #[tokio::test]
+    async fn autoflush_threshold() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            autoflush_threshold: Some(16),
+            ..Default::default()
+        };
+
+        let mut server = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        let mut buf = BytesMut::new();
+
+        client.write_text("a").await.expect("Write failure");
+        assert!(server.read(&mut buf).now_or_never().is_none());
+
+        client.flush().await.expect("Flush failure");
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.split().as_ref(), b"a");
+
+        for _ in 0..3 {
+            client.write_text("a").await.expect("Write failure");
+        }
+        for _ in 0..3 {
+            let message = server.read(&mut buf).await.expect("Read failure");
+            assert_eq!(message, Message::Text);
+            assert_eq!(buf.split().as_ref(), b"a");
+        }
+
+        client.write_text("a").await.expect("Write failure");
+        client.write_ping("ping").await.expect("Write failure");
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+    }
+
+    #[tokio::test]
+    async fn auto_fragment() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            auto_fragment_above: Some(4),
+            ..Default::default()
+        };
+
+        let mut server =
+            WebSocket::from_upgraded(config, server, Some(NoExt), BytesMut::new(), Role::Server);
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        client
+            .write_text("fragmented")
+            .await
+            .expect("Write failure");
+        client
+            .write_binary([1, 2, 3, 4])
+            .await
+            .expect("Write failure");
+
+        let mut buf = BytesMut::new();
+        for (payload, fin) in [("frag", false), ("ment", false), ("ed", true)] {
+            let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+            assert_eq!(fragment, Fragment::Text { fin });
+            assert_eq!(buf.split().as_ref(), payload.as_bytes());
+        }
+
+        let fragment = server.read_fragment(&mut buf).await.expect("Read failure");
+        assert_eq!(fragment, Fragment::Binary { fin: true });
+        assert_eq!(buf.split().as_ref(), &[1, 2, 3, 4]);
+
+        let (mut sender, _receiver) = client.split().expect("Split failure");
+        sender
+            .write_text("fragmented")
+            .await
+            .expect("Write failure");
+
+        let message = server.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"fragmented");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_timeout() {
+        let (server, client) = duplex(512);
+        let config = WebSocketConfig {
+            read_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+
+        let mut server = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        let mut buf = BytesMut::new();
+        let error = client.read(&mut buf).await.expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Read)
+        );
+        assert!(client.is_active());
+
+        // A message which is only partially received before the timeout is retained.
+        server
+            .write_fragment("frag", MessageType::Text, false)
+            .await
+            .expect("Write failure");
+        let error = client.read(&mut buf).await.expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+
+        server
+            .write_fragment("mented", MessageType::Text, true)
+            .await
+            .expect("Write failure");
+        let message = client.read(&mut buf).await.expect("Read failure");
+        assert_eq!(message, Message::Text);
+        assert_eq!(buf.as_ref(), b"fragmented");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_timeout() {
+        let (server, client) = duplex(64);
+        let config = WebSocketConfig {
+            write_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+
+        let mut server = WebSocket::from_upgraded(
+            WebSocketConfig::default(),
+            server,
+            Some(NoExt),
+            BytesMut::new(),
+            Role::Server,
+        );
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        // The peer isn't reading and so the message can't be written in full.
+        let payload = vec![7; 256];
+        let error = client
+            .write_binary(&payload)
+            .await
+            .expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Write)
+        );
+        assert!(client.is_active());
+
+        // The rest of the message is sent by the next flush.
+        let mut buf = BytesMut::new();
+        let (flushed, message) = tokio::join!(client.flush(), server.read(&mut buf));
+        flushed.expect("Flush failure");
+        assert_eq!(message.expect("Read failure"), Message::Binary);
+        assert_eq!(buf.as_ref(), payload.as_slice());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn write_fragmented_timeout() {
+        let (_server, client) = duplex(64);
+        let config = WebSocketConfig {
+            write_timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let mut client =
+            WebSocket::from_upgraded(config, client, Some(NoExt), BytesMut::new(), Role::Client);
+
+        // The peer isn't reading and so the fragments can't be written in full.
+        let payload = vec![7; 256];
+        let error = client
+            .write_fragmented(&payload, MessageType::Binary, 32)
+            .await
+            .expect_err("Expected a timeout");
+        assert!(error.is_timeout());
+        assert_eq!(
+            error.downcast_ref::<TimeoutCause>(),
+            Some(&TimeoutCause::Write)
+        );
+    }
 }
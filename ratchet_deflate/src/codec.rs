@@ -12,72 +12,106 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! The interface between the encoder and decoder and the compression backend. None of the types
+//! here belong to a backend, so a backend is added by implementing `BufCompress` and
+//! `BufDecompress` behind its own feature and selecting it as the `Compressor` and `Decompressor`.
+
+use crate::error::DeflateExtensionError;
 use bytes::BytesMut;
-use flate2::{
-    Compress, CompressError, Decompress, DecompressError, FlushCompress, FlushDecompress, Status,
-};
-use std::slice;
+use std::fmt::Debug;
 
-pub trait BufCompress {
-    fn buf_compress(
-        &mut self,
-        input: &[u8],
-        output: &mut BytesMut,
-        flush: FlushCompress,
-    ) -> Result<Status, CompressError>;
+#[cfg(not(feature = "zlib"))]
+compile_error!("ratchet_deflate requires a zlib backend feature to be enabled");
+
+#[cfg(feature = "zlib")]
+pub use crate::zlib::{Compressor, Decompressor};
+
+/// A compression level, on a scale from 0, for no compression, to 9, for the best compression.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Compression(u32);
+
+impl Compression {
+    /// Creates a compression level of `level`, which is typically between 0 and 9.
+    pub const fn new(level: u32) -> Compression {
+        Compression(level)
+    }
+
+    /// No compression is performed and messages are only framed by the compressor.
+    pub const fn none() -> Compression {
+        Compression(0)
+    }
+
+    /// Optimises for the speed of compression.
+    pub const fn fast() -> Compression {
+        Compression(1)
+    }
+
+    /// Optimises for the size of the compressed messages.
+    pub const fn best() -> Compression {
+        Compression(9)
+    }
+
+    /// Returns the level as an integer.
+    pub const fn level(&self) -> u32 {
+        self.0
+    }
 }
 
-pub trait BufDecompress {
-    fn buf_decompress(
-        &mut self,
-        input: &[u8],
-        output: &mut BytesMut,
-        flush: FlushDecompress,
-    ) -> Result<Status, DecompressError>;
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression(6)
+    }
 }
 
-impl BufCompress for Compress {
+/// The outcome of a call to compress or decompress a buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Progress was made and the operation may be continued.
+    Ok,
+    /// No progress could be made as there was no space left in the output buffer or, when
+    /// flushing, there was no input left.
+    BufError,
+    /// The end of the stream was reached.
+    StreamEnd,
+}
+
+/// The operations that the encoder requires from a backend's raw deflate compressor.
+pub trait BufCompress: Debug + Sized {
+    /// Creates a raw deflate compressor using an LZ77 window of `2^window_bits` bytes.
+    fn with_window_bits(level: Compression, window_bits: u8) -> Self;
+
+    /// Compresses as much of `input` into the spare capacity of `output` as possible, performing a
+    /// sync flush so that all of the compressed data is available, as is required by RFC 7692
+    /// 7.2.1.
     fn buf_compress(
         &mut self,
         input: &[u8],
         output: &mut BytesMut,
-        flush: FlushCompress,
-    ) -> Result<Status, CompressError> {
-        op_buf(input, output, self.total_out(), |input, out| {
-            let ret = self.compress(input, out, flush);
-            (ret, self.total_out())
-        })
-    }
+    ) -> Result<Status, DeflateExtensionError>;
+
+    fn set_compression_level(&mut self, level: Compression) -> Result<(), DeflateExtensionError>;
+
+    fn reset_context(&mut self);
+
+    /// The total number of bytes that have been consumed from the input.
+    fn bytes_in(&self) -> u64;
 }
 
-impl BufDecompress for Decompress {
+/// The operations that the decoder requires from a backend's raw deflate decompressor.
+pub trait BufDecompress: Debug + Sized {
+    /// Creates a raw deflate decompressor using an LZ77 window of `2^window_bits` bytes.
+    fn with_window_bits(window_bits: u8) -> Self;
+
+    /// Decompresses as much of `input` into the spare capacity of `output` as possible, performing
+    /// a sync flush.
     fn buf_decompress(
         &mut self,
         input: &[u8],
         output: &mut BytesMut,
-        flush: FlushDecompress,
-    ) -> Result<Status, DecompressError> {
-        op_buf(input, output, self.total_out(), |input, out| {
-            let ret = self.decompress(input, out, flush);
-            (ret, self.total_out())
-        })
-    }
-}
+    ) -> Result<Status, DeflateExtensionError>;
 
-// This function's body is a copy of the Compress::compress_vec and Decompress::decompress_vec
-// functions to work with a BytesMut.
-fn op_buf<Fn, E>(input: &[u8], output: &mut BytesMut, before: u64, op: Fn) -> Result<Status, E>
-where
-    Fn: FnOnce(&[u8], &mut [u8]) -> (Result<Status, E>, u64),
-{
-    let cap = output.capacity();
-    let len = output.len();
-
-    unsafe {
-        let ptr = output.as_mut_ptr().add(len);
-        let out = slice::from_raw_parts_mut(ptr, cap - len);
-        let (ret, total_out) = op(input, out);
-        output.set_len((total_out - before) as usize + len);
-        ret
-    }
+    fn reset_context(&mut self);
+
+    /// The total number of bytes that have been consumed from the input.
+    fn bytes_in(&self) -> u64;
 }
@@ -12,7 +12,6 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use flate2::{CompressError, DecompressError};
 use http::header::InvalidHeaderValue;
 use std::str::Utf8Error;
 use thiserror::Error;
@@ -49,6 +48,32 @@ impl From<DecompressError> for DeflateExtensionError {
     }
 }
 
+/// An error produced by the compression backend when deflating a message.
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct CompressError {
+    message: String,
+}
+
+impl CompressError {
+    pub(crate) fn new(message: String) -> CompressError {
+        CompressError { message }
+    }
+}
+
+/// An error produced by the compression backend when inflating a message.
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct DecompressError {
+    message: String,
+}
+
+impl DecompressError {
+    pub(crate) fn new(message: String) -> DecompressError {
+        DecompressError { message }
+    }
+}
+
 impl From<Utf8Error> for DeflateExtensionError {
     fn from(e: Utf8Error) -> Self {
         DeflateExtensionError::NegotiationError(format!(
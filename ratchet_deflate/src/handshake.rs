@@ -13,16 +13,17 @@
 // limitations under the License.
 
 use crate::error::DeflateExtensionError;
-use crate::{Deflate, DeflateConfig, WindowBits, LZ77_MAX_WINDOW_SIZE, LZ77_MIN_WINDOW_SIZE};
+use crate::{
+    Compression, Deflate, DeflateConfig, WindowBits, LZ77_MAX_WINDOW_SIZE, LZ77_MIN_WINDOW_SIZE,
+};
 use bytes::BytesMut;
-use flate2::Compression;
 use http::header::SEC_WEBSOCKET_EXTENSIONS;
 use http::{HeaderMap, HeaderValue};
 use std::fmt::Write;
 use std::str::Utf8Error;
 
 /// The WebSocket Extension Identifier as per the IANA registry.
-const EXT_IDENT: &str = "permessage-deflate";
+pub(crate) const EXT_IDENT: &str = "permessage-deflate";
 
 const SERVER_MAX_BITS: &str = "server_max_window_bits";
 const CLIENT_MAX_BITS: &str = "client_max_window_bits";
@@ -46,8 +47,10 @@ impl InitialisedDeflateConfig {
     fn from_config(config: &DeflateConfig) -> InitialisedDeflateConfig {
         InitialisedDeflateConfig {
             server_max_window_bits: config.server_max_window_bits,
-            client_max_window_bits: config.client_max_window_bits,
-            compress_reset: config.accept_no_context_takeover,
+            // Unless the client offers to limit its window size, it may use a window of up to
+            // 32,768 bytes. RFC 7692 7.1.2.2.
+            client_max_window_bits: WindowBits(LZ77_MAX_WINDOW_SIZE),
+            compress_reset: false,
             decompress_reset: false,
             compression_level: config.compression_level,
         }
@@ -83,6 +86,11 @@ impl<'c> DeflateHeaderEncoder<'c> {
             write(into, SERVER_MAX_BITS);
             write(into, "=");
             write(into, server_max_window_bits.as_str());
+        } else if *server_max_window_bits < LZ77_MAX_WINDOW_SIZE {
+            write(into, "; ");
+            write(into, SERVER_MAX_BITS);
+            write(into, "=");
+            write(into, server_max_window_bits.as_str());
         }
 
         if *request_server_no_context_takeover {
@@ -96,6 +104,7 @@ impl<'c> DeflateHeaderEncoder<'c> {
     #[inline]
     fn size_hint(&self) -> usize {
         let DeflateConfig {
+            server_max_window_bits,
             client_max_window_bits,
             request_server_no_context_takeover,
             request_client_no_context_takeover,
@@ -104,7 +113,9 @@ impl<'c> DeflateHeaderEncoder<'c> {
 
         let mut len = EXT_IDENT.len();
 
-        if *client_max_window_bits < LZ77_MAX_WINDOW_SIZE {
+        if *client_max_window_bits < LZ77_MAX_WINDOW_SIZE
+            || *server_max_window_bits < LZ77_MAX_WINDOW_SIZE
+        {
             // 4 for pairs & 2 for bits
             len += 4 + CLIENT_MAX_BITS.len() + SERVER_MAX_BITS.len() + 2;
         } else {
@@ -220,6 +231,7 @@ fn validate_request_header(
     let mut seen_client_takeover = false;
     let mut seen_server_max_bits = false;
     let mut seen_client_max_bits = false;
+    let mut client_bits_offered = false;
     let mut initialised_config = InitialisedDeflateConfig::from_config(config);
 
     for param in param_iter {
@@ -245,10 +257,13 @@ fn validate_request_header(
                     let mut window_param = param.split('=').skip(1);
                     match window_param.next() {
                         Some(window_param) => {
-                            initialised_config.server_max_window_bits = parse_window_parameter(
+                            // The server may use a smaller window than the client has offered.
+                            let offered = parse_window_parameter(
                                 window_param,
-                                config.server_max_window_bits,
+                                WindowBits(LZ77_MAX_WINDOW_SIZE),
                             )?;
+                            initialised_config.server_max_window_bits =
+                                min_window_bits(offered, config.server_max_window_bits);
                             Ok(())
                         }
                         None => {
@@ -262,21 +277,17 @@ fn validate_request_header(
             param if param.starts_with(CLIENT_MAX_BITS) => {
                 check_param(CLIENT_MAX_BITS, &mut seen_client_max_bits, || {
                     let mut window_param = param.split('=').skip(1);
-                    if let Some(window_param) = window_param.next() {
-                        // Absence of this parameter in an extension negotiation offer indicates
-                        // that the client can receive messages compressed using an LZ77 sliding
-                        // window of up to 32,768 bytes.
-                        initialised_config.client_max_window_bits =
-                            parse_window_parameter(window_param, config.client_max_window_bits)?;
-
-                        write!(
-                            response_str,
-                            "; {}={}",
-                            CLIENT_MAX_BITS,
-                            initialised_config.client_max_window_bits.as_str()
-                        )
-                        .expect("Write failure");
-                    }
+                    // Absence of a value indicates that the client supports limiting its window
+                    // size to any value that the server responds with.
+                    let offered = match window_param.next() {
+                        Some(window_param) => {
+                            client_bits_offered = true;
+                            parse_window_parameter(window_param, WindowBits(LZ77_MAX_WINDOW_SIZE))?
+                        }
+                        None => WindowBits(LZ77_MAX_WINDOW_SIZE),
+                    };
+                    initialised_config.client_max_window_bits =
+                        min_window_bits(offered, config.client_max_window_bits);
                     Ok(())
                 })?;
             }
@@ -290,6 +301,40 @@ fn validate_request_header(
         }
     }
 
+    // The server may ask the client to reset its context even if the client didn't offer to.
+    // RFC 7692 7.1.1.2.
+    if config.request_client_no_context_takeover && !seen_client_takeover {
+        initialised_config.decompress_reset = true;
+        response_str.push_str("; client_no_context_takeover");
+    }
+
+    // The response must contain the server's window size if the client offered one but the server
+    // may also choose to limit its own window size regardless. RFC 7692 7.1.2.1.
+    let server_max_window_bits = initialised_config.server_max_window_bits;
+    if seen_server_max_bits || server_max_window_bits < LZ77_MAX_WINDOW_SIZE {
+        write!(
+            response_str,
+            "; {}={}",
+            SERVER_MAX_BITS,
+            server_max_window_bits.as_str()
+        )
+        .expect("Write failure");
+    }
+
+    // The client's window size may only be limited if it has offered to do so. RFC 7692 7.1.2.2.
+    let client_max_window_bits = initialised_config.client_max_window_bits;
+    if client_bits_offered
+        || (seen_client_max_bits && client_max_window_bits < LZ77_MAX_WINDOW_SIZE)
+    {
+        write!(
+            response_str,
+            "; {}={}",
+            CLIENT_MAX_BITS,
+            client_max_window_bits.as_str()
+        )
+        .expect("Write failure");
+    }
+
     Ok((
         initialised_config,
         HeaderValue::from_str(response_str.as_str()).map_err(DeflateExtensionError::from)?,
@@ -330,7 +375,9 @@ pub fn on_response(
     let mut compress_reset = false;
     let mut decompress_reset = false;
 
-    let mut server_max_window_bits = config.server_max_window_bits;
+    // If the server does not respond with its window size then it may use a window of up to
+    // 32,768 bytes. RFC 7692 7.1.2.1.
+    let mut server_max_window_bits = WindowBits(LZ77_MAX_WINDOW_SIZE);
     let mut client_max_window_bits = config.client_max_window_bits;
     let accept_no_context_takeover = config.accept_no_context_takeover;
 
@@ -341,66 +388,73 @@ pub fn on_response(
 
     for (_name, value) in header_iter {
         let header_value = std::str::from_utf8(value.as_bytes())?;
-        let mut param_iter = header_value.split(';');
 
-        if let Some(param) = param_iter.next() {
-            if param.trim().eq_ignore_ascii_case(EXT_IDENT) {
-                check_param(EXT_IDENT, &mut seen_extension_name, || {
-                    enabled = true;
-                    Ok(())
-                })?
-            } else {
-                return Err(NegotiationErr::Failed);
-            }
-        }
+        // The server may have accepted other extensions and so any which aren't
+        // permessage-deflate are ignored.
+        for extension in header_value.split(',') {
+            let mut param_iter = extension.split(';');
 
-        for param in param_iter {
-            match param.trim().to_lowercase().as_str() {
-                n if n == SERVER_NO_TAKEOVER => {
-                    check_param(n, &mut seen_server_takeover, || {
-                        decompress_reset = true;
+            match param_iter.next() {
+                Some(param) if param.trim().eq_ignore_ascii_case(EXT_IDENT) => {
+                    check_param(EXT_IDENT, &mut seen_extension_name, || {
+                        enabled = true;
                         Ok(())
-                    })?;
+                    })?
                 }
-                n if n == CLIENT_NO_TAKEOVER => {
-                    check_param(n, &mut seen_client_takeover, || {
-                        if accept_no_context_takeover {
-                            compress_reset = true;
+                _ => continue,
+            }
+
+            for param in param_iter {
+                match param.trim().to_lowercase().as_str() {
+                    n if n == SERVER_NO_TAKEOVER => {
+                        check_param(n, &mut seen_server_takeover, || {
+                            decompress_reset = true;
                             Ok(())
-                        } else {
-                            Err(DeflateExtensionError::NegotiationError(ERR_TAKEOVER.into()).into())
-                        }
-                    })?;
-                }
-                param if param.starts_with(SERVER_MAX_BITS) => {
-                    check_param(SERVER_MAX_BITS, &mut seen_server_max_window_bits, || {
-                        let mut window_param = param.split('=').skip(1);
-                        match window_param.next() {
-                            Some(window_param) => {
-                                server_max_window_bits =
-                                    parse_window_parameter(window_param, server_max_window_bits)?;
+                        })?;
+                    }
+                    n if n == CLIENT_NO_TAKEOVER => {
+                        check_param(n, &mut seen_client_takeover, || {
+                            if accept_no_context_takeover {
+                                compress_reset = true;
                                 Ok(())
+                            } else {
+                                Err(DeflateExtensionError::NegotiationError(ERR_TAKEOVER.into())
+                                    .into())
                             }
-                            None => Err(DeflateExtensionError::InvalidMaxWindowBits.into()),
-                        }
-                    })?;
-                }
-                param if param.starts_with(CLIENT_MAX_BITS) => {
-                    check_param(CLIENT_MAX_BITS, &mut seen_client_max_window_bits, || {
-                        let mut window_param = param.split('=').skip(1);
-                        if let Some(window_param) = window_param.next() {
-                            client_max_window_bits =
-                                parse_window_parameter(window_param, client_max_window_bits)?;
-                        }
-                        Ok(())
-                    })?;
-                }
-                p => {
-                    return Err(DeflateExtensionError::NegotiationError(format!(
-                        "{}: {}",
-                        UNKNOWN_PARAM, p
-                    ))
-                    .into());
+                        })?;
+                    }
+                    param if param.starts_with(SERVER_MAX_BITS) => {
+                        check_param(SERVER_MAX_BITS, &mut seen_server_max_window_bits, || {
+                            let mut window_param = param.split('=').skip(1);
+                            match window_param.next() {
+                                Some(window_param) => {
+                                    server_max_window_bits = parse_window_parameter(
+                                        window_param,
+                                        config.server_max_window_bits,
+                                    )?;
+                                    Ok(())
+                                }
+                                None => Err(DeflateExtensionError::InvalidMaxWindowBits.into()),
+                            }
+                        })?;
+                    }
+                    param if param.starts_with(CLIENT_MAX_BITS) => {
+                        check_param(CLIENT_MAX_BITS, &mut seen_client_max_window_bits, || {
+                            let mut window_param = param.split('=').skip(1);
+                            if let Some(window_param) = window_param.next() {
+                                client_max_window_bits =
+                                    parse_window_parameter(window_param, client_max_window_bits)?;
+                            }
+                            Ok(())
+                        })?;
+                    }
+                    p => {
+                        return Err(DeflateExtensionError::NegotiationError(format!(
+                            "{}: {}",
+                            UNKNOWN_PARAM, p
+                        ))
+                        .into());
+                    }
                 }
             }
         }
@@ -450,3 +504,11 @@ fn parse_window_parameter(
         Err(_) => Err(DeflateExtensionError::InvalidMaxWindowBits.into()),
     }
 }
+
+fn min_window_bits(left: WindowBits, right: WindowBits) -> WindowBits {
+    if left.0 < right.0 {
+        left
+    } else {
+        right
+    }
+}
@@ -16,6 +16,17 @@
 //! as an extension for [Ratchet](../ratchet).
 //!
 //! See the documentation in [Ratchet](../ratchet) for more details on using this extension.
+//!
+//! # Backends
+//!
+//! Compression is performed by the zlib backend of `flate2`, which is selected by the `zlib`
+//! feature and is enabled by default. The encoder and decoder only access the backend through an
+//! internal trait, expressed in this crate's own types such as [`Compression`], and so other
+//! backends may be added behind their own features without changing the public API. No other
+//! backend is currently provided.
+//!
+//! `flate2` is only a dependency of the `zlib` feature. Building with `default-features = false`
+//! and without enabling a backend feature fails with an error naming the missing feature.
 
 #![deny(missing_docs, missing_debug_implementations)]
 
@@ -23,19 +34,18 @@ use std::cmp::Ordering;
 use std::convert::TryFrom;
 
 use bytes::BytesMut;
-pub use flate2::Compression;
-use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
 use thiserror::Error;
 
-pub use error::DeflateExtensionError;
+pub use codec::Compression;
+pub use error::{CompressError, DecompressError, DeflateExtensionError};
 use ratchet_ext::{
-    Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider, FrameHeader, HeaderMap,
-    HeaderValue, OpCode, ReunitableExtension, RsvBits, SplittableExtension,
+    EncoderParameters, Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider,
+    FrameHeader, HeaderMap, HeaderValue, OpCode, ReunitableExtension, RsvBits, SplittableExtension,
 };
 
-use crate::codec::{BufCompress, BufDecompress};
+use crate::codec::{BufCompress, BufDecompress, Compressor, Decompressor, Status};
 use crate::handshake::{
-    apply_headers, negotiate_client, negotiate_server, InitialisedDeflateConfig,
+    apply_headers, negotiate_client, negotiate_server, InitialisedDeflateConfig, EXT_IDENT,
 };
 
 #[cfg(test)]
@@ -44,6 +54,8 @@ mod tests;
 mod codec;
 mod error;
 mod handshake;
+#[cfg(feature = "zlib")]
+mod zlib;
 
 const DEFLATE_TRAILER: [u8; 4] = [0, 0, 255, 255];
 
@@ -55,22 +67,93 @@ const LZ77_MIN_WINDOW_SIZE: u8 = 8;
 /// 32,768 bytes. RFC 7692 7.1.2.1.
 const LZ77_MAX_WINDOW_SIZE: u8 = 15;
 
+/// A predicate which decides whether to compress a message from its opcode and the length of its
+/// first fragment.
+///
+/// Small messages and payloads which are already compressed may be larger once they have been
+/// compressed and compressing them is a waste of CPU time:
+/// ```
+/// use ratchet_deflate::DeflateExtProvider;
+/// use ratchet_ext::OpCode;
+///
+/// let provider = DeflateExtProvider::default()
+///     .compress_if(|opcode, len| matches!(opcode, OpCode::Text) && len > 256);
+/// ```
+pub type CompressIf = fn(OpCode, usize) -> bool;
+
 /// An [ExtensionProvider] for negotiating permessage-deflate during a WebSocket handshake.
 #[derive(Copy, Clone, Debug, Default)]
 pub struct DeflateExtProvider {
     config: DeflateConfig,
+    compress_if: Option<CompressIf>,
 }
 
 impl DeflateExtProvider {
     /// Initialise a `DeflateExtProvider` with `config`.
     pub fn with_config(config: DeflateConfig) -> DeflateExtProvider {
-        DeflateExtProvider { config }
+        DeflateExtProvider {
+            config,
+            compress_if: None,
+        }
+    }
+
+    /// Sets a predicate which decides whether to compress a message. Messages which are not
+    /// compressed are sent without RSV1 set and so the peer does not decompress them.
+    pub fn compress_if(mut self, compress_if: CompressIf) -> DeflateExtProvider {
+        self.compress_if = Some(compress_if);
+        self
     }
 
     /// Provides a reference to the configuration that this provider has been initialised with.
     pub fn config(&self) -> &DeflateConfig {
         &self.config
     }
+
+    /// Sets the maximum LZ77 sliding window size that the server may use when compressing
+    /// messages. As a client, this is offered to the server and as a server, this is the largest
+    /// window that will be used; a smaller window offered by the client is always honored.
+    pub fn server_max_window_bits(mut self, bits: WindowBits) -> DeflateExtProvider {
+        self.config.server_max_window_bits = bits;
+        self
+    }
+
+    /// Sets whether to request that the server resets its compression context between messages.
+    /// Only applicable when negotiating as a client.
+    pub fn request_server_no_context_takeover(mut self, request: bool) -> DeflateExtProvider {
+        self.config.request_server_no_context_takeover = request;
+        self
+    }
+
+    /// Sets whether to request that the client resets its compression context between messages.
+    /// As a client, this is offered to the server and as a server, this is requested in the
+    /// response even if the client did not offer it.
+    pub fn request_client_no_context_takeover(mut self, request: bool) -> DeflateExtProvider {
+        self.config.request_client_no_context_takeover = request;
+        self
+    }
+
+    /// Sets whether to accept a request from the peer to reset the compression context between
+    /// messages. If this is not accepted then a client fails the negotiation and a server does
+    /// not include `server_no_context_takeover` in its response.
+    pub fn accept_no_context_takeover(mut self, accept: bool) -> DeflateExtProvider {
+        self.config.accept_no_context_takeover = accept;
+        self
+    }
+
+    /// Sets the compression level that is used for every message unless it is overridden for a
+    /// message using [`Deflate::compress_next_with`].
+    pub fn compression_level(mut self, level: Compression) -> DeflateExtProvider {
+        self.config.compression_level = level;
+        self
+    }
+
+    /// Sets the maximum LZ77 sliding window size that the client may use when compressing
+    /// messages. As a client, this is offered to the server and as a server, this is the largest
+    /// window that a client will be asked to use if it has offered to limit its window size.
+    pub fn client_max_window_bits(mut self, bits: WindowBits) -> DeflateExtProvider {
+        self.config.client_max_window_bits = bits;
+        self
+    }
 }
 
 impl ExtensionProvider for DeflateExtProvider {
@@ -85,14 +168,22 @@ impl ExtensionProvider for DeflateExtProvider {
         &self,
         headers: &HeaderMap,
     ) -> Result<Option<Self::Extension>, Self::Error> {
-        negotiate_client(headers, &self.config)
+        let extension = negotiate_client(headers, &self.config)?;
+        Ok(extension.map(|mut extension| {
+            extension.set_compress_if(self.compress_if);
+            extension
+        }))
     }
 
     fn negotiate_server(
         &self,
         headers: &HeaderMap,
     ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
-        negotiate_server(headers, &self.config)
+        let extension = negotiate_server(headers, &self.config)?;
+        Ok(extension.map(|(mut extension, header)| {
+            extension.set_compress_if(self.compress_if);
+            (extension, header)
+        }))
     }
 }
 
@@ -189,7 +280,7 @@ impl From<WindowBits> for u8 {
 /// A permessage-deflate configuration.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct DeflateConfig {
-    /// The client's LZ77 sliding window size. Negotiated during the HTTP upgrade. In client mode,
+    /// The server's LZ77 sliding window size. Negotiated during the HTTP upgrade. In client mode,
     /// this conforms to RFC 7692 7.1.2.1. In server mode, this conforms to RFC 7692 7.1.2.2. Must
     /// be in range 8..15 inclusive.
     pub server_max_window_bits: WindowBits,
@@ -221,6 +312,35 @@ impl Default for DeflateConfig {
     }
 }
 
+/// The permessage-deflate parameters that were agreed with the peer during the handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeflateParameters {
+    /// The LZ77 sliding window size that the server compresses messages with.
+    pub server_max_window_bits: WindowBits,
+    /// The LZ77 sliding window size that the client compresses messages with.
+    pub client_max_window_bits: WindowBits,
+    /// Whether the server resets its compression context after each message.
+    pub server_no_context_takeover: bool,
+    /// Whether the client resets its compression context after each message.
+    pub client_no_context_takeover: bool,
+}
+
+impl DeflateParameters {
+    fn new(config: &InitialisedDeflateConfig, is_server: bool) -> DeflateParameters {
+        let (server_no_context_takeover, client_no_context_takeover) = if is_server {
+            (config.compress_reset, config.decompress_reset)
+        } else {
+            (config.decompress_reset, config.compress_reset)
+        };
+        DeflateParameters {
+            server_max_window_bits: config.server_max_window_bits,
+            client_max_window_bits: config.client_max_window_bits,
+            server_no_context_takeover,
+            client_no_context_takeover,
+        }
+    }
+}
+
 /// A negotiated permessage-deflate extension. Used by a WebSocket session for compressing and
 /// decompressing data.
 #[derive(Debug)]
@@ -231,16 +351,19 @@ pub struct Deflate {
 
 impl Deflate {
     fn initialise_from(config: InitialisedDeflateConfig, is_server: bool) -> Deflate {
+        let parameters = DeflateParameters::new(&config, is_server);
         if is_server {
             Deflate {
                 decoder: DeflateDecoder::new(
                     config.client_max_window_bits.0,
                     config.decompress_reset,
+                    parameters,
                 ),
                 encoder: DeflateEncoder::new(
                     config.compression_level,
                     config.server_max_window_bits.0,
                     config.compress_reset,
+                    parameters,
                 ),
             }
         } else {
@@ -248,17 +371,52 @@ impl Deflate {
                 decoder: DeflateDecoder::new(
                     config.server_max_window_bits.0,
                     config.decompress_reset,
+                    parameters,
                 ),
                 encoder: DeflateEncoder::new(
                     config.compression_level,
                     config.client_max_window_bits.0,
                     config.compress_reset,
+                    parameters,
                 ),
             }
         }
     }
 }
 
+impl Deflate {
+    /// Returns the parameters that were negotiated with the peer.
+    pub fn parameters(&self) -> DeflateParameters {
+        self.encoder.parameters
+    }
+
+    /// Returns the compression level that is used for messages which have not been compressed with
+    /// an overridden compression level.
+    pub fn compression_level(&self) -> Compression {
+        self.encoder.compression_level()
+    }
+
+    /// Sets the compression level that is used for every subsequent message.
+    pub fn set_compression_level(
+        &mut self,
+        level: Compression,
+    ) -> Result<(), DeflateExtensionError> {
+        self.encoder.set_compression_level(level)
+    }
+
+    /// Overrides the compression level for the next message that is written. Once the message has
+    /// been compressed, the connection's compression level is restored.
+    pub fn compress_next_with(&mut self, level: Compression) {
+        self.encoder.compress_next_with(level)
+    }
+
+    /// Sets a predicate which decides whether to compress a message from its opcode and the length
+    /// of its first fragment. If no predicate is set then every non-empty message is compressed.
+    pub fn set_compress_if(&mut self, compress_if: Option<CompressIf>) {
+        self.encoder.set_compress_if(compress_if)
+    }
+}
+
 impl Extension for Deflate {
     fn bits(&self) -> RsvBits {
         RsvBits {
@@ -289,24 +447,83 @@ impl ReunitableExtension for Deflate {
 /// `Deflate`.
 #[derive(Debug)]
 pub struct DeflateEncoder {
+    parameters: DeflateParameters,
     buf: BytesMut,
-    compress: Compress,
+    compress: Compressor,
+    // The negotiated LZ77 sliding window size
+    window_bits: u8,
     compress_reset: bool,
+    level: Compression,
+    // A compression level to use for the next message
+    next_level: Option<Compression>,
+    // Whether the current message is being compressed with an overridden compression level
+    level_overridden: bool,
+    compress_if: Option<CompressIf>,
+    // Whether the current message is being compressed
+    compressing: bool,
 }
 
 impl DeflateEncoder {
-    fn new(compression: Compression, mut window_size: u8, compress_reset: bool) -> DeflateEncoder {
+    fn new(
+        compression: Compression,
+        window_bits: u8,
+        compress_reset: bool,
+        parameters: DeflateParameters,
+    ) -> DeflateEncoder {
         // https://github.com/madler/zlib/blob/cacf7f1d4e3d44d871b605da3b647f07d718623f/deflate.c#L303
-        if window_size == 8 {
-            window_size = 9;
-        }
+        let window_size = if window_bits == 8 { 9 } else { window_bits };
 
         DeflateEncoder {
+            parameters,
             buf: BytesMut::default(),
-            compress: Compress::new_with_window_bits(compression, false, window_size),
+            compress: Compressor::with_window_bits(compression, window_size),
+            window_bits,
             compress_reset,
+            level: compression,
+            next_level: None,
+            level_overridden: false,
+            compress_if: None,
+            compressing: false,
         }
     }
+
+    /// Returns the parameters that were negotiated with the peer.
+    pub fn parameters(&self) -> DeflateParameters {
+        self.parameters
+    }
+
+    /// Sets a predicate which decides whether to compress a message from its opcode and the length
+    /// of its first fragment. If no predicate is set then every non-empty message is compressed.
+    pub fn set_compress_if(&mut self, compress_if: Option<CompressIf>) {
+        self.compress_if = compress_if;
+    }
+
+    /// Returns the compression level that is used for messages which have not been compressed with
+    /// an overridden compression level.
+    pub fn compression_level(&self) -> Compression {
+        self.level
+    }
+
+    /// Sets the compression level that is used for every subsequent message.
+    ///
+    /// If this is called while a fragmented message is being compressed then the level is applied
+    /// once the message has been compressed.
+    pub fn set_compression_level(
+        &mut self,
+        level: Compression,
+    ) -> Result<(), DeflateExtensionError> {
+        if !self.level_overridden && level != self.level {
+            self.compress.set_compression_level(level)?;
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    /// Overrides the compression level for the next message that is written. Once the message has
+    /// been compressed, the connection's compression level is restored.
+    pub fn compress_next_with(&mut self, level: Compression) {
+        self.next_level = Some(level);
+    }
 }
 
 impl ExtensionEncoder for Deflate {
@@ -319,6 +536,10 @@ impl ExtensionEncoder for Deflate {
     ) -> Result<(), Self::Error> {
         self.encoder.encode(payload, header)
     }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        self.encoder.stateless_parameters()
+    }
 }
 
 impl ExtensionEncoder for DeflateEncoder {
@@ -329,24 +550,48 @@ impl ExtensionEncoder for DeflateEncoder {
         payload: &mut BytesMut,
         header: &mut FrameHeader,
     ) -> Result<(), Self::Error> {
-        if payload.is_empty() {
-            return Ok(());
-        }
-
         let DeflateEncoder {
             buf,
             compress,
             compress_reset,
+            level,
+            next_level,
+            level_overridden,
+            compress_if,
+            compressing,
+            ..
         } = self;
 
+        let is_continuation = matches!(header.opcode, OpCode::Continuation);
+        if !is_continuation {
+            // An empty message doesn't need compressing but the fragments of a message must all
+            // be compressed as the fragments are decompressed together.
+            let is_empty = payload.is_empty() && header.fin;
+            *compressing = !is_empty
+                && compress_if
+                    .map(|compress_if| compress_if(header.opcode, payload.len()))
+                    .unwrap_or(true);
+
+            if let Some(next_level) = next_level.take() {
+                if *compressing && next_level != *level {
+                    compress.set_compression_level(next_level)?;
+                    *level_overridden = true;
+                }
+            }
+        }
+
+        if !*compressing {
+            return Ok(());
+        }
+
         buf.clear();
         buf.reserve(payload.len() * 2);
 
-        let before_in = compress.total_in();
+        let before_in = compress.bytes_in();
 
-        while compress.total_in() - before_in < payload.as_ref().len() as u64 {
-            let i = compress.total_in() as usize - before_in as usize;
-            match compress.buf_compress(&payload[i..], buf, FlushCompress::Sync)? {
+        while compress.bytes_in() - before_in < payload.as_ref().len() as u64 {
+            let i = compress.bytes_in() as usize - before_in as usize;
+            match compress.buf_compress(&payload[i..], buf)? {
                 Status::BufError => buf.reserve((buf.len() as f64 * 1.5) as usize),
                 Status::Ok => continue,
                 Status::StreamEnd => break,
@@ -355,52 +600,85 @@ impl ExtensionEncoder for DeflateEncoder {
 
         while !buf.ends_with(&[0, 0, 0xFF, 0xFF]) {
             buf.reserve(5);
-            match compress.buf_compress(&[], buf, FlushCompress::Sync)? {
+            match compress.buf_compress(&[], buf)? {
                 Status::Ok | Status::BufError => continue,
                 Status::StreamEnd => break,
             }
         }
 
-        buf.truncate(buf.len() - DEFLATE_TRAILER.len());
+        // The trailer is only removed from the end of the message as the decoder reassembles the
+        // fragments before decompressing them. RFC 7692 7.2.1.
+        if header.fin {
+            buf.truncate(buf.len() - DEFLATE_TRAILER.len());
+        }
         std::mem::swap(payload, buf);
 
-        if *compress_reset {
-            compress.reset();
+        if header.fin {
+            // The context may only be reset once the final fragment of a message has been
+            // compressed.
+            if *compress_reset {
+                compress.reset_context();
+            }
+            if *level_overridden {
+                compress.set_compression_level(*level)?;
+                *level_overridden = false;
+            }
         }
 
-        if !matches!(header.opcode, OpCode::Continuation) {
+        if !is_continuation {
             header.rsv1 = true;
         }
 
         Ok(())
     }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        // Without context takeover, a message only depends on the window size that it was
+        // compressed with. RFC 7692 7.1.1.
+        if self.compress_reset {
+            Some(EncoderParameters::new(EXT_IDENT, [self.window_bits]))
+        } else {
+            None
+        }
+    }
 }
 
 /// A permessage-deflate decompressor. Only producible by the `SplittableExtension` implementation
 /// on `Deflate`.
 #[derive(Debug)]
 pub struct DeflateDecoder {
+    parameters: DeflateParameters,
     buf: BytesMut,
-    decompress: Decompress,
+    decompress: Decompressor,
     decompress_reset: bool,
     // Whether we're reading a compressed message
     compressed: bool,
 }
 
 impl DeflateDecoder {
-    fn new(mut window_size: u8, decompress_reset: bool) -> DeflateDecoder {
+    fn new(
+        mut window_size: u8,
+        decompress_reset: bool,
+        parameters: DeflateParameters,
+    ) -> DeflateDecoder {
         // https://github.com/madler/zlib/blob/cacf7f1d4e3d44d871b605da3b647f07d718623f/deflate.c#L303
         if window_size == 8 {
             window_size = 9;
         }
 
         DeflateDecoder {
+            parameters,
             buf: BytesMut::default(),
-            decompress: Decompress::new_with_window_bits(false, window_size),
+            decompress: Decompressor::with_window_bits(window_size),
             decompress_reset,
             compressed: false,
         }
     }
+
+    /// Returns the parameters that were negotiated with the peer.
+    pub fn parameters(&self) -> DeflateParameters {
+        self.parameters
+    }
 }
 
 impl ExtensionDecoder for Deflate {
@@ -428,6 +706,7 @@ impl ExtensionDecoder for DeflateDecoder {
             decompress,
             decompress_reset,
             compressed,
+            ..
         } = self;
 
         match header.opcode {
@@ -437,20 +716,26 @@ impl ExtensionDecoder for DeflateDecoder {
                     return Ok(());
                 }
             }
+            OpCode::Binary | OpCode::Text => {
+                *compressed = false;
+                return Ok(());
+            }
             OpCode::Continuation if header.fin && *compressed => {}
             _ => return Ok(()),
         }
 
+        *compressed = false;
+
         payload.extend_from_slice(&DEFLATE_TRAILER);
 
         buf.clear();
         buf.reserve(payload.len() * 2);
 
-        let before_in = decompress.total_in();
+        let before_in = decompress.bytes_in();
 
-        while decompress.total_in() - before_in < payload.as_ref().len() as u64 {
-            let i = decompress.total_in() as usize - before_in as usize;
-            match decompress.buf_decompress(&payload[i..], buf, FlushDecompress::Sync)? {
+        while decompress.bytes_in() - before_in < payload.as_ref().len() as u64 {
+            let i = decompress.bytes_in() as usize - before_in as usize;
+            match decompress.buf_decompress(&payload[i..], buf)? {
                 Status::BufError => buf.reserve((buf.len() as f64 * 1.5) as usize),
                 Status::Ok => continue,
                 Status::StreamEnd => break,
@@ -460,8 +745,10 @@ impl ExtensionDecoder for DeflateDecoder {
         buf.truncate(buf.len());
         std::mem::swap(payload, buf);
 
+        // Messages are only decompressed once their final fragment has been received and so the
+        // context is reset between messages.
         if *decompress_reset {
-            decompress.reset(false);
+            decompress.reset_context();
         }
 
         header.rsv1 = true;
@@ -14,10 +14,17 @@
 
 use crate::error::DeflateExtensionError;
 use crate::handshake::{apply_headers, on_request, on_response, NegotiationErr};
-use crate::{DeflateConfig, InitialisedDeflateConfig, WindowBits};
-use flate2::Compression;
+use crate::{
+    Compression, Deflate, DeflateConfig, DeflateExtProvider, DeflateParameters,
+    InitialisedDeflateConfig, WindowBits,
+};
+use bytes::BytesMut;
 use http::header::SEC_WEBSOCKET_EXTENSIONS;
 use http::{HeaderMap, HeaderValue};
+use ratchet_ext::{
+    BoxExtension, BoxExtensionProvider, Extension, ExtensionDecoder, ExtensionEncoder,
+    ExtensionProvider, FrameHeader, OpCode, ReunitableExtension, SplittableExtension,
+};
 
 fn test_headers(config: DeflateConfig, expected: &str) {
     let mut header_map = HeaderMap::new();
@@ -339,3 +346,460 @@ fn response_no_context_takeover() {
         r => panic!("Expected an error. Got: {:?}", r),
     }
 }
+
+fn request_window_bits(
+    offer: &'static str,
+    config: DeflateConfig,
+) -> (InitialisedDeflateConfig, String) {
+    let headers =
+        HeaderMap::from_iter([(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static(offer))]);
+    match on_request(&headers, &config) {
+        Ok((config, header)) => (
+            config,
+            header
+                .to_str()
+                .expect("Malformatted header produced")
+                .to_string(),
+        ),
+        e => panic!("Expected a valid config. Got: {:?}", e),
+    }
+}
+
+#[test]
+fn request_window_bits_offered() {
+    let (config, header) = request_window_bits(
+        "permessage-deflate; server_max_window_bits=10; client_max_window_bits=12",
+        DeflateConfig::default(),
+    );
+    assert_eq!(
+        header,
+        "permessage-deflate; client_no_context_takeover; server_max_window_bits=10; client_max_window_bits=12"
+    );
+    assert_eq!(config.server_max_window_bits, WindowBits::ten());
+    assert_eq!(config.client_max_window_bits, WindowBits::twelve());
+}
+
+#[test]
+fn request_window_bits_limited_by_server() {
+    let config = DeflateConfig {
+        server_max_window_bits: WindowBits::nine(),
+        client_max_window_bits: WindowBits::eleven(),
+        ..Default::default()
+    };
+
+    // The server uses a smaller window than the client offered.
+    let (initialised, header) = request_window_bits(
+        "permessage-deflate; server_max_window_bits=12; client_max_window_bits",
+        config,
+    );
+    assert_eq!(
+        header,
+        "permessage-deflate; client_no_context_takeover; server_max_window_bits=9; client_max_window_bits=11"
+    );
+    assert_eq!(initialised.server_max_window_bits, WindowBits::nine());
+    assert_eq!(initialised.client_max_window_bits, WindowBits::eleven());
+
+    // The client did not offer to limit its window and so the server must accept any window size
+    // but may still limit its own window size.
+    let (initialised, header) = request_window_bits("permessage-deflate", config);
+    assert_eq!(
+        header,
+        "permessage-deflate; client_no_context_takeover; server_max_window_bits=9"
+    );
+    assert_eq!(initialised.server_max_window_bits, WindowBits::nine());
+    assert_eq!(initialised.client_max_window_bits, WindowBits::fifteen());
+}
+
+#[test]
+fn applies_server_window_bits() {
+    test_headers(
+        DeflateConfig {
+            server_max_window_bits: WindowBits::ten(),
+            client_max_window_bits: WindowBits::fifteen(),
+            request_server_no_context_takeover: false,
+            request_client_no_context_takeover: false,
+            accept_no_context_takeover: false,
+            compression_level: Default::default(),
+        },
+        "permessage-deflate; client_max_window_bits; server_max_window_bits=10",
+    );
+}
+
+#[test]
+fn response_window_bits() {
+    let config = DeflateConfig {
+        server_max_window_bits: WindowBits::twelve(),
+        client_max_window_bits: WindowBits::twelve(),
+        ..Default::default()
+    };
+
+    match on_response(
+        &HeaderMap::from_iter([(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static(
+                "permessage-deflate; server_max_window_bits=10; client_max_window_bits=9",
+            ),
+        )]),
+        &config,
+    ) {
+        Ok(initialised) => {
+            assert_eq!(initialised.server_max_window_bits, WindowBits::ten());
+            assert_eq!(initialised.client_max_window_bits, WindowBits::nine());
+        }
+        r => panic!("Expected a valid config. Got: {:?}", r),
+    }
+
+    // Without a server window size in the response, the server may use any window size.
+    match on_response(
+        &HeaderMap::from_iter([(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static("permessage-deflate"),
+        )]),
+        &config,
+    ) {
+        Ok(initialised) => {
+            assert_eq!(initialised.server_max_window_bits, WindowBits::fifteen());
+            assert_eq!(initialised.client_max_window_bits, WindowBits::twelve());
+        }
+        r => panic!("Expected a valid config. Got: {:?}", r),
+    }
+}
+
+#[test]
+fn negotiated_window_bits_round_trip() {
+    let provider = DeflateExtProvider::default()
+        .server_max_window_bits(WindowBits::nine())
+        .client_max_window_bits(WindowBits::ten());
+
+    let mut request_headers = HeaderMap::new();
+    provider.apply_headers(&mut request_headers);
+
+    let (mut server, response) = provider
+        .negotiate_server(&request_headers)
+        .expect("Negotiation failed")
+        .expect("No extension negotiated");
+    let response_headers = HeaderMap::from_iter([(SEC_WEBSOCKET_EXTENSIONS, response)]);
+    let mut client = provider
+        .negotiate_client(&response_headers)
+        .expect("Negotiation failed")
+        .expect("No extension negotiated");
+
+    // Both peers report the same parameters. The server requested that the client resets its
+    // context and the client requested the same from the server.
+    let expected = DeflateParameters {
+        server_max_window_bits: WindowBits::nine(),
+        client_max_window_bits: WindowBits::ten(),
+        server_no_context_takeover: true,
+        client_no_context_takeover: true,
+    };
+    assert_eq!(server.parameters(), expected);
+    assert_eq!(client.parameters(), expected);
+
+    let message = (0..4096u32)
+        .flat_map(|i| (i % 251).to_be_bytes())
+        .collect::<Vec<_>>();
+
+    round_trip(&mut client, &mut server, &message);
+    round_trip(&mut server, &mut client, &message);
+}
+
+fn round_trip(encoder: &mut Deflate, decoder: &mut Deflate, message: &[u8]) {
+    for _ in 0..2 {
+        let mut payload = BytesMut::from(message);
+        let mut header = FrameHeader {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: OpCode::Binary,
+        };
+
+        encoder.encode(&mut payload, &mut header).unwrap();
+        assert!(header.rsv1);
+        assert!(payload.len() < message.len());
+
+        decoder.decode(&mut payload, &mut header).unwrap();
+        assert_eq!(payload.as_ref(), message);
+    }
+}
+
+#[test]
+fn request_client_no_context_takeover() {
+    let (initialised, header) = request_window_bits("permessage-deflate", DeflateConfig::default());
+    assert_eq!(header, "permessage-deflate; client_no_context_takeover");
+    assert!(initialised.decompress_reset);
+    assert!(!initialised.compress_reset);
+
+    let config = DeflateConfig {
+        request_client_no_context_takeover: false,
+        ..Default::default()
+    };
+    let (initialised, header) = request_window_bits("permessage-deflate", config);
+    assert_eq!(header, "permessage-deflate");
+    assert!(!initialised.decompress_reset);
+    assert!(!initialised.compress_reset);
+}
+
+fn deflate(reset: bool, is_server: bool) -> Deflate {
+    Deflate::initialise_from(
+        InitialisedDeflateConfig {
+            server_max_window_bits: WindowBits::fifteen(),
+            client_max_window_bits: WindowBits::fifteen(),
+            compress_reset: reset,
+            decompress_reset: reset,
+            compression_level: Compression::fast(),
+        },
+        is_server,
+    )
+}
+
+fn encode_fragments(
+    encoder: &mut Deflate,
+    message: &[u8],
+    fragments: usize,
+) -> Vec<(FrameHeader, BytesMut)> {
+    let chunk_size = message.len() / fragments;
+    message
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            let mut header = FrameHeader {
+                fin: idx == fragments - 1,
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+                opcode: if idx == 0 {
+                    OpCode::Text
+                } else {
+                    OpCode::Continuation
+                },
+            };
+            let mut payload = BytesMut::from(chunk);
+            encoder.encode(&mut payload, &mut header).unwrap();
+            (header, payload)
+        })
+        .collect()
+}
+
+fn decode_fragments(decoder: &mut Deflate, fragments: Vec<(FrameHeader, BytesMut)>) -> BytesMut {
+    let mut message = BytesMut::new();
+    for (mut header, payload) in fragments {
+        message.extend_from_slice(&payload);
+        decoder.decode(&mut message, &mut header).unwrap();
+    }
+    message
+}
+
+#[test]
+fn no_context_takeover_resets_per_message() {
+    let message = "a message that is repeated. ".repeat(32);
+
+    for reset in [true, false] {
+        let mut client = deflate(reset, false);
+        let mut server = deflate(reset, true);
+
+        let first = encode_fragments(&mut client, message.as_bytes(), 4);
+        let second = encode_fragments(&mut client, message.as_bytes(), 4);
+
+        // Only the first fragment of each message has RSV1 set.
+        assert!(first[0].0.rsv1 && first[1..].iter().all(|(header, _)| !header.rsv1));
+
+        // The context is reset between messages but not between fragments so each message is
+        // compressed identically.
+        let first_payloads = first.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>();
+        let second_payloads = second.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>();
+        assert_eq!(first_payloads == second_payloads, reset);
+
+        assert_eq!(
+            decode_fragments(&mut server, first).as_ref(),
+            message.as_bytes()
+        );
+        assert_eq!(
+            decode_fragments(&mut server, second).as_ref(),
+            message.as_bytes()
+        );
+    }
+}
+
+#[test]
+fn uncompressed_fragments_after_compressed_message() {
+    let mut client = deflate(false, false);
+    let mut server = deflate(false, true);
+
+    let compressed = encode_fragments(&mut client, b"compressed message", 2);
+    assert_eq!(
+        decode_fragments(&mut server, compressed).as_ref(),
+        b"compressed message"
+    );
+
+    let uncompressed = vec![
+        (
+            FrameHeader {
+                fin: false,
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+                opcode: OpCode::Binary,
+            },
+            BytesMut::from("uncompressed "),
+        ),
+        (
+            FrameHeader {
+                fin: true,
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+                opcode: OpCode::Continuation,
+            },
+            BytesMut::from("message"),
+        ),
+    ];
+    assert_eq!(
+        decode_fragments(&mut server, uncompressed).as_ref(),
+        b"uncompressed message"
+    );
+}
+
+fn encode_message(encoder: &mut Deflate, message: &[u8]) -> (FrameHeader, BytesMut) {
+    encode_fragments(encoder, message, 1).pop().unwrap()
+}
+
+#[test]
+fn compression_level_per_message() {
+    let message = "a message that is repeated. ".repeat(32);
+    let mut client = deflate(true, false);
+    let mut server = deflate(true, true);
+
+    let (_, default) = encode_message(&mut client, message.as_bytes());
+
+    client.compress_next_with(Compression::none());
+    let (header, stored) = encode_message(&mut client, message.as_bytes());
+    assert!(header.rsv1);
+    assert!(stored.len() > message.len());
+
+    // The connection's compression level is restored after the message.
+    let (_, restored) = encode_message(&mut client, message.as_bytes());
+    assert_eq!(restored, default);
+    assert_eq!(client.compression_level(), Compression::fast());
+
+    for payload in [default, stored, restored] {
+        let fragments = vec![(
+            FrameHeader {
+                fin: true,
+                rsv1: true,
+                rsv2: false,
+                rsv3: false,
+                opcode: OpCode::Text,
+            },
+            payload,
+        )];
+        assert_eq!(
+            decode_fragments(&mut server, fragments).as_ref(),
+            message.as_bytes()
+        );
+    }
+}
+
+#[test]
+fn set_compression_level() {
+    let message = "a message that is repeated. ".repeat(32);
+    let mut client = deflate(true, false);
+
+    client.set_compression_level(Compression::none()).unwrap();
+    assert_eq!(client.compression_level(), Compression::none());
+
+    let (_, first) = encode_message(&mut client, message.as_bytes());
+    let (_, second) = encode_message(&mut client, message.as_bytes());
+    assert!(first.len() > message.len());
+    assert_eq!(first, second);
+}
+
+#[test]
+fn compress_if() {
+    let mut client = deflate(true, false);
+    let mut server = deflate(true, true);
+    client.set_compress_if(Some(|opcode, len| {
+        matches!(opcode, OpCode::Text) && len > 16
+    }));
+
+    let message = "a message that is repeated. ".repeat(32);
+
+    // The decision is made from the first fragment and applies to the whole message.
+    let fragments = encode_fragments(&mut client, message.as_bytes(), 4);
+    assert!(fragments[0].0.rsv1);
+    assert_eq!(
+        decode_fragments(&mut server, fragments).as_ref(),
+        message.as_bytes()
+    );
+
+    let fragments = encode_fragments(&mut client, b"tiny", 1);
+    assert!(!fragments[0].0.rsv1);
+    assert_eq!(fragments[0].1.as_ref(), b"tiny");
+    assert_eq!(decode_fragments(&mut server, fragments).as_ref(), b"tiny");
+
+    let mut payload = BytesMut::from(message.as_bytes());
+    let mut header = FrameHeader {
+        fin: true,
+        rsv1: false,
+        rsv2: false,
+        rsv3: false,
+        opcode: OpCode::Binary,
+    };
+    client.encode(&mut payload, &mut header).unwrap();
+    assert!(!header.rsv1);
+    assert_eq!(payload.as_ref(), message.as_bytes());
+}
+
+#[test]
+fn response_ignores_other_extensions() {
+    match on_response(
+        &HeaderMap::from_iter([(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static(
+                "x-webkit-deflate-frame, permessage-deflate; server_no_context_takeover",
+            ),
+        )]),
+        &DeflateConfig::default(),
+    ) {
+        Ok(config) => assert!(config.decompress_reset),
+        r => panic!("Expected a valid config. Got: {:?}", r),
+    }
+}
+
+#[test]
+fn boxed_provider() {
+    let provider: BoxExtensionProvider =
+        Box::new(DeflateExtProvider::default().compression_level(Compression::best()));
+
+    let mut request = HeaderMap::new();
+    provider.apply_headers(&mut request);
+
+    let (server, response) = provider.negotiate_server(&request).unwrap().unwrap();
+    let mut client = provider
+        .negotiate_client(&HeaderMap::from_iter([(
+            SEC_WEBSOCKET_EXTENSIONS,
+            response,
+        )]))
+        .unwrap()
+        .unwrap();
+    assert!(server.bits().rsv1);
+
+    let message = "a message that is repeated. ".repeat(32);
+    let mut header = FrameHeader {
+        fin: true,
+        rsv1: false,
+        rsv2: false,
+        rsv3: false,
+        opcode: OpCode::Text,
+    };
+    let mut payload = BytesMut::from(message.as_str());
+    client.encode(&mut payload, &mut header).unwrap();
+    assert!(header.rsv1);
+    assert!(payload.len() < message.len());
+
+    let (encoder, mut decoder) = server.split();
+    decoder.decode(&mut payload, &mut header).unwrap();
+    assert_eq!(payload, message.as_bytes());
+
+    BoxExtension::reunite(encoder, decoder);
+}
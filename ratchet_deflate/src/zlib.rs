@@ -0,0 +1,133 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The zlib backend, provided by `flate2`.
+
+use crate::codec::{BufCompress, BufDecompress, Compression, Status};
+use crate::error::{CompressError, DecompressError, DeflateExtensionError};
+use bytes::BytesMut;
+use flate2::{FlushCompress, FlushDecompress};
+use std::slice;
+
+/// The compressor of the zlib backend.
+pub type Compressor = flate2::Compress;
+
+/// The decompressor of the zlib backend.
+pub type Decompressor = flate2::Decompress;
+
+fn level(level: Compression) -> flate2::Compression {
+    flate2::Compression::new(level.level())
+}
+
+fn status(status: flate2::Status) -> Status {
+    match status {
+        flate2::Status::Ok => Status::Ok,
+        flate2::Status::BufError => Status::BufError,
+        flate2::Status::StreamEnd => Status::StreamEnd,
+    }
+}
+
+impl From<flate2::CompressError> for DeflateExtensionError {
+    fn from(e: flate2::CompressError) -> Self {
+        CompressError::new(e.to_string()).into()
+    }
+}
+
+impl From<flate2::DecompressError> for DeflateExtensionError {
+    fn from(e: flate2::DecompressError) -> Self {
+        DecompressError::new(e.to_string()).into()
+    }
+}
+
+impl BufCompress for flate2::Compress {
+    fn with_window_bits(compression: Compression, window_bits: u8) -> Self {
+        flate2::Compress::new_with_window_bits(level(compression), false, window_bits)
+    }
+
+    fn buf_compress(
+        &mut self,
+        input: &[u8],
+        output: &mut BytesMut,
+    ) -> Result<Status, DeflateExtensionError> {
+        let ret = op_buf(input, output, self.total_out(), |input, out| {
+            let ret = self.compress(input, out, FlushCompress::Sync);
+            (ret, self.total_out())
+        });
+        Ok(status(ret?))
+    }
+
+    fn set_compression_level(
+        &mut self,
+        compression: Compression,
+    ) -> Result<(), DeflateExtensionError> {
+        Ok(self.set_level(level(compression))?)
+    }
+
+    fn reset_context(&mut self) {
+        self.reset();
+    }
+
+    fn bytes_in(&self) -> u64 {
+        self.total_in()
+    }
+}
+
+impl BufDecompress for flate2::Decompress {
+    fn with_window_bits(window_bits: u8) -> Self {
+        flate2::Decompress::new_with_window_bits(false, window_bits)
+    }
+
+    fn buf_decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut BytesMut,
+    ) -> Result<Status, DeflateExtensionError> {
+        let ret = op_buf(input, output, self.total_out(), |input, out| {
+            let ret = self.decompress(input, out, FlushDecompress::Sync);
+            (ret, self.total_out())
+        });
+        Ok(status(ret?))
+    }
+
+    fn reset_context(&mut self) {
+        self.reset(false);
+    }
+
+    fn bytes_in(&self) -> u64 {
+        self.total_in()
+    }
+}
+
+// This function's body is a copy of the Compress::compress_vec and Decompress::decompress_vec
+// functions to work with a BytesMut.
+fn op_buf<Fn, E>(
+    input: &[u8],
+    output: &mut BytesMut,
+    before: u64,
+    op: Fn,
+) -> Result<flate2::Status, E>
+where
+    Fn: FnOnce(&[u8], &mut [u8]) -> (Result<flate2::Status, E>, u64),
+{
+    let cap = output.capacity();
+    let len = output.len();
+
+    unsafe {
+        let ptr = output.as_mut_ptr().add(len);
+        let out = slice::from_raw_parts_mut(ptr, cap - len);
+        let (ret, total_out) = op(input, out);
+        output.set_len((total_out - before) as usize + len);
+        ret
+    }
+}
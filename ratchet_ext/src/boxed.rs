@@ -0,0 +1,410 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    EncoderParameters, Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider,
+    FrameHeader, HeaderMap, HeaderValue, ReservedOpCodes, ReunitableExtension, RsvBits,
+    SplittableExtension,
+};
+use bytes::BytesMut;
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+/// An object-safe variant of [ExtensionProvider].
+///
+/// This trait is implemented for every [ExtensionProvider] whose extension is reunitable and may be
+/// sent between threads. A [BoxExtensionProvider] is itself an [ExtensionProvider], which allows
+/// for the extension that is offered or accepted to be selected at runtime while only a single
+/// instance of the handshake and connection-handling code is required:
+///
+/// ```
+/// use ratchet_ext::{BoxExtensionProvider, ExtensionProvider, ReunitableExtension};
+///
+/// fn select_provider<A, B>(use_a: bool, a: A, b: B) -> BoxExtensionProvider
+/// where
+///     A: ExtensionProvider + Send + Sync + 'static,
+///     A::Extension: ReunitableExtension + Send + Sync + 'static,
+///     B: ExtensionProvider + Send + Sync + 'static,
+///     B::Extension: ReunitableExtension + Send + Sync + 'static,
+/// {
+///     if use_a {
+///         Box::new(a)
+///     } else {
+///         Box::new(b)
+///     }
+/// }
+/// ```
+///
+/// The methods of this trait are prefixed with `dyn_` so that they do not conflict with the
+/// methods of [ExtensionProvider] when both traits are in scope.
+pub trait DynExtensionProvider {
+    /// Apply this extension's headers to a request. See [ExtensionProvider::apply_headers].
+    fn dyn_apply_headers(&self, headers: &mut HeaderMap);
+
+    /// Negotiate the headers that the server responded with. See
+    /// [ExtensionProvider::negotiate_client].
+    fn dyn_negotiate_client(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<BoxExtension>, BoxExtensionError>;
+
+    /// Negotiate the headers that a client has sent. See [ExtensionProvider::negotiate_server].
+    fn dyn_negotiate_server(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<(BoxExtension, HeaderValue)>, BoxExtensionError>;
+}
+
+impl<P> DynExtensionProvider for P
+where
+    P: ExtensionProvider,
+    P::Extension: ReunitableExtension + Send + Sync + 'static,
+{
+    fn dyn_apply_headers(&self, headers: &mut HeaderMap) {
+        self.apply_headers(headers)
+    }
+
+    fn dyn_negotiate_client(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<BoxExtension>, BoxExtensionError> {
+        self.negotiate_client(headers)
+            .map(|ext| ext.map(BoxExtension::new))
+            .map_err(BoxExtensionError::new)
+    }
+
+    fn dyn_negotiate_server(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<(BoxExtension, HeaderValue)>, BoxExtensionError> {
+        self.negotiate_server(headers)
+            .map(|ext| ext.map(|(ext, header)| (BoxExtension::new(ext), header)))
+            .map_err(BoxExtensionError::new)
+    }
+}
+
+/// A boxed [DynExtensionProvider].
+pub type BoxExtensionProvider = Box<dyn DynExtensionProvider + Send + Sync>;
+
+impl ExtensionProvider for BoxExtensionProvider {
+    type Extension = BoxExtension;
+    type Error = BoxExtensionError;
+
+    fn apply_headers(&self, headers: &mut HeaderMap) {
+        (**self).dyn_apply_headers(headers)
+    }
+
+    fn negotiate_client(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<Self::Extension>, Self::Error> {
+        (**self).dyn_negotiate_client(headers)
+    }
+
+    fn negotiate_server(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
+        (**self).dyn_negotiate_server(headers)
+    }
+}
+
+/// A type-erased error produced by a [BoxExtensionProvider] or a [BoxExtension].
+#[derive(Debug)]
+pub struct BoxExtensionError(Box<dyn Error + Send + Sync + 'static>);
+
+impl BoxExtensionError {
+    /// Box `error`.
+    pub fn new<E>(error: E) -> BoxExtensionError
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        BoxExtensionError(Box::new(error))
+    }
+
+    /// Returns a reference to the underlying error.
+    pub fn get_ref(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    /// Returns the underlying error.
+    pub fn into_inner(self) -> Box<dyn Error + Send + Sync + 'static> {
+        self.0
+    }
+}
+
+impl Display for BoxExtensionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Error for BoxExtensionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.0.source()
+    }
+}
+
+trait DynExtension: Debug + Send + Sync {
+    fn bits(&self) -> RsvBits;
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes;
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError>;
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters>;
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError>;
+
+    fn split(self: Box<Self>) -> (BoxExtensionEncoder, BoxExtensionDecoder);
+}
+
+impl<E> DynExtension for E
+where
+    E: ReunitableExtension + Send + Sync + 'static,
+{
+    fn bits(&self) -> RsvBits {
+        Extension::bits(self)
+    }
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        Extension::reserved_opcodes(self)
+    }
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError> {
+        ExtensionEncoder::encode(self, payload, header).map_err(BoxExtensionError::new)
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        ExtensionEncoder::stateless_parameters(self)
+    }
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError> {
+        ExtensionDecoder::decode(self, payload, header).map_err(BoxExtensionError::new)
+    }
+
+    fn split(self: Box<Self>) -> (BoxExtensionEncoder, BoxExtensionDecoder) {
+        let (encoder, decoder) = SplittableExtension::split(*self);
+        (
+            BoxExtensionEncoder(Box::new(EncoderHalf::<E>(encoder))),
+            BoxExtensionDecoder(Box::new(DecoderHalf::<E>(decoder))),
+        )
+    }
+}
+
+/// A type-erased extension that has been negotiated by a [BoxExtensionProvider].
+#[derive(Debug)]
+pub struct BoxExtension(Box<dyn DynExtension>);
+
+impl BoxExtension {
+    /// Box `extension`.
+    pub fn new<E>(extension: E) -> BoxExtension
+    where
+        E: ReunitableExtension + Send + Sync + 'static,
+    {
+        BoxExtension(Box::new(extension))
+    }
+}
+
+impl Extension for BoxExtension {
+    fn bits(&self) -> RsvBits {
+        self.0.bits()
+    }
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        self.0.reserved_opcodes()
+    }
+}
+
+impl ExtensionEncoder for BoxExtension {
+    type Error = BoxExtensionError;
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.0.encode(payload, header)
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        self.0.stateless_parameters()
+    }
+}
+
+impl ExtensionDecoder for BoxExtension {
+    type Error = BoxExtensionError;
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.0.decode(payload, header)
+    }
+}
+
+impl SplittableExtension for BoxExtension {
+    type SplitEncoder = BoxExtensionEncoder;
+    type SplitDecoder = BoxExtensionDecoder;
+
+    fn split(self) -> (Self::SplitEncoder, Self::SplitDecoder) {
+        self.0.split()
+    }
+}
+
+impl ReunitableExtension for BoxExtension {
+    /// # Panics
+    /// Panics if `encoder` and `decoder` were not produced by splitting the same type of
+    /// extension.
+    fn reunite(encoder: Self::SplitEncoder, decoder: Self::SplitDecoder) -> Self {
+        encoder.0.reunite(decoder)
+    }
+}
+
+trait DynEncoder: Send + Sync {
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError>;
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters>;
+
+    fn reunite(self: Box<Self>, decoder: BoxExtensionDecoder) -> BoxExtension;
+}
+
+trait DynDecoder: Send + Sync {
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError>;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+struct EncoderHalf<E: SplittableExtension>(E::SplitEncoder);
+
+struct DecoderHalf<E: SplittableExtension>(E::SplitDecoder);
+
+impl<E> DynEncoder for EncoderHalf<E>
+where
+    E: ReunitableExtension + Send + Sync + 'static,
+{
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError> {
+        self.0
+            .encode(payload, header)
+            .map_err(BoxExtensionError::new)
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        self.0.stateless_parameters()
+    }
+
+    fn reunite(self: Box<Self>, decoder: BoxExtensionDecoder) -> BoxExtension {
+        match decoder.0.into_any().downcast::<DecoderHalf<E>>() {
+            Ok(decoder) => BoxExtension::new(E::reunite(self.0, decoder.0)),
+            Err(_) => panic!("Attempted to reunite the halves of different extensions"),
+        }
+    }
+}
+
+impl<E> DynDecoder for DecoderHalf<E>
+where
+    E: ReunitableExtension + Send + Sync + 'static,
+{
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), BoxExtensionError> {
+        self.0
+            .decode(payload, header)
+            .map_err(BoxExtensionError::new)
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// The encoder half of a split [BoxExtension].
+pub struct BoxExtensionEncoder(Box<dyn DynEncoder>);
+
+impl Debug for BoxExtensionEncoder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoxExtensionEncoder")
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExtensionEncoder for BoxExtensionEncoder {
+    type Error = BoxExtensionError;
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.0.encode(payload, header)
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        self.0.stateless_parameters()
+    }
+}
+
+/// The decoder half of a split [BoxExtension].
+pub struct BoxExtensionDecoder(Box<dyn DynDecoder>);
+
+impl Debug for BoxExtensionDecoder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BoxExtensionDecoder")
+            .finish_non_exhaustive()
+    }
+}
+
+impl ExtensionDecoder for BoxExtensionDecoder {
+    type Error = BoxExtensionError;
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.0.decode(payload, header)
+    }
+}
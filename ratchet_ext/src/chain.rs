@@ -0,0 +1,514 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    EncoderParameters, Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider,
+    FrameHeader, HeaderMap, HeaderValue, ReservedOpCodes, ReunitableExtension, RsvBits,
+    SplittableExtension,
+};
+use bytes::BytesMut;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+const HEADER_ERR: &str = "Failed to produce header";
+
+/// An [ExtensionProvider] which offers two extensions and negotiates each of them independently,
+/// as per [RFC6455 § 9.1](https://datatracker.ietf.org/doc/html/rfc6455#section-9.1).
+///
+/// More than two extensions may be offered by nesting chains. The negotiated extensions are applied
+/// in the order of the chain: `left` encodes a frame before `right` and `right` decodes a frame
+/// before `left`. Extensions may not use the same reserved bits or opcodes; as a server, `right` is
+/// not negotiated if it would use a bit or an opcode that `left` uses and as a client, the
+/// negotiation fails if the server accepted both.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ExtensionChain<L, R> {
+    left: L,
+    right: R,
+}
+
+impl<L, R> ExtensionChain<L, R> {
+    /// Initialise a new chain which offers `left` followed by `right`.
+    pub fn new(left: L, right: R) -> ExtensionChain<L, R> {
+        ExtensionChain { left, right }
+    }
+}
+
+fn conflicting<L, R>(left: &L, right: &R) -> bool
+where
+    L: Extension,
+    R: Extension,
+{
+    u8::from(left.bits()) & u8::from(right.bits()) != 0
+        || u16::from(left.reserved_opcodes()) & u16::from(right.reserved_opcodes()) != 0
+}
+
+impl<L, R> ExtensionProvider for ExtensionChain<L, R>
+where
+    L: ExtensionProvider,
+    R: ExtensionProvider,
+{
+    type Extension = ChainedExtension<L::Extension, R::Extension>;
+    type Error = ChainError<L::Error, R::Error>;
+
+    fn apply_headers(&self, headers: &mut HeaderMap) {
+        // Providers may replace any existing header values and so they are applied to their own
+        // map before being appended.
+        let mut left = HeaderMap::new();
+        self.left.apply_headers(&mut left);
+        let mut right = HeaderMap::new();
+        self.right.apply_headers(&mut right);
+
+        for (name, value) in left.iter().chain(right.iter()) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    fn negotiate_client(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<Self::Extension>, Self::Error> {
+        let left = self
+            .left
+            .negotiate_client(headers)
+            .map_err(ChainError::Left)?;
+        let right = self
+            .right
+            .negotiate_client(headers)
+            .map_err(ChainError::Right)?;
+
+        match (left, right) {
+            (None, None) => Ok(None),
+            (Some(left), Some(right)) if conflicting(&left, &right) => {
+                Err(ChainError::ReservedBits)
+            }
+            (left, right) => Ok(Some(ChainedExtension { left, right })),
+        }
+    }
+
+    fn negotiate_server(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
+        let left = self
+            .left
+            .negotiate_server(headers)
+            .map_err(ChainError::Left)?;
+        let right = self
+            .right
+            .negotiate_server(headers)
+            .map_err(ChainError::Right)?;
+
+        let (left, right, header) = match (left, right) {
+            (None, None) => return Ok(None),
+            (Some((left, header)), None) => (Some(left), None, header),
+            (None, Some((right, header))) => (None, Some(right), header),
+            (Some((left, header)), Some((right, _))) if conflicting(&left, &right) => {
+                (Some(left), None, header)
+            }
+            (Some((left, left_header)), Some((right, right_header))) => {
+                let mut value = BytesMut::from(left_header.as_bytes());
+                value.extend_from_slice(b", ");
+                value.extend_from_slice(right_header.as_bytes());
+                let header = HeaderValue::from_maybe_shared(value.freeze()).expect(HEADER_ERR);
+                (Some(left), Some(right), header)
+            }
+        };
+
+        Ok(Some((ChainedExtension { left, right }, header)))
+    }
+}
+
+/// The extensions that were negotiated by an [ExtensionChain]. At least one of the extensions will
+/// have been negotiated.
+#[derive(Debug)]
+pub struct ChainedExtension<L, R> {
+    left: Option<L>,
+    right: Option<R>,
+}
+
+impl<L, R> ChainedExtension<L, R> {
+    /// Returns a reference to the left extension, if it was negotiated.
+    pub fn left(&self) -> Option<&L> {
+        self.left.as_ref()
+    }
+
+    /// Returns a mutable reference to the left extension, if it was negotiated.
+    pub fn left_mut(&mut self) -> Option<&mut L> {
+        self.left.as_mut()
+    }
+
+    /// Returns a reference to the right extension, if it was negotiated.
+    pub fn right(&self) -> Option<&R> {
+        self.right.as_ref()
+    }
+
+    /// Returns a mutable reference to the right extension, if it was negotiated.
+    pub fn right_mut(&mut self) -> Option<&mut R> {
+        self.right.as_mut()
+    }
+}
+
+impl<L, R> Extension for ChainedExtension<L, R>
+where
+    L: Extension,
+    R: Extension,
+{
+    fn bits(&self) -> RsvBits {
+        let left = self.left.bits();
+        let right = self.right.bits();
+        RsvBits {
+            rsv1: left.rsv1 || right.rsv1,
+            rsv2: left.rsv2 || right.rsv2,
+            rsv3: left.rsv3 || right.rsv3,
+        }
+    }
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        self.left
+            .reserved_opcodes()
+            .union(self.right.reserved_opcodes())
+    }
+}
+
+impl<L, R> ExtensionEncoder for ChainedExtension<L, R>
+where
+    L: ExtensionEncoder,
+    R: ExtensionEncoder,
+{
+    type Error = ChainError<L::Error, R::Error>;
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.left
+            .encode(payload, header)
+            .map_err(ChainError::Left)?;
+        self.right
+            .encode(payload, header)
+            .map_err(ChainError::Right)
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        let left = self.left.stateless_parameters()?;
+        let right = self.right.stateless_parameters()?;
+        Some(left.chain(right))
+    }
+}
+
+impl<L, R> ExtensionDecoder for ChainedExtension<L, R>
+where
+    L: ExtensionDecoder,
+    R: ExtensionDecoder,
+{
+    type Error = ChainError<L::Error, R::Error>;
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        self.right
+            .decode(payload, header)
+            .map_err(ChainError::Right)?;
+        self.left.decode(payload, header).map_err(ChainError::Left)
+    }
+}
+
+impl<L, R> SplittableExtension for ChainedExtension<L, R>
+where
+    L: SplittableExtension,
+    R: SplittableExtension,
+{
+    type SplitEncoder = ChainedExtension<L::SplitEncoder, R::SplitEncoder>;
+    type SplitDecoder = ChainedExtension<L::SplitDecoder, R::SplitDecoder>;
+
+    fn split(self) -> (Self::SplitEncoder, Self::SplitDecoder) {
+        let ChainedExtension { left, right } = self;
+        let (left_encoder, left_decoder) = left.split();
+        let (right_encoder, right_decoder) = right.split();
+        (
+            ChainedExtension {
+                left: left_encoder,
+                right: right_encoder,
+            },
+            ChainedExtension {
+                left: left_decoder,
+                right: right_decoder,
+            },
+        )
+    }
+}
+
+impl<L, R> ReunitableExtension for ChainedExtension<L, R>
+where
+    L: ReunitableExtension,
+    R: ReunitableExtension,
+{
+    fn reunite(encoder: Self::SplitEncoder, decoder: Self::SplitDecoder) -> Self {
+        ChainedExtension {
+            left: Option::<L>::reunite(encoder.left, decoder.left),
+            right: Option::<R>::reunite(encoder.right, decoder.right),
+        }
+    }
+}
+
+/// An error produced by an [ExtensionChain] or a [ChainedExtension].
+#[derive(Debug)]
+pub enum ChainError<L, R> {
+    /// An error produced by the left extension.
+    Left(L),
+    /// An error produced by the right extension.
+    Right(R),
+    /// The peer negotiated extensions which use the same reserved bits or opcodes.
+    ReservedBits,
+}
+
+impl<L, R> Display for ChainError<L, R>
+where
+    L: Display,
+    R: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainError::Left(e) => e.fmt(f),
+            ChainError::Right(e) => e.fmt(f),
+            ChainError::ReservedBits => {
+                write!(
+                    f,
+                    "Negotiated extensions use the same reserved bits or opcodes"
+                )
+            }
+        }
+    }
+}
+
+impl<L, R> Error for ChainError<L, R>
+where
+    L: Error + 'static,
+    R: Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChainError::Left(e) => Some(e),
+            ChainError::Right(e) => Some(e),
+            ChainError::ReservedBits => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChainError, ExtensionChain};
+    use crate::{
+        Extension, ExtensionDecoder, ExtensionEncoder, ExtensionProvider, FrameHeader, HeaderMap,
+        HeaderValue, OpCode, RsvBits,
+    };
+    use bytes::BytesMut;
+    use http::header::SEC_WEBSOCKET_EXTENSIONS;
+    use std::convert::Infallible;
+
+    /// A provider for an extension that is negotiated if its name is in the extension headers
+    /// and which appends its name to the payloads that it encodes.
+    #[derive(Debug)]
+    struct MockProvider {
+        name: &'static str,
+        bits: u8,
+    }
+
+    #[derive(Debug)]
+    struct MockExtension {
+        name: &'static str,
+        bits: u8,
+    }
+
+    fn offered(headers: &HeaderMap, name: &str) -> bool {
+        headers
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap().split(','))
+            .any(|extension| extension.trim() == name)
+    }
+
+    impl ExtensionProvider for MockProvider {
+        type Extension = MockExtension;
+        type Error = Infallible;
+
+        fn apply_headers(&self, headers: &mut HeaderMap) {
+            headers.insert(
+                SEC_WEBSOCKET_EXTENSIONS,
+                HeaderValue::from_static(self.name),
+            );
+        }
+
+        fn negotiate_client(
+            &self,
+            headers: &HeaderMap,
+        ) -> Result<Option<Self::Extension>, Self::Error> {
+            Ok(offered(headers, self.name).then_some(MockExtension {
+                name: self.name,
+                bits: self.bits,
+            }))
+        }
+
+        fn negotiate_server(
+            &self,
+            headers: &HeaderMap,
+        ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
+            Ok(self
+                .negotiate_client(headers)?
+                .map(|ext| (ext, HeaderValue::from_static(self.name))))
+        }
+    }
+
+    impl Extension for MockExtension {
+        fn bits(&self) -> RsvBits {
+            RsvBits {
+                rsv1: self.bits & 0b100 != 0,
+                rsv2: self.bits & 0b010 != 0,
+                rsv3: self.bits & 0b001 != 0,
+            }
+        }
+    }
+
+    impl ExtensionEncoder for MockExtension {
+        type Error = Infallible;
+
+        fn encode(
+            &mut self,
+            payload: &mut BytesMut,
+            _header: &mut FrameHeader,
+        ) -> Result<(), Self::Error> {
+            payload.extend_from_slice(self.name.as_bytes());
+            Ok(())
+        }
+    }
+
+    impl ExtensionDecoder for MockExtension {
+        type Error = Infallible;
+
+        fn decode(
+            &mut self,
+            payload: &mut BytesMut,
+            _header: &mut FrameHeader,
+        ) -> Result<(), Self::Error> {
+            assert!(payload.ends_with(self.name.as_bytes()));
+            payload.truncate(payload.len() - self.name.len());
+            Ok(())
+        }
+    }
+
+    fn chain(left_bits: u8, right_bits: u8) -> ExtensionChain<MockProvider, MockProvider> {
+        ExtensionChain::new(
+            MockProvider {
+                name: "left",
+                bits: left_bits,
+            },
+            MockProvider {
+                name: "right",
+                bits: right_bits,
+            },
+        )
+    }
+
+    fn extension_headers(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(SEC_WEBSOCKET_EXTENSIONS, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn applies_all_headers() {
+        let mut headers = HeaderMap::new();
+        chain(0b100, 0b010).apply_headers(&mut headers);
+
+        let values = headers
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["left", "right"]);
+    }
+
+    #[test]
+    fn negotiates_independently() {
+        let provider = chain(0b100, 0b010);
+
+        let (extension, header) = provider
+            .negotiate_server(&extension_headers("right, left"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header, "left, right");
+        assert_eq!(u8::from(extension.bits()), 0b0110_0000);
+
+        let (extension, header) = provider
+            .negotiate_server(&extension_headers("right"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header, "right");
+        assert!(extension.left().is_none());
+        assert!(extension.right().is_some());
+
+        assert!(provider
+            .negotiate_server(&extension_headers("other"))
+            .unwrap()
+            .is_none());
+
+        let extension = provider
+            .negotiate_client(&extension_headers("left"))
+            .unwrap()
+            .unwrap();
+        assert!(extension.left().is_some());
+        assert!(extension.right().is_none());
+    }
+
+    #[test]
+    fn overlapping_bits() {
+        let provider = chain(0b100, 0b110);
+
+        let (extension, header) = provider
+            .negotiate_server(&extension_headers("left, right"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(header, "left");
+        assert!(extension.right().is_none());
+
+        assert!(matches!(
+            provider.negotiate_client(&extension_headers("left, right")),
+            Err(ChainError::ReservedBits)
+        ));
+    }
+
+    #[test]
+    fn encodes_in_order() {
+        let (mut extension, _) = chain(0b100, 0b010)
+            .negotiate_server(&extension_headers("left, right"))
+            .unwrap()
+            .unwrap();
+
+        let mut header = FrameHeader {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: OpCode::Text,
+        };
+        let mut payload = BytesMut::from("payload ");
+
+        extension.encode(&mut payload, &mut header).unwrap();
+        assert_eq!(payload.as_ref(), b"payload leftright");
+
+        // Decoding is performed in the reverse order.
+        extension.decode(&mut payload, &mut header).unwrap();
+        assert_eq!(payload.as_ref(), b"payload ");
+    }
+}
@@ -27,6 +27,18 @@
 //! implement the `SplittableExtension` trait and if it is to be reunited then it must implement the
 //! `ReunitableExtension`. This allows more fine-grained control over the BiLock within the
 //! receiver.
+//!
+//! # Chaining extensions
+//! Multiple extensions may be offered during a handshake by chaining their providers using an
+//! [ExtensionChain]. Each extension is negotiated independently and the negotiated extensions are
+//! applied to frames in the order of the chain.
+//!
+//! # Selecting an extension at runtime
+//! [ExtensionProvider] is implemented for `Option<E>` and [Either], which allows for an extension
+//! to be toggled or selected from a known set at runtime. Alternatively, any provider whose
+//! extension is reunitable may be boxed into a [BoxExtensionProvider], which negotiates a
+//! type-erased [BoxExtension]. This allows for the extension to be chosen from a configuration
+//! without the rest of the connection-handling code being generic over it.
 
 #![deny(
     missing_docs,
@@ -35,9 +47,18 @@
     unused_import_braces
 )]
 
+pub use boxed::{
+    BoxExtension, BoxExtensionDecoder, BoxExtensionEncoder, BoxExtensionError,
+    BoxExtensionProvider, DynExtensionProvider,
+};
+pub use chain::{ChainError, ChainedExtension, ExtensionChain};
+pub use either::Either;
 pub use http::{HeaderMap, HeaderValue};
 pub use httparse::Header;
 
+mod boxed;
+mod chain;
+
 use bytes::BytesMut;
 use std::error::Error;
 use std::fmt::Debug;
@@ -85,7 +106,7 @@ pub trait ExtensionProvider {
     ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error>;
 }
 
-impl<'r, E> ExtensionProvider for &'r mut E
+impl<E> ExtensionProvider for &mut E
 where
     E: ExtensionProvider,
 {
@@ -111,7 +132,7 @@ where
     }
 }
 
-impl<'r, E> ExtensionProvider for &'r E
+impl<E> ExtensionProvider for &E
 where
     E: ExtensionProvider,
 {
@@ -171,6 +192,54 @@ where
     }
 }
 
+impl<L, R> ExtensionProvider for Either<L, R>
+where
+    L: ExtensionProvider,
+    R: ExtensionProvider,
+{
+    type Extension = Either<L::Extension, R::Extension>;
+    type Error = Either<L::Error, R::Error>;
+
+    fn apply_headers(&self, headers: &mut HeaderMap) {
+        match self {
+            Either::Left(provider) => provider.apply_headers(headers),
+            Either::Right(provider) => provider.apply_headers(headers),
+        }
+    }
+
+    fn negotiate_client(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<Self::Extension>, Self::Error> {
+        match self {
+            Either::Left(provider) => provider
+                .negotiate_client(headers)
+                .map(|ext| ext.map(Either::Left))
+                .map_err(Either::Left),
+            Either::Right(provider) => provider
+                .negotiate_client(headers)
+                .map(|ext| ext.map(Either::Right))
+                .map_err(Either::Right),
+        }
+    }
+
+    fn negotiate_server(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<(Self::Extension, HeaderValue)>, Self::Error> {
+        match self {
+            Either::Left(provider) => provider
+                .negotiate_server(headers)
+                .map(|ext| ext.map(|(ext, header)| (Either::Left(ext), header)))
+                .map_err(Either::Left),
+            Either::Right(provider) => provider
+                .negotiate_server(headers)
+                .map(|ext| ext.map(|(ext, header)| (Either::Right(ext), header)))
+                .map_err(Either::Right),
+        }
+    }
+}
+
 /// A data code for a frame.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum OpCode {
@@ -180,6 +249,9 @@ pub enum OpCode {
     Text,
     /// The message is binary.
     Binary,
+    /// The frame has a reserved opcode which the extension has claimed using
+    /// [Extension::reserved_opcodes].
+    Reserved(u8),
 }
 
 impl OpCode {
@@ -197,13 +269,19 @@ impl OpCode {
     pub fn is_binary(&self) -> bool {
         matches!(self, OpCode::Binary)
     }
+
+    /// Returns whether this `OpCode` is reserved.
+    pub fn is_reserved(&self) -> bool {
+        matches!(self, OpCode::Reserved(_))
+    }
 }
 
 /// A frame's header.
 ///
-/// This is passed to both `ExtensionEncoder::encode` and `ExtensionDecoder::decode` when a frame
-/// has been received. Changes to the reserved bits on a decode call will be sent to the peer.
-/// Any other changes or changes made when decoding will have no effect.
+/// This is passed to `ExtensionEncoder::encode` when a frame is about to be written and to
+/// `ExtensionDecoder::decode` when a frame has been received. The reserved bits are those of the
+/// individual frame and changes made to them by an encoder are written to the frame that is sent to
+/// the peer. Any other changes or changes made when decoding will have no effect.
 #[derive(Debug, PartialEq, Eq)]
 pub struct FrameHeader {
     /// Whether this is the final frame.
@@ -243,10 +321,93 @@ impl From<RsvBits> for u8 {
     }
 }
 
+/// A set of reserved opcodes.
+///
+/// Only the opcodes that RFC6455 reserves for further non-control frames (`3..=7`) and for further
+/// control frames (`11..=15`) may be contained in the set.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ReservedOpCodes(u16);
+
+impl ReservedOpCodes {
+    const RESERVED: u16 = 0b1111_1000_1111_1000;
+
+    /// Returns an empty set.
+    pub const fn empty() -> ReservedOpCodes {
+        ReservedOpCodes(0)
+    }
+
+    /// Returns this set with `opcode` added to it.
+    ///
+    /// # Panics
+    /// Panics if `opcode` is not a reserved opcode.
+    pub const fn with(self, opcode: u8) -> ReservedOpCodes {
+        if opcode > 15 || Self::RESERVED & 1 << opcode == 0 {
+            panic!("Not a reserved opcode");
+        }
+        ReservedOpCodes(self.0 | 1 << opcode)
+    }
+
+    /// Returns the union of this set and `other`.
+    pub const fn union(self, other: ReservedOpCodes) -> ReservedOpCodes {
+        ReservedOpCodes(self.0 | other.0)
+    }
+
+    /// Returns whether `opcode` is in this set.
+    pub const fn contains(&self, opcode: u8) -> bool {
+        opcode <= 15 && self.0 & 1 << opcode != 0
+    }
+
+    /// Returns whether this set is empty.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<ReservedOpCodes> for u16 {
+    fn from(opcodes: ReservedOpCodes) -> Self {
+        opcodes.0
+    }
+}
+
+/// The parameters that an [ExtensionEncoder] encodes every message with when it encodes each
+/// message independently of those before it. A message which has been encoded by one such encoder
+/// may be sent by any other encoder which returns equal parameters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EncoderParameters(Vec<(&'static str, Vec<u8>)>);
+
+impl EncoderParameters {
+    /// Returns the parameters of the extension named `extension` which encodes messages using
+    /// `parameters`. The parameters must include everything that determines whether a peer is able
+    /// to decode a message, such as a negotiated window size.
+    pub fn new<P>(extension: &'static str, parameters: P) -> EncoderParameters
+    where
+        P: Into<Vec<u8>>,
+    {
+        EncoderParameters(vec![(extension, parameters.into())])
+    }
+
+    /// Returns the parameters of an encoder which encodes messages using the encoder of these
+    /// parameters followed by the encoder of `next`.
+    pub fn chain(mut self, next: EncoderParameters) -> EncoderParameters {
+        self.0.extend(next.0);
+        self
+    }
+}
+
 /// A negotiated WebSocket extension.
 pub trait Extension: ExtensionEncoder + ExtensionDecoder + Debug {
     /// Returns the reserved bits that this extension *may* set high during a session.
     fn bits(&self) -> RsvBits;
+
+    /// Returns the reserved opcodes that this extension handles.
+    ///
+    /// Frames that are received with one of these opcodes are passed to
+    /// [ExtensionDecoder::decode], with an opcode of [OpCode::Reserved], instead of failing the
+    /// session. Such frames must not be fragmented and are consumed by the extension; they are not
+    /// returned to the application. By default, an extension handles no reserved opcodes.
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        ReservedOpCodes::empty()
+    }
 }
 
 /// A per-message frame encoder.
@@ -254,11 +415,15 @@ pub trait ExtensionEncoder {
     /// The error type produced by this extension if encoding fails.
     type Error: Error + Send + Sync + 'static;
 
-    /// Invoked when a frame has been received.
+    /// Invoked when a frame is about to be written.
     ///
     /// # Continuation frames
-    /// If this frame is not final or a continuation frame then `payload` will contain all of the
-    /// data received up to and including this frame.
+    /// A fragmented message is encoded one fragment at a time and in order, as each fragment is
+    /// written. `payload` only contains the data of this frame and so an encoder must retain any
+    /// state which is required across the fragments of a message, such as a compression context,
+    /// until it receives a frame with `fin` set. The first fragment has the opcode of the message
+    /// and any subsequent fragments are continuation frames. Only the reserved bits of the first
+    /// fragment may be set.
     ///
     /// # Note
     /// If a condition is not met an implementation may opt to not encode this frame; such as the
@@ -268,6 +433,16 @@ pub trait ExtensionEncoder {
         payload: &mut BytesMut,
         header: &mut FrameHeader,
     ) -> Result<(), Self::Error>;
+
+    /// Returns the parameters that this encoder encodes every message with if it encodes each
+    /// message independently of those that it has encoded before, such as permessage-deflate
+    /// without context takeover. Messages which are encoded ahead of time by one encoder may only be
+    /// sent by encoders which return equal parameters.
+    ///
+    /// By default, an encoder does not encode messages independently and returns `None`.
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        None
+    }
 }
 
 /// A per-message frame decoder.
@@ -281,6 +456,12 @@ pub trait ExtensionDecoder {
     /// If this frame is not final or a continuation frame then `payload` will contain all of the
     /// data received up to and including this frame.
     ///
+    /// # Reserved opcodes
+    /// If the extension handles any reserved opcodes then this is also invoked with each frame that
+    /// is received with one of them. `payload` then only contains the data of that frame. When
+    /// extensions are chained, frames with reserved opcodes are passed to every extension in the
+    /// chain and so an extension should ignore any frames with opcodes that it does not handle.
+    ///
     /// # Note
     /// If a condition is not met an implementation may opt to not decode this frame; such as the
     /// payload length not being large enough to require decoding.
@@ -323,6 +504,13 @@ where
             },
         }
     }
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        match self {
+            Some(ext) => ext.reserved_opcodes(),
+            None => ReservedOpCodes::empty(),
+        }
+    }
 }
 
 impl<E> ExtensionEncoder for Option<E>
@@ -341,6 +529,10 @@ where
             None => Ok(()),
         }
     }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        self.as_ref().and_then(E::stateless_parameters)
+    }
 }
 
 impl<E> ExtensionDecoder for Option<E>
@@ -387,3 +579,110 @@ where
         }
     }
 }
+
+impl<L, R> Extension for Either<L, R>
+where
+    L: Extension,
+    R: Extension,
+{
+    fn bits(&self) -> RsvBits {
+        match self {
+            Either::Left(ext) => ext.bits(),
+            Either::Right(ext) => ext.bits(),
+        }
+    }
+
+    fn reserved_opcodes(&self) -> ReservedOpCodes {
+        match self {
+            Either::Left(ext) => ext.reserved_opcodes(),
+            Either::Right(ext) => ext.reserved_opcodes(),
+        }
+    }
+}
+
+impl<L, R> ExtensionEncoder for Either<L, R>
+where
+    L: ExtensionEncoder,
+    R: ExtensionEncoder,
+{
+    type Error = Either<L::Error, R::Error>;
+
+    fn encode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Either::Left(e) => e.encode(payload, header).map_err(Either::Left),
+            Either::Right(e) => e.encode(payload, header).map_err(Either::Right),
+        }
+    }
+
+    fn stateless_parameters(&self) -> Option<EncoderParameters> {
+        match self {
+            Either::Left(e) => e.stateless_parameters(),
+            Either::Right(e) => e.stateless_parameters(),
+        }
+    }
+}
+
+impl<L, R> ExtensionDecoder for Either<L, R>
+where
+    L: ExtensionDecoder,
+    R: ExtensionDecoder,
+{
+    type Error = Either<L::Error, R::Error>;
+
+    fn decode(
+        &mut self,
+        payload: &mut BytesMut,
+        header: &mut FrameHeader,
+    ) -> Result<(), Self::Error> {
+        match self {
+            Either::Left(e) => e.decode(payload, header).map_err(Either::Left),
+            Either::Right(e) => e.decode(payload, header).map_err(Either::Right),
+        }
+    }
+}
+
+impl<L, R> SplittableExtension for Either<L, R>
+where
+    L: SplittableExtension,
+    R: SplittableExtension,
+{
+    type SplitEncoder = Either<L::SplitEncoder, R::SplitEncoder>;
+    type SplitDecoder = Either<L::SplitDecoder, R::SplitDecoder>;
+
+    fn split(self) -> (Self::SplitEncoder, Self::SplitDecoder) {
+        match self {
+            Either::Left(ext) => {
+                let (encoder, decoder) = ext.split();
+                (Either::Left(encoder), Either::Left(decoder))
+            }
+            Either::Right(ext) => {
+                let (encoder, decoder) = ext.split();
+                (Either::Right(encoder), Either::Right(decoder))
+            }
+        }
+    }
+}
+
+impl<L, R> ReunitableExtension for Either<L, R>
+where
+    L: ReunitableExtension,
+    R: ReunitableExtension,
+{
+    /// # Panics
+    /// Panics if `encoder` and `decoder` are not the same variant.
+    fn reunite(encoder: Self::SplitEncoder, decoder: Self::SplitDecoder) -> Self {
+        match (encoder, decoder) {
+            (Either::Left(encoder), Either::Left(decoder)) => {
+                Either::Left(L::reunite(encoder, decoder))
+            }
+            (Either::Right(encoder), Either::Right(decoder)) => {
+                Either::Right(R::reunite(encoder, decoder))
+            }
+            _ => panic!("Attempted to reunite the halves of different extensions"),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+
+/// # Additional implementation sources:
+/// <https://developer.mozilla.org/en-US/docs/Web/API/CloseEvent>
+/// <https://mailarchive.ietf.org/arch/msg/hybi/P_1vbD9uyHl63nbIIbFxKMfSwcM/>
+/// <https://tools.ietf.org/id/draft-ietf-hybi-thewebsocketprotocol-09.html>
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CloseCode {
+    Normal,
+    GoingAway,
+    Protocol,
+    Unsupported,
+    Status,
+    Abnormal,
+    Invalid,
+    Policy,
+    Overflow,
+    Extension,
+    Unexpected,
+    Restarting,
+    TryAgain,
+    Tls,
+    ReservedExtension(u16),
+    Library(u16),
+    Application(u16),
+}
+
+impl CloseCode {
+    /// Whether this code is reserved and must not be sent in a close frame by an endpoint. This
+    /// also covers codes which are reserved for extensions that have not been defined.
+    pub fn is_illegal(&self) -> bool {
+        matches!(
+            self,
+            CloseCode::Status
+                | CloseCode::Tls
+                | CloseCode::Abnormal
+                | CloseCode::ReservedExtension(_)
+        )
+    }
+}
+
+/// An error produced when parsing a close code which is not defined by
+/// [RFC6455](https://datatracker.ietf.org/doc/html/rfc6455).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CloseCodeParseErr(pub u16);
+
+impl Display for CloseCodeParseErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown close code: `{}`", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CloseCodeParseErr {}
+
+impl TryFrom<[u8; 2]> for CloseCode {
+    type Error = CloseCodeParseErr;
+
+    fn try_from(value: [u8; 2]) -> Result<Self, Self::Error> {
+        let value = u16::from_be_bytes(value);
+        match value {
+            n @ 0..=999 => Err(CloseCodeParseErr(n)),
+            1000 => Ok(CloseCode::Normal),
+            1001 => Ok(CloseCode::GoingAway),
+            1002 => Ok(CloseCode::Protocol),
+            1003 => Ok(CloseCode::Unsupported),
+            1005 => Ok(CloseCode::Status),
+            1006 => Ok(CloseCode::Abnormal),
+            1007 => Ok(CloseCode::Invalid),
+            1008 => Ok(CloseCode::Policy),
+            1009 => Ok(CloseCode::Overflow),
+            1010 => Ok(CloseCode::Extension),
+            1011 => Ok(CloseCode::Unexpected),
+            1012 => Ok(CloseCode::Restarting),
+            1013 => Ok(CloseCode::TryAgain),
+            1015 => Ok(CloseCode::Tls),
+            n @ 1016..=1999 => Err(CloseCodeParseErr(n)),
+            n @ 2000..=2999 => Ok(CloseCode::ReservedExtension(n)),
+            n @ 3000..=3999 => Ok(CloseCode::Library(n)),
+            n @ 4000..=4999 => Ok(CloseCode::Application(n)),
+            n => Err(CloseCodeParseErr(n)),
+        }
+    }
+}
+
+impl From<CloseCode> for u16 {
+    fn from(code: CloseCode) -> u16 {
+        match code {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::Protocol => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::Status => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::Invalid => 1007,
+            CloseCode::Policy => 1008,
+            CloseCode::Overflow => 1009,
+            CloseCode::Extension => 1010,
+            CloseCode::Unexpected => 1011,
+            CloseCode::Restarting => 1012,
+            CloseCode::TryAgain => 1013,
+            CloseCode::Tls => 1015,
+            CloseCode::ReservedExtension(n) => n,
+            CloseCode::Library(n) => n,
+            CloseCode::Application(n) => n,
+        }
+    }
+}
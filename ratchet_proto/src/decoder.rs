@@ -0,0 +1,102 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{apply_mask, FrameError, FrameHeader};
+use bytes::{Buf, BytesMut};
+use either::Either;
+
+/// A sans-IO decoder for WebSocket frames.
+///
+/// Bytes that are received from the peer are appended to a buffer which is then passed to
+/// [`FrameDecoder::decode`]. The decoder retains its progress between calls and so the buffer may
+/// be filled incrementally, from any source.
+// todo: this could be reworked to save space as it's 64 bytes
+#[derive(Copy, Clone, Debug, Default)]
+pub enum FrameDecoder {
+    /// Waiting for a complete frame header.
+    #[default]
+    DecodingHeader,
+    /// The header of a frame has been decoded and the decoder is waiting for the payload. Contains
+    /// the header, its length and the length of the payload.
+    DecodingPayload(FrameHeader, usize, usize),
+}
+
+/// The result of a decode operation.
+#[derive(Debug)]
+pub enum DecodeResult {
+    /// More bytes are required. Contains the minimum number of additional bytes that are needed to
+    /// make progress.
+    Incomplete(usize),
+    /// A frame was decoded. Contains its header and its unmasked payload.
+    Finished(FrameHeader, BytesMut),
+}
+
+impl FrameDecoder {
+    /// Attempts to decode a frame from `buf`. If a frame is decoded then its bytes are removed
+    /// from `buf` and anything past the end of the frame is left for the next call.
+    ///
+    /// `is_server` determines whether frames are expected to be masked, `rsv_bits` contains the
+    /// reserved bits which have been negotiated by extensions, `reserved_opcodes` is a bitmask of
+    /// the reserved opcodes which have been claimed by extensions (where bit `n` is opcode `n`) and
+    /// any frame with a payload larger than `max_frame_size` is rejected.
+    pub fn decode(
+        &mut self,
+        buf: &mut BytesMut,
+        is_server: bool,
+        rsv_bits: u8,
+        reserved_opcodes: u16,
+        max_frame_size: usize,
+    ) -> Result<DecodeResult, FrameError> {
+        loop {
+            match self {
+                FrameDecoder::DecodingHeader => {
+                    match FrameHeader::read_from(
+                        buf,
+                        is_server,
+                        rsv_bits,
+                        reserved_opcodes,
+                        max_frame_size,
+                    )? {
+                        Either::Left((header, header_len, payload_len)) => {
+                            *self = FrameDecoder::DecodingPayload(header, header_len, payload_len);
+                        }
+                        Either::Right(count) => return Ok(DecodeResult::Incomplete(count)),
+                    }
+                }
+                FrameDecoder::DecodingPayload(header, header_len, payload_len) => {
+                    let frame_len = *header_len + *payload_len;
+                    let buf_len = buf.len();
+
+                    if buf_len < frame_len {
+                        let dif = frame_len - buf_len;
+                        return Ok(DecodeResult::Incomplete(dif));
+                    }
+
+                    buf.advance(*header_len);
+
+                    let mut payload = buf.split_to(*payload_len);
+
+                    if let Some(mask) = header.mask {
+                        apply_mask(mask, &mut payload);
+                    }
+
+                    let result = DecodeResult::Finished(*header, payload);
+                    *self = FrameDecoder::DecodingHeader;
+
+                    return Ok(result);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::OpCodeParseErr;
+use core::fmt::{self, Display, Formatter};
+
+/// Errors produced when decoding a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// An invalid OpCode was received.
+    OpCode(OpCodeParseErr),
+    /// The peer sent an unmasked frame when one was expected.
+    UnmaskedFrame,
+    /// The peer sent an masked frame when one was not expected.
+    MaskedFrame,
+    /// Received a fragmented control frame
+    FragmentedControl,
+    /// Received a fragmented frame with a reserved opcode
+    FragmentedReserved,
+    /// A received frame exceeded the maximum permitted size
+    FrameOverflow,
+    /// A peer attempted to use an extension that has not been negotiated
+    UnknownExtension,
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::OpCode(e) => write!(f, "Bad OpCode: `{}`", e),
+            FrameError::UnmaskedFrame => write!(f, "Received an unexpected unmasked frame"),
+            FrameError::MaskedFrame => write!(f, "Received an unexpected masked frame"),
+            FrameError::FragmentedControl => write!(f, "Received a fragmented control frame"),
+            FrameError::FragmentedReserved => {
+                write!(f, "Received a fragmented frame with a reserved opcode")
+            }
+            FrameError::FrameOverflow => write!(f, "A frame exceeded the maximum permitted size"),
+            FrameError::UnknownExtension => write!(
+                f,
+                "Attempted to use an extension that has not been negotiated"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+impl From<OpCodeParseErr> for FrameError {
+    fn from(e: OpCodeParseErr) -> Self {
+        FrameError::OpCode(e)
+    }
+}
@@ -12,19 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::errors::ProtocolError;
-use crate::protocol::{HeaderFlags, OpCode};
+use crate::{FrameError, HeaderFlags, OpCode, OpCodeParseErr};
 use bytes::{BufMut, BytesMut};
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+use core::mem::size_of;
 use either::Either;
-use std::convert::TryFrom;
-use std::fmt::{Display, Formatter};
-use std::mem::size_of;
 
 const U16_MAX: usize = u16::MAX as usize;
 
+/// Formats a frame header for logging.
+#[derive(Debug)]
 pub struct FramePrinter<'l>(pub &'l FrameHeader);
 impl<'l> Display for FramePrinter<'l> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let FrameHeader {
             opcode,
             flags,
@@ -38,8 +39,11 @@ impl<'l> Display for FramePrinter<'l> {
     }
 }
 
+/// Formats the borrowed fields of a frame header for logging.
+#[derive(Debug)]
 pub struct BorrowedFramePrinter<'l>(pub BorrowedFrameHeader<'l>);
 impl<'l> BorrowedFramePrinter<'l> {
+    /// Creates a printer for the provided header fields.
     pub fn new(
         opcode: &'l OpCode,
         flags: &'l HeaderFlags,
@@ -54,7 +58,7 @@ impl<'l> BorrowedFramePrinter<'l> {
 }
 
 impl<'l> Display for BorrowedFramePrinter<'l> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let BorrowedFrameHeader {
             opcode,
             flags,
@@ -68,17 +72,25 @@ impl<'l> Display for BorrowedFramePrinter<'l> {
     }
 }
 
+/// A frame header whose fields are borrowed.
 #[derive(Debug)]
 pub struct BorrowedFrameHeader<'l> {
+    /// The frame's opcode.
     pub opcode: &'l OpCode,
+    /// The flags that are set in the frame's header.
     pub flags: &'l HeaderFlags,
+    /// The masking key that is used to mask the payload, if the frame is masked.
     pub mask: &'l Option<u32>,
 }
 
+/// The header of a WebSocket frame.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct FrameHeader {
+    /// The frame's opcode.
     pub opcode: OpCode,
+    /// The flags that were set in the frame's header.
     pub flags: HeaderFlags,
+    /// The masking key that was used to mask the payload, if the frame was masked.
     pub mask: Option<u32>,
 }
 
@@ -101,6 +113,8 @@ macro_rules! try_parse_int {
 }
 
 impl FrameHeader {
+    /// Writes a frame header into `dst` for a payload of `payload_len` bytes. If `mask` is set then
+    /// enough capacity is reserved in `dst` for the payload to be written after the header.
     pub fn write_into(
         dst: &mut BytesMut,
         opcode: OpCode,
@@ -137,12 +151,18 @@ impl FrameHeader {
         }
     }
 
+    /// Attempts to read a frame header from `source`.
+    ///
+    /// Returns the header, its length and the length of the payload that follows it if `source`
+    /// contains a complete header, or the minimum number of additional bytes that are required
+    /// otherwise.
     pub fn read_from(
         source: &[u8],
         is_server: bool,
         rsv_bits: u8,
-        max_message_size: usize,
-    ) -> Result<Either<(FrameHeader, usize, usize), usize>, ProtocolError> {
+        reserved_opcodes: u16,
+        max_frame_size: usize,
+    ) -> Result<Either<(FrameHeader, usize, usize), usize>, FrameError> {
         let source_length = source.len();
         if source_length < 2 {
             return Ok(Either::Right(2 - source_length));
@@ -150,16 +170,26 @@ impl FrameHeader {
 
         let first = source[0];
         let received_flags = HeaderFlags::from_bits_truncate(first);
-        let opcode = OpCode::try_from(first & 0xF)?;
+        let opcode = match OpCode::try_from(first & 0xF) {
+            Ok(opcode) => opcode,
+            Err(OpCodeParseErr::Reserved(code)) if reserved_opcodes & 1 << code != 0 => {
+                // An extension has claimed this opcode
+                OpCode::Reserved(code)
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         if opcode.is_control() && !received_flags.is_fin() {
             // rfc6455 § 5.4: Control frames themselves MUST NOT be fragmented
-            return Err(ProtocolError::FragmentedControl);
+            return Err(FrameError::FragmentedControl);
+        } else if opcode.is_reserved() && !received_flags.is_fin() {
+            // The fragmentation of frames with reserved opcodes is not defined
+            return Err(FrameError::FragmentedReserved);
         }
 
         if (received_flags.bits() & !rsv_bits & 0x70) != 0 {
             // Peer set a RSV bit high that hasn't been negotiated
-            return Err(ProtocolError::UnknownExtension);
+            return Err(FrameError::UnknownExtension);
         }
 
         let second = source[1];
@@ -167,10 +197,10 @@ impl FrameHeader {
 
         if !masked && is_server {
             // rfc6455 § 6.1: Client must send masked data
-            return Err(ProtocolError::UnmaskedFrame);
+            return Err(FrameError::UnmaskedFrame);
         } else if masked && !is_server {
             // rfc6455 § 6.2: Server must remove masking
-            return Err(ProtocolError::MaskedFrame);
+            return Err(FrameError::MaskedFrame);
         }
 
         let payload_length = second & 0x7F;
@@ -184,8 +214,8 @@ impl FrameHeader {
             usize::from(payload_length)
         };
 
-        if length > max_message_size {
-            return Err(ProtocolError::FrameOverflow);
+        if length > max_frame_size {
+            return Err(FrameError::FrameOverflow);
         }
 
         let mask = if masked {
@@ -211,15 +241,3 @@ impl FrameHeader {
         )))
     }
 }
-
-/// Writes a WebSocket text frame header into `dst` with FIN set high.
-#[cfg(feature = "fixture")]
-pub fn write_text_frame_header(dst: &mut BytesMut, mask: Option<u32>, payload_len: usize) {
-    FrameHeader::write_into(
-        dst,
-        OpCode::DataCode(crate::protocol::DataCode::Text),
-        HeaderFlags::FIN,
-        mask,
-        payload_len,
-    )
-}
@@ -0,0 +1,31 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+/// The GUID that is appended to a `Sec-WebSocket-Key` when deriving its `Sec-WebSocket-Accept`
+/// value.
+pub const ACCEPT_KEY: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Derives the `Sec-WebSocket-Accept` header value for the provided `Sec-WebSocket-Key`, as per
+/// [RFC6455 § 4.2.2](https://datatracker.ietf.org/doc/html/rfc6455#section-4.2.2).
+pub fn accept_key(key: impl AsRef<[u8]>) -> String {
+    let mut digest = Sha1::new();
+    Digest::update(&mut digest, key);
+    Digest::update(&mut digest, ACCEPT_KEY);
+    STANDARD.encode(digest.finalize())
+}
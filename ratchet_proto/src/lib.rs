@@ -0,0 +1,135 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sans-IO primitives for the WebSocket protocol.
+//!
+//! This crate contains the parts of Ratchet which do not depend upon an IO runtime: encoding and
+//! decoding frames, masking payloads and deriving the keys used during the opening handshake.
+//! Every operation works on byte buffers that are owned by the caller and so may be used to drive
+//! a WebSocket over any transport, such as a QUIC stream, an in-memory ring buffer or an FFI
+//! boundary. [ratchet_core](../ratchet_core) uses this crate with Tokio.
+//!
+//! # `no_std` support
+//! The crate is `no_std` compatible and only requires `alloc` when its default `std` feature is
+//! disabled. This allows embedded devices to reuse the same codec with their own transport. Without
+//! `std`, the error types do not implement `std::error::Error`, SIMD masking is only used if the
+//! instructions are enabled at compile time and `DataCode` does not convert into a
+//! `ratchet_ext::OpCode`.
+//!
+//! # Decoding
+//! A [`FrameDecoder`] is fed the bytes that have been received from the peer and produces a
+//! [`DecodeResult`] for each frame once it has been fully received:
+//!
+//! ```
+//! use bytes::BytesMut;
+//! use ratchet_proto::{DataCode, DecodeResult, FrameDecoder, FrameHeader, HeaderFlags, OpCode};
+//!
+//! let mut buf = BytesMut::new();
+//! FrameHeader::write_into(
+//!     &mut buf,
+//!     OpCode::DataCode(DataCode::Text),
+//!     HeaderFlags::FIN,
+//!     None,
+//!     5,
+//! );
+//! buf.extend_from_slice(b"hello");
+//!
+//! let mut decoder = FrameDecoder::default();
+//! match decoder.decode(&mut buf, false, 0, 0, usize::MAX).unwrap() {
+//!     DecodeResult::Finished(header, payload) => {
+//!         assert_eq!(header.opcode, OpCode::DataCode(DataCode::Text));
+//!         assert_eq!(payload.as_ref(), b"hello");
+//!     }
+//!     DecodeResult::Incomplete(_) => unreachable!(),
+//! }
+//! ```
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![deny(
+    missing_docs,
+    missing_copy_implementations,
+    missing_debug_implementations,
+    trivial_numeric_casts,
+    unstable_features,
+    unused_must_use,
+    unused_mut,
+    unused_imports,
+    unused_import_braces
+)]
+
+extern crate alloc;
+
+#[cfg(test)]
+mod tests;
+
+mod close;
+mod decoder;
+mod error;
+mod frame;
+mod handshake;
+mod mask;
+mod opcode;
+
+pub use close::{CloseCode, CloseCodeParseErr};
+pub use decoder::{DecodeResult, FrameDecoder};
+pub use error::FrameError;
+pub use frame::*;
+pub use handshake::{accept_key, ACCEPT_KEY};
+pub use mask::apply_mask;
+pub use opcode::{ControlCode, DataCode, OpCode, OpCodeParseErr};
+
+bitflags::bitflags! {
+    /// The flags that are set in the first byte of a frame's header.
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct HeaderFlags: u8 {
+        /// Set if this is the final fragment of a message.
+        const FIN       = 0b1000_0000;
+
+        /// The first reserved bit.
+        const RSV_1     = 0b0100_0000;
+        /// The second reserved bit.
+        const RSV_2     = 0b0010_0000;
+        /// The third reserved bit.
+        const RSV_3     = 0b0001_0000;
+
+        // The extension bits that *may* be high. Anything outside this range is illegal.
+        /// All of the reserved bits.
+        const RESERVED  = Self::RSV_1.bits() | Self::RSV_2.bits() | Self::RSV_3.bits();
+
+        // no new flags should be added
+    }
+}
+
+#[allow(warnings)]
+impl HeaderFlags {
+    /// Whether the FIN bit is set.
+    pub fn is_fin(&self) -> bool {
+        self.contains(HeaderFlags::FIN)
+    }
+
+    /// Whether the first reserved bit is set.
+    pub fn is_rsv1(&self) -> bool {
+        self.contains(HeaderFlags::RSV_1)
+    }
+
+    /// Whether the second reserved bit is set.
+    pub fn is_rsv2(&self) -> bool {
+        self.contains(HeaderFlags::RSV_2)
+    }
+
+    /// Whether the third reserved bit is set.
+    pub fn is_rsv3(&self) -> bool {
+        self.contains(HeaderFlags::RSV_3)
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+const WORD_SIZE: usize = core::mem::size_of::<usize>() * 2;
+
+#[inline]
+fn apply_mask_unoptimised(buf: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte ^= mask[i & 3];
+    }
+}
+
+/// Applies the masking key `mask` to `bytes` in place. As masking is an XOR operation, this both
+/// masks and unmasks a payload.
+#[inline]
+pub fn apply_mask(mask: u32, bytes: &mut [u8]) {
+    let mask = mask.to_ne_bytes();
+    if bytes.len() < WORD_SIZE {
+        apply_mask_unoptimised(bytes, mask);
+    } else {
+        apply_mask_simd(bytes, mask)
+    }
+}
+
+/// Whether the CPU supports a SIMD instruction set. Without `std` the features cannot be detected
+/// at runtime and so only those which are enabled at compile time are used.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+#[cfg(feature = "std")]
+macro_rules! has_target_feature {
+    (x86, $feature:tt) => {
+        std::is_x86_feature_detected!($feature)
+    };
+    (aarch64, $feature:tt) => {
+        std::arch::is_aarch64_feature_detected!($feature)
+    };
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+#[cfg(not(feature = "std"))]
+macro_rules! has_target_feature {
+    ($arch:tt, $feature:tt) => {
+        cfg!(target_feature = $feature)
+    };
+}
+
+/// Applies the mask using the widest SIMD instructions that are supported by the CPU, falling back
+/// to `apply_mask_fast` for any remaining bytes or if no SIMD instructions are available.
+#[inline]
+fn apply_mask_simd(bytes: &mut [u8], mask: [u8; 4]) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if bytes.len() >= simd::AVX2_WIDTH && has_target_feature!(x86, "avx2") {
+            // SAFETY: the CPU supports AVX2.
+            let consumed = unsafe { simd::apply_mask_avx2(bytes, mask) };
+            return apply_mask_fast(&mut bytes[consumed..], mask);
+        }
+        if bytes.len() >= simd::SSE2_WIDTH && has_target_feature!(x86, "sse2") {
+            // SAFETY: the CPU supports SSE2.
+            let consumed = unsafe { simd::apply_mask_sse2(bytes, mask) };
+            return apply_mask_fast(&mut bytes[consumed..], mask);
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if bytes.len() >= simd::NEON_WIDTH && has_target_feature!(aarch64, "neon") {
+            // SAFETY: the CPU supports NEON.
+            let consumed = unsafe { simd::apply_mask_neon(bytes, mask) };
+            return apply_mask_fast(&mut bytes[consumed..], mask);
+        }
+    }
+
+    apply_mask_fast(bytes, mask)
+}
+
+/// SIMD masking implementations. Each function masks as many whole vectors as fit in `bytes` and
+/// returns the number of bytes that were masked. As the vector widths are multiples of the mask
+/// length, the remaining bytes may be masked using the same mask.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))]
+mod simd {
+    #[cfg(target_arch = "x86")]
+    use core::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use core::arch::x86_64::*;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const AVX2_WIDTH: usize = 32;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub const SSE2_WIDTH: usize = 16;
+    #[cfg(target_arch = "aarch64")]
+    pub const NEON_WIDTH: usize = 16;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn apply_mask_avx2(bytes: &mut [u8], mask: [u8; 4]) -> usize {
+        let mask = _mm256_set1_epi32(i32::from_ne_bytes(mask));
+        let len = bytes.len() - bytes.len() % AVX2_WIDTH;
+
+        for chunk in bytes.chunks_exact_mut(AVX2_WIDTH) {
+            let ptr = chunk.as_mut_ptr() as *mut __m256i;
+            let masked = _mm256_xor_si256(_mm256_loadu_si256(ptr), mask);
+            _mm256_storeu_si256(ptr, masked);
+        }
+
+        len
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn apply_mask_sse2(bytes: &mut [u8], mask: [u8; 4]) -> usize {
+        let mask = _mm_set1_epi32(i32::from_ne_bytes(mask));
+        let len = bytes.len() - bytes.len() % SSE2_WIDTH;
+
+        for chunk in bytes.chunks_exact_mut(SSE2_WIDTH) {
+            let ptr = chunk.as_mut_ptr() as *mut __m128i;
+            let masked = _mm_xor_si128(_mm_loadu_si128(ptr), mask);
+            _mm_storeu_si128(ptr, masked);
+        }
+
+        len
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[target_feature(enable = "neon")]
+    pub unsafe fn apply_mask_neon(bytes: &mut [u8], mask: [u8; 4]) -> usize {
+        use core::arch::aarch64::*;
+
+        let mask = vreinterpretq_u8_u32(vdupq_n_u32(u32::from_ne_bytes(mask)));
+        let len = bytes.len() - bytes.len() % NEON_WIDTH;
+
+        for chunk in bytes.chunks_exact_mut(NEON_WIDTH) {
+            let ptr = chunk.as_mut_ptr();
+            vst1q_u8(ptr, veorq_u8(vld1q_u8(ptr), mask));
+        }
+
+        len
+    }
+}
+
+#[inline]
+pub fn apply_mask_fast(bytes: &mut [u8], mask: [u8; 4]) {
+    let mut mask_u32 = u32::from_ne_bytes(mask);
+    let (prefix, shorts, suffix) = unsafe { bytes.align_to_mut::<u32>() };
+    let prefix_len = prefix.len();
+
+    if prefix_len > 0 {
+        apply_mask_unoptimised(prefix, mask);
+
+        if cfg!(target_endian = "big") {
+            mask_u32 = mask_u32.rotate_left(8 * prefix_len as u32)
+        } else {
+            mask_u32 = mask_u32.rotate_right(8 * prefix_len as u32)
+        }
+    }
+
+    for word in shorts.iter_mut() {
+        *word ^= mask_u32;
+    }
+
+    if !suffix.is_empty() {
+        apply_mask_unoptimised(suffix, mask_u32.to_ne_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mask::{apply_mask_fast, apply_mask_unoptimised};
+
+    // Tests that the fast masking produces the same results an the unoptimised version against
+    // different alignments
+    #[test]
+    fn apply_mask_unaligned() {
+        let mask = [0x1, 0x2, 0x3, 0x3];
+        let payload = (0..100u8).collect::<Vec<_>>();
+
+        // So the offset isn't out of bounds
+        let start = 3;
+
+        for idx in start..=payload.len() {
+            let unmasked = &payload[0..idx];
+            for offset in 0..=3 {
+                let mut masked_unoptimised = unmasked.to_vec();
+                apply_mask_unoptimised(&mut masked_unoptimised[offset..], mask);
+
+                let mut masked_fast = unmasked.to_vec();
+                apply_mask_fast(&mut masked_fast[offset..], mask);
+
+                assert_eq!(masked_unoptimised, masked_fast);
+            }
+        }
+    }
+
+    // Tests that the SIMD masking, and its scalar handling of any trailing bytes, produces the same
+    // results as the unoptimised version against different lengths and alignments
+    #[test]
+    fn apply_mask_simd() {
+        let mask = [0x1, 0x2, 0x3, 0x4];
+        let payload = (0..200u8).collect::<Vec<_>>();
+
+        for idx in 0..=payload.len() {
+            let unmasked = &payload[0..idx];
+            for offset in 0..=3.min(idx) {
+                let mut masked_unoptimised = unmasked.to_vec();
+                apply_mask_unoptimised(&mut masked_unoptimised[offset..], mask);
+
+                let mut masked_simd = unmasked.to_vec();
+                super::apply_mask_simd(&mut masked_simd[offset..], mask);
+
+                assert_eq!(masked_unoptimised, masked_simd);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_mask_aligned() {
+        let mask = [0x1, 0x2, 0x3, 0x3];
+        let payload = (0..100u8).collect::<Vec<_>>();
+
+        let unmasked = &payload[..];
+
+        let mut masked_unoptimised = unmasked.to_vec();
+        apply_mask_unoptimised(&mut masked_unoptimised, mask);
+
+        let mut masked_fast = unmasked.to_vec();
+        apply_mask_fast(&mut masked_fast, mask);
+
+        assert_eq!(masked_unoptimised, masked_fast);
+    }
+}
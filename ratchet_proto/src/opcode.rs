@@ -0,0 +1,145 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+
+/// A frame's opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpCode {
+    /// A data frame opcode.
+    DataCode(DataCode),
+    /// A control frame opcode.
+    ControlCode(ControlCode),
+    /// An opcode that is reserved for future use and which has been claimed by a negotiated
+    /// extension. Contains the opcode, in the range `3..=7` or `11..=15`.
+    Reserved(u8),
+}
+
+impl Display for OpCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl OpCode {
+    /// Whether this is a data frame opcode.
+    pub fn is_data(&self) -> bool {
+        matches!(self, OpCode::DataCode(_))
+    }
+
+    /// Whether this is a control frame opcode.
+    pub fn is_control(&self) -> bool {
+        matches!(self, OpCode::ControlCode(_))
+    }
+
+    /// Whether this is a reserved opcode.
+    pub fn is_reserved(&self) -> bool {
+        matches!(self, OpCode::Reserved(_))
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(op: OpCode) -> Self {
+        match op {
+            OpCode::DataCode(code) => code as u8,
+            OpCode::ControlCode(code) => code as u8,
+            OpCode::Reserved(code) => code,
+        }
+    }
+}
+
+/// A data frame opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataCode {
+    /// A continuation frame.
+    Continuation = 0,
+    /// A text frame.
+    Text = 1,
+    /// A binary frame.
+    Binary = 2,
+}
+
+impl Display for DataCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<DataCode> for ratchet_ext::OpCode {
+    fn from(e: DataCode) -> Self {
+        match e {
+            DataCode::Continuation => ratchet_ext::OpCode::Continuation,
+            DataCode::Text => ratchet_ext::OpCode::Text,
+            DataCode::Binary => ratchet_ext::OpCode::Binary,
+        }
+    }
+}
+
+/// A control frame opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlCode {
+    /// A close frame.
+    Close = 8,
+    /// A ping frame.
+    Ping = 9,
+    /// A pong frame.
+    Pong = 10,
+}
+
+impl Display for ControlCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// An error produced when parsing an opcode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpCodeParseErr {
+    /// The opcode is reserved for future use.
+    Reserved(u8),
+    /// The value is not a valid opcode.
+    Invalid(u8),
+}
+
+impl Display for OpCodeParseErr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OpCodeParseErr::Reserved(code) => write!(f, "Reserved OpCode: `{}`", code),
+            OpCodeParseErr::Invalid(code) => write!(f, "Invalid OpCode: `{}`", code),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OpCodeParseErr {}
+
+impl TryFrom<u8> for OpCode {
+    type Error = OpCodeParseErr;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(OpCode::DataCode(DataCode::Continuation)),
+            1 => Ok(OpCode::DataCode(DataCode::Text)),
+            2 => Ok(OpCode::DataCode(DataCode::Binary)),
+            r @ 3..=7 => Err(OpCodeParseErr::Reserved(r)),
+            8 => Ok(OpCode::ControlCode(ControlCode::Close)),
+            9 => Ok(OpCode::ControlCode(ControlCode::Ping)),
+            10 => Ok(OpCode::ControlCode(ControlCode::Pong)),
+            r @ 11..=15 => Err(OpCodeParseErr::Reserved(r)),
+            e => Err(OpCodeParseErr::Invalid(e)),
+        }
+    }
+}
@@ -14,8 +14,8 @@
 
 #[cfg(test)]
 mod encoding {
-    use crate::protocol::{apply_mask, DataCode, HeaderFlags};
-    use crate::protocol::{FrameHeader, OpCode};
+    use crate::{apply_mask, DataCode, HeaderFlags};
+    use crate::{FrameHeader, OpCode};
     use bytes::BytesMut;
 
     fn encode<A>(
@@ -141,22 +141,21 @@ mod encoding {
 
 #[cfg(test)]
 mod decode {
-    use crate::protocol::{DataCode, FrameHeader, HeaderFlags, OpCode};
-    use crate::ProtocolError;
+    use crate::{DataCode, FrameError, FrameHeader, HeaderFlags, OpCode, OpCodeParseErr};
     use bytes::BytesMut;
     use either::Either;
     use std::iter::FromIterator;
 
-    fn expect_protocol_error(
-        result: Result<Either<(FrameHeader, usize, usize), usize>, ProtocolError>,
-        error: ProtocolError,
+    fn expect_frame_error(
+        result: Result<Either<(FrameHeader, usize, usize), usize>, FrameError>,
+        error: FrameError,
     ) {
         match result {
             Err(e) => {
                 assert_eq!(e, error)
             }
             o => {
-                panic!("Expected a protocol error. Got: `{:?}`", o)
+                panic!("Expected a frame error. Got: `{:?}`", o)
             }
         }
     }
@@ -165,7 +164,7 @@ mod decode {
     fn header() {
         let bytes = BytesMut::from_iter([129, 4, 1, 2, 3, 4]);
         let (header, _header_len, _payload_len) =
-            FrameHeader::read_from(&bytes, false, 0, usize::MAX)
+            FrameHeader::read_from(&bytes, false, 0, 0, usize::MAX)
                 .unwrap()
                 .unwrap_left();
 
@@ -182,15 +181,15 @@ mod decode {
     #[test]
     fn rsv() {
         let bytes = BytesMut::from_iter([161, 4, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, false, 0, usize::MAX);
-        expect_protocol_error(r, ProtocolError::UnknownExtension);
+        let r = FrameHeader::read_from(&bytes, false, 0, 0, usize::MAX);
+        expect_frame_error(r, FrameError::UnknownExtension);
 
         let bytes = BytesMut::from_iter([161, 4, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, false, 1 << 6 & 1 << 4, usize::MAX);
-        expect_protocol_error(r, ProtocolError::UnknownExtension);
+        let r = FrameHeader::read_from(&bytes, false, 1 << 6 & 1 << 4, 0, usize::MAX);
+        expect_frame_error(r, FrameError::UnknownExtension);
 
         let bytes = BytesMut::from_iter([193, 4, 1, 2, 3, 4]);
-        let result = FrameHeader::read_from(&bytes, false, 1 << 6, usize::MAX);
+        let result = FrameHeader::read_from(&bytes, false, 1 << 6, 0, usize::MAX);
 
         let _expected = FrameHeader {
             opcode: OpCode::DataCode(DataCode::Text),
@@ -203,28 +202,134 @@ mod decode {
     #[test]
     fn overflow() {
         let bytes = BytesMut::from_iter([129, 4, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, false, 0, 1);
-        expect_protocol_error(r, ProtocolError::FrameOverflow);
+        let r = FrameHeader::read_from(&bytes, false, 0, 0, 1);
+        expect_frame_error(r, FrameError::FrameOverflow);
     }
 
     #[test]
     fn fragmented_control() {
         let bytes = BytesMut::from_iter([8, 4, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, false, 0, usize::MAX);
-        expect_protocol_error(r, ProtocolError::FragmentedControl);
+        let r = FrameHeader::read_from(&bytes, false, 0, 0, usize::MAX);
+        expect_frame_error(r, FrameError::FragmentedControl);
+    }
+
+    #[test]
+    fn reserved_opcode() {
+        let bytes = BytesMut::from_iter([131, 4, 1, 2, 3, 4]);
+        let r = FrameHeader::read_from(&bytes, false, 0, 1 << 11, usize::MAX);
+        expect_frame_error(r, FrameError::OpCode(OpCodeParseErr::Reserved(3)));
+
+        let (header, _header_len, _payload_len) =
+            FrameHeader::read_from(&bytes, false, 0, 1 << 3, usize::MAX)
+                .unwrap()
+                .unwrap_left();
+        assert_eq!(header.opcode, OpCode::Reserved(3));
+
+        let bytes = BytesMut::from_iter([3, 4, 1, 2, 3, 4]);
+        let r = FrameHeader::read_from(&bytes, false, 0, 1 << 3, usize::MAX);
+        expect_frame_error(r, FrameError::FragmentedReserved);
     }
 
     #[test]
     fn unmasked() {
         let bytes = BytesMut::from_iter([1, 132, 0, 0, 0, 0, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, false, 0, usize::MAX);
-        expect_protocol_error(r, ProtocolError::MaskedFrame);
+        let r = FrameHeader::read_from(&bytes, false, 0, 0, usize::MAX);
+        expect_frame_error(r, FrameError::MaskedFrame);
     }
 
     #[test]
     fn masked_err() {
         let bytes = BytesMut::from_iter([129, 4, 1, 2, 3, 4]);
-        let r = FrameHeader::read_from(&bytes, true, 0, usize::MAX);
-        expect_protocol_error(r, ProtocolError::UnmaskedFrame);
+        let r = FrameHeader::read_from(&bytes, true, 0, 0, usize::MAX);
+        expect_frame_error(r, FrameError::UnmaskedFrame);
+    }
+}
+
+#[cfg(test)]
+mod decoder {
+    use crate::{DataCode, DecodeResult, FrameDecoder, FrameHeader, HeaderFlags, OpCode};
+    use bytes::BytesMut;
+
+    #[test]
+    fn incremental() {
+        let frame = [
+            129, 133, 191, 180, 236, 135, 247, 209, 128, 235, 208, 137, 128,
+        ];
+        let mut decoder = FrameDecoder::default();
+        let mut buf = BytesMut::new();
+
+        for byte in &frame[..frame.len() - 3] {
+            buf.extend_from_slice(&[*byte]);
+            let result = decoder.decode(&mut buf, true, 0, 0, usize::MAX).unwrap();
+            assert!(matches!(result, DecodeResult::Incomplete(_)));
+        }
+
+        buf.extend_from_slice(&frame[frame.len() - 3..]);
+        match decoder.decode(&mut buf, true, 0, 0, usize::MAX).unwrap() {
+            DecodeResult::Finished(header, payload) => {
+                assert_eq!(
+                    header,
+                    FrameHeader {
+                        opcode: OpCode::DataCode(DataCode::Text),
+                        flags: HeaderFlags::FIN,
+                        mask: Some(2280436927),
+                    }
+                );
+                assert_eq!(payload.as_ref(), b"Hello");
+            }
+            DecodeResult::Incomplete(_) => panic!("Expected a frame"),
+        }
+
+        // The bytes of the next frame are retained.
+        assert_eq!(buf.as_ref(), &[137, 128]);
+        let result = decoder.decode(&mut buf, true, 0, 0, usize::MAX).unwrap();
+        assert!(matches!(result, DecodeResult::Incomplete(4)));
+    }
+}
+
+#[cfg(test)]
+mod handshake {
+    use crate::accept_key;
+
+    #[test]
+    fn rfc_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+}
+
+#[cfg(test)]
+mod close {
+    use crate::{CloseCode, CloseCodeParseErr};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn round_trip() {
+        for code in [1000u16, 1001, 1011, 2000, 3000, 4999] {
+            let parsed = CloseCode::try_from(code.to_be_bytes()).unwrap();
+            assert_eq!(u16::from(parsed), code);
+        }
+    }
+
+    #[test]
+    fn unknown() {
+        for code in [0u16, 999, 1004, 1014, 1016, 1999, 5000] {
+            assert_eq!(
+                CloseCode::try_from(code.to_be_bytes()),
+                Err(CloseCodeParseErr(code))
+            );
+        }
+    }
+
+    #[test]
+    fn illegal() {
+        assert!(CloseCode::Status.is_illegal());
+        assert!(CloseCode::Abnormal.is_illegal());
+        assert!(CloseCode::Tls.is_illegal());
+        assert!(CloseCode::ReservedExtension(2000).is_illegal());
+        assert!(!CloseCode::Normal.is_illegal());
+        assert!(!CloseCode::Application(4000).is_illegal());
     }
 }
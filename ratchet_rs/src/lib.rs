@@ -20,7 +20,26 @@
 //! - Implement your own own extensions using [ratchet_ext](../ratchet_ext).
 //! - Per-message deflate with [ratchet_deflate](../ratchet_deflate) or enable with the `deflate`
 //!   feature.
-//! - Split WebSocket with the `split` feature.
+//! - Open a client connection over TCP from a `ws` URI with `connect`, or over a Unix domain
+//!   socket with `connect_unix`, when the `net` feature is enabled.
+//! - Bootstrap WebSockets over HTTP/2 and HTTP/3 streams with the extended CONNECT method using
+//!   `extended_connect`.
+//! - Split a WebSocket into its sender and receiver halves with `WebSocket::split`, or into
+//!   frame-level halves with `WebSocket::split_raw`, and track the senders of live connections
+//!   with `manager::ConnectionManager` when the `manager` feature is enabled.
+//! - Fan messages out to the connections subscribed to a topic with `hub::Hub` when the `hub`
+//!   feature is enabled.
+//! - SIMD-accelerated UTF-8 validation of text messages with the `simdutf8` feature.
+//! - Use streams from async-std, smol and other runtimes which implement the `futures::io` traits
+//!   with the `futures-io` feature.
+//! - Run over completion-based runtimes, such as tokio-uring, with
+//!   `completion::CompletionWebSocket` and the `completion` feature.
+//! - Block the calling thread instead of awaiting operations with `blocking::WebSocket` and the
+//!   `blocking` feature.
+//! - Transparently redial client connections after IO errors with
+//!   `reconnect::ReconnectingWebSocket` and the `reconnect` feature.
+//! - A browser client backed by the WebSocket API when compiling to `wasm32-unknown-unknown` with
+//!   the `wasm` feature.
 //!
 //! # Error handling
 //! Ratchet is strict over its implementation of The WebSocket protocol and as such any errors in
@@ -45,16 +64,45 @@
 )]
 
 pub use ratchet_core::{
-    accept, accept_with, subscribe, subscribe_with, CloseCode, CloseReason, CloseState, Error,
-    ErrorKind, HttpError, Message, MessageType, NoExt, NoExtDecoder, NoExtEncoder, NoExtProvider,
-    PayloadType, ProtocolError, Role, SubprotocolRegistry, TryIntoRequest, UpgradedClient,
-    UpgradedServer, WebSocket, WebSocketClientBuilder, WebSocketConfig, WebSocketResponse,
-    WebSocketServerBuilder, WebSocketStream, WebSocketUpgrader,
+    accept, accept_with, accept_with_buffer, subscribe, subscribe_with, Accepted, ClientAddr,
+    ClientAddrSource, CloseCode, CloseReason, CloseState, CookieStore, Error, ErrorKind, Fragment,
+    HttpError, HttpRequest, Message, MessageType, NoExt, NoExtDecoder, NoExtEncoder, NoExtProvider,
+    OriginPolicy, OwnedMessage, PayloadType, PendingPong, PreparedMessage, PreparedMessageError,
+    ProtocolError, Proxy, ProxyHeader, RateLimit, RateLimitAction, RateLimitCause, Rejection, Role,
+    SubprotocolRegistry, TimeoutCause, TrustedProxies, TryIntoRequest, UpgradeDecision,
+    UpgradedClient, UpgradedServer, WebSocket, WebSocketClientBuilder, WebSocketConfig,
+    WebSocketHandle, WebSocketResponse, WebSocketServerBuilder, WebSocketStream, WebSocketUpgrader,
 };
 pub use ratchet_ext::{self, *};
 
-#[cfg(feature = "split")]
-pub use ratchet_core::{Receiver, ReuniteError, Sender};
+pub use ratchet_core::{
+    Receiver, ReuniteError, Sender, SenderHandle, SharedWebSocket, TrySendError,
+};
+
+pub use ratchet_core::extended_connect;
+pub use ratchet_core::raw;
+
+#[cfg(feature = "blocking")]
+pub use ratchet_core::blocking;
+#[cfg(feature = "completion")]
+pub use ratchet_core::completion;
+#[cfg(feature = "hub")]
+pub use ratchet_core::hub;
+#[cfg(feature = "manager")]
+pub use ratchet_core::manager;
+#[cfg(feature = "reconnect")]
+pub use ratchet_core::reconnect;
+
+#[cfg(feature = "futures-io")]
+pub use ratchet_core::futures_io;
+
+#[cfg(all(feature = "net", unix))]
+pub use ratchet_core::connect_unix;
+#[cfg(feature = "net")]
+pub use ratchet_core::{connect, connect_with, Resolver, Resolving, SystemResolver};
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
 
 /// Per-message deflate.
 #[cfg(feature = "deflate")]
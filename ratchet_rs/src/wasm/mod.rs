@@ -0,0 +1,431 @@
+// Copyright 2015-2021 Swim Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A WebSocket client for browsers, backed by the
+//! [WebSocket API](https://developer.mozilla.org/en-US/docs/Web/API/WebSocket).
+//!
+//! When compiling to `wasm32-unknown-unknown`, the `wasm` feature provides a [`WebSocket`] which
+//! mirrors the read and write API of the native [`crate::WebSocket`] so that client code may be
+//! shared between native and browser targets. The browser performs the opening handshake, frames
+//! the messages and answers pings itself and so there is no stream or extension to provide.
+//!
+//! # Example
+//! ```no_run
+//! # use ratchet_rs::wasm::WebSocket;
+//! # use ratchet_rs::{Error, Message};
+//! # use bytes::BytesMut;
+//! # async fn f() -> Result<(), Error> {
+//! let mut websocket = WebSocket::connect("wss://example.com").await?;
+//! websocket.write_text("hello").await?;
+//!
+//! let mut buf = BytesMut::new();
+//! if let Message::Text = websocket.read(&mut buf).await? {
+//!     println!("Received: {:?}", buf);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use bytes::BytesMut;
+use js_sys::{Array, ArrayBuffer, JsString, Uint8Array};
+use ratchet_core::{
+    CloseCause, CloseCode, CloseReason, CloseState, Error, ErrorKind, HttpError, Message,
+    PayloadType, ProtocolError, Role,
+};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
+use std::future::poll_fn;
+use std::io;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{BinaryType, CloseEvent, MessageEvent};
+
+/// The close code that browsers report when the peer's close frame contained no status code.
+const NO_STATUS_RECEIVED: u16 = 1005;
+
+/// An event emitted by the browser's WebSocket.
+enum Event {
+    Open,
+    Text(String),
+    Binary(Vec<u8>),
+    Error,
+    Close { code: u16, reason: String },
+}
+
+/// Events which have been received from the browser but not yet been read.
+#[derive(Default)]
+struct Shared {
+    events: VecDeque<Event>,
+    waker: Option<Waker>,
+}
+
+impl Shared {
+    fn push(shared: &RefCell<Shared>, event: Event) {
+        let mut shared = shared.borrow_mut();
+        shared.events.push_back(event);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_event(shared: &RefCell<Shared>, cx: &mut Context<'_>) -> Poll<Event> {
+        let mut shared = shared.borrow_mut();
+        match shared.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The event handlers that have been registered with the browser. These must live for as long as
+/// the socket.
+struct Callbacks {
+    _on_open: Closure<dyn FnMut(JsValue)>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_error: Closure<dyn FnMut(JsValue)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+/// A WebSocket client connection that is backed by the browser's WebSocket API.
+///
+/// See the [module level documentation](self) for more information.
+pub struct WebSocket {
+    socket: web_sys::WebSocket,
+    shared: Rc<RefCell<Shared>>,
+    close_state: CloseState,
+    errored: bool,
+    subprotocol: Option<String>,
+    _callbacks: Callbacks,
+}
+
+impl Debug for WebSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("url", &self.socket.url())
+            .field("close_state", &self.close_state)
+            .field("subprotocol", &self.subprotocol)
+            .finish()
+    }
+}
+
+impl WebSocket {
+    /// Opens a new WebSocket connection to `url`.
+    ///
+    /// # Errors
+    /// Returns an HTTP error if `url` is invalid or an IO error if the connection could not be
+    /// established.
+    pub async fn connect(url: &str) -> Result<WebSocket, Error> {
+        WebSocket::connect_with(url, std::iter::empty::<&str>()).await
+    }
+
+    /// Opens a new WebSocket connection to `url`, requesting that one of `subprotocols` is used.
+    /// The subprotocol that the server selected is available through [`WebSocket::subprotocol`].
+    ///
+    /// # Errors
+    /// See [`WebSocket::connect`].
+    pub async fn connect_with<I, P>(url: &str, subprotocols: I) -> Result<WebSocket, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let subprotocols = subprotocols
+            .into_iter()
+            .map(|protocol| JsString::from(protocol.as_ref()))
+            .collect::<Array>();
+        let socket =
+            web_sys::WebSocket::new_with_str_sequence(url, &subprotocols).map_err(|e| {
+                Error::with_cause(
+                    ErrorKind::Http,
+                    HttpError::MalformattedUri(Some(js_error(&e))),
+                )
+            })?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let callbacks = register_callbacks(&socket, &shared);
+
+        let mut websocket = WebSocket {
+            socket,
+            shared,
+            close_state: CloseState::NotClosed,
+            errored: false,
+            subprotocol: None,
+            _callbacks: callbacks,
+        };
+
+        match poll_fn(|cx| Shared::poll_event(&websocket.shared, cx)).await {
+            Event::Open => {
+                let protocol = websocket.socket.protocol();
+                websocket.subprotocol = (!protocol.is_empty()).then_some(protocol);
+                Ok(websocket)
+            }
+            _ => {
+                websocket.close_state = CloseState::Closed;
+                Err(Error::with_cause(
+                    ErrorKind::IO,
+                    io::Error::from(io::ErrorKind::ConnectionRefused),
+                ))
+            }
+        }
+    }
+
+    /// Returns the role of this WebSocket. This is always [`Role::Client`].
+    pub fn role(&self) -> Role {
+        Role::Client
+    }
+
+    /// Returns the subprotocol that was negotiated with the server, if any.
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.subprotocol.as_deref()
+    }
+
+    /// Attempt to read a message from the WebSocket. Text and binary payloads are appended to
+    /// `read_buffer`.
+    ///
+    /// The browser transparently responds to pings and so `Message::Ping` and `Message::Pong` are
+    /// never returned. If the peer closes the connection then `Message::Close` is returned and if
+    /// a close that was initiated by [`WebSocket::close`] completes then a close error is
+    /// returned, as with the native WebSocket.
+    ///
+    /// # Cancel safety
+    ///
+    /// This function is cancellation safe. Messages are queued by the browser until they are read.
+    pub async fn read(&mut self, read_buffer: &mut BytesMut) -> Result<Message, Error> {
+        poll_fn(|cx| self.poll_read_message(cx, read_buffer)).await
+    }
+
+    /// Attempt to read a message from the WebSocket, registering the current task for wakeup if
+    /// no message is available yet.
+    ///
+    /// See [`WebSocket::read`].
+    pub fn poll_read_message(
+        &mut self,
+        cx: &mut Context<'_>,
+        read_buffer: &mut BytesMut,
+    ) -> Poll<Result<Message, Error>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(Error::with_cause(ErrorKind::Close, CloseCause::Error)));
+        }
+
+        loop {
+            let event = match Shared::poll_event(&self.shared, cx) {
+                Poll::Ready(event) => event,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                Event::Open => {}
+                Event::Text(text) => {
+                    read_buffer.extend_from_slice(text.as_bytes());
+                    return Poll::Ready(Ok(Message::Text));
+                }
+                Event::Binary(payload) => {
+                    read_buffer.extend_from_slice(&payload);
+                    return Poll::Ready(Ok(Message::Binary));
+                }
+                Event::Error => {
+                    // The browser exposes no detail about an error and it is always followed by a
+                    // close event.
+                    self.errored = true;
+                }
+                Event::Close { code, reason } => {
+                    let close_state = self.close_state;
+                    self.close_state = CloseState::Closed;
+
+                    let result = if close_state == CloseState::Closing {
+                        Err(Error::with_cause(ErrorKind::Close, CloseCause::Stopped))
+                    } else if self.errored {
+                        Err(Error::with_cause(
+                            ErrorKind::IO,
+                            io::Error::from(io::ErrorKind::ConnectionAborted),
+                        ))
+                    } else if code == NO_STATUS_RECEIVED {
+                        Ok(Message::Close(None))
+                    } else {
+                        let reason = CloseCode::try_from(code.to_be_bytes())
+                            .map(|code| {
+                                CloseReason::new(code, (!reason.is_empty()).then_some(reason))
+                            })
+                            .ok();
+                        Ok(Message::Close(reason))
+                    };
+                    return Poll::Ready(result);
+                }
+            }
+        }
+    }
+
+    /// Sends a new text WebSocket message with a payload of `data`.
+    pub async fn write_text<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<str>,
+    {
+        self.write(data.as_ref(), PayloadType::Text).await
+    }
+
+    /// Sends a new binary WebSocket message with a payload of `data`.
+    pub async fn write_binary<I>(&mut self, data: I) -> Result<(), Error>
+    where
+        I: AsRef<[u8]>,
+    {
+        self.write(data.as_ref(), PayloadType::Binary).await
+    }
+
+    /// Sends a new WebSocket message of `message_type` and with a payload of `buf`.
+    ///
+    /// The message is handed to the browser, which queues it to be sent.
+    ///
+    /// # Errors
+    /// Browsers do not permit control frames to be sent and so an IO error is returned if
+    /// `message_type` is `PayloadType::Ping` or `PayloadType::Pong`. An encoding error is returned
+    /// if a text payload is not valid UTF-8.
+    pub async fn write<A>(&mut self, buf: A, message_type: PayloadType) -> Result<(), Error>
+    where
+        A: AsRef<[u8]>,
+    {
+        if !self.is_active() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+
+        let buf = buf.as_ref();
+        let result = match message_type {
+            PayloadType::Text => self.socket.send_with_str(std::str::from_utf8(buf)?),
+            PayloadType::Binary => self.socket.send_with_u8_array(buf),
+            PayloadType::Ping | PayloadType::Pong => {
+                return Err(Error::with_cause(
+                    ErrorKind::IO,
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Browsers do not support sending control frames",
+                    ),
+                ))
+            }
+        };
+        result.map_err(|e| {
+            Error::with_cause(
+                ErrorKind::IO,
+                io::Error::new(io::ErrorKind::NotConnected, js_error(&e)),
+            )
+        })
+    }
+
+    /// Close this WebSocket with the reason provided. The close is completed once the peer's close
+    /// frame has been received by [`WebSocket::read`].
+    ///
+    /// # Errors
+    /// Browsers only permit the normal close code and application close codes (3000 to 4999) to
+    /// be sent and so a protocol error is returned for any other code.
+    pub async fn close(&mut self, reason: CloseReason) -> Result<(), Error> {
+        if !self.is_active() {
+            return Ok(());
+        }
+
+        let CloseReason { code, description } = reason;
+        let code = u16::from(code);
+        self.socket
+            .close_with_code_and_reason(code, description.as_deref().unwrap_or_default())
+            .map_err(|_| Error::with_cause(ErrorKind::Protocol, ProtocolError::CloseCode(code)))?;
+        self.close_state = CloseState::Closing;
+        Ok(())
+    }
+
+    /// Messages are buffered by the browser and so this only checks that the WebSocket has not
+    /// been closed.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        if self.is_closed() {
+            return Err(Error::with_cause(ErrorKind::Close, CloseCause::Error));
+        }
+        Ok(())
+    }
+
+    /// Returns whether this WebSocket is closed.
+    pub fn is_closed(&self) -> bool {
+        self.close_state == CloseState::Closed
+    }
+
+    /// Returns whether this WebSocket is closing or closed.
+    pub fn is_active(&self) -> bool {
+        matches!(self.close_state, CloseState::NotClosed)
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let WebSocket { socket, .. } = self;
+        socket.set_onopen(None);
+        socket.set_onmessage(None);
+        socket.set_onerror(None);
+        socket.set_onclose(None);
+        if !self.is_closed() {
+            let _ = self.socket.close();
+        }
+    }
+}
+
+fn register_callbacks(socket: &web_sys::WebSocket, shared: &Rc<RefCell<Shared>>) -> Callbacks {
+    let on_open = {
+        let shared = shared.clone();
+        Closure::<dyn FnMut(JsValue)>::new(move |_| Shared::push(&shared, Event::Open))
+    };
+    let on_message = {
+        let shared = shared.clone();
+        Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let data = event.data();
+            if let Some(text) = data.as_string() {
+                Shared::push(&shared, Event::Text(text));
+            } else if let Some(buffer) = data.dyn_ref::<ArrayBuffer>() {
+                Shared::push(&shared, Event::Binary(Uint8Array::new(buffer).to_vec()));
+            }
+        })
+    };
+    let on_error = {
+        let shared = shared.clone();
+        Closure::<dyn FnMut(JsValue)>::new(move |_| Shared::push(&shared, Event::Error))
+    };
+    let on_close = {
+        let shared = shared.clone();
+        Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+            Shared::push(
+                &shared,
+                Event::Close {
+                    code: event.code(),
+                    reason: event.reason(),
+                },
+            )
+        })
+    };
+
+    socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+    Callbacks {
+        _on_open: on_open,
+        _on_message: on_message,
+        _on_error: on_error,
+        _on_close: on_close,
+    }
+}
+
+fn js_error(value: &JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}